@@ -0,0 +1,180 @@
+// Times each stage of loading and rendering a scene on its own, so a regression can be
+// pinned to a stage. Results are printed, and written as JSON to target/stage_benchmarks.json
+// for CI to pick up. To run them, use `cargo bench --bench stages`. Any argument that isn't
+// a flag filters the stages by name.
+
+use std::{sync::{atomic::Ordering, Arc}, time::{Duration, Instant}};
+
+use glam::Vec4;
+use image::{DynamicImage, RgbaImage};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rustic::{asset::World, atlas::pack_textures, bvh::{sort_triangles_morton, BVHBuilder}, ground::GroundSettings, light_pick::rebuild_light_pick_table, trace::*};
+
+const OUTPUT_PATH: &str = "target/stage_benchmarks.json";
+
+struct StageResult {
+    name: &'static str,
+    iterations: u32,
+    mean: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl StageResult {
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"name\": \"{}\", \"iterations\": {}, \"mean_ms\": {:.4}, \"min_ms\": {:.4}, \"max_ms\": {:.4}}}",
+            self.name,
+            self.iterations,
+            self.mean.as_secs_f64() * 1000.0,
+            self.min.as_secs_f64() * 1000.0,
+            self.max.as_secs_f64() * 1000.0,
+        )
+    }
+}
+
+// Runs a stage a number of times. The stage returns how long the part being measured took,
+// which lets it leave out its own setup.
+fn run_stage(name: &'static str, iterations: u32, mut stage: impl FnMut() -> Duration) -> StageResult {
+    let times = (0..iterations).map(|_| stage()).collect::<Vec<_>>();
+    let result = StageResult {
+        name,
+        iterations,
+        mean: times.iter().sum::<Duration>() / iterations,
+        min: times.iter().copied().min().unwrap_or_default(),
+        max: times.iter().copied().max().unwrap_or_default(),
+    };
+    println!("{:<24} mean {:>10.3} ms  min {:>10.3} ms  max {:>10.3} ms", name, result.mean.as_secs_f64() * 1000.0, result.min.as_secs_f64() * 1000.0, result.max.as_secs_f64() * 1000.0);
+    result
+}
+
+fn time(f: impl FnOnce()) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+// Textures of assorted sizes, like those of a typical scene
+fn random_textures(count: usize) -> Vec<DynamicImage> {
+    let mut rng = StdRng::seed_from_u64(0);
+    (0..count)
+        .map(|_| {
+            let size = 1 << rng.gen_range(6..11);
+            DynamicImage::ImageRgba8(RgbaImage::from_fn(size, size, |x, y| image::Rgba([x as u8, y as u8, 128, 255])))
+        })
+        .collect()
+}
+
+// Renders a fixed number of samples, one per sync, and returns the totals of each pass
+fn render_totals(use_cpu: bool, morton_order: bool, primary_pass: bool, scene: &str, samples: u32) -> TimingTotals {
+    let state = Arc::new(TracingState::new(1280, 720));
+    state.config.write().render.morton_order = morton_order as u32;
+    state.config.write().render.primary_pass = primary_pass as u32;
+    state.sync_rate.store(1, Ordering::Relaxed);
+    state.sample_limit.store(samples, Ordering::Relaxed);
+    state.running.store(true, Ordering::Relaxed);
+    if use_cpu {
+        trace_cpu(scene, None, state.clone());
+    } else {
+        trace_gpu(scene, None, state.clone());
+    }
+    let totals = *state.timing_totals.lock();
+    totals
+}
+
+fn main() {
+    let filters = std::env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect::<Vec<_>>();
+    let enabled = |name: &str| filters.is_empty() || filters.iter().any(|filter| name.contains(filter.as_str()));
+
+    let world = World::from_path("scenes/BreakTime.glb", 1.0, &GroundSettings::default()).expect("Failed to load benchmark scene.");
+    let vertices = world.per_vertex_buffer.iter().map(|data| data.vertex).collect::<Vec<Vec4>>();
+
+    let mut results = Vec::new();
+    if enabled("scene_import") {
+        results.push(run_stage("scene_import", 5, || time(|| {
+            World::from_path("scenes/BreakTime.glb", 1.0, &GroundSettings::default());
+        })));
+    }
+    if enabled("bvh_build") {
+        results.push(run_stage("bvh_build", 10, || {
+            let mut indices = world.index_buffer.clone();
+            time(|| {
+                BVHBuilder::new(&vertices, &mut indices).sah_samples(128).build();
+            })
+        }));
+    }
+    if enabled("bvh_build_morton") {
+        results.push(run_stage("bvh_build_morton", 10, || {
+            let mut indices = world.index_buffer.clone();
+            time(|| {
+                sort_triangles_morton(&vertices, &mut indices);
+                BVHBuilder::new(&vertices, &mut indices).sah_samples(128).build();
+            })
+        }));
+    }
+    if enabled("atlas_packing") {
+        let textures = random_textures(32);
+        results.push(run_stage("atlas_packing", 10, || time(|| {
+            pack_textures(&textures, 4096, 4096);
+        })));
+    }
+    if enabled("light_table_build") {
+        results.push(run_stage("light_table_build", 20, || time(|| {
+            rebuild_light_pick_table(&world.per_vertex_buffer, &world.index_buffer, &world.material_data_buffer);
+        })));
+    }
+
+    // Dispatch and readback can't be timed apart from a running render, so these come from the render loop's own timings
+    let samples = 64;
+    if enabled("gpu_dispatch") || enabled("gpu_readback") {
+        let mut dispatch = Vec::new();
+        let mut readback = Vec::new();
+        for _ in 0..5 {
+            let totals = render_totals(false, false, false, "scenes/DarkCornell.glb", samples);
+            dispatch.push(totals.trace / totals.samples.max(1));
+            readback.push(totals.resolve / totals.samples.max(1));
+        }
+        let mut dispatch = dispatch.into_iter();
+        let mut readback = readback.into_iter();
+        if enabled("gpu_dispatch") {
+            results.push(run_stage("gpu_dispatch", 5, || dispatch.next().unwrap()));
+        }
+        if enabled("gpu_readback") {
+            results.push(run_stage("gpu_readback", 5, || readback.next().unwrap()));
+        }
+    }
+    if enabled("gpu_dispatch_morton") {
+        results.push(run_stage("gpu_dispatch_morton", 5, || {
+            let totals = render_totals(false, true, false, "scenes/DarkCornell.glb", samples);
+            totals.trace / totals.samples.max(1)
+        }));
+    }
+    // Against gpu_dispatch, which traces camera rays in the path tracing kernel
+    if enabled("gpu_dispatch_primary") {
+        results.push(run_stage("gpu_dispatch_primary", 5, || {
+            let totals = render_totals(false, false, true, "scenes/DarkCornell.glb", samples);
+            totals.trace / totals.samples.max(1)
+        }));
+    }
+    if enabled("cpu_sample") {
+        results.push(run_stage("cpu_sample", 3, || {
+            let totals = render_totals(true, false, false, "scenes/DarkCornell.glb", 4);
+            totals.trace / totals.samples.max(1)
+        }));
+    }
+    if enabled("cpu_sample_morton") {
+        results.push(run_stage("cpu_sample_morton", 3, || {
+            let totals = render_totals(true, true, false, "scenes/DarkCornell.glb", 4);
+            totals.trace / totals.samples.max(1)
+        }));
+    }
+
+    let json = format!(
+        "{{\n  \"stages\": [\n    {}\n  ]\n}}\n",
+        results.iter().map(StageResult::to_json).collect::<Vec<_>>().join(",\n    ")
+    );
+    match std::fs::write(OUTPUT_PATH, &json) {
+        Ok(()) => println!("Wrote results to {}", OUTPUT_PATH),
+        Err(err) => eprintln!("Failed to write {}: {}", OUTPUT_PATH, err),
+    }
+}
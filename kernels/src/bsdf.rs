@@ -1,9 +1,9 @@
-use shared_structs::{MaterialData, TracingConfig};
+use shared_structs::{MaterialData, TracingConfig, PATTERN_NONE};
 use spirv_std::{glam::{Vec3, Vec2, Vec4Swizzles}};
 #[allow(unused_imports)]
 use spirv_std::num_traits::Float;
 
-use crate::{rng, util::{self}};
+use crate::{rng, pattern, util::{self}};
 use shared_structs::{Image, Sampler};
 
 type Spectrum = Vec3;
@@ -190,6 +190,14 @@ pub struct PBR {
 }
 
 impl PBR {
+    // Albedo for the denoiser. OIDN wants the reflectance of specular surfaces, so metals use
+    // their Fresnel reflectance at the view angle rather than the base color alone.
+    #[cfg(not(target_arch = "spirv"))]
+    pub fn denoise_albedo(&self, cos_theta: f32) -> Spectrum {
+        let specular = util::fresnel_schlick(cos_theta.abs().min(1.0), self.albedo);
+        self.albedo.lerp(specular, self.metallic).clamp(Vec3::ZERO, Vec3::ONE)
+    }
+
     fn evaluate_diffuse_fast(
         &self,
         cos_theta: f32,
@@ -352,22 +360,31 @@ impl BSDF for PBR {
 }
 
 pub fn get_pbr_bsdf(config: &TracingConfig, material: &MaterialData, uv: Vec2, atlas: &Image!(2D, type=f32, sampled), sampler: &Sampler) -> PBR {
-    let albedo = if material.has_albedo_texture() {
-        let scaled_uv = material.albedo.xy() + uv * material.albedo.zw();
+    let albedo = if material.patterns.x != PATTERN_NONE {
+        let weight = pattern::pattern_weight(material.patterns.x, uv, material.pattern_scale.x);
+        material.albedo.xyz().lerp(material.albedo_pattern.xyz(), weight)
+    } else if material.has_albedo_texture() {
+        let scaled_uv = util::atlas_uv(material.albedo, material.udim_grid.x, util::transform_uv(material.albedo_transform, material.uv_rotation.x, uv));
         let albedo = atlas.sample_by_lod(*sampler, scaled_uv, 0.0);
         albedo.xyz()
     } else {
         material.albedo.xyz()
     };
-    let roughness = if material.has_roughness_texture() {
-        let scaled_uv = material.roughness.xy() + uv * material.roughness.zw();
+    let roughness = if material.patterns.y != PATTERN_NONE {
+        let weight = pattern::pattern_weight(material.patterns.y, uv, material.pattern_scale.y);
+        material.roughness.x + (material.roughness.y - material.roughness.x) * weight
+    } else if material.has_roughness_texture() {
+        let scaled_uv = util::atlas_uv(material.roughness, material.udim_grid.z, util::transform_uv(material.roughness_transform, material.uv_rotation.z, uv));
         let roughness = atlas.sample_by_lod(*sampler, scaled_uv, 0.0);
         roughness.x
     } else {
         material.roughness.x
     };
-    let metallic = if material.has_metallic_texture() {
-        let scaled_uv = material.metallic.xy() + uv * material.metallic.zw();
+    let metallic = if material.patterns.z != PATTERN_NONE {
+        let weight = pattern::pattern_weight(material.patterns.z, uv, material.pattern_scale.z);
+        material.metallic.x + (material.metallic.y - material.metallic.x) * weight
+    } else if material.has_metallic_texture() {
+        let scaled_uv = util::atlas_uv(material.metallic, material.udim_grid.y, util::transform_uv(material.metallic_transform, material.uv_rotation.y, uv));
         let metallic = atlas.sample_by_lod(*sampler, scaled_uv, 0.0);
         metallic.x
     } else {
@@ -382,6 +399,6 @@ pub fn get_pbr_bsdf(config: &TracingConfig, material: &MaterialData, uv: Vec2, a
         albedo,
         roughness,
         metallic,
-        specular_weight_clamp: config.specular_weight_clamp,
+        specular_weight_clamp: config.render.specular_weight_clamp,
     }
 }
\ No newline at end of file
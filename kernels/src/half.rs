@@ -0,0 +1,79 @@
+use spirv_std::glam::Vec2;
+
+// Software conversions between f32 and IEEE 754 half precision floats. These are
+// shared by the kernel and the host, so they can't rely on GPU-only intrinsics.
+// Values too small for a normal half become half denormals, rounded the same way,
+// and out-of-range values are clamped to the largest finite half instead of
+// becoming infinite.
+
+// Rounding bits for both halves of a packed pair that round to nearest.
+const ROUND_TO_NEAREST: u32 = 0xFFF | (0xFFF << 13);
+
+// `rounding` decides which of the 13 dropped mantissa bits round up. 0xFFF rounds
+// to nearest, a uniformly random value gives unbiased stochastic rounding.
+pub fn f32_to_f16_bits(value: f32, rounding: u32) -> u32 {
+    let bits = value.to_bits();
+    let sign = (bits >> 16) & 0x8000;
+    let exponent = (bits >> 23) & 0xFF;
+    let mantissa = bits & 0x7FFFFF;
+
+    if exponent == 0xFF {
+        // Inf or NaN
+        return sign | 0x7C00 | (if mantissa != 0 { 0x200 } else { 0 });
+    }
+    if exponent < 113 {
+        // Denormal half, 2^-24 times a mantissa without the implicit leading bit. Flushing
+        // these to zero would pull the stochastically rounded mean of dark pixels to 0.
+        let full = if exponent == 0 { mantissa } else { mantissa | 0x800000 };
+        let shift = 126 - exponent.max(1);
+        let mut half = if shift < 32 { full >> shift } else { 0 };
+        let dropped = if shift - 13 < 32 { (full >> (shift - 13)) & 0x1FFF } else { 0 };
+        if dropped > (rounding & 0x1FFF) {
+            // Carries into the smallest normal half when it has to
+            half += 1;
+        }
+        return sign | half;
+    }
+    if exponent > 142 {
+        return sign | 0x7BFF;
+    }
+
+    let mut half = ((exponent - 112) << 10) | (mantissa >> 13);
+    if (mantissa & 0x1FFF) > (rounding & 0x1FFF) {
+        // May carry into the exponent, which is the correct rounding behavior
+        half += 1;
+    }
+    if half > 0x7BFF {
+        half = 0x7BFF;
+    }
+    sign | half
+}
+
+pub fn f16_bits_to_f32(half: u32) -> f32 {
+    let sign = (half & 0x8000) << 16;
+    let exponent = (half >> 10) & 0x1F;
+    let mantissa = half & 0x3FF;
+
+    if exponent == 0 {
+        // Zero or denormal
+        let magnitude = mantissa as f32 * (1.0 / 16777216.0);
+        return if sign != 0 { -magnitude } else { magnitude };
+    }
+    if exponent == 0x1F {
+        return f32::from_bits(sign | 0x7F800000 | (mantissa << 13));
+    }
+    f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13))
+}
+
+pub fn pack_half2x16(value: Vec2) -> u32 {
+    pack_half2x16_dithered(value, ROUND_TO_NEAREST)
+}
+
+// Uses the lower 26 bits of `rounding`, 13 for each half.
+pub fn pack_half2x16_dithered(value: Vec2, rounding: u32) -> u32 {
+    f32_to_f16_bits(value.x, rounding) | (f32_to_f16_bits(value.y, rounding >> 13) << 16)
+}
+
+pub fn unpack_half2x16(packed: u32) -> Vec2 {
+    Vec2::new(f16_bits_to_f32(packed & 0xFFFF), f16_bits_to_f32(packed >> 16))
+}
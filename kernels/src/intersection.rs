@@ -1,9 +1,11 @@
-use shared_structs::{BVHNode, PerVertexData};
+use shared_structs::{csg_inside, csg_operation, is_point, is_sdf, point_sphere, BVHNode, PerVertexData, CSG_HAS_INTERSECT, CSG_SUBTRACT, CSG_UNION};
 #[allow(unused_imports)]
 use spirv_std::num_traits::Float;
-use spirv_std::{glam::{UVec4, Vec4, Vec3, Vec4Swizzles}, num_traits::Signed};
+use spirv_std::{glam::{IVec3, UVec4, Vec4, Vec3, Vec4Swizzles}, num_traits::Signed};
 
-use crate::vec::FixedVec;
+use crate::{sdf::{intersect_sdf, SdfShape}, vec::FixedVec};
+
+const CSG_SDF_SURFACES: u32 = 8; // Surfaces of an SDF skipped over before giving up on it, see BVHReference::csg_hit
 
 // Adapted from raytri.c
 fn muller_trumbore(ro: Vec3, rd: Vec3, a: Vec3, b: Vec3, c: Vec3, out_t: &mut f32, out_backface: &mut bool) -> bool
@@ -53,12 +55,46 @@ fn muller_trumbore(ro: Vec3, rd: Vec3, a: Vec3, b: Vec3, c: Vec3, out_t: &mut f3
     return true;
 }
 
+// A disc through the point's center, facing the ray. Rays starting inside the point's sphere, like
+// those leaving the point itself, pass through it.
+fn intersect_point(ro: Vec3, rd: Vec3, a: Vec3, b: Vec3, c: Vec3, out_t: &mut f32) -> bool {
+    let (center, radius) = point_sphere(a, b, c);
+    let to_center = center - ro;
+    if to_center.length_squared() < radius * radius {
+        return false;
+    }
+    let t = to_center.dot(rd);
+    *out_t = t;
+    t > 0.0 && (to_center - rd * t).length_squared() < radius * radius
+}
+
+// Triangles and points, see shared_structs::PRIMITIVE_POINT. Points have no back face. Signed
+// distance fields need more than the corners, see sdf::intersect_sdf, and are missed here.
+fn intersect_primitive(triangle: UVec4, ro: Vec3, rd: Vec3, a: Vec3, b: Vec3, c: Vec3, out_t: &mut f32, out_backface: &mut bool) -> bool {
+    if is_sdf(triangle) {
+        false
+    } else if is_point(triangle) {
+        *out_backface = false;
+        intersect_point(ro, rd, a, b, c, out_t)
+    } else {
+        muller_trumbore(ro, rd, a, b, c, out_t, out_backface)
+    }
+}
+
+// Surfaces of a CSG result face out of it, whichever mesh they come from, so a cutter's faces are
+// turned around. Back faces, where the ray leaves the result, face along the ray.
+pub fn csg_normal(normal: Vec3, rd: Vec3, backface: bool) -> Vec3 {
+    if (normal.dot(rd) > 0.0) != backface { -normal } else { normal }
+}
+
+#[derive(Clone, Copy)]
 pub struct TraceResult {
     pub triangle: UVec4,
     pub triangle_index: u32,
     pub t: f32,
     pub hit: bool,
     pub backface: bool,
+    pub section: bool, // the fill of a cut through a closed mesh rather than the triangle, see section::SectionSpan
 }
 
 impl Default for TraceResult {
@@ -69,12 +105,13 @@ impl Default for TraceResult {
             t: 1000000.0,
             hit: false,
             backface: false,
+            section: false,
         }
     }
 }
 
-#[allow(dead_code)]
-fn intersect_slow_as_shit(
+// Tests every triangle, as ground truth for the BVH traversal. CSG is left out, see BVHReference::csg_hit.
+pub fn intersect_slow_as_shit(
     vertex_buffer: &[Vec4],
     index_buffer: &[UVec4],
     ro: Vec3,
@@ -89,7 +126,7 @@ fn intersect_slow_as_shit(
 
         let mut t = 0.0;
         let mut backface = false;
-        if muller_trumbore(ro, rd, a, b, c, &mut t, &mut backface) && t > 0.001 && t < result.t {
+        if intersect_primitive(triangle, ro, rd, a, b, c, &mut t, &mut backface) && t > 0.001 && t < result.t {
             result.triangle = triangle;
             result.triangle_index = i as u32;
             result.t = result.t.min(t);
@@ -123,6 +160,7 @@ fn intersect_aabb(aabb_min: Vec3, aabb_max: Vec3, ro: Vec3, rd: Vec3, prev_min_t
 
 pub struct BVHReference<'a> {
     pub nodes: &'a [BVHNode],
+    pub min_t: f32, // hits closer than this are self-intersections
 }
 
 impl<'a> BVHReference<'a> {
@@ -149,7 +187,7 @@ impl<'a> BVHReference<'a> {
     
                     let mut t = 0.0;
                     let mut backface = false;
-                    if muller_trumbore(ro, rd, a, b, c, &mut t, &mut backface) && t > 0.001 && t < result.t {
+                    if intersect_primitive(triangle, ro, rd, a, b, c, &mut t, &mut backface) && t > self.min_t && t < result.t {
                         result.triangle = triangle;
                         result.triangle_index = triangle_index;
                         result.t = result.t.min(t);
@@ -166,15 +204,97 @@ impl<'a> BVHReference<'a> {
         result
     }
 
-    pub fn intersect_nearest(&self, per_vertex_buffer: &[PerVertexData], index_buffer: &[UVec4], ro: Vec3, rd: Vec3) -> TraceResult {
-        self.intersect_front_to_back::<true>(per_vertex_buffer, index_buffer, ro, rd, 0.0)
+    // Triangles with any of the hidden_flags visibility flags are passed through, see shared_structs::HIDDEN_FROM_CAMERA
+    pub fn intersect_nearest(&self, per_vertex_buffer: &[PerVertexData], index_buffer: &[UVec4], ro: Vec3, rd: Vec3, hidden_flags: u32) -> TraceResult {
+        self.intersect_front_to_back::<true>(per_vertex_buffer, index_buffer, ro, rd, 0.0, hidden_flags)
+    }
+
+    pub fn intersect_any(&self, per_vertex_buffer: &[PerVertexData], index_buffer: &[UVec4], ro: Vec3, rd: Vec3, max_t: f32, hidden_flags: u32) -> TraceResult {
+        self.intersect_front_to_back::<false>(per_vertex_buffer, index_buffer, ro, rd, max_t, hidden_flags)
+    }
+
+    // Net crossings of CSG meshes past `t`, per operation, which is how many of each the ray is
+    // inside of just past it, as leaving a closed mesh crosses one more back face than front faces.
+    // SDFs are tested at the point instead. The primitive at `skip` is left out. Hidden meshes
+    // still count, visibility flags only hide their surfaces.
+    fn csg_depths(&self, per_vertex_buffer: &[PerVertexData], index_buffer: &[UVec4], ro: Vec3, rd: Vec3, t: f32, skip: u32) -> IVec3 {
+        let point = ro + rd * t;
+        let mut depths = IVec3::ZERO;
+        let mut stack = FixedVec::<usize, 32>::new();
+        stack.push(0);
+        while !stack.is_empty() {
+            let node = &self.nodes[stack.pop().unwrap()];
+            if intersect_aabb(node.aabb_min(), node.aabb_max(), ro, rd, f32::INFINITY).is_infinite() {
+                continue;
+            }
+
+            if node.is_leaf() {
+                for i in 0..node.triangle_count() {
+                    let triangle_index = node.first_triangle_index() + i;
+                    let triangle = index_buffer[triangle_index as usize];
+                    let operation = csg_operation(triangle);
+                    if operation == 0 || is_point(triangle) || triangle_index == skip {
+                        continue;
+                    }
+                    let crossing = if is_sdf(triangle) {
+                        let shape = SdfShape::from_corners(per_vertex_buffer, triangle);
+                        let inside = point.cmpge(shape.min).all() && point.cmple(shape.max).all() && shape.distance(per_vertex_buffer, point) < 0.0;
+                        if inside { 1 } else { 0 }
+                    } else {
+                        let a = per_vertex_buffer[triangle.x as usize].vertex.xyz();
+                        let b = per_vertex_buffer[triangle.y as usize].vertex.xyz();
+                        let c = per_vertex_buffer[triangle.z as usize].vertex.xyz();
+                        let mut crossing_t = 0.0;
+                        let mut backface = false;
+                        if !muller_trumbore(ro, rd, a, b, c, &mut crossing_t, &mut backface) || crossing_t <= t {
+                            0
+                        } else if backface {
+                            1
+                        } else {
+                            -1
+                        }
+                    };
+                    depths += csg_axis(operation) * crossing;
+                }
+            } else {
+                stack.push(node.right_node_index() as usize);
+                stack.push(node.left_node_index() as usize);
+            }
+        }
+        depths
     }
 
-    pub fn intersect_any(&self, per_vertex_buffer: &[PerVertexData], index_buffer: &[UVec4], ro: Vec3, rd: Vec3, max_t: f32) -> TraceResult {
-        self.intersect_front_to_back::<false>(per_vertex_buffer, index_buffer, ro, rd, max_t)
+    // Whether a hit on a CSG mesh is on the surface of the result, which is where the result differs
+    // on either side of it. Its back faces are those the ray leaves the result through. An SDF is only
+    // found up to its first surface per trace, so it's traced again past each one left out.
+    fn csg_hit(&self, per_vertex_buffer: &[PerVertexData], index_buffer: &[UVec4], triangle: UVec4, triangle_index: u32, ro: Vec3, rd: Vec3, max_t: f32, t: &mut f32, backface: &mut bool) -> bool {
+        let axis = csg_axis(csg_operation(triangle));
+        let has_intersect = triangle.w & CSG_HAS_INTERSECT != 0;
+        for _ in 0..CSG_SDF_SURFACES {
+            // The hit itself is left out of the count, and added back on the side of it that it's inside of
+            let mut after = self.csg_depths(per_vertex_buffer, index_buffer, ro, rd, *t, triangle_index);
+            if is_sdf(triangle) && !*backface {
+                after += axis;
+            }
+            let before = after + if *backface { axis } else { -axis };
+            let inside_before = csg_inside(before.x, before.y, before.z, has_intersect);
+            if inside_before != csg_inside(after.x, after.y, after.z, has_intersect) {
+                *backface = inside_before;
+                return true;
+            }
+            if !is_sdf(triangle) {
+                return false;
+            }
+            let from = *t;
+            if !intersect_sdf(per_vertex_buffer, triangle, ro + rd * from, rd, self.min_t, max_t - from, t, backface) {
+                return false;
+            }
+            *t += from;
+        }
+        false
     }
 
-    fn intersect_front_to_back<const NEAREST_HIT: bool>(&self, per_vertex_buffer: &[PerVertexData], index_buffer: &[UVec4], ro: Vec3, rd: Vec3, max_t: f32) -> TraceResult {
+    fn intersect_front_to_back<const NEAREST_HIT: bool>(&self, per_vertex_buffer: &[PerVertexData], index_buffer: &[UVec4], ro: Vec3, rd: Vec3, max_t: f32, hidden_flags: u32) -> TraceResult {
         let mut stack = FixedVec::<usize, 32>::new();
         stack.push(0);
 
@@ -186,13 +306,24 @@ impl<'a> BVHReference<'a> {
                 for i in 0..node.triangle_count() {
                     let triangle_index = node.first_triangle_index() + i;
                     let triangle = index_buffer[triangle_index as usize];
+                    if triangle.w & hidden_flags != 0 {
+                        continue;
+                    }
                     let a = per_vertex_buffer[triangle.x as usize].vertex.xyz();
                     let b = per_vertex_buffer[triangle.y as usize].vertex.xyz();
                     let c = per_vertex_buffer[triangle.z as usize].vertex.xyz();
     
                     let mut t = 0.0;
                     let mut backface = false;
-                    if muller_trumbore(ro, rd, a, b, c, &mut t, &mut backface) && t > 0.001 && t < result.t && (NEAREST_HIT || t <= max_t) {
+                    let hit = if is_sdf(triangle) {
+                        intersect_sdf(per_vertex_buffer, triangle, ro, rd, self.min_t, result.t, &mut t, &mut backface)
+                    } else {
+                        intersect_primitive(triangle, ro, rd, a, b, c, &mut t, &mut backface)
+                    };
+                    let furthest = if NEAREST_HIT { result.t } else { max_t };
+                    if hit && t > self.min_t && t < result.t && (NEAREST_HIT || t <= max_t)
+                        && (csg_operation(triangle) == 0 || self.csg_hit(per_vertex_buffer, index_buffer, triangle, triangle_index, ro, rd, furthest, &mut t, &mut backface))
+                    {
                         result.triangle = triangle;
                         result.triangle_index = triangle_index;
                         result.t = result.t.min(t);
@@ -233,3 +364,14 @@ impl<'a> BVHReference<'a> {
         result
     }
 }
+
+// Which of the counts of csg_depths an operation goes into: solids, cutters, then intersectors
+fn csg_axis(operation: u32) -> IVec3 {
+    if operation == CSG_UNION {
+        IVec3::X
+    } else if operation == CSG_SUBTRACT {
+        IVec3::Y
+    } else {
+        IVec3::Z
+    }
+}
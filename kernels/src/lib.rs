@@ -2,9 +2,12 @@
 
 use bsdf::BSDF;
 use glam::*;
-use intersection::BVHReference;
+use intersection::{BVHReference, TraceResult};
+use section::SectionSpan;
 use shared_structs::{Image, Sampler};
-use shared_structs::{TracingConfig, BVHNode, MaterialData, PerVertexData, LightPickEntry, NextEventEstimation};
+use shared_structs::{TracingConfig, CameraUniform, RenderSettings, EnvironmentSettings, BVHNode, FirstHit, MaterialData, PerVertexData, LightPickEntry, NextEventEstimation};
+use shared_structs::{csg_operation, is_point, is_sdf, triangle_material_index, ENVIRONMENT_LIGHT_GROUP, HIDDEN_FROM_CAMERA, HIDDEN_FROM_INDIRECT, HIDDEN_FROM_SHADOWS, PATTERN_NONE, PROJECTION_EQUIRECTANGULAR};
+use shared_structs::{kernel_features, FEATURE_NEE_MASK, FEATURE_NORMAL_MAPS, FEATURE_SKYBOX_IMAGE};
 #[allow(unused_imports)]
 use spirv_std::num_traits::Float;
 use spirv_std::{glam, spirv};
@@ -12,10 +15,113 @@ use spirv_std::{glam, spirv};
 mod bsdf;
 mod rng;
 mod util;
-mod intersection;
+pub mod intersection;
 mod vec;
-mod skybox;
-mod light_pick;
+pub mod skybox;
+pub mod light_pick;
+mod path_record;
+pub mod ray_cone;
+pub mod pattern;
+pub mod half;
+pub mod morton;
+pub mod sdf;
+pub mod section;
+
+pub use bsdf::LobeType;
+pub use path_record::{PathEvent, PathRecorder, PathVertex};
+pub use util::luminance;
+
+// Camera ray through a point on the screen, given in pixels from the top left corner
+pub fn camera_ray(config: &TracingConfig, screen: Vec2) -> (Vec3, Vec3) {
+    if config.camera.projection == PROJECTION_EQUIRECTANGULAR {
+        // Laid out like skybox images, see environment::texel_direction on the host
+        let phi = (screen.x / config.render.width as f32 - 0.5) * 2.0 * core::f32::consts::PI;
+        let latitude = (0.5 - screen.y / config.render.height as f32) * core::f32::consts::PI;
+        let direction = Vec3::new(latitude.cos() * phi.cos(), latitude.sin(), latitude.cos() * phi.sin());
+        return (config.camera.cam_position.xyz(), direction);
+    }
+
+    let mut uv = Vec2::new(
+        screen.x / config.render.width as f32,
+        1.0 - screen.y / config.render.height as f32,
+    ) * 2.0
+        - 1.0;
+    uv.y *= config.render.height as f32 / config.render.width as f32;
+
+    let euler_mat = Mat3::from_rotation_y(config.camera.cam_rotation.y) * Mat3::from_rotation_x(config.camera.cam_rotation.x);
+    (config.camera.cam_position.xyz(), euler_mat * Vec3::new(uv.x, uv.y, 1.0).normalize())
+}
+
+// Where a direction from a camera with the given rotation lands on the screen, in pixels from the
+// top left corner. The inverse of camera_ray. z is the depth along the view direction, which is
+// negative behind the camera.
+pub fn screen_position(config: &TracingConfig, cam_rotation: Vec4, direction: Vec3) -> Vec3 {
+    let euler_mat = Mat3::from_rotation_y(cam_rotation.y) * Mat3::from_rotation_x(cam_rotation.x);
+    let local = euler_mat.transpose() * direction;
+    let mut uv = local.xy() / local.z;
+    uv.y /= config.render.height as f32 / config.render.width as f32;
+    let screen = Vec2::new(
+        (uv.x + 1.0) * 0.5 * config.render.width as f32,
+        (1.0 - (uv.y + 1.0) * 0.5) * config.render.height as f32,
+    );
+    screen.extend(local.z)
+}
+
+// Motion vector of the camera ray through the center of a pixel: the offset from the pixel to where
+// the same surface was on screen with the previous camera, in pixels. Only the camera moves, and the
+// sky is infinitely far away, so only rotation moves it. Surfaces the previous camera didn't see
+// in front of it have no motion.
+pub fn pixel_motion(
+    config: &TracingConfig,
+    pixel: UVec2,
+    per_vertex_buffer: &[PerVertexData],
+    index_buffer: &[UVec4],
+    nodes_buffer: &[BVHNode],
+) -> Vec2 {
+    let bvh = BVHReference {
+        nodes: nodes_buffer,
+        min_t: config.render.ray_offset,
+    };
+    // Panoramas are only rendered from a fixed point
+    if config.camera.projection == PROJECTION_EQUIRECTANGULAR {
+        return Vec2::ZERO;
+    }
+    let (ray_origin, ray_direction) = camera_ray(config, pixel.as_vec2() + 0.5);
+    let span = SectionSpan::new(&config.render, ray_origin, ray_direction);
+    let trace_result = span.clip(&config.render, bvh.intersect_nearest(per_vertex_buffer, index_buffer, span.origin(ray_origin, ray_direction), ray_direction, HIDDEN_FROM_CAMERA));
+    let (current, previous) = if trace_result.hit {
+        let hit = ray_origin + ray_direction * trace_result.t;
+        (hit - config.camera.cam_position.xyz(), hit - config.camera.prev_cam_position.xyz())
+    } else {
+        (ray_direction, ray_direction)
+    };
+    let current = screen_position(config, config.camera.cam_rotation, current);
+    let previous = screen_position(config, config.camera.prev_cam_rotation, previous);
+    if previous.z <= 0.0 {
+        return Vec2::ZERO;
+    }
+    previous.xy() - current.xy()
+}
+
+// Get anti-aliased pixel coordinates. The jitter is always drawn, so the rest of the
+// path sees the same random numbers whether or not it is used.
+#[cfg_attr(target_arch = "spirv", inline(always))]
+fn primary_ray(config: &TracingConfig, id: UVec3, rng_state: &mut rng::RngState) -> (Vec3, Vec3) {
+    let jitter = rng_state.gen_r2();
+    let suv = id.xy().as_vec2() + if config.render.jitter != 0 { jitter } else { Vec2::splat(0.5) };
+    camera_ray(config, suv)
+}
+
+// Nearest hit of a camera ray, past what the section planes cut away
+#[cfg_attr(target_arch = "spirv", inline(always))]
+fn trace_camera_ray(config: &TracingConfig, per_vertex_buffer: &[PerVertexData], index_buffer: &[UVec4], nodes_buffer: &[BVHNode], ray_origin: Vec3, ray_direction: Vec3) -> TraceResult {
+    let bvh = BVHReference {
+        nodes: nodes_buffer,
+        min_t: config.render.ray_offset,
+    };
+    let span = SectionSpan::new(&config.render, ray_origin, ray_direction);
+    span.clip(&config.render, bvh.intersect_nearest(per_vertex_buffer, index_buffer, span.origin(ray_origin, ray_direction), ray_direction, HIDDEN_FROM_CAMERA))
+}
 
 #[cfg_attr(target_arch = "spirv", inline(always))]
 pub fn trace_pixel(
@@ -30,154 +136,560 @@ pub fn trace_pixel(
     sampler: &Sampler,
     atlas: &Image!(2D, type=f32, sampled),
     skybox: &Image!(2D, type=f32, sampled),
+    blue_noise_buffer: &[u32],
+    recorder: &mut impl PathRecorder,
 ) -> (Vec4, UVec2) {
-    let nee_mode = NextEventEstimation::from_u32(config.nee);
-    let nee = nee_mode.uses_nee();
-    let mut rng_state = rng::RngState::new(rng);
+    let mut rng_state = rng::RngState::new(rng, config.render.use_blue_noise != 0, config.render.seed, id.xy(), blue_noise_buffer);
+    let (ray_origin, ray_direction) = primary_ray(config, id, &mut rng_state);
+    let first_hit = trace_camera_ray(config, per_vertex_buffer, index_buffer, nodes_buffer, ray_origin, ray_direction);
+    // Not worth specializing on the CPU, so every feature is checked at runtime
+    trace_path(
+        kernel_features(config, true),
+        config,
+        rng_state,
+        ray_origin,
+        ray_direction,
+        first_hit,
+        per_vertex_buffer,
+        index_buffer,
+        nodes_buffer,
+        material_data_buffer,
+        light_pick_buffer,
+        sampler,
+        atlas,
+        skybox,
+        recorder,
+    )
+}
 
-    // Get anti-aliased pixel coordinates.
-    let suv = id.xy().as_vec2() + rng_state.gen_r2();
-    let mut uv = Vec2::new(
-        suv.x as f32 / config.width as f32,
-        1.0 - suv.y as f32 / config.height as f32,
-    ) * 2.0
-        - 1.0;
-    uv.y *= config.height as f32 / config.width as f32;
+// Radiance arriving at `origin` from `direction`, for baking, where rays start on surfaces instead
+// of at the camera. The ray is treated as one that has bounced, so it passes through what is hidden
+// from indirect rays. Only used on the CPU.
+pub fn trace_ray(
+    id: UVec3,
+    config: &TracingConfig,
+    rng: UVec2,
+    origin: Vec3,
+    direction: Vec3,
+    per_vertex_buffer: &[PerVertexData],
+    index_buffer: &[UVec4],
+    nodes_buffer: &[BVHNode],
+    material_data_buffer: &[MaterialData],
+    light_pick_buffer: &[LightPickEntry],
+    sampler: &Sampler,
+    atlas: &Image!(2D, type=f32, sampled),
+    skybox: &Image!(2D, type=f32, sampled),
+    blue_noise_buffer: &[u32],
+) -> (Vec4, UVec2) {
+    let rng_state = rng::RngState::new(rng, config.render.use_blue_noise != 0, config.render.seed, id.xy(), blue_noise_buffer);
+    let bvh = BVHReference {
+        nodes: nodes_buffer,
+        min_t: config.render.ray_offset,
+    };
+    let first_hit = bvh.intersect_nearest(per_vertex_buffer, index_buffer, origin, direction, HIDDEN_FROM_INDIRECT);
+    trace_path(
+        kernel_features(config, true),
+        config,
+        rng_state,
+        origin,
+        direction,
+        first_hit,
+        per_vertex_buffer,
+        index_buffer,
+        nodes_buffer,
+        material_data_buffer,
+        light_pick_buffer,
+        sampler,
+        atlas,
+        skybox,
+        &mut (),
+    )
+}
+
+// Same as trace_pixel, but picks up the first hit found by primary_kernel instead of tracing it again,
+// unless the render settings skip that dispatch. The features are constant in each entry point, see
+// trace_kernel_variants.
+#[cfg_attr(target_arch = "spirv", inline(always))]
+pub fn trace_pixel_from_first_hit(
+    features: u32,
+    id: UVec3,
+    config: &TracingConfig,
+    rng: UVec2,
+    first_hit: FirstHit,
+    per_vertex_buffer: &[PerVertexData],
+    index_buffer: &[UVec4],
+    nodes_buffer: &[BVHNode],
+    material_data_buffer: &[MaterialData],
+    light_pick_buffer: &[LightPickEntry],
+    sampler: &Sampler,
+    atlas: &Image!(2D, type=f32, sampled),
+    skybox: &Image!(2D, type=f32, sampled),
+    blue_noise_buffer: &[u32],
+    recorder: &mut impl PathRecorder,
+) -> (Vec4, UVec2) {
+    let mut rng_state = rng::RngState::new(rng, config.render.use_blue_noise != 0, config.render.seed, id.xy(), blue_noise_buffer);
+    let (ray_origin, ray_direction) = primary_ray(config, id, &mut rng_state);
+    let first_hit = if config.render.primary_pass != 0 {
+        TraceResult {
+            triangle: index_buffer[first_hit.triangle_index as usize],
+            triangle_index: first_hit.triangle_index,
+            t: first_hit.t,
+            hit: first_hit.hit(),
+            backface: first_hit.backface(),
+            section: first_hit.section(),
+        }
+    } else {
+        trace_camera_ray(config, per_vertex_buffer, index_buffer, nodes_buffer, ray_origin, ray_direction)
+    };
+    trace_path(
+        features,
+        config,
+        rng_state,
+        ray_origin,
+        ray_direction,
+        first_hit,
+        per_vertex_buffer,
+        index_buffer,
+        nodes_buffer,
+        material_data_buffer,
+        light_pick_buffer,
+        sampler,
+        atlas,
+        skybox,
+        recorder,
+    )
+}
+
+// Chance of a path surviving Russian roulette after a bounce. Paths are only ever at risk after
+// min_bounces, or in adaptive mode once they carry less than the albedo of the surface they just
+// bounced off, so paths that lose energy slowly in bright scenes go deep, while the many dark
+// bounces of an interior are cut short.
+pub fn russian_roulette_survival(config: &TracingConfig, bounce: u32, throughput: Vec3, albedo: Vec3) -> f32 {
+    if config.render.adaptive_roulette != 0 {
+        (throughput.max_element() / albedo.max_element().max(util::EPS)).min(1.0)
+    } else if bounce > config.render.min_bounces {
+        throughput.max_element().min(1.0)
+    } else {
+        1.0
+    }
+}
 
-    // Setup camera.
-    let mut ray_origin = config.cam_position.xyz();
-    let mut ray_direction = Vec3::new(uv.x, uv.y, 1.0).normalize();
-    let euler_mat = Mat3::from_rotation_y(config.cam_rotation.y) * Mat3::from_rotation_x(config.cam_rotation.x);
-    ray_direction = euler_mat * ray_direction;
+#[cfg_attr(target_arch = "spirv", inline(always))]
+fn apply_normal_map(
+    material: &MaterialData,
+    vertices: [PerVertexData; 3],
+    bary: Vec3,
+    normal: Vec3,
+    uv: Vec2,
+    atlas: &Image!(2D, type=f32, sampled),
+    sampler: &Sampler,
+) -> Vec3 {
+    let scaled_uv = util::atlas_uv(material.normals, material.udim_grid.w, util::transform_uv(material.normal_transform, material.uv_rotation.w, uv));
+    let normal_map = atlas.sample_by_lod(*sampler, scaled_uv, 0.0) * 2.0 - 1.0;
+    let tangent = bary.x * vertices[0].tangent.xyz() + bary.y * vertices[1].tangent.xyz() + bary.z * vertices[2].tangent.xyz();
+    let tbn = Mat3::from_cols(tangent, tangent.cross(normal), normal);
+    (tbn * normal_map.xyz()).normalize()
+}
 
+// Albedo and normal of the surface a camera ray first hits, the auxiliary images OIDN uses to
+// keep detail. Albedo is kept within [0, 1], and the sky has an albedo of 1 and no normal.
+#[cfg(not(target_arch = "spirv"))]
+pub fn denoise_auxiliary(
+    config: &TracingConfig,
+    screen: Vec2,
+    per_vertex_buffer: &[PerVertexData],
+    index_buffer: &[UVec4],
+    nodes_buffer: &[BVHNode],
+    material_data_buffer: &[MaterialData],
+    sampler: &Sampler,
+    atlas: &Image!(2D, type=f32, sampled),
+) -> (Vec3, Vec3) {
     let bvh = BVHReference {
         nodes: nodes_buffer,
+        min_t: config.render.ray_offset,
     };
+    let (ray_origin, ray_direction) = camera_ray(config, screen);
+    let span = SectionSpan::new(&config.render, ray_origin, ray_direction);
+    let trace_result = span.clip(&config.render, bvh.intersect_nearest(per_vertex_buffer, index_buffer, span.origin(ray_origin, ray_direction), ray_direction, HIDDEN_FROM_CAMERA));
+    if !trace_result.hit {
+        return (Vec3::ONE, Vec3::ZERO);
+    }
+    if trace_result.section {
+        return (config.render.section_color.xyz().clamp(Vec3::ZERO, Vec3::ONE), span.normal);
+    }
+
+    let vertices = [
+        per_vertex_buffer[trace_result.triangle.x as usize],
+        per_vertex_buffer[trace_result.triangle.y as usize],
+        per_vertex_buffer[trace_result.triangle.z as usize],
+    ];
+    let hit = ray_origin + ray_direction * trace_result.t;
+    let bary = util::barycentric(hit, vertices[0].vertex.xyz(), vertices[1].vertex.xyz(), vertices[2].vertex.xyz());
+    let mut normal = (bary.x * vertices[0].normal.xyz() + bary.y * vertices[1].normal.xyz() + bary.z * vertices[2].normal.xyz()).normalize();
+    let uv = util::triangle_uv(per_vertex_buffer, trace_result.triangle, bary);
+    let material = material_data_buffer[triangle_material_index(trace_result.triangle) as usize];
+    let point = is_point(trace_result.triangle);
+    if point {
+        normal = -ray_direction;
+    } else if is_sdf(trace_result.triangle) {
+        normal = sdf::SdfShape::from_corners(per_vertex_buffer, trace_result.triangle).normal(per_vertex_buffer, hit);
+    } else if material.has_normal_texture() {
+        normal = apply_normal_map(&material, vertices, bary, normal, uv, atlas, sampler);
+    }
+    if csg_operation(trace_result.triangle) != 0 {
+        normal = intersection::csg_normal(normal, ray_direction, trace_result.backface);
+    }
+    let mut bsdf = bsdf::get_pbr_bsdf(config, &material, uv, atlas, sampler);
+    if point {
+        bsdf.albedo *= vertices[0].normal.xyz();
+    }
+    (bsdf.denoise_albedo(normal.dot(-ray_direction)), normal)
+}
+
+// Odd samples are also averaged on their own, as the luminance of each pixel. Along with the mean
+// of every sample, that gives two independent halves of the estimate, and how far they are apart
+// tells how noisy it still is. Returns the new mean of the odd samples.
+pub fn accumulate_odd_sample(odd_mean: f32, radiance: Vec3, sample_count: u32) -> f32 {
+    if sample_count % 2 == 0 {
+        odd_mean
+    } else {
+        util::lerp(odd_mean, luminance(radiance), 1.0 / (sample_count / 2 + 1) as f32)
+    }
+}
+
+// Samples before a pixel's deviation is trusted to reject fireflies
+const FIREFLY_WARMUP_SAMPLES: u32 = 8;
+
+// Clamps samples brighter than the pixel's running mean by more than `firefly_rejection` times its
+// mean absolute deviation, in luminance. The deviation is tracked in outlier.w, and the energy taken
+// off is averaged in outlier.xyz, so the host can spread it over the neighbourhood instead of losing
+// it. Returns the sample to accumulate and the new outlier state.
+pub fn reject_firefly(firefly_rejection: f32, mean: Vec3, outlier: Vec4, radiance: Vec4, sample_count: u32) -> (Vec4, Vec4) {
+    let weight = 1.0 / (sample_count + 1) as f32;
+    let mean_luminance = luminance(mean);
+    let mut kept = radiance;
+    if firefly_rejection > 0.0 && sample_count >= FIREFLY_WARMUP_SAMPLES {
+        let threshold = mean_luminance + firefly_rejection * outlier.w;
+        let sample_luminance = luminance(radiance.xyz());
+        if sample_luminance > threshold {
+            kept = (radiance.xyz() * (threshold / sample_luminance)).extend(radiance.w);
+        }
+    }
+    let rejected = outlier.xyz().lerp(radiance.xyz() - kept.xyz(), weight);
+    let deviation = util::lerp(outlier.w, (luminance(kept.xyz()) - mean_luminance).abs(), weight);
+    (kept, rejected.extend(deviation))
+}
+
+// Number of paths to trace from a camera ray's first hit. Splitting is decided by the material
+// alone, rather than the lobe sampled, so the estimate stays unbiased. Metals and lights gain
+// little from it, since their paths mostly end or go one way.
+#[cfg_attr(target_arch = "spirv", inline(always))]
+fn path_splits(config: &TracingConfig, first_hit: &TraceResult, material_data_buffer: &[MaterialData]) -> u32 {
+    if !first_hit.hit || first_hit.section {
+        return 1;
+    }
+    let material = material_data_buffer[triangle_material_index(first_hit.triangle) as usize];
+    let diffuse = material.patterns.z != PATTERN_NONE || material.has_metallic_texture() || material.metallic.x < 1.0;
+    if diffuse && material.emissive.xyz() == Vec3::ZERO {
+        config.render.path_splits.max(1)
+    } else {
+        1
+    }
+}
+
+#[cfg_attr(target_arch = "spirv", inline(always))]
+fn trace_path(
+    features: u32,
+    config: &TracingConfig,
+    mut rng_state: rng::RngState,
+    camera_origin: Vec3,
+    camera_direction: Vec3,
+    first_hit: TraceResult,
+    per_vertex_buffer: &[PerVertexData],
+    index_buffer: &[UVec4],
+    nodes_buffer: &[BVHNode],
+    material_data_buffer: &[MaterialData],
+    light_pick_buffer: &[LightPickEntry],
+    sampler: &Sampler,
+    atlas: &Image!(2D, type=f32, sampled),
+    skybox: &Image!(2D, type=f32, sampled),
+    recorder: &mut impl PathRecorder,
+) -> (Vec4, UVec2) {
+    let nee_mode = NextEventEstimation::from_u32(features & FEATURE_NEE_MASK);
+    let nee = nee_mode.uses_nee();
+
+    let bvh = BVHReference {
+        nodes: nodes_buffer,
+        min_t: config.render.ray_offset,
+    };
+
+    // Every split continues from the same first hit, and carries an equal share of the result
+    let splits = path_splits(config, &first_hit, material_data_buffer);
+    let split_weight = 1.0 / splits as f32;
+
+    // The procedural sky is shown on its own, or added on top of a skybox image
+    let sky_weight = if features & FEATURE_SKYBOX_IMAGE == 0 { 1.0 } else { config.environment.sky_weight };
 
-    let mut throughput = Vec3::ONE;
     let mut radiance = Vec3::ZERO;
-    let mut last_bsdf_sample = bsdf::BSDFSample::default();
-    let mut last_light_sample = light_pick::DirectLightSample::default(); 
+    for _ in 0..splits {
+        let mut ray_origin = camera_origin;
+        let mut ray_direction = camera_direction;
+        let mut throughput = Vec3::splat(split_weight);
+        let mut last_bsdf_sample = bsdf::BSDFSample::default();
+        let mut last_light_sample = light_pick::DirectLightSample::default();
+        let mut light_exclude = 0; // light groups unlinked from the last surface, camera rays see all of them
+        let mut cone = ray_cone::RayCone::camera(config);
 
-    for bounce in 0..config.max_bounces {
-        let trace_result = bvh.intersect_nearest(per_vertex_buffer, index_buffer, ray_origin, ray_direction);
-        let hit = ray_origin + ray_direction * trace_result.t;
+        for bounce in 0..config.render.max_bounces {
+            let trace_result = if bounce == 0 {
+                first_hit
+            } else {
+                bvh.intersect_nearest(per_vertex_buffer, index_buffer, ray_origin, ray_direction, HIDDEN_FROM_INDIRECT)
+            };
+            let hit = ray_origin + ray_direction * trace_result.t;
+            let mut vertex = PathVertex {
+                bounce,
+                origin: ray_origin,
+                direction: ray_direction,
+                position: hit,
+                ..Default::default()
+            };
 
-        if !trace_result.hit {
-            if config.has_skybox == 0 {
-                // Fallback to procedural skybox
-                radiance += throughput * skybox::scatter(config.sun_direction, ray_origin, ray_direction);
+            if !trace_result.hit {
+                // Surfaces unlinked from the environment don't see it, like unlinked lights
+                let environment_linked = light_exclude & ENVIRONMENT_LIGHT_GROUP == 0;
+                if environment_linked && sky_weight > 0.0 {
+                    // Near the sun, escaped rays are weighed against the sun samples taken after diffuse bounces
+                    let sun_sampled = nee && bounce > 0 && last_bsdf_sample.sampled_lobe == bsdf::LobeType::DiffuseReflection;
+                    let weight = if sun_sampled && skybox::in_sun_cone(config.environment.sun_direction, ray_direction) {
+                        if nee_mode.uses_mis() { util::power_heuristic(last_bsdf_sample.pdf, skybox::SUN_CONE_PDF) } else { 0.0 }
+                    } else {
+                        1.0
+                    };
+                    radiance += throughput * skybox::scatter(config.environment.sun_direction, ray_origin, ray_direction) * sky_weight * weight;
+                }
+                if environment_linked && features & FEATURE_SKYBOX_IMAGE != 0 {
+                    // Read skybox from image
+                    let rotation = config.environment.sun_direction.z.atan2(config.environment.sun_direction.x);
+                    let rotated = Mat3::from_rotation_y(rotation) * ray_direction;
+                    let u = 0.5 + rotated.z.atan2(rotated.x) / (2.0 * core::f32::consts::PI);
+                    let v = 1.0 - (0.5 + rotated.y.asin() / core::f32::consts::PI);
+                    let intensity = config.environment.sun_direction.w * (1.0 / 15.0);
+                    // Only the background is blurred, light reaching the scene stays sharp
+                    let lod = if bounce == 0 { config.environment.background_lod } else { 0.0 };
+                    let color = skybox::sample_skybox(skybox, sampler, Vec2::new(u, v), lod, config.environment.skybox_mips);
+                    radiance += throughput * color * intensity;
+                }
+                vertex.event = PathEvent::Escaped;
+                vertex.throughput = throughput;
+                vertex.radiance = radiance;
+                recorder.record(&vertex);
+                break;
+            } else if trace_result.section {
+                // Unlit, as the inside of a closed mesh is in its own shadow
+                radiance += throughput * config.render.section_color.xyz();
+                vertex.event = PathEvent::Section;
+                vertex.throughput = throughput;
+                vertex.radiance = radiance;
+                recorder.record(&vertex);
+                break;
             } else {
-                // Read skybox from image
-                let rotation = config.sun_direction.z.atan2(config.sun_direction.x);
-                let rotated = Mat3::from_rotation_y(rotation) * ray_direction;
-                let u = 0.5 + rotated.z.atan2(rotated.x) / (2.0 * core::f32::consts::PI);
-                let v = 1.0 - (0.5 + rotated.y.asin() / core::f32::consts::PI);
-                let intensity = config.sun_direction.w * (1.0 / 15.0);
-                radiance += throughput * skybox.sample_by_lod(*sampler, Vec2::new(u, v), 0.0).xyz() * intensity;
-            }
-            break;
-        } else {
-            // Get material
-            let material_index = trace_result.triangle.w;
-            let material = material_data_buffer[material_index as usize];
-
-            // Add emission
-            if material.emissive.xyz() != Vec3::ZERO {
-                // Emissive triangles are single-sided
-                if trace_result.backface {
-                    break; // Break since emissives don't bounce light
+                cone.propagate(trace_result.t);
+                vertex.cone_width = cone.width;
+
+                // Haze between the camera and the first surface. Sunlight is only scattered where the
+                // sun is visible, which is estimated from one point along the ray, and casts shafts.
+                if bounce == 0 && config.environment.haze_density > 0.0 {
+                    let sun = config.environment.sun_direction;
+                    let (transmittance, inscattered) = skybox::aerial_perspective(sun, config.environment.haze_density, ray_origin, ray_direction, trace_result.t);
+                    let point = ray_origin + ray_direction * (trace_result.t * rng_state.gen_r1());
+                    let sun_direction = sun.xyz().normalize();
+                    let shadowed = sun_direction.y < 0.0 || bvh.intersect_any(per_vertex_buffer, index_buffer, point, sun_direction, f32::INFINITY, HIDDEN_FROM_SHADOWS).hit;
+                    if !shadowed {
+                        radiance += util::mask_nan(throughput * inscattered);
+                    }
+                    throughput *= transmittance;
                 }
 
-                // We want to add emissive contribution if:
-                // - We are not doing NEE at all.
-                // - This is the first bounce (so light sources don't look black).
-                // - This is a non-diffuse bounce (so we don't double count emissive light).
-                // AND we aren't hitting a backface (to match direct light sampling behavior).
-                if !nee || bounce == 0 || last_bsdf_sample.sampled_lobe != bsdf::LobeType::DiffuseReflection {
-                    radiance += util::mask_nan(throughput * material.emissive.xyz());
-                    break;
+                // Get material
+                let material_index = triangle_material_index(trace_result.triangle);
+                let material = material_data_buffer[material_index as usize];
+                vertex.material_index = material_index;
+                vertex.throughput = throughput;
+
+                // Interpolate vertex data
+                let vertex_data_a = per_vertex_buffer[trace_result.triangle.x as usize];
+                let vertex_data_b = per_vertex_buffer[trace_result.triangle.y as usize];
+                let vertex_data_c = per_vertex_buffer[trace_result.triangle.z as usize];
+                let vert_a = vertex_data_a.vertex.xyz();
+                let vert_b = vertex_data_b.vertex.xyz();
+                let vert_c = vertex_data_c.vertex.xyz();
+                let norm_a = vertex_data_a.normal.xyz();
+                let norm_b = vertex_data_b.normal.xyz();
+                let norm_c = vertex_data_c.normal.xyz();
+                let bary = util::barycentric(hit, vert_a, vert_b, vert_c);
+                let mut normal = bary.x * norm_a + bary.y * norm_b + bary.z * norm_c;
+                let uv = util::triangle_uv(per_vertex_buffer, trace_result.triangle, bary);
+
+                // Points face the ray, and keep their color where triangles keep normals. Signed
+                // distance fields keep their shape there, and take the normal from its gradient.
+                let point = is_point(trace_result.triangle);
+                let distance_field = is_sdf(trace_result.triangle);
+                if point {
+                    normal = -ray_direction;
+                } else if distance_field {
+                    normal = sdf::SdfShape::from_corners(per_vertex_buffer, trace_result.triangle).normal(per_vertex_buffer, hit);
                 }
 
-                // If we have hit a light source, and we are using NEE with MIS, we use last bounces data
-                // to add the BSDF contribution, weighted by MIS.
-                if nee_mode.uses_mis() && last_bsdf_sample.sampled_lobe == bsdf::LobeType::DiffuseReflection {
-                    let direct_contribution = light_pick::calculate_bsdf_mis_contribution(&trace_result, &last_bsdf_sample, &last_light_sample);
-                    radiance += util::mask_nan(direct_contribution);
-                    break;
+                // Add emission. Points and SDFs are never lights, see light_pick::compute_emissive_mask.
+                if material.emissive.xyz() != Vec3::ZERO && !point && !distance_field {
+                    // Emissive triangles are single-sided, unless the material says otherwise
+                    if trace_result.backface && !material.double_sided() {
+                        vertex.event = PathEvent::EmitterBackface;
+                        vertex.radiance = radiance;
+                        recorder.record(&vertex);
+                        break; // Break since emissives don't bounce light
+                    }
+
+                    // Lights unlinked from the surface the ray came from are black, to match direct light sampling
+                    if material.light_group & light_exclude != 0 {
+                        vertex.event = PathEvent::EmitterUnlinked;
+                        vertex.radiance = radiance;
+                        recorder.record(&vertex);
+                        break;
+                    }
+
+                    // We want to add emissive contribution if:
+                    // - We are not doing NEE at all.
+                    // - This is the first bounce (so light sources don't look black).
+                    // - This is a non-diffuse bounce (so we don't double count emissive light).
+                    // AND we aren't hitting the backface of a single-sided light (to match direct light sampling behavior).
+                    let emission = light_pick::evaluate_emission(&material, uv, atlas, sampler);
+                    if !nee || bounce == 0 || last_bsdf_sample.sampled_lobe != bsdf::LobeType::DiffuseReflection {
+                        radiance += util::mask_nan(throughput * emission);
+                        vertex.event = PathEvent::Emitter;
+                        vertex.radiance = radiance;
+                        recorder.record(&vertex);
+                        break;
+                    }
+
+                    // If we have hit a light source, and we are using NEE with MIS, we use last bounces data
+                    // to add the BSDF contribution, weighted by MIS.
+                    if nee_mode.uses_mis() && last_bsdf_sample.sampled_lobe == bsdf::LobeType::DiffuseReflection {
+                        let direct_contribution = light_pick::calculate_bsdf_mis_contribution(&trace_result, emission, &last_bsdf_sample, &last_light_sample);
+                        radiance += util::mask_nan(direct_contribution);
+                        vertex.event = PathEvent::Emitter;
+                        vertex.radiance = radiance;
+                        recorder.record(&vertex);
+                        break;
+                    }
                 }
-            }
 
-            // Interpolate vertex data
-            let vertex_data_a = per_vertex_buffer[trace_result.triangle.x as usize];
-            let vertex_data_b = per_vertex_buffer[trace_result.triangle.y as usize];
-            let vertex_data_c = per_vertex_buffer[trace_result.triangle.z as usize];
-            let vert_a = vertex_data_a.vertex.xyz();
-            let vert_b = vertex_data_b.vertex.xyz();
-            let vert_c = vertex_data_c.vertex.xyz();
-            let norm_a = vertex_data_a.normal.xyz();
-            let norm_b = vertex_data_b.normal.xyz();
-            let norm_c = vertex_data_c.normal.xyz();
-            let uv_a = vertex_data_a.uv0;
-            let uv_b = vertex_data_b.uv0;
-            let uv_c = vertex_data_c.uv0;
-            let bary = util::barycentric(hit, vert_a, vert_b, vert_c);
-            let mut normal = bary.x * norm_a + bary.y * norm_b + bary.z * norm_c;
-            let mut uv = bary.x * uv_a + bary.y * uv_b + bary.z * uv_c;
-            if uv.clamp(Vec2::ZERO, Vec2::ONE) != uv {
-                uv = uv.fract(); // wrap UVs
-            }
+                // Apply normal map
+                if features & FEATURE_NORMAL_MAPS != 0 && material.has_normal_texture() && !point && !distance_field {
+                    normal = apply_normal_map(&material, [vertex_data_a, vertex_data_b, vertex_data_c], bary, normal, uv, atlas, sampler);
+                }
+                if csg_operation(trace_result.triangle) != 0 {
+                    normal = intersection::csg_normal(normal, ray_direction, trace_result.backface);
+                }
+                
+                // Sample BSDF
+                let mut bsdf = bsdf::get_pbr_bsdf(config, &material, uv, atlas, sampler);
+                if point {
+                    bsdf.albedo *= norm_a;
+                }
+                let bsdf_sample = bsdf.sample(-ray_direction, normal, &mut rng_state);
+                last_bsdf_sample = bsdf_sample;
 
-            // Apply normal map
-            if material.has_normal_texture() {
-                let scaled_uv = material.normals.xy() + uv * material.normals.zw();
-                let normal_map = atlas.sample_by_lod(*sampler, scaled_uv, 0.0) * 2.0 - 1.0;
-                let tangent_a = vertex_data_a.tangent.xyz();
-                let tangent_b = vertex_data_b.tangent.xyz();
-                let tangent_c = vertex_data_c.tangent.xyz();
-                let tangent = bary.x * tangent_a + bary.y * tangent_b + bary.z * tangent_c;
-                let tbn = Mat3::from_cols(tangent, tangent.cross(normal), normal);
-                normal = (tbn * normal_map.xyz()).normalize();
-            }
-            
-            // Sample BSDF
-            let bsdf = bsdf::get_pbr_bsdf(config, &material, uv, atlas, sampler);
-            let bsdf_sample = bsdf.sample(-ray_direction, normal, &mut rng_state);
-            last_bsdf_sample = bsdf_sample;
-
-            // Sample lights directly
-            if nee && bsdf_sample.sampled_lobe == bsdf::LobeType::DiffuseReflection {
-                last_light_sample = light_pick::sample_direct_lighting(
-                    nee_mode,
-                    index_buffer,
-                    per_vertex_buffer,
-                    material_data_buffer,
-                    light_pick_buffer,
-                    &bvh,
-                    throughput,
-                    &bsdf,
-                    hit,
-                    normal,
-                    ray_direction,
-                    &mut rng_state
-                );
-                radiance += util::mask_nan(last_light_sample.direct_light_contribution);
-            }
+                // Sample lights directly
+                if nee && bsdf_sample.sampled_lobe == bsdf::LobeType::DiffuseReflection {
+                    last_light_sample = light_pick::sample_direct_lighting(
+                        nee_mode,
+                        index_buffer,
+                        per_vertex_buffer,
+                        material_data_buffer,
+                        light_pick_buffer,
+                        &bvh,
+                        throughput,
+                        &bsdf,
+                        hit,
+                        normal,
+                        material.light_exclude,
+                        ray_direction,
+                        atlas,
+                        sampler,
+                        &mut rng_state
+                    );
+                    radiance += util::mask_nan(last_light_sample.direct_light_contribution);
+                }
 
-            // Attenuate by BSDF
-            throughput *= bsdf_sample.spectrum / bsdf_sample.pdf;
+                let environment_linked = material.light_exclude & ENVIRONMENT_LIGHT_GROUP == 0;
 
-            // Update ray
-            ray_direction = bsdf_sample.sampled_direction;
-            ray_origin = hit + ray_direction * util::EPS;
+                // Sample the light extracted from the skybox, which BSDF samples can never hit
+                if environment_linked && features & FEATURE_SKYBOX_IMAGE != 0 && config.environment.environment_light_irradiance.xyz() != Vec3::ZERO && bsdf_sample.sampled_lobe == bsdf::LobeType::DiffuseReflection {
+                    let rotation = config.environment.sun_direction.z.atan2(config.environment.sun_direction.x);
+                    let light_direction = Mat3::from_rotation_y(rotation).transpose() * config.environment.environment_light_direction.xyz();
+                    let shadow_trace = bvh.intersect_any(per_vertex_buffer, index_buffer, hit + light_direction * config.render.ray_offset, light_direction, f32::INFINITY, HIDDEN_FROM_SHADOWS);
+                    if !shadow_trace.hit {
+                        let intensity = config.environment.sun_direction.w * (1.0 / 15.0);
+                        let bsdf_attenuation = bsdf.evaluate(-ray_direction, normal, light_direction, bsdf::LobeType::DiffuseReflection);
+                        radiance += util::mask_nan(throughput * bsdf_attenuation * config.environment.environment_light_irradiance.xyz() * intensity);
+                    }
+                }
+
+                // Sample the sun of the procedural sky, which BSDF samples rarely find on their own
+                if environment_linked && nee && sky_weight > 0.0 && bsdf_sample.sampled_lobe == bsdf::LobeType::DiffuseReflection {
+                    let sun_direction = skybox::sample_sun_cone(config.environment.sun_direction, rng_state.gen_r2());
+                    if sun_direction.dot(normal) > 0.0 {
+                        let shadow_trace = bvh.intersect_any(per_vertex_buffer, index_buffer, hit + sun_direction * config.render.ray_offset, sun_direction, f32::INFINITY, HIDDEN_FROM_SHADOWS);
+                        if !shadow_trace.hit {
+                            let bsdf_attenuation = bsdf.evaluate(-ray_direction, normal, sun_direction, bsdf::LobeType::DiffuseReflection);
+                            let bsdf_pdf = bsdf.pdf(-ray_direction, normal, sun_direction, bsdf::LobeType::DiffuseReflection);
+                            let weight = light_pick::get_weight(nee_mode, skybox::SUN_CONE_PDF, bsdf_pdf);
+                            let sky = skybox::scatter(config.environment.sun_direction, hit, sun_direction) * sky_weight;
+                            radiance += util::mask_nan(throughput * bsdf_attenuation * sky * weight / skybox::SUN_CONE_PDF);
+                        }
+                    }
+                }
+
+                // Attenuate by BSDF
+                throughput *= bsdf_sample.spectrum / bsdf_sample.pdf;
+                vertex.normal = normal;
+                vertex.lobe = bsdf_sample.sampled_lobe;
+                vertex.pdf = bsdf_sample.pdf;
+                vertex.throughput = throughput;
+                vertex.radiance = radiance;
 
-            // Russian roulette
-            if bounce > config.min_bounces {
-                let prob = throughput.max_element();
-                if rng_state.gen_r1() > prob {
-                    break;
+                // Update ray
+                light_exclude = material.light_exclude;
+                ray_direction = bsdf_sample.sampled_direction;
+                ray_origin = hit + ray_direction * config.render.ray_offset;
+
+                // Glossy chains that have spread out too far are terminated, see RayCone
+                cone.scatter(bsdf_sample.sampled_lobe, bsdf.roughness);
+                let prob = cone.glossy_survival(config.render.glossy_cone_limit, bsdf_sample.sampled_lobe);
+                if prob < 1.0 {
+                    if rng_state.gen_r1() > prob {
+                        vertex.event = PathEvent::GlossyTerminated;
+                        recorder.record(&vertex);
+                        break;
+                    }
+                    throughput *= 1.0 / prob;
+                    vertex.throughput = throughput;
+                }
+
+                // Russian roulette, on the throughput the path would have without splitting
+                let prob = russian_roulette_survival(config, bounce, throughput / split_weight, bsdf.albedo);
+                if prob < 1.0 {
+                    if rng_state.gen_r1() > prob {
+                        vertex.event = PathEvent::RussianRoulette;
+                        recorder.record(&vertex);
+                        break;
+                    }
+                    throughput *= 1.0 / prob;
+                    vertex.throughput = throughput;
                 }
-                throughput *= 1.0 / prob;
+                recorder.record(&vertex);
             }
         }
     }
@@ -186,32 +698,72 @@ pub fn trace_pixel(
 }
 
 
+// Traces the camera ray of each pixel to its first hit, for the path tracing kernels to continue
+// from and for the G-buffer. Without any shading it needs far fewer registers than the path
+// tracing kernels, so more threads are in flight for the traversal of the most coherent rays.
 #[spirv(compute(threads(8, 8, 1)))]
-pub fn trace_kernel(
+pub fn primary_kernel(
     #[spirv(global_invocation_id)] id: UVec3,
-    #[spirv(uniform, descriptor_set = 0, binding = 0)] config: &TracingConfig,
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] rng: &mut [UVec2],
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] output: &mut [Vec4],
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] camera: &CameraUniform,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] rng: &[UVec2],
     #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] per_vertex_buffer: &[PerVertexData],
     #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] index_buffer: &[UVec4],
     #[spirv(storage_buffer, descriptor_set = 0, binding = 5)] nodes_buffer: &[BVHNode],
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 6)] material_data_buffer: &[MaterialData],
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 7)] light_pick_buffer: &[LightPickEntry],
-    #[spirv(descriptor_set = 0, binding = 8)] sampler: &Sampler,
-    #[spirv(descriptor_set = 0, binding = 9)] atlas: &Image!(2D, type=f32, sampled),
-    #[spirv(descriptor_set = 0, binding = 10)] skybox: &Image!(2D, type=f32, sampled),
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 11)] blue_noise_buffer: &[u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 12)] first_hits: &mut [FirstHit],
+    #[spirv(uniform, descriptor_set = 0, binding = 13)] render: &RenderSettings,
+    #[spirv(uniform, descriptor_set = 0, binding = 14)] environment: &EnvironmentSettings,
+) {
+    let config = &TracingConfig::from_blocks(camera, render, environment);
+    let pixel = morton::invocation_pixel(id, config.render.width, config.render.morton_order != 0);
+
+    // Handle non-divisible workgroup sizes.
+    if pixel.x >= config.render.width || pixel.y >= config.render.height {
+        return;
+    }
+
+    let index = (pixel.y * config.render.width + pixel.x) as usize;
+    let mut rng_state = rng::RngState::new(rng[index], config.render.use_blue_noise != 0, config.render.seed, pixel, blue_noise_buffer);
+    let (ray_origin, ray_direction) = primary_ray(config, pixel.extend(id.z), &mut rng_state);
+    let result = trace_camera_ray(config, per_vertex_buffer, index_buffer, nodes_buffer, ray_origin, ray_direction);
+    first_hits[index] = FirstHit::new(result.t, result.triangle_index, result.hit, result.backface, result.section);
+}
+
+#[cfg_attr(target_arch = "spirv", inline(always))]
+fn trace_kernel(
+    features: u32,
+    id: UVec3,
+    config: &TracingConfig,
+    rng: &mut [UVec2],
+    output: &mut [Vec4],
+    odd_output: &mut [f32],
+    outlier_output: &mut [Vec4],
+    per_vertex_buffer: &[PerVertexData],
+    index_buffer: &[UVec4],
+    nodes_buffer: &[BVHNode],
+    material_data_buffer: &[MaterialData],
+    light_pick_buffer: &[LightPickEntry],
+    sampler: &Sampler,
+    atlas: &Image!(2D, type=f32, sampled),
+    skybox: &Image!(2D, type=f32, sampled),
+    blue_noise_buffer: &[u32],
+    first_hits: &[FirstHit],
 ) {
+    let pixel = morton::invocation_pixel(id, config.render.width, config.render.morton_order != 0);
+
     // Handle non-divisible workgroup sizes.
-    if id.x > config.width || id.y > config.height {
+    if pixel.x >= config.render.width || pixel.y >= config.render.height {
         return;
     }
     
-    let index = (id.y * config.width + id.x) as usize;
+    let index = (pixel.y * config.render.width + pixel.x) as usize;
 
-    let (radiance, rng_state) = trace_pixel(
-        id,
+    let (radiance, rng_state) = trace_pixel_from_first_hit(
+        features,
+        pixel.extend(id.z),
         config,
         rng[index],
+        first_hits[index],
         per_vertex_buffer,
         index_buffer,
         nodes_buffer,
@@ -220,8 +772,180 @@ pub fn trace_kernel(
         sampler,
         atlas,
         skybox,
+        blue_noise_buffer,
+        &mut (),
     );
     
-    output[index] += radiance;
+    // Only touched when enabled, to save the bandwidth
+    let mut radiance = radiance;
+    if config.render.firefly_rejection > 0.0 {
+        let (kept, outlier) = reject_firefly(config.render.firefly_rejection, output[index].xyz(), outlier_output[index], radiance, config.camera.sample_count);
+        radiance = kept;
+        outlier_output[index] = outlier;
+    }
+
+    // Running mean, so precision doesn't degrade as the sum grows
+    output[index] = output[index].lerp(radiance, 1.0 / (config.camera.sample_count + 1) as f32);
+    odd_output[index] = accumulate_odd_sample(odd_output[index], radiance.xyz(), config.camera.sample_count);
+    rng[index] = rng_state;
+}
+
+// Same as trace_kernel, but accumulates a running mean in RGBA16F to halve the size
+// of the output buffer. Stochastic rounding keeps the mean unbiased, even once
+// individual samples are smaller than the precision of the stored value.
+#[cfg_attr(target_arch = "spirv", inline(always))]
+fn trace_kernel_half(
+    features: u32,
+    id: UVec3,
+    config: &TracingConfig,
+    rng: &mut [UVec2],
+    output: &mut [UVec2],
+    odd_output: &mut [f32],
+    outlier_output: &mut [Vec4],
+    per_vertex_buffer: &[PerVertexData],
+    index_buffer: &[UVec4],
+    nodes_buffer: &[BVHNode],
+    material_data_buffer: &[MaterialData],
+    light_pick_buffer: &[LightPickEntry],
+    sampler: &Sampler,
+    atlas: &Image!(2D, type=f32, sampled),
+    skybox: &Image!(2D, type=f32, sampled),
+    blue_noise_buffer: &[u32],
+    first_hits: &[FirstHit],
+) {
+    let pixel = morton::invocation_pixel(id, config.render.width, config.render.morton_order != 0);
+
+    // Handle non-divisible workgroup sizes.
+    if pixel.x >= config.render.width || pixel.y >= config.render.height {
+        return;
+    }
+    
+    let index = (pixel.y * config.render.width + pixel.x) as usize;
+
+    let (radiance, rng_state) = trace_pixel_from_first_hit(
+        features,
+        pixel.extend(id.z),
+        config,
+        rng[index],
+        first_hits[index],
+        per_vertex_buffer,
+        index_buffer,
+        nodes_buffer,
+        material_data_buffer,
+        light_pick_buffer,
+        sampler,
+        atlas,
+        skybox,
+        blue_noise_buffer,
+        &mut (),
+    );
+
+    let previous_rg = half::unpack_half2x16(output[index].x);
+    let previous_ba = half::unpack_half2x16(output[index].y);
+    let previous = Vec4::new(previous_rg.x, previous_rg.y, previous_ba.x, previous_ba.y);
+    let mut radiance = radiance;
+    if config.render.firefly_rejection > 0.0 {
+        let (kept, outlier) = reject_firefly(config.render.firefly_rejection, previous.xyz(), outlier_output[index], radiance, config.camera.sample_count);
+        radiance = kept;
+        outlier_output[index] = outlier;
+    }
+    let mean = previous.lerp(radiance, 1.0 / (config.camera.sample_count + 1) as f32);
+    let rounding = rng::pcg_hash(rng::pcg_hash(index as u32) ^ rng_state.x);
+    output[index] = UVec2::new(
+        half::pack_half2x16_dithered(mean.xy(), rounding),
+        half::pack_half2x16_dithered(mean.zw(), rng::pcg_hash(rounding)),
+    );
+    odd_output[index] = accumulate_odd_sample(odd_output[index], radiance.xyz(), config.camera.sample_count);
     rng[index] = rng_state;
 }
+
+// Generates a pair of path tracing entry points for each feature set, one accumulating in full
+// precision, and one in half precision. The host picks them by name, see kernel_entry_point.
+// Every pair is a full copy of the path tracer, so new features multiply compile times.
+macro_rules! trace_kernel_variants {
+    ($($features:expr => $full:ident, $half:ident;)*) => {
+        $(
+            #[spirv(compute(threads(8, 8, 1)))]
+            pub fn $full(
+                #[spirv(global_invocation_id)] id: UVec3,
+                #[spirv(uniform, descriptor_set = 0, binding = 0)] camera: &CameraUniform,
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] rng: &mut [UVec2],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] output: &mut [Vec4],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] per_vertex_buffer: &[PerVertexData],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] index_buffer: &[UVec4],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 5)] nodes_buffer: &[BVHNode],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 6)] material_data_buffer: &[MaterialData],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 7)] light_pick_buffer: &[LightPickEntry],
+                #[spirv(descriptor_set = 0, binding = 8)] sampler: &Sampler,
+                #[spirv(descriptor_set = 0, binding = 9)] atlas: &Image!(2D, type=f32, sampled),
+                #[spirv(descriptor_set = 0, binding = 10)] skybox: &Image!(2D, type=f32, sampled),
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 11)] blue_noise_buffer: &[u32],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 12)] first_hits: &[FirstHit],
+                #[spirv(uniform, descriptor_set = 0, binding = 13)] render: &RenderSettings,
+                #[spirv(uniform, descriptor_set = 0, binding = 14)] environment: &EnvironmentSettings,
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 15)] odd_output: &mut [f32],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 16)] outlier_output: &mut [Vec4],
+            ) {
+                let config = &TracingConfig::from_blocks(camera, render, environment);
+                trace_kernel(
+                    $features, id, config, rng, output, odd_output, outlier_output, per_vertex_buffer, index_buffer, nodes_buffer, material_data_buffer,
+                    light_pick_buffer, sampler, atlas, skybox, blue_noise_buffer, first_hits,
+                );
+            }
+
+            #[spirv(compute(threads(8, 8, 1)))]
+            pub fn $half(
+                #[spirv(global_invocation_id)] id: UVec3,
+                #[spirv(uniform, descriptor_set = 0, binding = 0)] camera: &CameraUniform,
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] rng: &mut [UVec2],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] output: &mut [UVec2],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] per_vertex_buffer: &[PerVertexData],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] index_buffer: &[UVec4],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 5)] nodes_buffer: &[BVHNode],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 6)] material_data_buffer: &[MaterialData],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 7)] light_pick_buffer: &[LightPickEntry],
+                #[spirv(descriptor_set = 0, binding = 8)] sampler: &Sampler,
+                #[spirv(descriptor_set = 0, binding = 9)] atlas: &Image!(2D, type=f32, sampled),
+                #[spirv(descriptor_set = 0, binding = 10)] skybox: &Image!(2D, type=f32, sampled),
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 11)] blue_noise_buffer: &[u32],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 12)] first_hits: &[FirstHit],
+                #[spirv(uniform, descriptor_set = 0, binding = 13)] render: &RenderSettings,
+                #[spirv(uniform, descriptor_set = 0, binding = 14)] environment: &EnvironmentSettings,
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 15)] odd_output: &mut [f32],
+                #[spirv(storage_buffer, descriptor_set = 0, binding = 16)] outlier_output: &mut [Vec4],
+            ) {
+                let config = &TracingConfig::from_blocks(camera, render, environment);
+                trace_kernel_half(
+                    $features, id, config, rng, output, odd_output, outlier_output, per_vertex_buffer, index_buffer, nodes_buffer, material_data_buffer,
+                    light_pick_buffer, sampler, atlas, skybox, blue_noise_buffer, first_hits,
+                );
+            }
+        )*
+    };
+}
+
+// Names are the feature bits, so the host can format them. Direct light sampling is 2, and MIS is 1.
+trace_kernel_variants! {
+    0 => trace_kernel_0, trace_kernel_half_0;
+    1 => trace_kernel_1, trace_kernel_half_1;
+    2 => trace_kernel_2, trace_kernel_half_2;
+    FEATURE_SKYBOX_IMAGE => trace_kernel_4, trace_kernel_half_4;
+    FEATURE_SKYBOX_IMAGE | 1 => trace_kernel_5, trace_kernel_half_5;
+    FEATURE_SKYBOX_IMAGE | 2 => trace_kernel_6, trace_kernel_half_6;
+    FEATURE_NORMAL_MAPS => trace_kernel_8, trace_kernel_half_8;
+    FEATURE_NORMAL_MAPS | 1 => trace_kernel_9, trace_kernel_half_9;
+    FEATURE_NORMAL_MAPS | 2 => trace_kernel_10, trace_kernel_half_10;
+    FEATURE_NORMAL_MAPS | FEATURE_SKYBOX_IMAGE => trace_kernel_12, trace_kernel_half_12;
+    FEATURE_NORMAL_MAPS | FEATURE_SKYBOX_IMAGE | 1 => trace_kernel_13, trace_kernel_half_13;
+    FEATURE_NORMAL_MAPS | FEATURE_SKYBOX_IMAGE | 2 => trace_kernel_14, trace_kernel_half_14;
+}
+
+// Name of the entry point specialized on a feature set, see shared_structs::kernel_features
+#[cfg(not(target_arch = "spirv"))]
+pub fn kernel_entry_point(features: u32, half_precision: bool) -> String {
+    if half_precision {
+        format!("trace_kernel_half_{}", features)
+    } else {
+        format!("trace_kernel_{}", features)
+    }
+}
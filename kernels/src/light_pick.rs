@@ -1,5 +1,6 @@
-use shared_structs::{LightPickEntry, PerVertexData, MaterialData, NextEventEstimation};
-use spirv_std::glam::{Vec3, UVec4, Vec4Swizzles};
+use shared_structs::{Image, LightPickEntry, PerVertexData, MaterialData, NextEventEstimation, Sampler};
+use shared_structs::{triangle_material_index, HIDDEN_FROM_INDIRECT, HIDDEN_FROM_SHADOWS};
+use spirv_std::glam::{Vec2, Vec3, UVec4, Vec4Swizzles};
 #[allow(unused_imports)]
 use spirv_std::num_traits::Float;
 
@@ -15,11 +16,57 @@ pub fn pick_light(table: &[LightPickEntry], rng_state: &mut RngState) -> (u32, f
     }
 }
 
+// Lights closer than this many times their size are sampled by solid angle rather than by area.
+// Area sampling puts as many samples on the far, grazing parts of a nearby light as on the
+// parts right in front of the surface, which is where the noise comes from.
+pub const SOLID_ANGLE_SAMPLING_DISTANCE: f32 = 4.0;
+
+// Barycentric coordinates of a uniformly distributed point on a triangle
 // https://www.cs.princeton.edu/~funk/tog02.pdf equation 1
-pub fn pick_triangle_point(a: Vec3, b: Vec3, c: Vec3, rng_state: &mut RngState) -> Vec3 {
-    let rng = rng_state.gen_r2();
+pub fn pick_triangle_point(rng: Vec2) -> Vec3 {
     let r1_sqrt = rng.x.sqrt();
-    (1.0 - r1_sqrt) * a + (r1_sqrt * (1.0 - rng.y)) * b + (r1_sqrt * rng.y) * c
+    Vec3::new(1.0 - r1_sqrt, r1_sqrt * (1.0 - rng.y), r1_sqrt * rng.y)
+}
+
+// Solid angle subtended by a triangle, given the normalized directions to its vertices
+// https://en.wikipedia.org/wiki/Solid_angle#Tetrahedron
+pub fn spherical_triangle_area(a: Vec3, b: Vec3, c: Vec3) -> f32 {
+    let numerator = a.dot(b.cross(c)).abs();
+    let denominator = 1.0 + a.dot(b) + b.dot(c) + c.dot(a);
+    2.0 * numerator.atan2(denominator)
+}
+
+// Uniformly distributed direction within a spherical triangle, given the normalized directions
+// to its vertices and its area. https://www.graphics.cornell.edu/pubs/1995/Arv95c.pdf
+pub fn sample_spherical_triangle(a: Vec3, b: Vec3, c: Vec3, area: f32, rng: Vec2) -> Vec3 {
+    // Interior angle at a, and the length of the side opposite of c
+    let cos_alpha = a.cross(b).normalize().dot(a.cross(c).normalize()).clamp(-1.0, 1.0);
+    let alpha = cos_alpha.acos();
+    let sin_alpha = alpha.sin();
+    let cos_c = a.dot(b);
+
+    // Find the point c_hat on the edge from a to c, such that the triangle a, b, c_hat has the sampled area
+    let area_hat = rng.x * area;
+    let s = (area_hat - alpha).sin();
+    let t = (area_hat - alpha).cos();
+    let u = t - cos_alpha;
+    let v = s + sin_alpha * cos_c;
+    let q = (((v * t - u * s) * cos_alpha - v) / ((v * s + u * t) * sin_alpha)).clamp(-1.0, 1.0);
+    let c_hat = q * a + (1.0 - q * q).sqrt() * (c - c.dot(a) * a).normalize();
+
+    // Pick a point on the arc from b to c_hat
+    let z = 1.0 - rng.y * (1.0 - c_hat.dot(b));
+    z * b + (1.0 - z * z).max(0.0).sqrt() * (c_hat - c_hat.dot(b) * b).normalize()
+}
+
+// Emitted radiance of a material at a point with the given UV
+pub fn evaluate_emission(material: &MaterialData, uv: Vec2, atlas: &Image!(2D, type=f32, sampled), sampler: &Sampler) -> Vec3 {
+    if material.has_emissive_texture() {
+        let scaled_uv = util::atlas_uv(material.emissive_texture, 0, uv); // emissive textures are always a single tile
+        material.emissive.xyz() * atlas.sample_by_lod(*sampler, scaled_uv, 0.0).xyz()
+    } else {
+        material.emissive.xyz()
+    }
 }
 
 // PDF of picking a point on a light source w.r.t area
@@ -78,6 +125,21 @@ pub fn calculate_light_pdf(light_area: f32, light_distance: f32, light_normal: V
     light_distance.powi(2) / (light_area * cos_theta)
 }
 
+// PDF of a direct light sample w.r.t solid angle, whichever way the light was sampled.
+// - solid_angle is the solid angle of the light if it was sampled by solid angle, zero otherwise
+// - the rest is as for calculate_light_pdf
+pub fn calculate_light_sample_pdf(solid_angle: f32, light_area: f32, light_distance: f32, light_normal: Vec3, light_direction: Vec3) -> f32 {
+    if solid_angle > 0.0 {
+        // Uniform over the light as seen from the surface, as long as we see its emitting side
+        if light_normal.dot(-light_direction) <= 0.0 {
+            return 0.0;
+        }
+        1.0 / solid_angle
+    } else {
+        calculate_light_pdf(light_area, light_distance, light_normal, light_direction)
+    }
+}
+
 pub fn get_weight(nee_mode: NextEventEstimation, p1: f32, p2: f32) -> f32 {
     match nee_mode {
         NextEventEstimation::None => 1.0,
@@ -91,7 +153,7 @@ pub struct DirectLightSample {
     pub light_area: f32,
     pub light_normal: Vec3,
     pub light_pick_pdf: f32,
-    pub light_emission: Vec3,
+    pub light_solid_angle: f32, // non-zero if the light was sampled by solid angle
     pub light_triangle_index: u32,
     pub throughput: Vec3,
     pub direct_light_contribution: Vec3,
@@ -108,7 +170,10 @@ pub fn sample_direct_lighting(
     surface_bsdf: &impl BSDF,
     surface_point: Vec3,
     surface_normal: Vec3,
+    surface_light_exclude: u32,
     ray_direction: Vec3,
+    atlas: &Image!(2D, type=f32, sampled),
+    sampler: &Sampler,
     rng_state: &mut RngState,
 ) -> DirectLightSample {
     // If the first entry is a sentinel, there are no lights
@@ -126,36 +191,76 @@ pub fn sample_direct_lighting(
     let light_norm_a = per_vertex_buffer[light_triangle.x as usize].normal.xyz();
     let light_norm_b = per_vertex_buffer[light_triangle.y as usize].normal.xyz();
     let light_norm_c = per_vertex_buffer[light_triangle.z as usize].normal.xyz();
-    let light_normal = (light_norm_a + light_norm_b + light_norm_c) / 3.0; // lights can use flat shading, no need to pay for interpolation
-    let light_material = material_data_buffer[light_triangle.w as usize];
-    let light_emission = light_material.emissive.xyz();
+    let mut light_normal = (light_norm_a + light_norm_b + light_norm_c) / 3.0; // lights can use flat shading, no need to pay for interpolation
+    let light_material = material_data_buffer[triangle_material_index(light_triangle) as usize];
+
+    // Pick a point on the light. Nearby lights are sampled by the solid angle they subtend, unless
+    // it is too small to sample reliably, such as when the surface is in the plane of the light.
+    let rng = rng_state.gen_r2();
+    let light_center = (light_vert_a + light_vert_b + light_vert_c) / 3.0;
+    let mut light_solid_angle = 0.0;
+    if (light_center - surface_point).length_squared() < SOLID_ANGLE_SAMPLING_DISTANCE * SOLID_ANGLE_SAMPLING_DISTANCE * light_area {
+        let solid_angle = spherical_triangle_area(
+            (light_vert_a - surface_point).normalize(),
+            (light_vert_b - surface_point).normalize(),
+            (light_vert_c - surface_point).normalize(),
+        );
+        if solid_angle > util::EPS {
+            light_solid_angle = solid_angle;
+        }
+    }
+    let (light_direction, light_distance, light_bary) = if light_solid_angle > 0.0 {
+        let light_direction = sample_spherical_triangle(
+            (light_vert_a - surface_point).normalize(),
+            (light_vert_b - surface_point).normalize(),
+            (light_vert_c - surface_point).normalize(),
+            light_solid_angle,
+            rng,
+        );
+        // Find where the direction meets the light
+        let plane_normal = (light_vert_b - light_vert_a).cross(light_vert_c - light_vert_a);
+        let light_distance = (light_vert_a - surface_point).dot(plane_normal) / light_direction.dot(plane_normal);
+        let light_point = surface_point + light_direction * light_distance;
+        (light_direction, light_distance, util::barycentric(light_point, light_vert_a, light_vert_b, light_vert_c))
+    } else {
+        let light_bary = pick_triangle_point(rng);
+        let light_point = light_bary.x * light_vert_a + light_bary.y * light_vert_b + light_bary.z * light_vert_c;
+        let light_direction_unorm = light_point - surface_point;
+        let light_distance = light_direction_unorm.length();
+        (light_direction_unorm / light_distance, light_distance, light_bary)
+    };
+    let light_uv = util::triangle_uv(per_vertex_buffer, light_triangle, light_bary);
+    let light_emission = evaluate_emission(&light_material, light_uv, atlas, sampler);
 
-    // Pick a point on the light
-    let light_point = pick_triangle_point(light_vert_a, light_vert_b, light_vert_c, rng_state);
-    let light_direction_unorm = light_point - surface_point;
-    let light_distance = light_direction_unorm.length();
-    let light_direction = light_direction_unorm / light_distance;
+    // Double-sided lights emit from whichever side faces the surface. The whole triangle is on
+    // one side of it, so BSDF samples that hit this light later see the same side.
+    if light_material.double_sided() && light_normal.dot(light_direction) > 0.0 {
+        light_normal = -light_normal;
+    }
 
-    // Sample the light directly using MIS
+    // Sample the light directly using MIS. Lights unlinked from the surface contribute nothing.
     let mut direct = Vec3::ZERO;
-    let light_trace = bvh.intersect_any(
+    let linked = light_material.light_group & surface_light_exclude == 0;
+    let blocked = !linked || bvh.intersect_any(
         per_vertex_buffer,
         index_buffer,
-        surface_point + light_direction * util::EPS,
+        surface_point + light_direction * bvh.min_t,
         light_direction,
-        light_distance - util::EPS * 2.0,
-    );
-    if !light_trace.hit {
+        light_distance - bvh.min_t * 2.0,
+        HIDDEN_FROM_SHADOWS,
+    ).hit;
+    if !blocked {
         // Calculate light pdf for this sample
-        let light_pdf = calculate_light_pdf(light_area, light_distance, light_normal, light_direction);
+        let light_pdf = calculate_light_sample_pdf(light_solid_angle, light_area, light_distance, light_normal, light_direction);
         if light_pdf > 0.0 {
             // Calculate BSDF attenuation for this sample
             let bsdf_attenuation = surface_bsdf.evaluate(-ray_direction, surface_normal, light_direction, bsdf::LobeType::DiffuseReflection);
             // Calculate BSDF pdf for this sample
             let bsdf_pdf = surface_bsdf.pdf(-ray_direction, surface_normal, light_direction, bsdf::LobeType::DiffuseReflection);
             if bsdf_pdf > 0.0 {
-                // MIS - add the weighted sample
-                let weight = get_weight(nee_mode, light_pdf, bsdf_pdf);
+                // MIS - add the weighted sample. Bounced rays can't hit lights hidden from them, so
+                // light sampling is the only way to reach those, and gets the full weight.
+                let weight = if light_triangle.w & HIDDEN_FROM_INDIRECT != 0 { 1.0 } else { get_weight(nee_mode, light_pdf, bsdf_pdf) };
                 direct = (bsdf_attenuation * light_emission * weight / light_pdf) / light_pick_pdf;
             }
         }
@@ -165,7 +270,7 @@ pub fn sample_direct_lighting(
     info.light_area = light_area;
     info.light_normal = light_normal;
     info.light_pick_pdf = light_pick_pdf;
-    info.light_emission = light_emission;
+    info.light_solid_angle = light_solid_angle;
     info.light_triangle_index = light_index;
     info.throughput = throughput;
     info.direct_light_contribution = throughput * direct;
@@ -176,8 +281,10 @@ pub fn sample_direct_lighting(
 // - We are using NEE with MIS
 // - We have hit a light source
 // - That last bounce was diffuse, so we did direct light sampling
+// The emission is evaluated where the light was hit, which differs from the sampled point for textured lights.
 pub fn calculate_bsdf_mis_contribution(
     trace_result: &intersection::TraceResult,
+    light_emission: Vec3,
     last_bsdf_sample: &bsdf::BSDFSample,
     last_light_sample: &DirectLightSample
 ) -> Vec3 {
@@ -187,11 +294,17 @@ pub fn calculate_bsdf_mis_contribution(
     }
 
     // Calculate the light pdf for this sample
-    let light_pdf = calculate_light_pdf(last_light_sample.light_area, trace_result.t, last_light_sample.light_normal, last_bsdf_sample.sampled_direction);
+    let light_pdf = calculate_light_sample_pdf(
+        last_light_sample.light_solid_angle,
+        last_light_sample.light_area,
+        trace_result.t,
+        last_light_sample.light_normal,
+        last_bsdf_sample.sampled_direction,
+    );
     if light_pdf > 0.0 {
         // MIS - add the weighted sample
         let weight = get_weight(NextEventEstimation::MultipleImportanceSampling, last_bsdf_sample.pdf, light_pdf);
-        let direct = (last_bsdf_sample.spectrum * last_light_sample.light_emission * weight / last_bsdf_sample.pdf) / last_light_sample.light_pick_pdf;
+        let direct = (last_bsdf_sample.spectrum * light_emission * weight / last_bsdf_sample.pdf) / last_light_sample.light_pick_pdf;
         last_light_sample.throughput * direct
     } else {
         Vec3::ZERO
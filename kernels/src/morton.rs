@@ -0,0 +1,61 @@
+use spirv_std::glam::{UVec2, UVec3};
+
+// With Morton order enabled, the pixel grid is split into blocks of this many pixels on a side,
+// taken in rows. Within a block, 8x8 pixel tiles are walked in Z-order, so tiles traced around
+// the same time are close together on screen, and so are the parts of the scene they hit.
+pub const MORTON_BLOCK_SIZE: u32 = 64;
+pub const TILE_SIZE: u32 = 8;
+pub const TILES_PER_BLOCK: u32 = (MORTON_BLOCK_SIZE / TILE_SIZE) * (MORTON_BLOCK_SIZE / TILE_SIZE);
+
+// Takes every other bit, undoing the interleaving of a Morton code
+fn compact_bits(mut x: u32) -> u32 {
+    x &= 0x55555555;
+    x = (x | (x >> 1)) & 0x33333333;
+    x = (x | (x >> 2)) & 0x0f0f0f0f;
+    x = (x | (x >> 4)) & 0x00ff00ff;
+    x = (x | (x >> 8)) & 0x0000ffff;
+    x
+}
+
+fn spread_bits(mut x: u32) -> u32 {
+    x &= 0x0000ffff;
+    x = (x | (x << 8)) & 0x00ff00ff;
+    x = (x | (x << 4)) & 0x0f0f0f0f;
+    x = (x | (x << 2)) & 0x33333333;
+    x = (x | (x << 1)) & 0x55555555;
+    x
+}
+
+pub fn morton_encode(coord: UVec2) -> u32 {
+    spread_bits(coord.x) | (spread_bits(coord.y) << 1)
+}
+
+pub fn morton_decode(code: u32) -> UVec2 {
+    UVec2::new(compact_bits(code), compact_bits(code >> 1))
+}
+
+// Number of 8x8 tiles to dispatch to cover the screen in Morton order. Blocks hanging over
+// the right and bottom edges are dispatched whole, and the pixels outside the screen skipped.
+pub fn tile_count(width: u32, height: u32) -> u32 {
+    let blocks_x = (width + MORTON_BLOCK_SIZE - 1) / MORTON_BLOCK_SIZE;
+    let blocks_y = (height + MORTON_BLOCK_SIZE - 1) / MORTON_BLOCK_SIZE;
+    blocks_x * blocks_y * TILES_PER_BLOCK
+}
+
+// Pixel traced by the given thread of the given tile, in Morton order
+pub fn morton_pixel(tile: u32, local: UVec2, width: u32) -> UVec2 {
+    let blocks_x = (width + MORTON_BLOCK_SIZE - 1) / MORTON_BLOCK_SIZE;
+    let block = tile / TILES_PER_BLOCK;
+    let block_origin = UVec2::new(block % blocks_x, block / blocks_x) * MORTON_BLOCK_SIZE;
+    block_origin + morton_decode(tile % TILES_PER_BLOCK) * TILE_SIZE + local
+}
+
+// Maps an invocation to the pixel it traces. In Morton order, tiles are dispatched in a single
+// row, so the workgroup along x is the tile index.
+pub fn invocation_pixel(id: UVec3, width: u32, morton_order: bool) -> UVec2 {
+    if morton_order {
+        morton_pixel(id.x / TILE_SIZE, UVec2::new(id.x % TILE_SIZE, id.y % TILE_SIZE), width)
+    } else {
+        UVec2::new(id.x, id.y)
+    }
+}
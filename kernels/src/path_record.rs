@@ -0,0 +1,42 @@
+use spirv_std::glam::Vec3;
+
+use crate::bsdf::LobeType;
+
+// What happened at a path vertex
+#[derive(Copy, Clone, PartialEq, Default)]
+pub enum PathEvent {
+    #[default] Bounced, // A new direction was sampled from the BSDF
+    Escaped, // The ray left the scene and picked up the sky
+    Emitter, // The ray hit a light, which ends the path
+    EmitterBackface, // The ray hit the back of a light, which is black
+    EmitterUnlinked, // The ray hit a light unlinked from the surface it came from, which is black
+    RussianRoulette, // The path was terminated at random after bouncing
+    GlossyTerminated, // The path was terminated at random after spreading out over many glossy bounces
+    Section, // The camera ray hit the fill of a cut, which is a flat color
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct PathVertex {
+    pub bounce: u32,
+    pub event: PathEvent,
+    pub origin: Vec3,
+    pub direction: Vec3,
+    pub position: Vec3, // only meaningful if the ray hit something
+    pub normal: Vec3,
+    pub material_index: u32,
+    pub lobe: LobeType,
+    pub pdf: f32,
+    pub cone_width: f32, // footprint of the path's ray cone at the hit
+    pub throughput: Vec3, // after attenuating by the sampled BSDF
+    pub radiance: Vec3, // gathered along the path so far
+}
+
+// Receives every vertex of a path as it is traced, for the debug tracer on the CPU.
+// The kernels pass (), which records nothing.
+pub trait PathRecorder {
+    fn record(&mut self, vertex: &PathVertex);
+}
+
+impl PathRecorder for () {
+    fn record(&mut self, _vertex: &PathVertex) {}
+}
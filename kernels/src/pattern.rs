@@ -0,0 +1,39 @@
+use shared_structs::{PATTERN_CHECKER, PATTERN_GRADIENT, PATTERN_NOISE};
+use spirv_std::glam::Vec2;
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+
+fn hash(x: i32, y: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6b343) ^ (y as u32).wrapping_mul(0xd8163841);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1e995);
+    h ^= h >> 15;
+    (h >> 8) as f32 / (1u32 << 24) as f32
+}
+
+fn value_noise(p: Vec2) -> f32 {
+    let cell = p.floor();
+    let t = p - cell;
+    let t = t * t * (Vec2::splat(3.0) - 2.0 * t);
+    let x = cell.x as i32;
+    let y = cell.y as i32;
+    let top = hash(x, y) + (hash(x + 1, y) - hash(x, y)) * t.x;
+    let bottom = hash(x, y + 1) + (hash(x + 1, y + 1) - hash(x, y + 1)) * t.x;
+    top + (bottom - top) * t.y
+}
+
+// How far to blend from the first value of a channel to the second, at a UV
+pub fn pattern_weight(pattern: u32, uv: Vec2, scale: f32) -> f32 {
+    let p = uv * scale;
+    if pattern == PATTERN_CHECKER {
+        let cell = p.floor();
+        let parity = (cell.x + cell.y) * 0.5;
+        if parity - parity.floor() < 0.25 { 0.0 } else { 1.0 }
+    } else if pattern == PATTERN_NOISE {
+        value_noise(p)
+    } else if pattern == PATTERN_GRADIENT {
+        p.x - p.x.floor()
+    } else {
+        0.0
+    }
+}
@@ -0,0 +1,51 @@
+use shared_structs::TracingConfig;
+use spirv_std::glam::Vec3;
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+
+use crate::bsdf::LobeType;
+
+// Spread of a diffuse bounce. The cone covers about a hemisphere, so any glossy chain after it is a blur.
+const DIFFUSE_SPREAD: f32 = core::f32::consts::FRAC_PI_2;
+
+// Cone around a path, which starts as the footprint of a pixel and widens with every rough bounce.
+// Curvature is ignored, so it only approximates the area a path covers.
+#[derive(Copy, Clone, Default)]
+pub struct RayCone {
+    pub width: f32, // at the last hit, in world units
+    pub spread: f32, // angle the cone widens by per unit of distance
+}
+
+impl RayCone {
+    // Camera rays pass through the screen at a distance of 1, where it is 2 units wide
+    pub fn camera(config: &TracingConfig) -> Self {
+        Self {
+            width: 0.0,
+            spread: 2.0 / config.render.width as f32,
+        }
+    }
+
+    pub fn propagate(&mut self, t: f32) {
+        self.width += self.spread * t;
+    }
+
+    // Widens the cone by the lobe the path was scattered into. The GGX lobe is roughly
+    // roughness squared wide on either side.
+    pub fn scatter(&mut self, lobe: LobeType, roughness: f32) {
+        self.spread += match lobe {
+            LobeType::DiffuseReflection | LobeType::DiffuseTransmission => DIFFUSE_SPREAD,
+            LobeType::SpecularReflection | LobeType::SpecularTransmission => 2.0 * roughness * roughness,
+        };
+    }
+
+    // Chance of a glossy bounce continuing. Once a chain of glossy bounces has spread wider than
+    // the limit, what it reflects is blurred beyond recognition and contributes little, so it is
+    // terminated in proportion. A limit of zero never terminates.
+    pub fn glossy_survival(&self, limit: f32, lobe: LobeType) -> f32 {
+        let glossy = matches!(lobe, LobeType::SpecularReflection | LobeType::SpecularTransmission);
+        if limit <= 0.0 || !glossy {
+            return 1.0;
+        }
+        (limit / self.spread).min(1.0)
+    }
+}
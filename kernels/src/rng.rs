@@ -1,3 +1,4 @@
+use shared_structs::{BLUE_NOISE_LAYERS, BLUE_NOISE_SIZE};
 use spirv_std::glam::{UVec2, Vec2, Vec3};
 
 #[allow(dead_code)]
@@ -26,21 +27,46 @@ const LDS_PRIMES: [u32; LDS_MAX_DIMENSIONS] = [
 ];
 
 // http://extremelearning.com.au/unreasonable-effectiveness-of-quasirandom-sequences/
-pub fn lds(n: u32, dimension: usize, offset: u32) -> f32 {
+fn lds_u32(n: u32, dimension: usize) -> u32 {
+    LDS_PRIMES[dimension].wrapping_mul(n)
+}
+
+fn to_unit_float(value: u32) -> f32 {
     const INV_U32_MAX_FLOAT: f32 = 1.0 / 4294967296.0;
-    (LDS_PRIMES[dimension].wrapping_mul(n.wrapping_add(offset))) as f32 * INV_U32_MAX_FLOAT 
+    value as f32 * INV_U32_MAX_FLOAT
 }
 
-pub struct RngState {
+pub fn lds(n: u32, dimension: usize, offset: u32) -> f32 {
+    to_unit_float(lds_u32(n.wrapping_add(offset), dimension))
+}
+
+// Hash of pixel, sample index and global seed, used to decorrelate consecutive samples.
+pub fn scramble(pixel: u32, sample: u32, seed: u32) -> u32 {
+    pcg_hash(pcg_hash(pcg_hash(seed) ^ pixel) ^ sample)
+}
+
+pub struct RngState<'a> {
     state: UVec2,
+    offset: u32,
     dimension: usize,
+    blue_noise: bool,
+    blue_noise_buffer: &'a [u32],
+    pixel: UVec2,
+    seed: u32,
 }
 
-impl RngState {
-    pub fn new(state: UVec2) -> Self {
+impl<'a> RngState<'a> {
+    // state.x is the sample index and state.y the pixel index. Without blue noise, the offset
+    // is rehashed for every sample so consecutive samples aren't correlated.
+    pub fn new(state: UVec2, blue_noise: bool, seed: u32, pixel: UVec2, blue_noise_buffer: &'a [u32]) -> Self {
         Self {
             state,
+            offset: scramble(state.y, state.x, seed),
             dimension: 0,
+            blue_noise,
+            blue_noise_buffer,
+            pixel,
+            seed,
         }
     }
 
@@ -48,9 +74,40 @@ impl RngState {
         UVec2::new(self.state.x + 1, self.state.y)
     }
 
+    // Each dimension is offset by its own blue noise texture. Dimensions beyond the number
+    // of textures reuse them, shifted by a random amount so they aren't correlated. The seed
+    // shifts all of them, which keeps them blue while changing the pattern.
+    fn blue_noise_offset(&self, dimension: usize) -> u32 {
+        let layer = dimension as u32 % BLUE_NOISE_LAYERS;
+        let cycle = dimension as u32 / BLUE_NOISE_LAYERS;
+        let shift = if cycle == 0 && self.seed == 0 { 0 } else { pcg_hash(cycle ^ pcg_hash(self.seed)) };
+        let x = self.pixel.x.wrapping_add(shift) % BLUE_NOISE_SIZE;
+        let y = self.pixel.y.wrapping_add(shift >> 16) % BLUE_NOISE_SIZE;
+        self.blue_noise_buffer[((layer * BLUE_NOISE_SIZE + y) * BLUE_NOISE_SIZE + x) as usize]
+    }
+
     pub fn gen_r1(&mut self) -> f32 {
         self.dimension += 1;
-        lds(self.state.x, self.dimension, self.state.y)
+        // Deep paths can use more dimensions than we have primes for. Past that point, wrap
+        // around and pad with a random shift per sample and pass, so the reused dimensions
+        // aren't correlated with the ones they alias.
+        let pass = (self.dimension / LDS_MAX_DIMENSIONS) as u32;
+        if self.blue_noise {
+            // Rotating the blue noise by the sequence keeps each frame blue, while each pixel
+            // still steps through a low discrepancy sequence over time.
+            let mut offset = self.blue_noise_offset(self.dimension);
+            if pass != 0 {
+                offset = pcg_hash(offset ^ pcg_hash(self.state.x ^ pcg_hash(pass)));
+            }
+            return to_unit_float(lds_u32(self.state.x, self.dimension % LDS_MAX_DIMENSIONS).wrapping_add(offset));
+        }
+
+        let offset = if pass == 0 {
+            self.offset
+        } else {
+            pcg_hash(self.offset ^ pcg_hash(self.state.x ^ pcg_hash(pass)))
+        };
+        lds(self.state.x, self.dimension % LDS_MAX_DIMENSIONS, offset)
     }
 
     pub fn gen_r2(&mut self) -> Vec2 {
@@ -60,4 +117,4 @@ impl RngState {
     pub fn gen_r3(&mut self) -> Vec3 {
         Vec3::new(self.gen_r1(), self.gen_r1(), self.gen_r1())
     }
-}
\ No newline at end of file
+}
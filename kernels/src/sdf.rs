@@ -0,0 +1,129 @@
+use shared_structs::{PerVertexData, SDF_BOX, SDF_CAPSULE, SDF_GRID, SDF_SPHERE, SDF_TORUS};
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+use spirv_std::glam::{UVec3, UVec4, Vec2, Vec3, Vec4, Vec4Swizzles};
+
+const MAX_STEPS: u32 = 256;
+const HIT_DISTANCE: f32 = 1e-4; // Of the size of the shape
+const NORMAL_OFFSET: f32 = 1e-3; // Of the size of the shape, for the central differences of the gradient
+
+// Shape, parameters and bounds of a signed distance field, read from its corners
+#[derive(Clone, Copy)]
+pub struct SdfShape {
+    pub kind: u32,
+    pub parameters: Vec4,
+    pub extra: Vec4,
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl SdfShape {
+    pub fn from_corners(per_vertex_buffer: &[PerVertexData], triangle: UVec4) -> Self {
+        let first = per_vertex_buffer[triangle.x as usize];
+        let a = first.vertex.xyz();
+        let b = per_vertex_buffer[triangle.y as usize].vertex.xyz();
+        let c = per_vertex_buffer[triangle.z as usize].vertex.xyz();
+        Self {
+            kind: first.normal.x as u32,
+            parameters: first.normal,
+            extra: first.tangent,
+            min: a.min(b).min(c),
+            max: a.max(b).max(c),
+        }
+    }
+
+    fn size(&self) -> f32 {
+        (self.max - self.min).max_element()
+    }
+
+    // Distance from a point in world space, negative inside
+    pub fn distance(&self, per_vertex_buffer: &[PerVertexData], position: Vec3) -> f32 {
+        let p = position - (self.min + self.max) * 0.5;
+        let parameters = self.parameters.yzw();
+        if self.kind == SDF_SPHERE {
+            p.length() - parameters.x
+        } else if self.kind == SDF_BOX {
+            let rounding = self.extra.x;
+            let q = p.abs() - (parameters - rounding);
+            q.max(Vec3::ZERO).length() + q.max_element().min(0.0) - rounding
+        } else if self.kind == SDF_TORUS {
+            let q = Vec2::new(Vec2::new(p.x, p.z).length() - parameters.x, p.y);
+            q.length() - parameters.y
+        } else if self.kind == SDF_CAPSULE {
+            let q = Vec3::new(p.x, p.y - p.y.clamp(-parameters.x, parameters.x), p.z);
+            q.length() - parameters.y
+        } else if self.kind == SDF_GRID {
+            self.grid_distance(per_vertex_buffer, position)
+        } else {
+            f32::INFINITY
+        }
+    }
+
+    // Trilinear, between samples placed on a lattice from one corner of the bounds to the other
+    fn grid_distance(&self, per_vertex_buffer: &[PerVertexData], position: Vec3) -> f32 {
+        let resolution = UVec3::new(self.parameters.y as u32, self.parameters.z as u32, self.parameters.w as u32).max(UVec3::splat(2));
+        let first = self.extra.y as usize;
+        let cells = (resolution - 1).as_vec3();
+        let grid = ((position - self.min) / (self.max - self.min) * cells).clamp(Vec3::ZERO, cells);
+        let cell = grid.floor().min(cells - 1.0);
+        let t = grid - cell;
+        let cell = cell.as_uvec3();
+
+        let mut result = 0.0;
+        for corner in 0..8u32 {
+            let offset = UVec3::new(corner & 1, (corner >> 1) & 1, corner >> 2);
+            let sample = cell + offset;
+            let index = ((sample.z * resolution.y + sample.y) * resolution.x + sample.x) as usize;
+            let packed = per_vertex_buffer[first + index / 4].vertex;
+            let value = match index % 4 {
+                0 => packed.x,
+                1 => packed.y,
+                2 => packed.z,
+                _ => packed.w,
+            };
+            let weight = |offset: u32, t: f32| if offset == 0 { 1.0 - t } else { t };
+            result += value * weight(offset.x, t.x) * weight(offset.y, t.y) * weight(offset.z, t.z);
+        }
+        result
+    }
+
+    // Gradient of the distance, pointing out of the shape
+    pub fn normal(&self, per_vertex_buffer: &[PerVertexData], position: Vec3) -> Vec3 {
+        let h = self.size() * NORMAL_OFFSET;
+        let dx = self.distance(per_vertex_buffer, position + Vec3::X * h) - self.distance(per_vertex_buffer, position - Vec3::X * h);
+        let dy = self.distance(per_vertex_buffer, position + Vec3::Y * h) - self.distance(per_vertex_buffer, position - Vec3::Y * h);
+        let dz = self.distance(per_vertex_buffer, position + Vec3::Z * h) - self.distance(per_vertex_buffer, position - Vec3::Z * h);
+        let gradient = Vec3::new(dx, dy, dz);
+        if gradient.length_squared() > 0.0 { gradient.normalize() } else { Vec3::Y }
+    }
+}
+
+// Sphere traces the shape from where the ray enters its bounds. Rays inside the shape by min_t, like
+// those refracted into it, trace the negated distance to the surface they leave through, which is a
+// back face. Hits closer than min_t are skipped over, so rays leaving the surface don't hit it again.
+pub fn intersect_sdf(per_vertex_buffer: &[PerVertexData], triangle: UVec4, ro: Vec3, rd: Vec3, min_t: f32, max_t: f32, out_t: &mut f32, out_backface: &mut bool) -> bool {
+    let shape = SdfShape::from_corners(per_vertex_buffer, triangle);
+    let t1 = (shape.min - ro) / rd;
+    let t2 = (shape.max - ro) / rd;
+    let mut t = t1.min(t2).max_element().max(0.0);
+    let exit = t1.max(t2).min_element().min(max_t);
+    if exit < t {
+        return false;
+    }
+
+    let epsilon = shape.size() * HIT_DISTANCE;
+    let sign = if shape.distance(per_vertex_buffer, ro + rd * t.max(min_t)) < 0.0 { -1.0 } else { 1.0 };
+    *out_backface = sign < 0.0;
+    for _ in 0..MAX_STEPS {
+        let distance = sign * shape.distance(per_vertex_buffer, ro + rd * t);
+        if distance < epsilon && t > min_t {
+            *out_t = t;
+            return true;
+        }
+        t += distance.max(epsilon);
+        if t > exit {
+            return false;
+        }
+    }
+    false
+}
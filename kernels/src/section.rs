@@ -0,0 +1,66 @@
+use shared_structs::{RenderSettings, MAX_SECTION_PLANES};
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+use spirv_std::glam::{Vec3, Vec4Swizzles};
+
+use crate::intersection::TraceResult;
+
+// Part of a camera ray on the kept side of every section plane, so what a camera ray can hit is
+// the same convex region for any number of planes
+#[derive(Clone, Copy)]
+pub struct SectionSpan {
+    pub enter: f32,
+    pub exit: f32,
+    pub normal: Vec3, // of the plane the ray enters through, facing the camera. Zero if it starts on the kept side.
+}
+
+impl SectionSpan {
+    pub fn new(render: &RenderSettings, ro: Vec3, rd: Vec3) -> Self {
+        let mut span = Self { enter: 0.0, exit: f32::INFINITY, normal: Vec3::ZERO };
+        for i in 0..render.section_plane_count.min(MAX_SECTION_PLANES as u32) {
+            let plane = render.section_planes[i as usize];
+            let side = plane.xyz().dot(ro) + plane.w;
+            let rate = plane.xyz().dot(rd);
+            if rate == 0.0 {
+                // Parallel rays are either cut away entirely, or not at all
+                if side > 0.0 {
+                    span.exit = -1.0;
+                }
+                continue;
+            }
+            let t = -side / rate;
+            if rate < 0.0 {
+                if t > span.enter {
+                    span.enter = t;
+                    span.normal = plane.xyz();
+                }
+            } else {
+                span.exit = span.exit.min(t);
+            }
+        }
+        span
+    }
+
+    // Where the ray starts tracing from, to pass through what is cut away
+    pub fn origin(&self, ro: Vec3, rd: Vec3) -> Vec3 {
+        ro + rd * self.enter
+    }
+
+    // Takes a hit of a ray traced from origin() back to the whole ray, and drops it past the span.
+    // A back face as the first hit means the ray entered a closed mesh through the cut, which is
+    // filled, as a hit on the plane, if the settings ask for it.
+    pub fn clip(&self, render: &RenderSettings, mut result: TraceResult) -> TraceResult {
+        if self.enter > self.exit {
+            return TraceResult::default();
+        }
+        result.t += self.enter;
+        if result.hit && result.backface && self.enter > 0.0 && render.section_color.w != 0.0 {
+            result.t = self.enter;
+            result.backface = false;
+            result.section = true;
+        } else if result.t > self.exit {
+            result = TraceResult::default();
+        }
+        result
+    }
+}
@@ -3,6 +3,7 @@ use spirv_std::glam::{Vec2, Vec3, Vec4, Vec4Swizzles};
 use spirv_std::num_traits::Float;
 
 use crate::util;
+use shared_structs::{Image, Sampler};
 
 // Constants
 const RAY_SCATTER_COEFF: Vec3 = Vec3::new(58e-7, 135e-7, 331e-7);
@@ -43,7 +44,9 @@ fn scatter_depth_int(o: Vec3, d: Vec3, l: f32) -> Vec2 {
     densities_rm(o) * (l / 2.) + densities_rm(o + d * l) * (l / 2.)
 }
 
-fn scatter_in(origin: Vec3, direction: Vec3, depth: f32, steps: u32, sundir: Vec3) -> (Vec3, Vec3) {
+// In-scattered light along a ray, and the optical depth of the ray itself. `density` scales the
+// air along the ray, but not along the paths of sunlight towards it.
+fn scatter_in(origin: Vec3, direction: Vec3, depth: f32, steps: u32, sundir: Vec3, density: f32) -> (Vec3, Vec3, Vec2) {
     let depth = depth / steps as f32;
 
     let mut i_r = Vec3::ZERO;
@@ -53,7 +56,7 @@ fn scatter_in(origin: Vec3, direction: Vec3, depth: f32, steps: u32, sundir: Vec
     let mut i = 0;
     while i < steps {
         let p = origin + direction * (depth * i as f32);
-        let d_rm = densities_rm(p) * depth;
+        let d_rm = densities_rm(p) * depth * density;
         total_depth_rm += d_rm;
 
         // Calculate optical depth
@@ -69,18 +72,11 @@ fn scatter_in(origin: Vec3, direction: Vec3, depth: f32, steps: u32, sundir: Vec
         i += 1;
     }
 
-    (i_r, i_m)
+    (i_r, i_m, total_depth_rm)
 }
 
-pub fn scatter(sundir: Vec4, origin: Vec3, direction: Vec3) -> Vec3 {
-    let (i_r, i_m) = scatter_in(
-        origin,
-        direction,
-        escape(origin, direction, ATMOSPHERE_RADIUS),
-        12,
-        sundir.xyz(),
-    );
-
+// Applies the phase functions to in-scattered light
+fn scattered_radiance(sundir: Vec4, direction: Vec3, i_r: Vec3, i_m: Vec3) -> Vec3 {
     let mu = direction.dot(sundir.xyz());
     let res = sundir.w
         * (1. + mu * mu)
@@ -92,3 +88,67 @@ pub fn scatter(sundir: Vec4, origin: Vec3, direction: Vec3) -> Vec3 {
 
     return util::mask_nan(Vec3::new(res.x.sqrt(), res.y.sqrt(), res.z.sqrt())).powf(2.2); // gamma -> linear since we render in linear
 }
+
+pub fn scatter(sundir: Vec4, origin: Vec3, direction: Vec3) -> Vec3 {
+    let (i_r, i_m, _) = scatter_in(
+        origin,
+        direction,
+        escape(origin, direction, ATMOSPHERE_RADIUS),
+        12,
+        sundir.xyz(),
+        1.0,
+    );
+    scattered_radiance(sundir, direction, i_r, i_m)
+}
+
+// Aerial perspective between a ray's origin and the surface it hits, using the same atmosphere as
+// the sky. Returns the transmittance, and the light scattered towards the origin along the way.
+// Real air is too thin to show at the scale of most scenes, so `density` thickens it.
+pub fn aerial_perspective(sundir: Vec4, density: f32, origin: Vec3, direction: Vec3, distance: f32) -> (Vec3, Vec3) {
+    let (i_r, i_m, depth_rm) = scatter_in(origin, direction, distance, 8, sundir.xyz(), density);
+    let transmittance = (-RAY_EFFECTIVE_COEFF * depth_rm.x - MIE_EFFECTIVE_COEFF * depth_rm.y).exp();
+    (transmittance, scattered_radiance(sundir, direction, i_r, i_m))
+}
+
+// Cosine of the half-angle of the cone around the sun that is sampled directly. It holds the bright
+// forward scattering peak, the rest of the sky is smooth enough to be found by BSDF samples.
+pub const SUN_CONE_COS: f32 = 0.99;
+pub const SUN_CONE_PDF: f32 = 1.0 / (2.0 * core::f32::consts::PI * (1.0 - SUN_CONE_COS));
+
+pub fn in_sun_cone(sundir: Vec4, direction: Vec3) -> bool {
+    direction.dot(sundir.xyz().normalize()) >= SUN_CONE_COS
+}
+
+// Uniformly samples a direction in the cone around the sun, with pdf SUN_CONE_PDF
+pub fn sample_sun_cone(sundir: Vec4, rng: Vec2) -> Vec3 {
+    let cos_theta = 1.0 - rng.x * (1.0 - SUN_CONE_COS);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * core::f32::consts::PI * rng.y;
+    let (up, right, forward) = util::create_cartesian(sundir.xyz().normalize());
+    up * cos_theta + (right * phi.cos() + forward * phi.sin()) * sin_theta
+}
+
+// Where a level of a packed skybox lives. The full image takes the top two thirds, and each
+// smaller level sits to the right of the previous one below it.
+fn mip_uv(uv: Vec2, level: u32) -> Vec2 {
+    if level == 0 {
+        return Vec2::new(uv.x, uv.y * (2.0 / 3.0));
+    }
+    let size = 1.0 / (1u32 << level) as f32;
+    Vec2::new(1.0 - 2.0 * size + uv.x * size, (2.0 + uv.y * size * 2.0) / 3.0)
+}
+
+// Samples an equirectangular skybox, blending between the two mip levels around `lod`
+pub fn sample_skybox(skybox: &Image!(2D, type=f32, sampled), sampler: &Sampler, uv: Vec2, lod: f32, mips: u32) -> Vec3 {
+    if mips == 0 {
+        return skybox.sample_by_lod(*sampler, uv, 0.0).xyz();
+    }
+    let lod = lod.clamp(0.0, (mips - 1) as f32);
+    let level = lod.floor();
+    let near = skybox.sample_by_lod(*sampler, mip_uv(uv, level as u32), 0.0).xyz();
+    if lod == level {
+        return near;
+    }
+    let far = skybox.sample_by_lod(*sampler, mip_uv(uv, level as u32 + 1), 0.0).xyz();
+    near.lerp(far, lod - level)
+}
@@ -1,4 +1,5 @@
-use spirv_std::glam::Vec3;
+use shared_structs::PerVertexData;
+use spirv_std::glam::{UVec4, Vec2, Vec3, Vec4, Vec4Swizzles};
 #[allow(unused_imports)]
 use spirv_std::num_traits::Float;
 
@@ -250,6 +251,37 @@ pub fn barycentric(p: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
     Vec3::new(1.0 - v - w, v, w)
 }
 
+// UV of a point on a triangle, given its barycentric coordinates
+pub fn triangle_uv(per_vertex_buffer: &[PerVertexData], triangle: UVec4, bary: Vec3) -> Vec2 {
+    let uv_a = per_vertex_buffer[triangle.x as usize].uv0;
+    let uv_b = per_vertex_buffer[triangle.y as usize].uv0;
+    let uv_c = per_vertex_buffer[triangle.z as usize].uv0;
+    bary.x * uv_a + bary.y * uv_b + bary.z * uv_c
+}
+
+// Scales, rotates around the origin, then offsets a UV, like KHR_texture_transform.
+// A zero scale means the channel has no transform, which is what materials default to.
+pub fn transform_uv(transform: Vec4, rotation: f32, uv: Vec2) -> Vec2 {
+    if transform.zw() == Vec2::ZERO {
+        return uv;
+    }
+    let scaled = uv * transform.zw();
+    let (sin, cos) = (rotation.sin(), rotation.cos());
+    transform.xy() + Vec2::new(cos * scaled.x - sin * scaled.y, sin * scaled.x + cos * scaled.y)
+}
+
+// Where in the atlas a UV lands. A texture repeats within its own slot, except for UDIM textures,
+// whose tile is picked by the integer part of the UV, clamped to the grid. See pack_udim_grid.
+pub fn atlas_uv(location: Vec4, udim_grid: u32, uv: Vec2) -> Vec2 {
+    if udim_grid == 0 {
+        let wrapped = if uv.clamp(Vec2::ZERO, Vec2::ONE) != uv { uv.fract() } else { uv };
+        return location.xy() + wrapped * location.zw();
+    }
+    let grid = Vec2::new((udim_grid & 0xFFFF) as f32, (udim_grid >> 16) as f32);
+    let tile = uv.floor();
+    location.xy() + (tile.clamp(Vec2::ZERO, grid - 1.0) + (uv - tile)) * location.zw()
+}
+
 pub fn power_heuristic(p1: f32, p2: f32) -> f32 {
     let p1_2 = p1 * p1;
     p1_2 / (p1_2 + p2 * p2)
@@ -276,6 +308,10 @@ pub fn mask_nan(v: Vec3) -> Vec3 {
     }
 }
 
+pub fn luminance(color: Vec3) -> f32 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
 pub fn lerp(a: f32, b: f32, t: f32) -> f32 {
     a * (1.0 - t) + b * t
 }
\ No newline at end of file
@@ -12,11 +12,24 @@ pub mod polyfill {
     #[derive(Clone, Copy)]
     pub struct Sampler;
 
+    // Procedural textures are this far apart, and far outside the image, so UVs that repeat
+    // a few times never reach a neighbouring texture or the image itself.
+    pub const PROCEDURAL_SPACING: f32 = 1024.0;
+
+    // A texture computed by a host callback. Materials point at it like any other texture,
+    // with the offset as its location, and it is passed the UV relative to that.
+    #[derive(Clone, Copy)]
+    pub struct ProceduralRegion<'a> {
+        pub offset: Vec2,
+        pub texture: &'a (dyn Fn(Vec2) -> Vec4 + Send + Sync),
+    }
+
     pub struct Image<'a, A,B,C,D,E,F> {
         _phantom: core::marker::PhantomData<(A,B,C,D,E,F)>,
         width: u32,
         height: u32,
         buffer: &'a [Vec4],
+        procedural: &'a [ProceduralRegion<'a>],
     }
 
     impl<'a, A> Image<'a, A,A,A,A,A,A> {
@@ -26,6 +39,14 @@ pub mod polyfill {
                 width,
                 height,
                 buffer,
+                procedural: &[],
+            }
+        }
+
+        pub fn with_procedural(self, procedural: &'a [ProceduralRegion<'a>]) -> Self {
+            Image {
+                procedural,
+                ..self
             }
         }
 
@@ -36,6 +57,13 @@ pub mod polyfill {
         }
 
         pub fn sample_by_lod(&self, _sampler: Sampler, coord: Vec2, _lod: f32) -> Vec4 {
+            for region in self.procedural {
+                let local = coord - region.offset;
+                if local.abs().max_element() < PROCEDURAL_SPACING / 2.0 {
+                    return (region.texture)(local);
+                }
+            }
+
             let scaled_uv = coord * Vec2::new(self.width as f32, self.height as f32);
             let frac_uv = scaled_uv.fract();
             let ceil_uv = scaled_uv.ceil().as_ivec2();
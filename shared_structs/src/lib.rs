@@ -1,42 +1,159 @@
 #![no_std]
 
 use bytemuck::{Pod, Zeroable};
-use glam::{Vec3, Vec4, Vec4Swizzles, Vec2};
+use glam::{UVec4, Vec3, Vec4, Vec4Swizzles, Vec2};
 
 mod image_polyfill;
 pub use image_polyfill::polyfill::{Image, Sampler};
 #[cfg(not(target_arch = "spirv"))]
 pub use image_polyfill::polyfill::CpuImage;
+#[cfg(not(target_arch = "spirv"))]
+pub use image_polyfill::polyfill::{ProceduralRegion, PROCEDURAL_SPACING};
+
 
+// Size and count of the blue noise textures used by the RNG
+pub const BLUE_NOISE_SIZE: u32 = 64;
+pub const BLUE_NOISE_LAYERS: u32 = 8;
 
+// Settings of a render, split by how often they change. Each block is its own uniform buffer
+// on the GPU, so changing one doesn't upload the others.
 #[repr(C)]
-#[derive(Copy, Clone, Pod, Zeroable)]
+#[derive(Copy, Clone, Pod, Zeroable, Default)]
 pub struct TracingConfig {
+    pub camera: CameraUniform,
+    pub render: RenderSettings,
+    pub environment: EnvironmentSettings,
+}
+
+impl TracingConfig {
+    // The kernels receive the blocks from separate bindings
+    pub fn from_blocks(camera: &CameraUniform, render: &RenderSettings, environment: &EnvironmentSettings) -> Self {
+        Self {
+            camera: *camera,
+            render: *render,
+            environment: *environment,
+        }
+    }
+}
+
+// Changes every frame while moving, and every dispatch for the sample count
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct CameraUniform {
     pub cam_position: Vec4,
     pub cam_rotation: Vec4,
+    pub prev_cam_position: Vec4, // camera of the previous frame, for motion vectors
+    pub prev_cam_rotation: Vec4,
+    pub sample_count: u32, // samples accumulated before the current dispatch
+    pub projection: u32, // PROJECTION_*
+    pub _padding2: u32,
+    pub _padding3: u32,
+}
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self {
+            cam_position: Vec4::new(0.0, 1.0, -5.0, 0.0),
+            cam_rotation: Vec4::ZERO,
+            prev_cam_position: Vec4::new(0.0, 1.0, -5.0, 0.0),
+            prev_cam_rotation: Vec4::ZERO,
+            sample_count: 0,
+            projection: PROJECTION_PERSPECTIVE,
+            _padding2: 0,
+            _padding3: 0,
+        }
+    }
+}
+
+// Section planes cut away geometry for camera rays only, shadows and bounces still see all of it
+pub const MAX_SECTION_PLANES: usize = 4;
+
+// Changes when the user tweaks a setting
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct RenderSettings {
     pub width: u32,
     pub height: u32,
     pub min_bounces: u32,
     pub max_bounces: u32,
-    pub sun_direction: Vec4,
     pub nee: u32,
-    pub has_skybox: u32,
+    pub use_blue_noise: u32,
+    pub seed: u32,
+    pub ray_offset: f32, // distance rays are pushed off surfaces, scaled to the scene size
     pub specular_weight_clamp: Vec2,
+    pub jitter: u32, // whether camera rays are jittered within the pixel for anti-aliasing
+    pub morton_order: u32, // whether pixels are traced in Z-order, see kernels::morton
+    pub adaptive_roulette: u32, // whether Russian roulette compares throughput to albedo, instead of starting after min_bounces
+    pub path_splits: u32, // paths traced from each camera ray's first hit, on surfaces that can reflect diffusely
+    pub glossy_cone_limit: f32, // ray cone spread past which glossy paths are terminated at random, 0 to disable
+    pub firefly_rejection: f32, // mean absolute deviations above its running mean at which a pixel's samples are clamped, 0 to disable
+    pub section_planes: [Vec4; MAX_SECTION_PLANES], // xyz = normal, w = offset. Camera rays pass through what is on the side the normal points to.
+    pub section_color: Vec4, // flat color the cut through closed meshes is filled with, w = 1 to fill it
+    pub section_plane_count: u32,
+    pub primary_pass: u32, // whether camera rays are traced in a dispatch of their own before the path tracing kernel, see kernels::primary_kernel
+    pub _padding2: u32,
+    pub _padding3: u32,
 }
 
-impl Default for TracingConfig {
+impl Default for RenderSettings {
     fn default() -> Self {
-        Self { 
-            cam_position: Vec4::new(0.0, 1.0, -5.0, 0.0),
-            cam_rotation: Vec4::ZERO,
+        Self {
             width: 1280,
             height: 720,
             min_bounces: 3,
             max_bounces: 4,
-            sun_direction: Vec3::new(0.5, 1.3, 1.0).normalize().extend(15.0),
             nee: 0,
-            has_skybox: 0,
+            use_blue_noise: 1,
+            seed: 0,
+            ray_offset: 0.001,
             specular_weight_clamp: Vec2::new(0.1, 0.9),
+            jitter: 1,
+            morton_order: 0,
+            adaptive_roulette: 0,
+            path_splits: 1,
+            glossy_cone_limit: 0.0,
+            firefly_rejection: 0.0,
+            section_planes: [Vec4::ZERO; MAX_SECTION_PLANES],
+            section_color: Vec4::new(0.8, 0.15, 0.1, 1.0),
+            section_plane_count: 0,
+            primary_pass: 1,
+            _padding2: 0,
+            _padding3: 0,
+        }
+    }
+}
+
+// Changes with the skybox and the sun
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct EnvironmentSettings {
+    pub sun_direction: Vec4,
+    pub environment_light_direction: Vec4, // directional light extracted from the skybox, in skybox space
+    pub environment_light_irradiance: Vec4, // zero when there is none
+    pub has_skybox: u32,
+    pub background_lod: f32, // mip level of the skybox seen directly by the camera, blurring the background but not the lighting
+    pub skybox_mips: u32, // levels packed into the skybox image, see pack_skybox_mips. 0 for a plain image.
+    pub sky_weight: f32, // how much of the procedural sky is added on top of a skybox image
+    pub haze_density: f32, // thickness of the air between the camera and the scene, relative to real air. 0 for none.
+    pub _padding1: u32,
+    pub _padding2: u32,
+    pub _padding3: u32,
+}
+
+impl Default for EnvironmentSettings {
+    fn default() -> Self {
+        Self {
+            sun_direction: Vec3::new(0.5, 1.3, 1.0).normalize().extend(15.0),
+            environment_light_direction: Vec4::ZERO,
+            environment_light_irradiance: Vec4::ZERO,
+            has_skybox: 0,
+            background_lod: 0.0,
+            skybox_mips: 0,
+            sky_weight: 0.0,
+            haze_density: 0.0,
+            _padding1: 0,
+            _padding2: 0,
+            _padding3: 0,
         }
     }
 }
@@ -49,10 +166,24 @@ pub struct MaterialData { // each Vec4 is either a color or an atlas location
     pub roughness: Vec4,
     pub metallic: Vec4,
     pub normals: Vec4,
+    pub emissive_texture: Vec4, // atlas location, multiplied with the emissive color
     has_albedo_texture: u32,
     has_metallic_texture: u32,
     has_roughness_texture: u32,
     has_normal_texture: u32,
+    has_emissive_texture: u32,
+    double_sided: u32, // whether emissive triangles emit from their back faces too
+    pub light_group: u32, // single bit identifying this material's emission for light linking
+    pub light_exclude: u32, // light groups that don't light this material, including ENVIRONMENT_LIGHT_GROUP
+    pub patterns: UVec4, // PATTERN_* for the albedo, roughness and metallic channels, which take precedence over textures
+    pub pattern_scale: Vec4, // cells per unit of UV of each channel's pattern
+    pub albedo_pattern: Vec4, // second color of the albedo pattern. Roughness and metallic patterns blend from x to y instead.
+    pub udim_grid: UVec4, // UDIM tile grids of the albedo, metallic, roughness and normal textures, see pack_udim_grid
+    pub albedo_transform: Vec4, // UV offset in xy and scale in zw, applied before the atlas lookup. A zero scale means no transform.
+    pub metallic_transform: Vec4,
+    pub roughness_transform: Vec4,
+    pub normal_transform: Vec4,
+    pub uv_rotation: Vec4, // radians, counter-clockwise, of the albedo, metallic, roughness and normal transforms
 }
 
 impl MaterialData {
@@ -87,6 +218,22 @@ impl MaterialData {
     pub fn set_has_normal_texture(&mut self, has_normal_texture: bool) {
         self.has_normal_texture = if has_normal_texture { 1 } else { 0 };
     }
+
+    pub fn has_emissive_texture(&self) -> bool {
+        self.has_emissive_texture != 0
+    }
+
+    pub fn set_has_emissive_texture(&mut self, has_emissive_texture: bool) {
+        self.has_emissive_texture = if has_emissive_texture { 1 } else { 0 };
+    }
+
+    pub fn double_sided(&self) -> bool {
+        self.double_sided != 0
+    }
+
+    pub fn set_double_sided(&mut self, double_sided: bool) {
+        self.double_sided = if double_sided { 1 } else { 0 };
+    }
 }
 
 #[repr(C)]
@@ -118,6 +265,146 @@ impl LightPickEntry {
     }
 }
 
+// UDIM textures are a grid of tiles in a single atlas slot, and their atlas location is that of
+// the first tile. 0 means a texture is a single tile, repeating across UV space.
+pub fn pack_udim_grid(columns: u32, rows: u32) -> u32 {
+    if columns <= 1 && rows <= 1 {
+        0
+    } else {
+        columns | rows << 16
+    }
+}
+
+// How camera rays are laid out over the image
+pub const PROJECTION_PERSPECTIVE: u32 = 0;
+pub const PROJECTION_EQUIRECTANGULAR: u32 = 1; // 360 degrees around the camera's position, laid out like skyboxes and ignoring its rotation
+
+// Patterns evaluated in the kernels in place of a texture, blending between two values of the channel
+pub const PATTERN_NONE: u32 = 0;
+pub const PATTERN_CHECKER: u32 = 1;
+pub const PATTERN_NOISE: u32 = 2; // smooth value noise
+pub const PATTERN_GRADIENT: u32 = 3; // along U
+
+// The last index of a triangle is its material index, with the visibility flags of the material
+// in the top bits, so traversal can pass through hidden triangles without reading their material
+pub const MATERIAL_INDEX_MASK: u32 = (1 << 24) - 1;
+pub const HIDDEN_FROM_CAMERA: u32 = 1 << 24; // camera rays pass through
+pub const HIDDEN_FROM_SHADOWS: u32 = 1 << 25; // shadow rays pass through, so it casts no shadows
+pub const HIDDEN_FROM_INDIRECT: u32 = 1 << 26; // rays that have bounced pass through
+pub const PRIMITIVE_POINT: u32 = 1 << 27; // a point of a point cloud rather than a triangle, see point_corners
+pub const PRIMITIVE_SDF: u32 = 1 << 28; // a signed distance field within the bounds of the corners, see SDF_SPHERE
+
+// Boolean operations between closed meshes, evaluated per ray by counting the meshes of each
+// operation a point is inside of, see csg_inside. Surfaces only show where the result changes.
+pub const CSG_UNION: u32 = 1 << 29; // solids the result is made of
+pub const CSG_SUBTRACT: u32 = 2 << 29; // cut away from the solids
+pub const CSG_INTERSECT: u32 = 3 << 29; // the solids are only kept inside these
+pub const CSG_MASK: u32 = 3 << 29;
+pub const CSG_HAS_INTERSECT: u32 = 1 << 31; // on every CSG triangle if any mesh is CSG_INTERSECT, as the kernels can't tell otherwise
+
+// Shapes of signed distance fields, centered on the bounds of their corners. The first corner's
+// normal holds the shape and up to three parameters, its tangent another two.
+pub const SDF_SPHERE: u32 = 0; // radius
+pub const SDF_BOX: u32 = 1; // half extents, then rounding of the edges
+pub const SDF_TORUS: u32 = 2; // around Y, radius of the ring and of the tube
+pub const SDF_CAPSULE: u32 = 3; // along Y, half the length of the segment and radius
+pub const SDF_GRID: u32 = 4; // resolution, then the index of the vertex its distances start at, 4 per vertex, X varying fastest
+
+// Light group of the skybox, the procedural sky and the lights taken from them. Emissive
+// materials never get this one.
+pub const ENVIRONMENT_LIGHT_GROUP: u32 = 1 << 31;
+
+pub fn triangle_material_index(triangle: UVec4) -> u32 {
+    triangle.w & MATERIAL_INDEX_MASK
+}
+
+pub fn is_point(triangle: UVec4) -> bool {
+    triangle.w & PRIMITIVE_POINT != 0
+}
+
+pub fn is_sdf(triangle: UVec4) -> bool {
+    triangle.w & PRIMITIVE_SDF != 0
+}
+
+pub fn csg_operation(triangle: UVec4) -> u32 {
+    triangle.w & CSG_MASK
+}
+
+// Whether a point is inside the result, given how many solids, cutters and intersectors it's inside of
+pub fn csg_inside(solids: i32, cutters: i32, intersectors: i32, has_intersect: bool) -> bool {
+    solids > 0 && cutters <= 0 && (intersectors > 0 || !has_intersect)
+}
+
+// Three corners whose bounds are the given box, and which don't lie on a line
+pub fn box_corners(min: Vec3, max: Vec3) -> [Vec3; 3] {
+    [min, Vec3::new(max.x, max.y, min.z), Vec3::new(max.x, min.y, max.z)]
+}
+
+// Points are stored as triangles whose corners span the bounding box of the point's sphere, so
+// everything built for triangles, like the BVH, bounds them without knowing about points. The
+// kernels intersect them as a disc through the center, facing the ray.
+pub fn point_corners(center: Vec3, radius: f32) -> [Vec3; 3] {
+    box_corners(center - radius, center + radius)
+}
+
+// Center and radius of a point from its corners
+pub fn point_sphere(a: Vec3, b: Vec3, c: Vec3) -> (Vec3, f32) {
+    let min = a.min(b).min(c);
+    let max = a.max(b).max(c);
+    ((min + max) * 0.5, (max.x - min.x) * 0.5)
+}
+
+// Branches the path tracing kernels are specialized on. Every combination is compiled into its
+// own entry point, so threads don't step around features the render doesn't use.
+pub const FEATURE_NEE_MASK: u32 = 0b11; // a NextEventEstimation
+pub const FEATURE_SKYBOX_IMAGE: u32 = 1 << 2; // the procedural sky otherwise
+pub const FEATURE_NORMAL_MAPS: u32 = 1 << 3;
+
+pub fn kernel_features(config: &TracingConfig, normal_maps: bool) -> u32 {
+    let mut features = config.render.nee & FEATURE_NEE_MASK;
+    if config.environment.has_skybox != 0 {
+        features |= FEATURE_SKYBOX_IMAGE;
+    }
+    if normal_maps {
+        features |= FEATURE_NORMAL_MAPS;
+    }
+    features
+}
+
+// Where the camera ray of a pixel first hit the scene, written by the primary ray kernel
+// and picked up by the path tracing kernels. One per pixel, for the latest sample.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable, Default)]
+pub struct FirstHit {
+    pub t: f32,
+    pub triangle_index: u32,
+    flags: u32, // bit 0 = hit, bit 1 = backface, bit 2 = section, the fill of a cut
+    _padding: u32,
+}
+
+impl FirstHit {
+    pub fn new(t: f32, triangle_index: u32, hit: bool, backface: bool, section: bool) -> Self {
+        Self {
+            t,
+            triangle_index,
+            flags: hit as u32 | ((backface as u32) << 1) | ((section as u32) << 2),
+            _padding: 0,
+        }
+    }
+
+    pub fn hit(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    pub fn backface(&self) -> bool {
+        self.flags & 2 != 0
+    }
+
+    pub fn section(&self) -> bool {
+        self.flags & 4 != 0
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Pod, Zeroable)]
 pub struct BVHNode {
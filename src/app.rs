@@ -1,7 +1,6 @@
 
-use std::num::NonZeroU32;
 use std::sync::atomic::Ordering;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::{iter, sync::Arc};
 use std::fmt::Debug;
 
@@ -10,37 +9,215 @@ use egui_winit_platform::Platform;
 use wgpu::util::DeviceExt;
 use winit::dpi::PhysicalSize;
 
-use glam::{Mat3, Vec3};
-use shared_structs::NextEventEstimation;
-
-use crate::trace::{trace_cpu, trace_gpu, TracingState};
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat3, Vec2, Vec3, Vec4};
+use kernels::PathEvent;
+use shared_structs::{NextEventEstimation, HIDDEN_FROM_CAMERA, HIDDEN_FROM_INDIRECT, HIDDEN_FROM_SHADOWS, MAX_SECTION_PLANES, PROJECTION_PERSPECTIVE};
+
+use crate::browser::{RecentFiles, SceneBrowser};
+use crate::ground::{GroundSettings, GroundShape};
+use crate::import::{find_conflict, route_files, ImportKind};
+use crate::layout::{Dock, Layout, Panel};
+use crate::logging;
+use crate::path_debug::{event_name, sampled_lobe};
+use crate::power::PowerMonitor;
+use crate::shortcuts::{Action, Shortcuts};
+use crate::settings::Settings;
+use crate::reference::{load_reference_image, mean_squared_error, structural_similarity};
+use crate::batch::{HeadlessScene, RenderProgress};
+use crate::contact_sheet::{ContactSheet, SweepAxis};
+use crate::material_preview::MaterialPreviews;
+use crate::export::{encode_preview, ExportSettings, ImageFormat, RenderMetadata};
+use crate::scene::CsgOperation;
+use crate::scene_file::{is_scene_file, SceneFile, SectionDescription};
+use crate::solar::SolarDescription;
+use crate::tonemap::Tonemapping;
+use crate::turntable::{Turntable, VideoFormat};
+use crate::probe::ProbeBake;
+use crate::lightmap::LightmapBake;
+use crate::surface_maps::SurfaceMapBake;
+use crate::vertex_bake::{VertexBake, VertexBakeKind};
+use crate::trace::{trace_cpu, trace_gpu_with_cpu_fallback, DirtyFlags, Tile, TracingState, GPU_LIMITS};
+use crate::variance::pixel_relative_error;
 
 #[repr(u32)]
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
-enum Tonemapping {
+enum DiffMode {
     None,
-    Reinhard,
-    ACESNarkowicz,
-    ACESNarkowiczOverexposed,
-    ACESHill,
-    Neutral,
-    Uncharted,
+    Absolute,
+    Relative,
 }
 
-impl Debug for Tonemapping {
+impl Debug for DiffMode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Tonemapping::None => write!(f, "None"),
-            Tonemapping::Reinhard => write!(f, "Reinhard"),
-            Tonemapping::ACESNarkowicz => write!(f, "ACES (N)"),
-            Tonemapping::ACESNarkowiczOverexposed => write!(f, "ACES (N, O)"),
-            Tonemapping::ACESHill => write!(f, "ACES (H)"),
-            Tonemapping::Neutral => write!(f, "Neutral"),
-            Tonemapping::Uncharted => write!(f, "Uncharted"),
+            DiffMode::None => write!(f, "None"),
+            DiffMode::Absolute => write!(f, "Absolute"),
+            DiffMode::Relative => write!(f, "Relative"),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct DisplayUniforms {
+    width: u32,
+    height: u32,
+    tonemapping: u32,
+    diff_mode: u32,
+    diff_gain: f32,
+    packed: u32,
+    encode_srgb: u32,
+    hdr_output: u32,
+    hdr_white: f32, // Relative to the 80 nit scRGB reference white
+    hdr_peak: f32,
+    _padding: [u32; 2],
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum CaptureInterval {
+    Samples,
+    Seconds,
+}
+
+// Saves the render into a numbered image sequence at a fixed interval, for time-lapses of convergence
+struct Timelapse {
+    enabled: bool,
+    unit: CaptureInterval,
+    interval: u32,
+    directory: Option<String>,
+    next_frame: u32,
+    last_samples: u32,
+    last_capture: Instant,
+}
+
+impl Default for Timelapse {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            unit: CaptureInterval::Samples,
+            interval: 16,
+            directory: None,
+            next_frame: 0,
+            last_samples: 0,
+            last_capture: Instant::now(),
+        }
+    }
+}
+
+// A sequence of headless renders running in the background, such as a contact sheet
+struct BackgroundRender<T> {
+    name: &'static str,
+    progress: Arc<RenderProgress>,
+    total: u32, // Renders in the sequence
+    handle: std::thread::JoinHandle<Result<T, String>>,
+}
+
+impl<T: Send + 'static> BackgroundRender<T> {
+    fn spawn(name: &'static str, total: u32, work: impl FnOnce(&RenderProgress) -> Result<T, String> + Send + 'static) -> Self {
+        let progress = Arc::new(RenderProgress::default());
+        let handle = {
+            let progress = progress.clone();
+            std::thread::spawn(move || work(&progress))
+        };
+        Self { name, progress, total, handle }
+    }
+
+    // Takes the result out once the work is done. Failures are logged, unless it was cancelled.
+    fn poll(slot: &mut Option<Self>) -> Option<T> {
+        if !slot.as_ref().map_or(false, |job| job.handle.is_finished()) {
+            return None;
+        }
+        let job = slot.take()?;
+        match job.handle.join() {
+            Ok(Ok(result)) => Some(result),
+            Ok(Err(err)) => {
+                if !job.progress.is_cancelled() {
+                    tracing::error!("Failed to render {}: {}", job.name, err);
+                }
+                None
+            }
+            Err(_) => {
+                tracing::error!("Rendering {} panicked.", job.name);
+                None
+            }
+        }
+    }
+
+    fn progress_ui(&self, ui: &mut egui::Ui) {
+        let finished = self.progress.finished();
+        let text = format!("Rendering {} {}/{}", self.name, finished, self.total);
+        ui.add(egui::ProgressBar::new(finished as f32 / self.total.max(1) as f32).text(text).desired_width(200.0));
+        if ui.button("Cancel").clicked() {
+            self.progress.cancel();
         }
     }
 }
 
+// Edited in the GUI, and handed to turntable::Turntable when rendering
+#[derive(Copy, Clone)]
+struct TurntableSettings {
+    frames: u32,
+    radius: f32, // 0 fits the scene
+    samples: u32, // Per frame
+    format: VideoFormat,
+}
+
+#[derive(Copy, Clone)]
+struct ConvergenceSample {
+    samples: u32,
+    mse: f32,
+    ssim: f32,
+}
+
+fn format_bytes(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    }
+}
+
+// Largest rect with the given aspect ratio that fits inside `rect`, centered
+fn fit_to_aspect(rect: egui::Rect, aspect: f32) -> egui::Rect {
+    let size = if rect.width() > rect.height() * aspect {
+        egui::vec2(rect.height() * aspect, rect.height())
+    } else {
+        egui::vec2(rect.width(), rect.width() / aspect)
+    };
+    egui::Rect::from_center_size(rect.center(), size)
+}
+
+// Point of a section plane closest to `point`, where its gizmo is drawn
+fn section_anchor(plane: Vec4, point: Vec3) -> Vec3 {
+    point - plane.truncate() * (plane.truncate().dot(point) + plane.w)
+}
+
+const HDR_SURFACE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// Mean absolute deviations above a pixel's mean at which samples are clamped, once rejection is turned on
+const DEFAULT_FIREFLY_REJECTION: f32 = 8.0;
+const EXPORT_PREVIEW_SIZE: u32 = 256; // Longest side of the thumbnail in the save dialog
+const CONTACT_SHEET_CELL_WIDTH: u32 = 480; // Renders wider than this are shrunk to fit each cell
+const TURNTABLE_FPS: u32 = 30;
+const PROBE_SPECULAR_SIZE: u32 = 128; // Of the sharpest level of baked specular cubemaps
+const MATERIAL_THUMBNAIL_SIZE: f32 = 32.0; // Points, the previews are rendered at twice this for high DPI screens
+const FRAME_BUDGET_MS: u32 = 50; // Longest the viewport waits for a sync before showing what has been traced so far
+const SECTION_GIZMO_SCALE: f32 = 0.1; // Of the scene's diagonal, for the arrow and outline of section planes
+
+fn tonemapping_combo(ui: &mut egui::Ui, id: &str, tonemapping: &mut Tonemapping) {
+    egui::ComboBox::from_id_source(id)
+        .selected_text(format!("{:?}", tonemapping))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(tonemapping, Tonemapping::None, "None");
+            ui.selectable_value(tonemapping, Tonemapping::ACESNarkowicz, "ACES (Narkowicz)");
+            ui.selectable_value(tonemapping, Tonemapping::ACESNarkowiczOverexposed, "ACES (Narkowicz, overexposed)");
+            ui.selectable_value(tonemapping, Tonemapping::ACESHill, "ACES (Hill)");
+            ui.selectable_value(tonemapping, Tonemapping::Neutral, "Neutral");
+            ui.selectable_value(tonemapping, Tonemapping::Reinhard, "Reinhard");
+            ui.selectable_value(tonemapping, Tonemapping::Uncharted, "Uncharted");
+        });
+}
+
 fn is_image(img: &str) -> bool {
     img.ends_with(".png")
     || img.ends_with(".jpg")
@@ -59,14 +236,66 @@ pub struct App {
     tonemapping: Tonemapping,
     selected_scene: String,
     selected_skybox: Option<String>,
-    show_environment_window: bool,
+    selected_reference: Option<String>,
+    reference: Option<Vec<f32>>,
+    diff_mode: DiffMode,
+    diff_gain: f32,
+    convergence: Vec<ConvergenceSample>,
+    convergence_log_scale: bool,
+    show_noise: bool, // Display the relative error of each pixel in place of the render
+    layout: Layout,
+    shortcuts: Shortcuts,
+    command_palette: Option<String>, // Search text, while the palette is open
+    dropped_files: Vec<String>,
+    pending_import: Option<Vec<(String, ImportKind)>>, // Files waiting for the user to confirm what they are
+    recent: RecentFiles,
+    timelapse: Timelapse,
+    export_settings: Option<ExportSettings>, // Remembered between saves, seeded from the viewport the first time
+    export_dialog_open: bool,
+    export_preview: Option<(egui::TextureHandle, ExportSettings, u32)>, // Along with the settings and sample count it shows
+    contact_sheet: (SweepAxis, SweepAxis, u32), // Rows, columns and samples of the next contact sheet
+    contact_sheet_job: Option<BackgroundRender<(Vec<u8>, u32, u32)>>,
+    turntable: TurntableSettings,
+    turntable_job: Option<BackgroundRender<()>>,
+    probe: (u32, u32), // Panorama height and samples
+    probe_job: Option<BackgroundRender<(std::path::PathBuf, std::path::PathBuf)>>,
+    lightmap: (u32, u32), // Resolution and samples per texel
+    lightmap_job: Option<BackgroundRender<Vec<std::path::PathBuf>>>,
+    surface_maps: (u32, u32, f32), // Resolution, occlusion samples per texel, and occlusion distance, 0 to fit the scene
+    surface_maps_job: Option<BackgroundRender<Vec<std::path::PathBuf>>>,
+    vertex_bake: (VertexBakeKind, u32, f32), // What to bake, rays per vertex, and occlusion distance, 0 to fit the scene
+    vertex_bake_job: Option<BackgroundRender<std::path::PathBuf>>,
+    ground: GroundSettings, // Edited here, and handed to the render thread when applied
+    frame_on_load: bool,
+    scene_scale: f32, // Edited here, and handed to the render thread when applied
+    environment_clamp: bool,
+    environment_clamp_threshold: f32,
+    solar: Option<SolarDescription>, // Places the sun by time and place while set
+    scene_browser: SceneBrowser,
+    material_previews: MaterialPreviews,
+    draw_debug_path: bool,
+    denoise_region_start: Option<glam::UVec2>, // Pixel an Alt-drag started on
+    section_drag: Option<usize>, // Section plane whose arrow is being dragged in the viewport
+    seen_error_count: u32,
+    uploaded_frame: Option<(bool, bool, u64)>, // Whether it was packed, whether it was the noise, and its generation
     last_input: Instant,
     mouse_delta: (f32, f32),
+    power: PowerMonitor,
+    pause_when_minimized: bool,
+    pause_when_unfocused: bool, // Off by default, as long renders are often left running behind other windows
+    low_power_on_battery: bool, // Halve the render load while running off a battery
+    focused: bool,
+    minimized: bool,
 
     device: wgpu::Device,
     queue: wgpu::Queue,
     window: winit::window::Window,
     surface_format: wgpu::TextureFormat,
+    hdr_supported: bool,
+    hdr_output: bool,
+    hdr_requested: bool, // Takes effect on the next launch, since the surface and GUI renderer depend on the format
+    hdr_white_nits: f32,
+    hdr_peak_nits: f32,
     surface_config: wgpu::SurfaceConfiguration,
     surface: wgpu::Surface,
     egui_renderer: egui_wgpu::renderer::Renderer,
@@ -94,7 +323,18 @@ impl App {
         .expect("Failed to creator wgpu device.");
 
         let size = window.inner_size();
-        let surface_format = surface.get_supported_formats(&adapter)[0];
+        // Prefer an sRGB surface, so the hardware encodes the output. Otherwise the display pass does it.
+        // A linear half float surface is only presented as scRGB by DX12, other backends treat it as
+        // ordinary sRGB content, so HDR output is only offered there.
+        let surface_formats = surface.get_supported_formats(&adapter);
+        let hdr_supported = adapter.get_info().backend == wgpu::Backend::Dx12 && surface_formats.contains(&HDR_SURFACE_FORMAT);
+        let hdr_output = hdr_supported && Settings::load().hdr_output;
+        let surface_format = if hdr_output {
+            HDR_SURFACE_FORMAT
+        } else {
+            surface_formats.iter().copied().find(|format| format.describe().srgb).unwrap_or(surface_formats[0])
+        };
+        tracing::debug!("Using surface format {:?}.", surface_format);
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
@@ -107,23 +347,77 @@ impl App {
 
         let egui_renderer = egui_wgpu::renderer::Renderer::new(&device, surface_format, None, 1);
         let tracing_state = Arc::new(TracingState::new(size.width, size.height));
+        tracing_state.async_textures.store(true, Ordering::Relaxed);
+        tracing_state.frame_budget_ms.store(FRAME_BUDGET_MS, Ordering::Relaxed);
         Self {
             tracing_state,
             last_input: Instant::now(),
             mouse_delta: (0.0, 0.0),
+            power: PowerMonitor::new(),
+            pause_when_minimized: true,
+            pause_when_unfocused: false,
+            low_power_on_battery: true,
+            focused: true,
+            minimized: false,
             device,
             queue,
             window,
             surface_config,
             surface,
             surface_format,
+            hdr_supported,
+            hdr_output,
+            hdr_requested: hdr_output,
+            hdr_white_nits: 203.0,
+            hdr_peak_nits: 1000.0,
             egui_renderer,
             compute_join_handle: None,
             selected_scene: "scene.glb".to_string(),
             selected_skybox: None,
+            selected_reference: None,
+            reference: None,
+            diff_mode: DiffMode::None,
+            diff_gain: 1.0,
+            convergence: Vec::new(),
+            convergence_log_scale: true,
+            show_noise: false,
             tonemapping: Tonemapping::None,
             use_cpu: false,
-            show_environment_window: false,
+            layout: Layout::load(),
+            shortcuts: Shortcuts::load(),
+            command_palette: None,
+            dropped_files: Vec::new(),
+            pending_import: None,
+            recent: RecentFiles::load(),
+            timelapse: Timelapse::default(),
+            export_settings: None,
+            export_dialog_open: false,
+            export_preview: None,
+            contact_sheet: (SweepAxis::NextEventEstimation, SweepAxis::Tonemapping, 64),
+            contact_sheet_job: None,
+            turntable: TurntableSettings { frames: 120, radius: 0.0, samples: 64, format: VideoFormat::Gif },
+            turntable_job: None,
+            probe: (512, 256),
+            probe_job: None,
+            lightmap: (512, 256),
+            lightmap_job: None,
+            surface_maps: (1024, 64, 0.0),
+            surface_maps_job: None,
+            vertex_bake: (VertexBakeKind::AmbientOcclusion, 256, 0.0),
+            vertex_bake_job: None,
+            ground: GroundSettings::default(),
+            frame_on_load: true,
+            scene_scale: 1.0,
+            environment_clamp: false,
+            environment_clamp_threshold: 100.0,
+            solar: None,
+            scene_browser: SceneBrowser::scan(),
+            material_previews: MaterialPreviews::default(),
+            draw_debug_path: true,
+            denoise_region_start: None,
+            section_drag: None,
+            seen_error_count: 0,
+            uploaded_frame: None,
         }
     }
 
@@ -142,11 +436,16 @@ impl App {
         if !continue_previous {
             let (config, framebuffer) = TracingState::make_view_dependent_state(size.width, size.height, Some(*self.tracing_state.config.read()));
             *self.tracing_state.config.write() = config;
-            *self.tracing_state.framebuffer.write() = framebuffer;
+            self.tracing_state.framebuffer.reset(framebuffer);
+            self.tracing_state.packed_framebuffer.reset(Vec::new());
+            self.tracing_state.variance.reset(Vec::new());
             self.tracing_state.samples.store(0, Ordering::Relaxed);
+            *self.tracing_state.render_time.lock() = Duration::ZERO;
 
             let render_resources = PaintCallbackResources::new(&self.device, self.surface_format, size.width, size.height);
             self.egui_renderer.paint_callback_resources.insert(render_resources);
+            self.uploaded_frame = None;
+            self.load_reference();
         }
         self.tracing_state.running.store(true, Ordering::Relaxed);
         let tracing_state = self.tracing_state.clone();
@@ -159,7 +458,7 @@ impl App {
             if use_cpu {
                 trace_cpu(&path, skybox_path_ref, tracing_state);
             } else {
-                trace_gpu(&path, skybox_path_ref, tracing_state);
+                trace_gpu_with_cpu_fallback(&path, skybox_path_ref, tracing_state);
             }
         }));
     }
@@ -169,7 +468,9 @@ impl App {
         self.tracing_state.running.store(false, Ordering::Relaxed);
 
         if let Some(handle) = self.compute_join_handle.take() {
-            handle.join().expect("Render thread died.");
+            if handle.join().is_err() {
+                tracing::error!("Render thread died unexpectedly.");
+            }
             self.compute_join_handle = None;
         }
     }
@@ -182,258 +483,2321 @@ impl App {
 
     fn set_skybox(&mut self, skybox: &str) {
         self.selected_skybox = Some(skybox.to_string());
-        self.tracing_state.config.write().has_skybox = 1;
+        self.recent.add_skybox(skybox);
+        self.tracing_state.config.write().environment.has_skybox = 1;
         self.restart_current_render(false);
     }
 
     fn clear_skybox(&mut self) {
         self.selected_skybox = None;
-        self.tracing_state.config.write().has_skybox = 0;
+        self.tracing_state.config.write().environment.has_skybox = 0;
         self.restart_current_render(false);
     }
 
+    fn load_reference(&mut self) {
+        let width = self.tracing_state.config.read().render.width;
+        let height = self.tracing_state.config.read().render.height;
+        self.reference = self.selected_reference.as_ref().and_then(|path| load_reference_image(path, width, height));
+        self.convergence.clear();
+        if let Some(resources) = self.egui_renderer.paint_callback_resources.get::<PaintCallbackResources>() {
+            resources.set_reference(&self.queue, self.reference.as_deref(), width, height);
+        }
+    }
+
+    fn set_reference(&mut self, reference: &str) {
+        self.selected_reference = Some(reference.to_string());
+        self.load_reference();
+        if self.diff_mode == DiffMode::None {
+            self.diff_mode = DiffMode::Absolute;
+        }
+    }
+
+    fn clear_reference(&mut self) {
+        self.selected_reference = None;
+        self.diff_mode = DiffMode::None;
+        self.load_reference();
+    }
+
+    fn record_convergence(&mut self, width: u32, height: u32) {
+        let Some(reference) = self.reference.as_ref() else {
+            return;
+        };
+        let framebuffer = self.tracing_state.framebuffer.read();
+
+        // Restarting the render resets the sample count, so start a new recording
+        let samples = self.tracing_state.samples.load(Ordering::Relaxed);
+        let last_samples = self.convergence.last().map_or(0, |s| s.samples);
+        if samples < last_samples {
+            self.convergence.clear();
+        }
+        if samples == 0 || samples == last_samples || reference.len() != framebuffer.len() {
+            return;
+        }
+
+        self.convergence.push(ConvergenceSample {
+            samples,
+            mse: mean_squared_error(&framebuffer, reference),
+            ssim: structural_similarity(&framebuffer, reference, width, height),
+        });
+    }
+
+    fn export_convergence_csv(&self) {
+        if let Some(path) = tinyfiledialogs::save_file_dialog("Export convergence data", "convergence.csv") {
+            let mut csv = String::from("samples,mse,ssim\n");
+            for sample in self.convergence.iter() {
+                csv.push_str(&format!("{},{},{}\n", sample.samples, sample.mse, sample.ssim));
+            }
+            let res = std::fs::write(path, csv);
+            if let Err(err) = res {
+                tracing::error!("Failed to export convergence data: {}", err);
+            }
+        }
+    }
+
     fn set_scene(&mut self, scene: &str) {
+        // Stop first, so the old render thread can't publish anything about the old scene after this
+        self.stop_render();
         self.selected_scene = scene.to_string();
+        // Emission edits only apply to the scene they were made in
+        self.tracing_state.materials.write().clear();
+        *self.tracing_state.scene_statistics.write() = None;
+        *self.tracing_state.debug_path.write() = None;
+        self.frame_on_load = true;
+        self.tracing_state.sample_limit.store(0, Ordering::Relaxed);
+        if is_scene_file(scene) {
+            self.apply_scene_file(scene);
+        }
+        self.recent.add_scene(scene);
         self.start_render(false);
     }
 
-    fn on_gui(&mut self, egui_ctx: &egui::Context) {
-        self.on_settings_gui(egui_ctx);
-        self.on_environment_gui(egui_ctx);
+    // Scene files bring their own camera, environment and render settings along with the scene
+    fn apply_scene_file(&mut self, path: &str) {
+        let Some(file) = SceneFile::load(path) else {
+            return;
+        };
+        file.apply_settings(&mut self.tracing_state.config.write());
+        self.frame_on_load = file.camera.is_none();
+        self.selected_skybox = file.skybox();
+        self.environment_clamp = file.environment.clamp.is_some();
+        if let Some(clamp) = file.environment.clamp {
+            self.environment_clamp_threshold = clamp;
+        }
+        *self.tracing_state.environment_clamp.write() = file.environment.clamp;
+        self.solar = file.environment.solar;
+        self.tracing_state.sample_limit.store(file.render.samples.unwrap_or(0), Ordering::Relaxed);
     }
 
-    fn on_settings_gui(&mut self, egui_ctx: &egui::Context) {
-        egui::Window::new("Settings").show(egui_ctx, |ui| {
-            egui::Grid::new("MainGrid")
-            .striped(true)
-            .show(ui, |ui| {
-                ui.vertical(|ui| {
-                    ui.label(format!("Selected scene: {}", self.selected_scene));
-                    ui.horizontal(|ui| {
-                        if self.compute_join_handle.as_ref().map_or(false, |t| !t.is_finished()) {
-                            if ui.button("Stop").clicked() {
-                                self.stop_render();
-                            }
-                        } else {
-                            if ui.button("Start").clicked() {
-                                self.start_render(false);
-                            }
-                        }
+    // For changes to how the scene is imported. The camera is framed again, since the scene changes size.
+    fn reload_scene(&mut self) {
+        let rendering = self.compute_join_handle.is_some();
+        self.stop_render();
+        *self.tracing_state.scene_statistics.write() = None;
+        *self.tracing_state.debug_path.write() = None;
+        self.frame_on_load = true;
+        if rendering {
+            self.start_render(false);
+        }
+    }
 
-                        if ui.button("Select scene").clicked() {
-                            if let Some(path) = tinyfiledialogs::open_file_dialog("Select scene", "", None) {
-                                if is_image(&path) {
-                                    self.set_skybox(&path);
-                                } else {
-                                    self.set_scene(&path);
-                                }
-                            }
-                        }
+    // Center of the scene, and how far away the camera has to be to fit all of it in view. The
+    // ground is left out, or the model would be a speck in the middle of it.
+    fn scene_framing(&self) -> Option<(Vec3, f32)> {
+        let (min, max) = self.tracing_state.scene_statistics.read().as_ref().map(|statistics| statistics.model_bounds)?;
+        let config = self.tracing_state.config.read();
+        let center = (min + max) / 2.0;
+        let radius = ((max - min).length() / 2.0).max(1e-3);
+
+        // Rays are cast through a plane at distance 1 spanning -1 to 1 horizontally, see the kernel
+        let half_fov = (config.render.height as f32 / config.render.width as f32).min(1.0).atan();
+        Some((center, radius / half_fov.sin()))
+    }
 
-                        if ui.button("Save image").clicked() {
-                            if let Some(resources) = self.egui_renderer.paint_callback_resources.get::<PaintCallbackResources>() {
-                                let width = self.tracing_state.config.read().width;
-                                let height = self.tracing_state.config.read().height;
-                                resources.save_render(width, height, self.surface_format, &self.device, &self.queue);
-                            }
-                        }
+    // Moves the camera back along its view direction until the scene's bounding sphere fits in view
+    fn frame_scene(&mut self) {
+        let Some((center, distance)) = self.scene_framing() else {
+            return;
+        };
+        let mut config = self.tracing_state.config.write();
+        let forward = Mat3::from_rotation_y(config.camera.cam_rotation.y) * Mat3::from_rotation_x(config.camera.cam_rotation.x) * Vec3::Z;
+        config.camera.cam_position = (center - forward * distance).extend(config.camera.cam_position.w);
+        self.tracing_state.mark_dirty(DirtyFlags::CAMERA);
+    }
+
+    // Newly loaded scenes are framed from the front, looking slightly down
+    fn frame_loaded_scene(&mut self) {
+        if !self.frame_on_load || self.tracing_state.scene_statistics.read().is_none() {
+            return;
+        }
+        self.frame_on_load = false;
+        self.tracing_state.config.write().camera.cam_rotation = Vec4::new(0.3, 0.0, 0.0, 0.0);
+        self.frame_scene();
+    }
+
+    fn is_rendering(&self) -> bool {
+        self.compute_join_handle.as_ref().map_or(false, |t| !t.is_finished())
+    }
+
+    fn toggle_render(&mut self) {
+        if self.is_rendering() {
+            self.stop_render();
+        } else {
+            self.start_render(false);
+        }
+    }
+
+    fn select_scene(&mut self) {
+        if let Some(path) = tinyfiledialogs::open_file_dialog("Select scene", "", None) {
+            if is_image(&path) {
+                self.set_skybox(&path);
+            } else {
+                self.set_scene(&path);
+            }
+        }
+    }
+
+    fn select_skybox(&mut self) {
+        if let Some(path) = tinyfiledialogs::open_file_dialog("Select skybox", "", None) {
+            self.set_skybox(&path);
+        }
+    }
+
+    fn select_reference(&mut self) {
+        if let Some(path) = tinyfiledialogs::open_file_dialog("Select reference", "", None) {
+            self.set_reference(&path);
+        }
+    }
+
+    fn render_metadata(&self, settings: &ExportSettings) -> RenderMetadata {
+        let config = self.tracing_state.config.read();
+        let nee = NextEventEstimation::from_u32(config.render.nee);
+        RenderMetadata {
+            entries: vec![
+                ("Software", format!("rust-path-tracer {}", env!("CARGO_PKG_VERSION"))),
+                ("Scene", self.selected_scene.clone()),
+                ("Skybox", self.selected_skybox.clone().unwrap_or_else(|| "Procedural".to_string())),
+                ("Samples", self.tracing_state.samples.load(Ordering::Relaxed).to_string()),
+                ("Render time", format!("{:.1} s", self.tracing_state.render_time.lock().as_secs_f32())),
+                ("Resolution", format!("{}x{}", config.render.width, config.render.height)),
+                ("Device", if self.use_cpu { "CPU" } else { "GPU" }.to_string()),
+                ("Bounces", format!("{}-{}", config.render.min_bounces, config.render.max_bounces)),
+                ("Russian roulette", if config.render.adaptive_roulette != 0 { "Adaptive" } else { "Fixed" }.to_string()),
+                ("Path splits", config.render.path_splits.to_string()),
+                ("Glossy cone limit", config.render.glossy_cone_limit.to_string()),
+                ("Firefly rejection", config.render.firefly_rejection.to_string()),
+                ("Next event estimation", format!("{:?}", nee)),
+                ("Tonemapping", format!("{:?}", settings.tonemapping)),
+                ("Exposure", format!("{:+} EV", settings.exposure)),
+                ("Seed", config.render.seed.to_string()),
+            ],
+        }
+    }
+
+    // Exports the linear framebuffer directly, so the result doesn't depend on the surface format
+    fn export_image(&self, path: &std::path::Path, settings: &ExportSettings) -> Result<(), String> {
+        let width = self.tracing_state.config.read().render.width;
+        let height = self.tracing_state.config.read().render.height;
+        let metadata = self.render_metadata(settings);
+        let framebuffer = self.tracing_state.framebuffer.read();
+        crate::export::save_image(path, &framebuffer, width, height, settings, &metadata)
+    }
+
+    fn save_image(&mut self) {
+        self.export_dialog_open = true;
+    }
+
+    fn save_image_as(&self, settings: &ExportSettings) {
+        let (filter, description): (&[&str], _) = match settings.format {
+            ImageFormat::Png => (&["*.png"], "PNG image"),
+            ImageFormat::Jpeg => (&["*.jpg", "*.jpeg"], "JPEG image"),
+        };
+        let default_path = format!("render.{}", settings.format.extension());
+        let Some(path) = tinyfiledialogs::save_file_dialog_with_filter("Save render", &default_path, filter, description) else {
+            return;
+        };
+        let mut path = std::path::PathBuf::from(path);
+        if ImageFormat::from_path(&path) != settings.format {
+            path.set_extension(settings.format.extension());
+        }
+        if let Err(err) = self.export_image(&path, settings) {
+            tracing::error!("Failed to save image '{}': {}", path.display(), err);
+        }
+    }
+
+    // Re-encodes the preview when the settings change, or the render has gained samples since
+    fn update_export_preview(&mut self, egui_ctx: &egui::Context, settings: &ExportSettings) -> Option<egui::TextureHandle> {
+        let samples = self.tracing_state.samples.load(Ordering::Relaxed);
+        let stale = match self.export_preview.as_ref() {
+            Some((_, preview_settings, preview_samples)) => preview_settings != settings || *preview_samples != samples,
+            None => true,
+        };
+        if stale {
+            let width = self.tracing_state.config.read().render.width;
+            let height = self.tracing_state.config.read().render.height;
+            let framebuffer = self.tracing_state.framebuffer.read();
+            let (pixels, preview_width, preview_height) = encode_preview(&framebuffer, width, height, EXPORT_PREVIEW_SIZE, settings.tonemapping, settings.exposure);
+            drop(framebuffer);
+            if pixels.is_empty() {
+                self.export_preview = None;
+            } else {
+                let image = egui::ColorImage::from_rgb([preview_width as usize, preview_height as usize], &pixels);
+                let texture = egui_ctx.load_texture("ExportPreview", image, egui::TextureOptions::LINEAR);
+                self.export_preview = Some((texture, *settings, samples));
+            }
+        }
+        self.export_preview.as_ref().map(|(texture, _, _)| texture.clone())
+    }
+
+    fn show_export_dialog(&mut self, egui_ctx: &egui::Context) {
+        if !self.export_dialog_open {
+            self.export_preview = None;
+            return;
+        }
+        let mut settings = self.export_settings.unwrap_or(ExportSettings {
+            tonemapping: self.tonemapping,
+            exposure: 0.0,
+            format: ImageFormat::Png,
+            jpeg_quality: 90,
+            footer: false,
+        });
+        let preview = self.update_export_preview(egui_ctx, &settings);
+
+        let mut save = false;
+        let mut cancel = false;
+        egui::Window::new("Save image")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(egui_ctx, |ui| {
+                match preview {
+                    Some(texture) => {
+                        ui.image(texture.id(), texture.size_vec2());
+                    }
+                    None => {
+                        ui.weak("Nothing has been rendered yet.");
+                    }
+                }
+                egui::Grid::new("ExportGrid")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Tonemapping");
+                    tonemapping_combo(ui, "ExportTonemapping", &mut settings.tonemapping);
+                    ui.end_row();
+
+                    ui.label("Exposure");
+                    ui.add(egui::Slider::new(&mut settings.exposure, -5.0..=5.0).step_by(0.1).suffix(" EV"));
+                    ui.end_row();
+
+                    ui.label("Format");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut settings.format, ImageFormat::Png, "PNG");
+                        ui.radio_value(&mut settings.format, ImageFormat::Jpeg, "JPEG");
                     });
+                    ui.end_row();
+
+                    ui.label("JPEG quality");
+                    ui.add_enabled(settings.format == ImageFormat::Jpeg, egui::Slider::new(&mut settings.jpeg_quality, 1..=100))
+                        .on_hover_text("PNG is always lossless.");
+                    ui.end_row();
+
+                    ui.label("Footer");
+                    ui.checkbox(&mut settings.footer, "Burn in render settings")
+                        .on_hover_text("Add a strip below the image with the scene, sample count, render time and settings.");
+                    ui.end_row();
                 });
-                ui.end_row();
-                
+                ui.weak("These only apply to the saved image, the viewport keeps its own settings.");
                 ui.horizontal(|ui| {
-                    #[cfg(feature = "oidn")]
-                    {
-                        let mut denoise_checked = self.tracing_state.denoise.load(Ordering::Relaxed);
-                        if ui.checkbox(&mut denoise_checked, "Denoise").changed() {
-                            self.tracing_state.denoise.store(denoise_checked, Ordering::Relaxed);
-                        }
-                    }
-    
-                    let mut use_blue_noise = self.tracing_state.use_blue_noise.load(Ordering::Relaxed);
-                    if ui.checkbox(&mut use_blue_noise, "Use blue noise").changed() {
-                        self.tracing_state.use_blue_noise.store(use_blue_noise, Ordering::Relaxed);
-                        self.tracing_state.dirty.store(true, Ordering::Relaxed);
+                    save = ui.add_enabled(self.export_preview.is_some(), egui::Button::new("Save...")).clicked();
+                    cancel = ui.button("Cancel").clicked() || ui.input().key_pressed(egui::Key::Escape);
+                });
+            });
+
+        self.export_settings = Some(settings);
+        if save {
+            self.save_image_as(&settings);
+        }
+        if save || cancel {
+            self.export_dialog_open = false;
+        }
+    }
+
+    fn save_motion_vectors(&self) {
+        let Some(path) = tinyfiledialogs::save_file_dialog_with_filter("Save motion vectors", "motion.exr", &["*.exr"], "OpenEXR image") else {
+            return;
+        };
+        let mut path = std::path::PathBuf::from(path);
+        path.set_extension("exr");
+        let width = self.tracing_state.config.read().render.width;
+        let height = self.tracing_state.config.read().render.height;
+        let motion_vectors = self.tracing_state.motion_vectors.read();
+        if let Err(err) = crate::export::save_motion_vectors(&path, &motion_vectors, width, height) {
+            tracing::error!("Failed to save motion vectors '{}': {}", path.display(), err);
+        }
+    }
+
+    fn save_variance(&self) {
+        let Some(path) = tinyfiledialogs::save_file_dialog_with_filter("Save variance", "variance.exr", &["*.exr"], "OpenEXR image") else {
+            return;
+        };
+        let mut path = std::path::PathBuf::from(path);
+        path.set_extension("exr");
+        let width = self.tracing_state.config.read().render.width;
+        let height = self.tracing_state.config.read().render.height;
+        let variance = self.tracing_state.variance.read();
+        if let Err(err) = crate::export::save_variance(&path, &variance, width, height) {
+            tracing::error!("Failed to save variance '{}': {}", path.display(), err);
+        }
+    }
+
+    // Saves the scene with the current camera, environment and render settings, to render it the same way later
+    fn save_scene_file(&self) {
+        let Some(path) = tinyfiledialogs::save_file_dialog_with_filter("Save scene file", "scene.ron", &["*.ron", "*.json"], "RON or JSON scene file") else {
+            return;
+        };
+        let mut file = if is_scene_file(&self.selected_scene) {
+            let Some(file) = SceneFile::load(&self.selected_scene) else {
+                return;
+            };
+            file
+        } else {
+            SceneFile {
+                scene: std::path::PathBuf::from(&self.selected_scene),
+                ..Default::default()
+            }
+        };
+        let scene_scale = *self.tracing_state.scene_scale.read();
+        if scene_scale != 1.0 {
+            file.scale = Some(file.scale.unwrap_or(1.0) * scene_scale);
+        }
+        let clamp = *self.tracing_state.environment_clamp.read();
+        file.capture_settings(&self.tracing_state.config.read(), self.selected_skybox.as_deref(), clamp);
+        file.environment.solar = self.solar;
+        if let Err(err) = file.save(&path) {
+            tracing::error!("Failed to save scene file '{}': {}", path, err);
+        }
+    }
+
+    fn capture_timelapse_frame(&mut self) {
+        let rendering = self.is_rendering();
+        let samples = self.tracing_state.samples.load(Ordering::Relaxed);
+        let timelapse = &mut self.timelapse;
+        if !timelapse.enabled || !rendering || samples == 0 {
+            return;
+        }
+        let Some(directory) = timelapse.directory.as_ref() else {
+            return;
+        };
+
+        // The render was restarted, so start counting samples from scratch
+        if samples < timelapse.last_samples {
+            timelapse.last_samples = 0;
+        }
+        let due = match timelapse.unit {
+            CaptureInterval::Samples => samples >= timelapse.last_samples + timelapse.interval,
+            CaptureInterval::Seconds => timelapse.last_capture.elapsed().as_secs() >= timelapse.interval as u64,
+        };
+        if !due || samples == timelapse.last_samples {
+            return;
+        }
+
+        // Frames look like the viewport, so the time-lapse matches what was watched
+        let path = std::path::Path::new(directory).join(format!("frame_{:05}.png", timelapse.next_frame));
+        let settings = ExportSettings { tonemapping: self.tonemapping, exposure: 0.0, format: ImageFormat::Png, jpeg_quality: 90, footer: false };
+        if let Err(err) = self.export_image(&path, &settings) {
+            tracing::error!("Failed to save time-lapse frame '{}': {}. Stopping capture.", path.display(), err);
+            self.timelapse.enabled = false;
+            return;
+        }
+        let timelapse = &mut self.timelapse;
+        timelapse.next_frame += 1;
+        timelapse.last_samples = samples;
+        timelapse.last_capture = Instant::now();
+    }
+
+    #[cfg(feature = "oidn")]
+    fn set_denoise(&mut self, denoise: bool) {
+        self.tracing_state.denoise.store(denoise, Ordering::Relaxed);
+        self.tracing_state.mark_dirty(DirtyFlags::DISPLAY);
+    }
+
+    fn set_use_cpu(&mut self, use_cpu: bool) {
+        self.use_cpu = use_cpu;
+        self.restart_current_render(true);
+    }
+
+    fn run_action(&mut self, action: Action) {
+        match action {
+            Action::CommandPalette => {
+                self.command_palette = match self.command_palette {
+                    Some(_) => None,
+                    None => Some(String::new()),
+                };
+            }
+            Action::ToggleRender => self.toggle_render(),
+            Action::SelectScene => self.select_scene(),
+            Action::SaveImage => self.save_image(),
+            #[cfg(feature = "oidn")]
+            Action::ToggleDenoise => self.set_denoise(!self.tracing_state.denoise.load(Ordering::Relaxed)),
+            Action::SwitchDevice => self.set_use_cpu(!self.use_cpu),
+            Action::SelectSkybox => self.select_skybox(),
+            Action::ResetSkybox => self.clear_skybox(),
+            Action::SelectReference => self.select_reference(),
+            Action::ClearReference => self.clear_reference(),
+            Action::FrameScene => self.frame_scene(),
+            Action::TogglePanel(panel) => self.layout.toggle(panel),
+        }
+    }
+
+    fn import_files(&mut self, files: &[(String, ImportKind)]) {
+        let mut scene = None;
+        let mut skybox = None;
+        for (path, kind) in files {
+            match kind {
+                ImportKind::Scene => scene = Some(path),
+                ImportKind::Skybox => skybox = Some(path),
+                ImportKind::Reference => self.set_reference(path),
+                ImportKind::Ignore => {}
+            }
+        }
+
+        // Loading a scene restarts the render anyway, so only restart once
+        if let Some(skybox) = skybox {
+            self.selected_skybox = Some(skybox.clone());
+            self.recent.add_skybox(skybox);
+            self.tracing_state.config.write().environment.has_skybox = 1;
+        }
+        if let Some(scene) = scene {
+            self.set_scene(scene);
+        } else if skybox.is_some() {
+            self.restart_current_render(false);
+        }
+    }
+
+    fn route_dropped_files(&mut self) {
+        if self.dropped_files.is_empty() {
+            return;
+        }
+        let paths = std::mem::take(&mut self.dropped_files);
+        let (kinds, ambiguous) = route_files(&paths);
+        let files = paths.into_iter().zip(kinds).collect::<Vec<_>>();
+
+        // Ask, rather than risk loading a file as the wrong thing
+        if ambiguous || self.pending_import.is_some() {
+            self.pending_import.get_or_insert_with(Vec::new).extend(files);
+        } else {
+            self.import_files(&files);
+        }
+    }
+
+    fn show_import_dialog(&mut self, egui_ctx: &egui::Context) {
+        let Some(mut files) = self.pending_import.take() else {
+            return;
+        };
+
+        let mut import = false;
+        let mut cancel = false;
+        egui::Window::new("Import files")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(egui_ctx, |ui| {
+                ui.label("Choose what to use each of the dropped files for.");
+                egui::Grid::new("ImportGrid")
+                .striped(true)
+                .show(ui, |ui| {
+                    for (index, (path, kind)) in files.iter_mut().enumerate() {
+                        let name = std::path::Path::new(path.as_str()).file_name().and_then(|n| n.to_str()).unwrap_or(path.as_str());
+                        ui.label(name).on_hover_text(path.as_str());
+                        egui::ComboBox::from_id_source(("ImportKind", index))
+                            .selected_text(format!("{:?}", kind))
+                            .show_ui(ui, |ui| {
+                                for option in ImportKind::ALL {
+                                    ui.selectable_value(kind, option, format!("{:?}", option));
+                                }
+                            });
+                        ui.end_row();
                     }
                 });
-                ui.end_row();
-    
+                ui.weak("Textures are loaded together with the scene that uses them.");
+
+                let kinds = files.iter().map(|(_, kind)| *kind).collect::<Vec<_>>();
+                let conflict = find_conflict(&kinds);
+                if let Some(conflict) = conflict.as_ref() {
+                    ui.colored_label(egui::Color32::LIGHT_RED, conflict.as_str());
+                }
                 ui.horizontal(|ui| {
-                    let mut config = self.tracing_state.config.write();
-                    if ui.add(egui::DragValue::new(&mut config.min_bounces)).changed() {
-                        if config.min_bounces > config.max_bounces {
-                            config.max_bounces = config.min_bounces;
-                        }
-                        self.tracing_state.dirty.store(true, Ordering::Relaxed);
-                    }
-                    ui.label("Min bounces");
-    
-                    if ui.add(egui::DragValue::new(&mut config.max_bounces)).changed() {
-                        if config.max_bounces < config.min_bounces {
-                            config.min_bounces = config.max_bounces;
-                        }
-                        self.tracing_state.dirty.store(true, Ordering::Relaxed);
-                    }
-                    ui.label("Max bounces");
+                    import = ui.add_enabled(conflict.is_none(), egui::Button::new("Import")).clicked();
+                    cancel = ui.button("Cancel").clicked();
                 });
-                ui.end_row();
+            });
 
-                let prev_nee_mode = NextEventEstimation::from_u32(self.tracing_state.config.read().nee);
-                let mut nee_mode = prev_nee_mode;
-                egui::ComboBox::from_label("Next event estimation")
-                    .selected_text(format!("{:?}", nee_mode))
-                    .show_ui(ui, |ui| {
-                        ui.selectable_value(&mut nee_mode, NextEventEstimation::None, "None");
-                        ui.selectable_value(&mut nee_mode, NextEventEstimation::MultipleImportanceSampling, "Multiple importance sampling");
-                        ui.selectable_value(&mut nee_mode, NextEventEstimation::DirectLightSampling, "Direct light sampling only");
+        if import {
+            self.import_files(&files);
+        } else if !cancel {
+            self.pending_import = Some(files);
+        }
+    }
+
+    fn show_command_palette(&mut self, egui_ctx: &egui::Context) {
+        let Some(mut filter) = self.command_palette.take() else {
+            return;
+        };
+
+        let mut open = true;
+        let mut selected = None;
+        egui::Window::new("Command palette")
+            .title_bar(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 40.0))
+            .show(egui_ctx, |ui| {
+                ui.add(egui::TextEdit::singleline(&mut filter).hint_text("Type a command")).request_focus();
+                let query = filter.to_lowercase();
+                let actions = Action::all()
+                    .into_iter()
+                    .filter(|action| *action != Action::CommandPalette && action.title().to_lowercase().contains(&query))
+                    .collect::<Vec<_>>();
+
+                ui.separator();
+                for &action in actions.iter() {
+                    ui.horizontal(|ui| {
+                        if ui.selectable_label(false, action.title()).clicked() {
+                            selected = Some(action);
+                        }
+                        if let Some(shortcut) = self.shortcuts.get(action) {
+                            ui.weak(shortcut.to_string());
+                        }
                     });
-                if nee_mode != prev_nee_mode {
-                    self.tracing_state.config.write().nee = nee_mode.to_u32();
-                    self.tracing_state.dirty.store(true, Ordering::Relaxed);
                 }
-                ui.end_row();
 
-                {
-                    let mut config = self.tracing_state.config.write();
-                    if ui.add(egui::Slider::new(&mut config.specular_weight_clamp.x, 0.0..=1.0).text("Min specular")).changed() {
-                        if config.specular_weight_clamp.x > config.specular_weight_clamp.y {
-                            config.specular_weight_clamp.y = config.specular_weight_clamp.x;
+                // Enter runs the best match, like in most editors
+                if ui.input().key_pressed(egui::Key::Enter) {
+                    selected = actions.first().copied();
+                }
+                if ui.input().key_pressed(egui::Key::Escape) {
+                    open = false;
+                }
+            });
+
+        if let Some(action) = selected {
+            self.run_action(action);
+        } else if open {
+            self.command_palette = Some(filter);
+        }
+    }
+
+    fn headless_scene(&self) -> HeadlessScene {
+        HeadlessScene {
+            scene: self.selected_scene.clone(),
+            skybox: self.selected_skybox.clone(),
+            environment_clamp: *self.tracing_state.environment_clamp.read(),
+            scene_scale: *self.tracing_state.scene_scale.read(),
+            ground: *self.tracing_state.ground.read(),
+            cpu: self.use_cpu,
+        }
+    }
+
+    fn start_contact_sheet(&mut self) {
+        // The sheet renders the scene as it is now, and has the device to itself
+        self.stop_render();
+        let config = *self.tracing_state.config.read();
+        let (rows, columns, samples) = self.contact_sheet;
+        let mut sheet = ContactSheet {
+            scene: self.headless_scene(),
+            config,
+            tonemapping: self.tonemapping,
+            rows,
+            columns,
+            samples,
+        };
+        let shrink = (config.render.width as f32 / CONTACT_SHEET_CELL_WIDTH as f32).max(1.0);
+        sheet.config.render.width = ((config.render.width as f32 / shrink) as u32).max(1);
+        sheet.config.render.height = ((config.render.height as f32 / shrink) as u32).max(1);
+
+        let cells = sheet.cell_count();
+        self.contact_sheet_job = Some(BackgroundRender::spawn("contact sheet", cells, move |progress| sheet.render(progress)));
+    }
+
+    // Asks where to save the sheet once it's done
+    fn poll_contact_sheet(&mut self) {
+        let Some((pixels, width, height)) = BackgroundRender::poll(&mut self.contact_sheet_job) else {
+            return;
+        };
+        let Some(path) = tinyfiledialogs::save_file_dialog_with_filter("Save contact sheet", "contact_sheet.png", &["*.png"], "PNG image") else {
+            return;
+        };
+        let mut path = std::path::PathBuf::from(path);
+        path.set_extension("png");
+        let (rows, columns, samples) = self.contact_sheet;
+        let settings = ExportSettings { tonemapping: self.tonemapping, exposure: 0.0, format: ImageFormat::Png, jpeg_quality: 90, footer: false };
+        let metadata = RenderMetadata {
+            entries: vec![
+                ("Software", format!("rust-path-tracer {}", env!("CARGO_PKG_VERSION"))),
+                ("Scene", self.selected_scene.clone()),
+                ("Samples", samples.to_string()),
+                ("Rows", rows.name().to_string()),
+                ("Columns", columns.name().to_string()),
+            ],
+        };
+        if let Err(err) = crate::export::save_pixels(&path, &pixels, width, height, &settings, &metadata) {
+            tracing::error!("Failed to save contact sheet '{}': {}", path.display(), err);
+        }
+    }
+
+    fn start_turntable(&mut self) {
+        let Some((center, fit_radius)) = self.scene_framing() else {
+            tracing::error!("Load a scene before rendering a turntable.");
+            return;
+        };
+        let settings = self.turntable;
+        let extension = settings.format.extension();
+        let filter = format!("*.{}", extension);
+        let default_path = format!("turntable.{}", extension);
+        let Some(path) = tinyfiledialogs::save_file_dialog_with_filter("Save turntable", &default_path, &[filter.as_str()], "Animation") else {
+            return;
+        };
+        let mut path = std::path::PathBuf::from(path);
+        path.set_extension(extension);
+
+        // Like contact sheets, turntables have the device to themselves
+        self.stop_render();
+        let turntable = Turntable {
+            scene: self.headless_scene(),
+            config: *self.tracing_state.config.read(),
+            tonemapping: self.tonemapping,
+            center,
+            radius: if settings.radius > 0.0 { settings.radius } else { fit_radius },
+            frames: settings.frames.max(1),
+            samples: settings.samples.max(1),
+            fps: TURNTABLE_FPS,
+            format: settings.format,
+        };
+        let frames = turntable.frames;
+        self.turntable_job = Some(BackgroundRender::spawn("turntable", frames, move |progress| turntable.render(&path, progress)));
+    }
+
+    fn turntable_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if let Some(job) = self.turntable_job.as_ref() {
+                job.progress_ui(ui);
+                return;
+            }
+
+            let settings = &mut self.turntable;
+            ui.add(egui::DragValue::new(&mut settings.frames).clamp_range(1..=3600).suffix(" frames"));
+            ui.add(egui::DragValue::new(&mut settings.radius).clamp_range(0.0..=f32::MAX).speed(0.1).prefix("radius "))
+                .on_hover_text("Distance from the center of the scene. 0 fits the whole scene in view.");
+            ui.add(egui::DragValue::new(&mut settings.samples).clamp_range(1..=65536).suffix(" spp"));
+            egui::ComboBox::from_id_source("TurntableFormat")
+                .selected_text(format!("{:?}", settings.format))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut settings.format, VideoFormat::Gif, "GIF");
+                    ui.selectable_value(&mut settings.format, VideoFormat::Mp4, "MP4 (ffmpeg)");
+                });
+            if ui.button("Render turntable")
+                .on_hover_text("Orbit the camera once around the scene at its current height, and encode the frames into an animation. Stops the current render.")
+                .clicked()
+            {
+                self.start_turntable();
+            }
+        });
+    }
+
+    fn start_probe_bake(&mut self) {
+        let Some(path) = tinyfiledialogs::save_file_dialog_with_filter("Save probe", "probe.exr", &["*.exr"], "OpenEXR panorama") else {
+            return;
+        };
+        let mut path = std::path::PathBuf::from(path);
+        path.set_extension("exr");
+
+        // Baked where the camera is, with the device to itself
+        self.stop_render();
+        let config = *self.tracing_state.config.read();
+        let (resolution, samples) = self.probe;
+        let bake = ProbeBake {
+            scene: self.headless_scene(),
+            config,
+            position: config.camera.cam_position.truncate(),
+            resolution: resolution.max(1),
+            samples: samples.max(1),
+            specular_size: PROBE_SPECULAR_SIZE,
+        };
+        // Rendering the panorama, then prefiltering it
+        self.probe_job = Some(BackgroundRender::spawn("probe", 2, move |progress| bake.bake(&path, progress)));
+    }
+
+    fn poll_probe_bake(&mut self) {
+        if let Some((irradiance, specular)) = BackgroundRender::poll(&mut self.probe_job) {
+            tracing::info!("Baked probe cubemaps to '{}' and '{}'.", irradiance.display(), specular.display());
+        }
+    }
+
+    fn probe_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if let Some(job) = self.probe_job.as_ref() {
+                job.progress_ui(ui);
+                return;
+            }
+
+            let (resolution, samples) = &mut self.probe;
+            ui.add(egui::DragValue::new(resolution).clamp_range(16..=8192).suffix(" px tall"));
+            ui.add(egui::DragValue::new(samples).clamp_range(1..=65536).suffix(" spp"));
+            if ui.button("Bake probe")
+                .on_hover_text("Render a 360 degree panorama from the camera's position, and prefilter it into diffuse irradiance and GGX specular cubemaps saved as KTX2 next to it. Stops the current render.")
+                .clicked()
+            {
+                self.start_probe_bake();
+            }
+        });
+    }
+
+    fn start_lightmap_bake(&mut self) {
+        let Some(meshes) = self.tracing_state.scene_statistics.read().as_ref().map(|statistics| statistics.meshes) else {
+            tracing::error!("Load a scene before baking lightmaps.");
+            return;
+        };
+        let Some(path) = tinyfiledialogs::save_file_dialog_with_filter("Save lightmaps", "lightmap.exr", &["*.exr"], "OpenEXR image") else {
+            return;
+        };
+        let path = std::path::PathBuf::from(path);
+
+        self.stop_render();
+        let (resolution, samples) = self.lightmap;
+        let bake = LightmapBake {
+            scene: self.headless_scene(),
+            config: *self.tracing_state.config.read(),
+            resolution: resolution.max(1),
+            samples: samples.max(1),
+        };
+        self.lightmap_job = Some(BackgroundRender::spawn("lightmaps", meshes as u32, move |progress| bake.bake(&path, progress)));
+    }
+
+    fn poll_lightmap_bake(&mut self) {
+        if let Some(paths) = BackgroundRender::poll(&mut self.lightmap_job) {
+            tracing::info!("Baked {} lightmaps.", paths.len());
+        }
+    }
+
+    fn lightmap_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if let Some(job) = self.lightmap_job.as_ref() {
+                job.progress_ui(ui);
+                return;
+            }
+
+            let (resolution, samples) = &mut self.lightmap;
+            ui.add(egui::DragValue::new(resolution).clamp_range(16..=8192).suffix(" px"));
+            ui.add(egui::DragValue::new(samples).clamp_range(1..=65536).suffix(" spp"));
+            if ui.button("Bake lightmaps")
+                .on_hover_text("Path trace the light reaching every texel of each mesh's second UV set, and save a lightmap per mesh as EXR. Meshes without a second UV set are skipped. Stops the current render.")
+                .clicked()
+            {
+                self.start_lightmap_bake();
+            }
+        });
+    }
+
+    fn start_surface_map_bake(&mut self) {
+        let Some(meshes) = self.tracing_state.scene_statistics.read().as_ref().map(|statistics| statistics.meshes) else {
+            tracing::error!("Load a scene before baking maps.");
+            return;
+        };
+        let Some(path) = tinyfiledialogs::save_file_dialog_with_filter("Save maps", "maps.png", &["*.png"], "PNG image") else {
+            return;
+        };
+        let path = std::path::PathBuf::from(path);
+
+        // Only traces occlusion rays, so the render can keep going
+        let (resolution, ao_samples, ao_distance) = self.surface_maps;
+        let bake = SurfaceMapBake {
+            scene: self.headless_scene(),
+            resolution: resolution.max(1),
+            ao_samples: ao_samples.max(1),
+            ao_distance,
+        };
+        self.surface_maps_job = Some(BackgroundRender::spawn("maps", meshes as u32, move |progress| bake.bake(&path, progress)));
+    }
+
+    fn poll_surface_map_bake(&mut self) {
+        if let Some(paths) = BackgroundRender::poll(&mut self.surface_maps_job) {
+            tracing::info!("Baked {} ambient occlusion and curvature maps.", paths.len());
+        }
+    }
+
+    fn surface_maps_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if let Some(job) = self.surface_maps_job.as_ref() {
+                job.progress_ui(ui);
+                return;
+            }
+
+            let (resolution, ao_samples, ao_distance) = &mut self.surface_maps;
+            ui.add(egui::DragValue::new(resolution).clamp_range(16..=8192).suffix(" px"));
+            ui.add(egui::DragValue::new(ao_samples).clamp_range(1..=4096).suffix(" AO rays"));
+            ui.add(egui::DragValue::new(ao_distance).clamp_range(0.0..=f32::MAX).speed(0.01).prefix("distance "))
+                .on_hover_text("How far away geometry still occludes. 0 uses a tenth of the scene's size.");
+            if ui.button("Bake AO and curvature")
+                .on_hover_text("Bake ambient occlusion and curvature maps of each mesh, laid out by its texture UVs, and save them as PNG.")
+                .clicked()
+            {
+                self.start_surface_map_bake();
+            }
+        });
+    }
+
+    fn start_vertex_bake(&mut self) {
+        let Some(meshes) = self.tracing_state.scene_statistics.read().as_ref().map(|statistics| statistics.meshes) else {
+            tracing::error!("Load a scene before baking vertices.");
+            return;
+        };
+        let Some(path) = tinyfiledialogs::save_file_dialog_with_filter("Save vertex bake", "vertex_bake.json", &["*.json"], "JSON with a binary sidecar") else {
+            return;
+        };
+        let path = std::path::PathBuf::from(path);
+
+        // Irradiance traces full paths on every core, occlusion only casts shadow rays
+        let (kind, samples, ao_distance) = self.vertex_bake;
+        if kind == VertexBakeKind::Irradiance {
+            self.stop_render();
+        }
+        let bake = VertexBake {
+            scene: self.headless_scene(),
+            config: *self.tracing_state.config.read(),
+            kind,
+            samples: samples.max(1),
+            ao_distance,
+        };
+        self.vertex_bake_job = Some(BackgroundRender::spawn("meshes", meshes as u32, move |progress| bake.bake(&path, progress)));
+    }
+
+    fn poll_vertex_bake(&mut self) {
+        if let Some(path) = BackgroundRender::poll(&mut self.vertex_bake_job) {
+            tracing::info!("Baked vertex data to '{}'.", path.display());
+        }
+    }
+
+    fn vertex_bake_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if let Some(job) = self.vertex_bake_job.as_ref() {
+                job.progress_ui(ui);
+                return;
+            }
+
+            let (kind, samples, ao_distance) = &mut self.vertex_bake;
+            egui::ComboBox::from_id_source("VertexBakeKind")
+                .selected_text(match kind {
+                    VertexBakeKind::AmbientOcclusion => "Vertex AO",
+                    VertexBakeKind::Irradiance => "Vertex SH irradiance",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(kind, VertexBakeKind::AmbientOcclusion, "Vertex AO");
+                    ui.selectable_value(kind, VertexBakeKind::Irradiance, "Vertex SH irradiance");
+                });
+            ui.add(egui::DragValue::new(samples).clamp_range(1..=65536).suffix(" rays"));
+            if *kind == VertexBakeKind::AmbientOcclusion {
+                ui.add(egui::DragValue::new(ao_distance).clamp_range(0.0..=f32::MAX).speed(0.01).prefix("distance "))
+                    .on_hover_text("How far away geometry still occludes. 0 uses a tenth of the scene's size.");
+            }
+            if ui.button("Bake vertices")
+                .on_hover_text("Trace a hemisphere of rays from every vertex, and save ambient occlusion or 9 spherical harmonics coefficients of irradiance per vertex to a JSON file and a binary file of floats next to it. Baking irradiance stops the current render.")
+                .clicked()
+            {
+                self.start_vertex_bake();
+            }
+        });
+    }
+
+    fn contact_sheet_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if let Some(job) = self.contact_sheet_job.as_ref() {
+                job.progress_ui(ui);
+                return;
+            }
+
+            let (rows, columns, samples) = &mut self.contact_sheet;
+            for (label, axis) in [("Rows", rows), ("Columns", columns)] {
+                egui::ComboBox::from_label(label)
+                    .selected_text(axis.name())
+                    .show_ui(ui, |ui| {
+                        for option in SweepAxis::ALL {
+                            ui.selectable_value(axis, option, option.name());
+                        }
+                    });
+            }
+            ui.add(egui::DragValue::new(samples).clamp_range(1..=65536).suffix(" spp"));
+            if ui.button("Render contact sheet")
+                .on_hover_text("Render the scene once for every combination of the two settings, and save the results side by side with labels. Stops the current render.")
+                .clicked()
+            {
+                self.start_contact_sheet();
+            }
+        });
+    }
+
+    fn on_gui(&mut self, egui_ctx: &egui::Context) {
+        // The render thread already switched to the CPU, reflect that in the GUI
+        if self.tracing_state.gpu_failed.swap(false, Ordering::Relaxed) {
+            self.use_cpu = true;
+        }
+
+        let triggered = self.shortcuts.triggered(&egui_ctx.input(), egui_ctx.wants_keyboard_input());
+        for action in triggered {
+            self.run_action(action);
+        }
+        self.frame_loaded_scene();
+        self.show_command_palette(egui_ctx);
+        self.route_dropped_files();
+        self.show_import_dialog(egui_ctx);
+        self.show_export_dialog(egui_ctx);
+        self.poll_contact_sheet();
+        BackgroundRender::poll(&mut self.turntable_job);
+        self.poll_probe_bake();
+        self.poll_lightmap_bake();
+        self.poll_surface_map_bake();
+        self.poll_vertex_bake();
+
+        // Pop the log open whenever something new goes wrong, so failures aren't silent
+        let error_count = logging::error_count();
+        if error_count != self.seen_error_count {
+            self.seen_error_count = error_count;
+            self.layout.set_open(Panel::Log, true);
+        }
+
+        // Only pay for profiling scopes while someone is looking at them, or a scene is loading
+        let loading = self.tracing_state.load_progress.read().is_some();
+        puffin::set_scopes_on(self.layout.is_open(Panel::Profiler) || loading);
+
+        // Docked areas have to be laid out before the viewport takes the remaining space
+        egui::TopBottomPanel::top("MenuBar").show(egui_ctx, |ui| self.menu_bar_ui(ui));
+        for dock in [Dock::Bottom, Dock::Left, Dock::Right] {
+            self.show_dock_area(egui_ctx, dock);
+        }
+        for panel in Panel::ALL {
+            if self.layout.is_open(panel) && self.layout.dock(panel) == Dock::Floating {
+                self.show_floating_panel(egui_ctx, panel);
+            }
+        }
+    }
+
+    // Planes cutting away what camera rays see, for section views. Each cuts away the side its normal faces.
+    fn section_planes_ui(&mut self, ui: &mut egui::Ui) {
+        let center = self.scene_framing().map_or(Vec3::ZERO, |(center, _)| center);
+        let scene_size = self.tracing_state.scene_statistics.read().as_ref().map_or(10.0, |statistics| (statistics.model_bounds.1 - statistics.model_bounds.0).length());
+        ui.vertical(|ui| {
+            let mut config = self.tracing_state.config.write();
+            let euler_mat = Mat3::from_rotation_y(config.camera.cam_rotation.y) * Mat3::from_rotation_x(config.camera.cam_rotation.x);
+            let towards_camera = -(euler_mat * Vec3::Z);
+            let render = &mut config.render;
+            let count = (render.section_plane_count as usize).min(MAX_SECTION_PLANES);
+            let mut changed = false;
+            let mut remove = None;
+            for (index, plane) in render.section_planes[..count].iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Section {}", index + 1));
+                    let anchor = section_anchor(*plane, center);
+                    for (label, normal) in [("X", Vec3::X), ("Y", Vec3::Y), ("Z", Vec3::Z), ("Camera", towards_camera)] {
+                        if ui.button(label).on_hover_text("Turn the plane to face along this direction, keeping it where it is.").clicked() {
+                            *plane = SectionDescription { normal, point: anchor }.to_plane();
+                            changed = true;
+                        }
+                    }
+                    if ui.button("Flip").on_hover_text("Cut away the other side of the plane.").clicked() {
+                        *plane = -*plane;
+                        changed = true;
+                    }
+                    let mut offset = -plane.w;
+                    if ui.add(egui::DragValue::new(&mut offset).speed(scene_size * 0.002).prefix("offset "))
+                        .on_hover_text("Distance of the plane from the origin along its normal. The arrow in the viewport drags it too.")
+                        .changed()
+                    {
+                        plane.w = -offset;
+                        changed = true;
+                    }
+                    if ui.button("Remove").clicked() {
+                        remove = Some(index);
+                    }
+                });
+            }
+            if let Some(index) = remove {
+                render.section_planes.copy_within(index + 1.., index);
+                render.section_plane_count = count as u32 - 1;
+                changed = true;
+            }
+
+            ui.horizontal(|ui| {
+                let count = render.section_plane_count as usize;
+                if ui.add_enabled(count < MAX_SECTION_PLANES, egui::Button::new("Add section plane"))
+                    .on_hover_text("Cut away the half of the scene between its center and the camera. Only camera rays are cut, shadows and bounced light still see all of the scene.")
+                    .clicked()
+                {
+                    render.section_planes[count] = SectionDescription { normal: towards_camera, point: center }.to_plane();
+                    render.section_plane_count = count as u32 + 1;
+                    changed = true;
+                }
+                let mut fill = render.section_color.w != 0.0;
+                if ui.checkbox(&mut fill, "Fill cuts")
+                    .on_hover_text("Fill where the planes cut through closed meshes with a flat color, rather than looking into them.")
+                    .changed()
+                {
+                    render.section_color.w = if fill { 1.0 } else { 0.0 };
+                    changed = true;
+                }
+                let mut color = render.section_color.truncate().to_array();
+                if ui.add_enabled_ui(fill, |ui| ui.color_edit_button_rgb(&mut color)).inner.changed() {
+                    render.section_color = Vec3::from(color).extend(render.section_color.w);
+                    changed = true;
+                }
+            });
+
+            if changed {
+                self.tracing_state.mark_dirty(DirtyFlags::CAMERA);
+            }
+        });
+    }
+
+    fn settings_ui(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("MainGrid")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.vertical(|ui| {
+                ui.label(format!("Selected scene: {}", self.selected_scene));
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut self.scene_scale).speed(0.01).clamp_range(0.001..=1000.0));
+                    ui.label("Scene scale")
+                        .on_hover_text("Applied on import, on top of the units stored in the file. Camera speed and ray offsets follow the scaled scene.");
+                    let applied = *self.tracing_state.scene_scale.read() == self.scene_scale;
+                    if ui.add_enabled(!applied, egui::Button::new("Apply scale")).on_hover_text("Reloads the scene").clicked() {
+                        *self.tracing_state.scene_scale.write() = self.scene_scale;
+                        self.reload_scene();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if ui.button(if self.is_rendering() { "Stop" } else { "Start" }).clicked() {
+                        self.toggle_render();
+                    }
+
+                    if ui.button("Select scene").clicked() {
+                        self.select_scene();
+                    }
+
+                    if ui.button("Save image").clicked() {
+                        self.save_image();
+                    }
+                });
+                let load_progress = self.tracing_state.load_progress.read().clone();
+                if let Some(progress) = load_progress {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::ProgressBar::new(progress.overall()).text(format!("{}...", progress.stage().name())).desired_width(200.0));
+                        // Stopping returns immediately, and the loader gives up after its current stage
+                        if ui.button("Cancel").clicked() {
+                            self.stop_render();
+                        }
+                    });
+                }
+            });
+            ui.end_row();
+
+            
+            ui.horizontal(|ui| {
+                #[cfg(feature = "oidn")]
+                {
+                    let mut denoise_checked = self.tracing_state.denoise.load(Ordering::Relaxed);
+                    if ui.checkbox(&mut denoise_checked, "Denoise").changed() {
+                        self.set_denoise(denoise_checked);
+                    }
+                    let mut comparison = self.tracing_state.denoise_comparison.load(Ordering::Relaxed);
+                    if ui.add_enabled(denoise_checked, egui::Checkbox::new(&mut comparison, "Compare"))
+                        .on_hover_text("Show the raw samples on the left half of the image, and the denoised result on the right. Saved images are split the same way.")
+                        .changed()
+                    {
+                        self.tracing_state.denoise_comparison.store(comparison, Ordering::Relaxed);
+                    }
+                    let has_region = self.tracing_state.denoise_region.read().is_some();
+                    if ui.add_enabled(has_region, egui::Button::new("Denoise all"))
+                        .on_hover_text("Alt-drag across the image to denoise only part of it. This goes back to denoising all of it.")
+                        .clicked()
+                    {
+                        *self.tracing_state.denoise_region.write() = None;
+                    }
+                }
+
+                let mut use_blue_noise = self.tracing_state.config.read().render.use_blue_noise != 0;
+                if ui.checkbox(&mut use_blue_noise, "Use blue noise").changed() {
+                    self.tracing_state.config.write().render.use_blue_noise = use_blue_noise as u32;
+                    self.tracing_state.mark_dirty(DirtyFlags::SAMPLING);
+                }
+
+                let mut jitter = self.tracing_state.config.read().render.jitter != 0;
+                if ui.checkbox(&mut jitter, "Jitter")
+                    .on_hover_text("Jitter camera rays within each pixel for anti-aliasing. Disable to trace through pixel centers when debugging single paths.")
+                    .changed()
+                {
+                    self.tracing_state.config.write().render.jitter = jitter as u32;
+                    self.tracing_state.mark_dirty(DirtyFlags::SAMPLING);
+                }
+
+                if ui.button("Re-seed")
+                    .on_hover_text("Continue rendering with a new random seed, which can break up a firefly pattern that keeps reappearing.")
+                    .clicked()
+                {
+                    self.tracing_state.config.write().render.seed = rand::random();
+                    self.tracing_state.mark_dirty(DirtyFlags::SEED);
+                }
+
+                let mut interactive_preview = self.tracing_state.interactive_preview.load(Ordering::Relaxed);
+                if ui.checkbox(&mut interactive_preview, "Interactive preview")
+                    .on_hover_text("Blend the first samples after a lighting change into the previous frame, instead of showing them as is.")
+                    .changed()
+                {
+                    self.tracing_state.interactive_preview.store(interactive_preview, Ordering::Relaxed);
+                }
+
+                let mut interactive_upscale = self.tracing_state.interactive_upscale.load(Ordering::Relaxed);
+                if ui.add_enabled(!self.use_cpu, egui::Checkbox::new(&mut interactive_upscale, "Low resolution while moving"))
+                    .on_hover_text("Trace a quarter of the pixels while flying the camera, and upscale them, so moving stays smooth at high resolutions.")
+                    .changed()
+                {
+                    self.tracing_state.interactive_upscale.store(interactive_upscale, Ordering::Relaxed);
+                }
+            });
+            ui.end_row();
+
+            ui.horizontal(|ui| {
+                let mut config = self.tracing_state.config.write();
+                let adaptive_roulette = config.render.adaptive_roulette != 0;
+                if ui.add_enabled(!adaptive_roulette, egui::DragValue::new(&mut config.render.min_bounces)).changed() {
+                    if config.render.min_bounces > config.render.max_bounces {
+                        config.render.max_bounces = config.render.min_bounces;
+                    }
+                    self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+                }
+                ui.label("Min bounces");
+
+                if ui.add(egui::DragValue::new(&mut config.render.max_bounces)).changed() {
+                    if config.render.max_bounces < config.render.min_bounces {
+                        config.render.min_bounces = config.render.max_bounces;
+                    }
+                    self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+                }
+                ui.label("Max bounces");
+            });
+            ui.end_row();
+
+            ui.horizontal(|ui| {
+                let mut config = self.tracing_state.config.write();
+                let mut adaptive_roulette = config.render.adaptive_roulette != 0;
+                if ui.checkbox(&mut adaptive_roulette, "Adaptive roulette")
+                    .on_hover_text("Start Russian roulette once a path carries less light than the surface it hit reflects, instead of after the minimum bounces. Ends paths sooner in dark interiors.")
+                    .changed()
+                {
+                    config.render.adaptive_roulette = adaptive_roulette as u32;
+                    self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+                }
+
+                if ui.add(egui::DragValue::new(&mut config.render.path_splits).clamp_range(1..=16))
+                    .on_hover_text("Paths traced from the first hit of each camera ray, when it can reflect diffusely. Spends more of each sample on indirect light.")
+                    .changed()
+                {
+                    self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+                }
+                ui.label("Path splits");
+            });
+            ui.end_row();
+
+            {
+                let mut config = self.tracing_state.config.write();
+                if ui.add(egui::Slider::new(&mut config.render.glossy_cone_limit, 0.0..=2.0).text("Glossy cone limit"))
+                    .on_hover_text("Terminate glossy paths at random once their ray cone has spread wider than this, as they only reflect a blur. 0 never terminates.")
+                    .changed()
+                {
+                    self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+                }
+                ui.end_row();
+            }
+
+            ui.horizontal(|ui| {
+                let mut config = self.tracing_state.config.write();
+                let mut reject_fireflies = config.render.firefly_rejection > 0.0;
+                if ui.checkbox(&mut reject_fireflies, "Reject fireflies")
+                    .on_hover_text("Clamp samples far brighter than the rest of their pixel, and spread the energy taken off over the pixels around it. Keeps single bright pixels out of the image the denoiser sees.")
+                    .changed()
+                {
+                    config.render.firefly_rejection = if reject_fireflies { DEFAULT_FIREFLY_REJECTION } else { 0.0 };
+                    self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+                }
+                if ui.add_enabled(reject_fireflies, egui::DragValue::new(&mut config.render.firefly_rejection).speed(0.1).clamp_range(1.0..=64.0))
+                    .on_hover_text("How many mean absolute deviations above its pixel's mean a sample may be. Lower rejects more.")
+                    .changed()
+                {
+                    self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+                }
+                ui.label("Threshold");
+            });
+            ui.end_row();
+
+            let prev_nee_mode = NextEventEstimation::from_u32(self.tracing_state.config.read().render.nee);
+            let mut nee_mode = prev_nee_mode;
+            egui::ComboBox::from_label("Next event estimation")
+                .selected_text(format!("{:?}", nee_mode))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut nee_mode, NextEventEstimation::None, "None");
+                    ui.selectable_value(&mut nee_mode, NextEventEstimation::MultipleImportanceSampling, "Multiple importance sampling");
+                    ui.selectable_value(&mut nee_mode, NextEventEstimation::DirectLightSampling, "Direct light sampling only");
+                });
+            if nee_mode != prev_nee_mode {
+                self.tracing_state.config.write().render.nee = nee_mode.to_u32();
+                self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+            }
+            ui.end_row();
+
+            {
+                let mut config = self.tracing_state.config.write();
+                if ui.add(egui::Slider::new(&mut config.render.specular_weight_clamp.x, 0.0..=1.0).text("Min specular")).changed() {
+                    if config.render.specular_weight_clamp.x > config.render.specular_weight_clamp.y {
+                        config.render.specular_weight_clamp.y = config.render.specular_weight_clamp.x;
+                    }
+                    self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+                }
+                ui.end_row();
+
+                if ui.add(egui::Slider::new(&mut config.render.specular_weight_clamp.y, 0.0..=1.0).text("Max specular")).changed() {
+                    if config.render.specular_weight_clamp.x > config.render.specular_weight_clamp.y {
+                        config.render.specular_weight_clamp.x = config.render.specular_weight_clamp.y;
+                    }
+                    self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+                }
+                ui.end_row();
+            }
+
+            self.section_planes_ui(ui);
+            ui.end_row();
+
+            ui.horizontal(|ui| {
+                tonemapping_combo(ui, "Tonemapping", &mut self.tonemapping);
+                ui.label("Tonemapping operator");
+            });
+            ui.end_row();
+
+            ui.horizontal(|ui| {
+                let hover = if self.hdr_supported {
+                    "Show the render in scRGB without tonemapping, through DX12. Saved images are still tonemapped. Takes effect after a restart."
+                } else {
+                    "Only available with the DX12 backend, on a display that supports a scRGB surface."
+                };
+                if ui.add_enabled(self.hdr_supported, egui::Checkbox::new(&mut self.hdr_requested, "HDR output"))
+                    .on_hover_text(hover)
+                    .changed()
+                {
+                    let hdr_output = self.hdr_requested;
+                    Settings::update(|settings| settings.hdr_output = hdr_output);
+                }
+                if self.hdr_requested != self.hdr_output {
+                    ui.weak("(restart to apply)");
+                }
+            });
+            ui.end_row();
+
+            if self.hdr_output {
+                ui.add(egui::Slider::new(&mut self.hdr_white_nits, 80.0..=500.0).text("Paper white (nits)"));
+                ui.end_row();
+                ui.add(egui::Slider::new(&mut self.hdr_peak_nits, 400.0..=10000.0).logarithmic(true).text("Peak brightness (nits)"));
+                ui.end_row();
+            }
+
+            ui.vertical(|ui| {
+                let reference_name = self.selected_reference.as_ref().map(|s| s.as_ref()).unwrap_or("None");
+                ui.label(format!("Selected reference: {}", reference_name));
+                ui.horizontal(|ui| {
+                    if ui.button("Select reference").clicked() {
+                        self.select_reference();
+                    }
+                    if ui.button("Clear reference").clicked() {
+                        self.clear_reference();
+                    }
+                    if ui.button("Convergence plot").clicked() {
+                        self.layout.toggle(Panel::Convergence);
+                    }
+                });
+            });
+            ui.end_row();
+
+            ui.add_enabled_ui(self.reference.is_some(), |ui| {
+                egui::ComboBox::from_label("Reference diff")
+                    .selected_text(format!("{:?}", self.diff_mode))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.diff_mode, DiffMode::None, "None");
+                        ui.selectable_value(&mut self.diff_mode, DiffMode::Absolute, "Absolute difference");
+                        ui.selectable_value(&mut self.diff_mode, DiffMode::Relative, "Relative difference");
+                    });
+            });
+            ui.end_row();
+
+            ui.add_enabled(self.diff_mode != DiffMode::None, egui::Slider::new(&mut self.diff_gain, 0.1..=100.0).logarithmic(true).text("Diff gain"));
+            ui.end_row();
+
+            ui.horizontal(|ui| {
+                if ui.button("Environment settings").clicked() {
+                    self.layout.toggle(Panel::Environment);
+                }
+                if ui.button("Materials").clicked() {
+                    self.layout.toggle(Panel::Materials);
+                }
+                if ui.button("Path debugger").clicked() {
+                    self.layout.toggle(Panel::PathDebugger);
+                }
+                if ui.button("Statistics").clicked() {
+                    self.layout.toggle(Panel::Statistics);
+                }
+                if ui.button("Resources").clicked() {
+                    self.layout.toggle(Panel::Resources);
+                }
+                if ui.button("Profiler").clicked() {
+                    self.layout.toggle(Panel::Profiler);
+                }
+                if ui.button("Log").clicked() {
+                    self.layout.toggle(Panel::Log);
+                }
+            });
+            ui.end_row();
+
+            ui.separator();
+            ui.end_row();
+
+            egui::ComboBox::from_label("Compute device")
+                .selected_text(if self.use_cpu { "CPU" } else { "GPU" })
+                .show_ui(ui, |ui| {
+                    if ui.selectable_label(!self.use_cpu, "GPU").clicked() { 
+                        self.set_use_cpu(false);
+                    };
+                    if ui.selectable_label(self.use_cpu, "CPU").clicked() {
+                        self.set_use_cpu(true);
+                    };
+                });
+            ui.end_row();
+
+            let mut sync_rate = self.tracing_state.sync_rate.load(Ordering::Relaxed);
+            if ui.add_enabled(!self.use_cpu, egui::Slider::new(&mut sync_rate, 1..=256).text("GPU sync rate")).changed() {
+                self.tracing_state.sync_rate.store(sync_rate, Ordering::Relaxed);
+            }
+            ui.end_row();
+
+            let mut frame_budget = self.tracing_state.frame_budget_ms.load(Ordering::Relaxed);
+            let slider = egui::Slider::new(&mut frame_budget, 0..=500)
+                .text("GPU frame budget")
+                .custom_formatter(|n, _| if n == 0.0 { "Off".to_string() } else { format!("{} ms", n) });
+            if ui.add_enabled(!self.use_cpu, slider)
+                .on_hover_text("Show the image after dispatching for this long, even if the sync rate isn't reached, and let the GUI present it before dispatching more. Keeps the GUI smooth in heavy scenes.")
+                .changed()
+            {
+                self.tracing_state.frame_budget_ms.store(frame_budget, Ordering::Relaxed);
+            }
+            ui.end_row();
+
+            let mut burst_samples = self.tracing_state.burst_samples.load(Ordering::Relaxed);
+            let slider = egui::Slider::new(&mut burst_samples, 0..=256)
+                .text("GPU burst after moving")
+                .custom_formatter(|n, _| if n == 0.0 { "Off".to_string() } else { format!("{} spp", n) });
+            if ui.add_enabled(!self.use_cpu, slider)
+                .on_hover_text("Once the camera stops, trace this many samples before showing the image again, so it cleans up quickly. Then carry on at the sync rate.")
+                .changed()
+            {
+                self.tracing_state.burst_samples.store(burst_samples, Ordering::Relaxed);
+            }
+            ui.end_row();
+
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.pause_when_minimized, "Pause when minimized")
+                    .on_hover_text("Stop rendering while the window is minimized, and carry on when it comes back.");
+                ui.checkbox(&mut self.pause_when_unfocused, "Pause when unfocused")
+                    .on_hover_text("Also stop rendering while another window has focus.");
+                ui.checkbox(&mut self.low_power_on_battery, "Save power on battery")
+                    .on_hover_text("Idle between syncs while running off a battery, which roughly halves the load.");
+            });
+            ui.end_row();
+
+            ui.horizontal(|ui| {
+                let max_threads = std::thread::available_parallelism().map_or(1, |n| n.get()) as u32;
+                let mut cpu_threads = self.tracing_state.cpu_threads.load(Ordering::Relaxed);
+                let slider = egui::Slider::new(&mut cpu_threads, 0..=max_threads)
+                    .text("CPU threads")
+                    .custom_formatter(|n, _| if n == 0.0 { "All".to_string() } else { format!("{}", n) });
+                if ui.add_enabled(self.use_cpu, slider).changed() {
+                    self.tracing_state.cpu_threads.store(cpu_threads, Ordering::Relaxed);
+                }
+
+                let mut cpu_low_priority = self.tracing_state.cpu_low_priority.load(Ordering::Relaxed);
+                if ui.add_enabled(self.use_cpu, egui::Checkbox::new(&mut cpu_low_priority, "Low priority"))
+                    .on_hover_text("Run CPU render threads at the lowest priority, to keep the GUI responsive.")
+                    .changed()
+                {
+                    self.tracing_state.cpu_low_priority.store(cpu_low_priority, Ordering::Relaxed);
+                }
+            });
+            ui.end_row();
+
+            let mut bucket_rendering = self.tracing_state.bucket_rendering.load(Ordering::Relaxed);
+            if ui.add_enabled(self.use_cpu, egui::Checkbox::new(&mut bucket_rendering, "Bucket rendering"))
+                .on_hover_text("Render in 32x32 buckets on the CPU, and show their progress in the viewport.")
+                .changed()
+            {
+                self.tracing_state.bucket_rendering.store(bucket_rendering, Ordering::Relaxed);
+            }
+            ui.end_row();
+
+            let mut morton_order = self.tracing_state.config.read().render.morton_order != 0;
+            if ui.checkbox(&mut morton_order, "Morton order")
+                .on_hover_text("Trace pixels and store triangles along a Z-order curve, which keeps memory access more coherent. Triangle order applies to the next scene loaded.")
+                .changed()
+            {
+                self.tracing_state.config.write().render.morton_order = morton_order as u32;
+            }
+            ui.end_row();
+
+            let mut primary_pass = self.tracing_state.config.read().render.primary_pass != 0;
+            if ui.add_enabled(!self.use_cpu, egui::Checkbox::new(&mut primary_pass, "Primary pass"))
+                .on_hover_text("Trace camera rays in a small kernel of their own before the path tracing kernel, rather than in it. Compare the two with cargo bench --bench stages.")
+                .changed()
+            {
+                self.tracing_state.config.write().render.primary_pass = primary_pass as u32;
+            }
+            ui.end_row();
+
+            let mut async_textures = self.tracing_state.async_textures.load(Ordering::Relaxed);
+            if ui.checkbox(&mut async_textures, "Stream textures")
+                .on_hover_text("Start rendering with placeholder colors, and decode textures in the background. Applies to the next scene loaded.")
+                .changed()
+            {
+                self.tracing_state.async_textures.store(async_textures, Ordering::Relaxed);
+            }
+            ui.end_row();
+
+            let mut motion_vectors = self.tracing_state.motion_vectors_enabled.load(Ordering::Relaxed);
+            if ui.checkbox(&mut motion_vectors, "Motion vectors")
+                .on_hover_text("Compute screen-space motion vectors from the previous camera, for denoisers and temporal reprojection. Saved from the File menu.")
+                .changed()
+            {
+                self.tracing_state.motion_vectors_enabled.store(motion_vectors, Ordering::Relaxed);
+            }
+            ui.end_row();
+
+            let mut hot_reload = self.tracing_state.hot_reload.load(Ordering::Relaxed);
+            if ui.add_enabled(!self.use_cpu, egui::Checkbox::new(&mut hot_reload, "Hot-reload kernels"))
+                .on_hover_text("Reload the GPU kernels whenever they are rebuilt with cargo build, keeping the scene and camera. Set RUSTIC_KERNEL_PATH to watch another .spv file.")
+                .changed()
+            {
+                self.tracing_state.hot_reload.store(hot_reload, Ordering::Relaxed);
+            }
+            ui.end_row();
+
+            let mut half_precision = self.tracing_state.half_precision.load(Ordering::Relaxed);
+            if ui.add_enabled(!self.use_cpu, egui::Checkbox::new(&mut half_precision, "Half precision accumulation"))
+                .on_hover_text("Accumulate in RGBA16F on the GPU. Halves readback and upload bandwidth, at the cost of precision.")
+                .changed()
+            {
+                self.tracing_state.half_precision.store(half_precision, Ordering::Relaxed);
+                self.restart_current_render(true);
+            }
+            ui.end_row();
+    
+            ui.horizontal(|ui| {
+                let timelapse = &mut self.timelapse;
+                ui.add_enabled(timelapse.directory.is_some(), egui::Checkbox::new(&mut timelapse.enabled, "Time-lapse every"))
+                    .on_hover_text("Save the render into a numbered image sequence while rendering.");
+                ui.add(egui::DragValue::new(&mut timelapse.interval).clamp_range(1..=u32::MAX));
+                egui::ComboBox::from_id_source("TimelapseUnit")
+                    .selected_text(match timelapse.unit {
+                        CaptureInterval::Samples => "samples",
+                        CaptureInterval::Seconds => "seconds",
+                    })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut timelapse.unit, CaptureInterval::Samples, "samples");
+                        ui.selectable_value(&mut timelapse.unit, CaptureInterval::Seconds, "seconds");
+                    });
+                let folder_label = timelapse.directory.as_deref().unwrap_or("Select folder");
+                if ui.button(folder_label).clicked() {
+                    if let Some(directory) = tinyfiledialogs::select_folder_dialog("Select time-lapse folder", "") {
+                        timelapse.directory = Some(directory);
+                        timelapse.next_frame = 0;
+                        timelapse.enabled = true;
+                    }
+                }
+            });
+            ui.end_row();
+
+            self.contact_sheet_ui(ui);
+            ui.end_row();
+
+            self.turntable_ui(ui);
+            ui.end_row();
+
+            self.probe_ui(ui);
+            ui.end_row();
+
+            self.lightmap_ui(ui);
+            ui.end_row();
+
+            self.surface_maps_ui(ui);
+            ui.end_row();
+
+            self.vertex_bake_ui(ui);
+            ui.end_row();
+
+            ui.label(format!(
+                "Samples: {}",
+                self.tracing_state.samples.load(Ordering::Relaxed)
+            ));
+            ui.end_row();
+        });
+    }
+
+    fn environment_ui(&mut self, ui: &mut egui::Ui) {
+        let mouse_down = ui.input().pointer.primary_down();
+        let sun_direction = self.tracing_state.config.read().environment.sun_direction;
+        {
+            let skybox_name = self.selected_skybox.as_ref().map(|s| s.as_ref()).unwrap_or("Procedural");
+            ui.label(format!("Selected skybox: {}", skybox_name));
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Select skybox").clicked() {
+                self.select_skybox();
+            }
+            if ui.button("Reset skybox").clicked() {
+                self.clear_skybox();
+            }
+        });
+
+        ui.label("Shift-click the sky in the viewport to place the sun there.");
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.environment_clamp, "Clamp skybox above")
+                .on_hover_text("Removes very bright parts of the skybox, such as a small sun, and lights the scene with an equivalent directional light instead. Reduces fireflies while keeping sharp shadows.");
+            ui.add_enabled(self.environment_clamp, egui::DragValue::new(&mut self.environment_clamp_threshold).speed(1.0).clamp_range(0.1..=100000.0));
+            let clamp = self.environment_clamp.then_some(self.environment_clamp_threshold);
+            let applied = *self.tracing_state.environment_clamp.read() == clamp;
+            if ui.add_enabled(!applied, egui::Button::new("Apply")).on_hover_text("Reloads the skybox").clicked() {
+                *self.tracing_state.environment_clamp.write() = clamp;
+                self.restart_current_render(false);
+            }
+        });
+
+        let environment = self.tracing_state.config.read().environment;
+        let mut background_lod = environment.background_lod;
+        if ui.add(egui::Slider::new(&mut background_lod, 0.0..=8.0).text("Background blur"))
+            .on_hover_text("Shows a blurred skybox behind the scene, while lighting still uses the sharp one")
+            .changed()
+        {
+            self.tracing_state.config.write().environment.background_lod = background_lod;
+            // Mips are generated when the skybox is loaded, so the first blur needs a reload
+            if background_lod > 0.0 && environment.skybox_mips == 0 && self.selected_skybox.is_some() {
+                self.restart_current_render(false);
+            } else {
+                self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+            }
+        }
+
+        let mut sky_weight = environment.sky_weight;
+        let blend = egui::Slider::new(&mut sky_weight, 0.0..=1.0).text("Procedural sky");
+        if ui.add_enabled(self.selected_skybox.is_some(), blend)
+            .on_hover_text("Adds the procedural sky and its sun on top of the skybox. Both are sampled together.")
+            .changed()
+        {
+            self.tracing_state.config.write().environment.sky_weight = sky_weight;
+            self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+        }
+
+        let mut haze = environment.haze_density;
+        if ui.add(egui::Slider::new(&mut haze, 0.0..=10000.0).logarithmic(true).text("Haze"))
+            .on_hover_text("Scatters sunlight in the air between the camera and the scene, as many times thicker than real air. Real air only shows over kilometers.")
+            .changed()
+        {
+            self.tracing_state.config.write().environment.haze_density = haze;
+            self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+        }
+
+        let mut use_solar = self.solar.is_some();
+        if ui.checkbox(&mut use_solar, "Sun from time and place")
+            .on_hover_text("Places the sun with a solar position algorithm. North is along -Z, and east along +X.")
+            .changed()
+        {
+            self.solar = use_solar.then(SolarDescription::default);
+        }
+        if let Some(solar) = self.solar.as_mut() {
+            egui::Grid::new("Solar").num_columns(2).show(ui, |ui| {
+                ui.label("Latitude");
+                ui.add(egui::DragValue::new(&mut solar.latitude).speed(0.1).clamp_range(-90.0..=90.0).suffix("°"));
+                ui.end_row();
+                ui.label("Longitude");
+                ui.add(egui::DragValue::new(&mut solar.longitude).speed(0.1).clamp_range(-180.0..=180.0).suffix("°"));
+                ui.end_row();
+                ui.label("Date");
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut solar.year).clamp_range(1900..=2100));
+                    ui.add(egui::DragValue::new(&mut solar.month).clamp_range(1..=12));
+                    ui.add(egui::DragValue::new(&mut solar.day).clamp_range(1..=31));
+                });
+                ui.end_row();
+                ui.label("Time");
+                ui.add(egui::Slider::new(&mut solar.hour, 0.0..=24.0));
+                ui.end_row();
+                ui.label("UTC offset");
+                ui.add(egui::DragValue::new(&mut solar.utc_offset).speed(0.25).clamp_range(-12.0..=14.0));
+                ui.end_row();
+                ui.label("Intensity");
+                ui.add(egui::DragValue::new(&mut solar.intensity).speed(0.1).clamp_range(0.0..=50.0));
+                ui.end_row();
+            });
+            // Kept in sync every frame, so the sun can't be moved by hand while this is on
+            let sun = solar.sun();
+            if self.tracing_state.config.read().environment.sun_direction != sun {
+                self.tracing_state.config.write().environment.sun_direction = sun;
+                self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+            }
+        } else {
+            self.sun_ui(ui, sun_direction, mouse_down);
+        }
+
+        ui.separator();
+        self.ground_ui(ui);
+    }
+
+    // Sun intensity, and a top-down view of the sky to drag the sun around in
+    fn sun_ui(&mut self, ui: &mut egui::Ui, sun_direction: Vec4, mouse_down: bool) {
+        let mut sun_intensity = sun_direction.w;
+        if ui.add(egui::Slider::new(&mut sun_intensity, 0.0..=50.0).text("Sun intensity")).changed() {
+            self.tracing_state.config.write().environment.sun_direction.w = sun_intensity;
+            self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+        }
+        ui.end_row();
+
+        egui::plot::Plot::new("Sun position")
+            .view_aspect(1.0)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .allow_boxed_zoom(false)
+            .height(250.0)
+            .show(ui, |ui| {
+            let n = 512;
+            let circle_points: egui::plot::PlotPoints = (0..=n)
+                .map(|i| {
+                    let t = egui::remap(i as f64, 0.0..=(n as f64), 0.0..=6.28);
+                    let r = 1.0;
+                    [
+                        r * t.cos() + 0.0 as f64,
+                        r * t.sin() + 0.0 as f64,
+                    ]
+                })
+                .collect();
+            ui.line(egui::plot::Line::new(circle_points));
+
+            let sun_pos = [sun_direction.x as f64, sun_direction.z as f64];
+            ui.points(egui::plot::Points::new(vec![sun_pos])
+                .color(egui::Color32::GOLD)
+                .shape(egui::plot::MarkerShape::Asterisk)
+                .radius(8.0)
+                .name("Sun position"));
+            
+            let pointer = ui.pointer_coordinate();
+            if let Some(pointer) = pointer {
+                if mouse_down && pointer.x.abs() <= 1.0 && pointer.y.abs() <= 1.0 {
+                    let mut new_pos = pointer.to_vec2();
+                    if new_pos.length() > 1.0 {
+                        new_pos = new_pos.normalized();
+                    }
+                    let new_pos_y = (1.0 - new_pos.x * new_pos.x - new_pos.y * new_pos.y).sqrt();
+                    let new_pos_vec = Vec3::new(new_pos.x as f32, new_pos_y as f32, new_pos.y as f32).normalize();
+                    
+                    self.tracing_state.config.write().environment.sun_direction = new_pos_vec.extend(sun_direction.w);
+                    self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+                }
+            }
+        });
+    }
+
+    // The ground is part of the scene geometry, so changes reload the scene
+    fn ground_ui(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Ground")
+            .selected_text(format!("{:?}", self.ground.shape))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.ground.shape, GroundShape::None, "None");
+                ui.selectable_value(&mut self.ground.shape, GroundShape::Plane, "Plane");
+                ui.selectable_value(&mut self.ground.shape, GroundShape::Cyclorama, "Cyclorama");
+            });
+        ui.add_enabled_ui(self.ground.shape != GroundShape::None, |ui| {
+            ui.horizontal(|ui| {
+                let mut albedo = self.ground.albedo.to_array();
+                if ui.color_edit_button_rgb(&mut albedo).changed() {
+                    self.ground.albedo = Vec3::from(albedo);
+                }
+                ui.label("Ground albedo");
+            });
+            ui.add(egui::Slider::new(&mut self.ground.roughness, 0.0..=1.0).text("Ground roughness"));
+            ui.add(egui::Slider::new(&mut self.ground.metallic, 0.0..=1.0).text("Ground metallic"));
+        });
+
+        let applied = *self.tracing_state.ground.read() == self.ground;
+        if ui.add_enabled(!applied, egui::Button::new("Apply ground")).on_hover_text("Reloads the scene").clicked() {
+            *self.tracing_state.ground.write() = self.ground;
+            self.restart_current_render(false);
+        }
+    }
+
+    fn convergence_ui(&mut self, ui: &mut egui::Ui) {
+        // Estimated from the render itself, so this doesn't need a reference
+        egui::Grid::new("Noise grid").show(ui, |ui| {
+            let samples = self.tracing_state.samples.load(Ordering::Relaxed);
+            let error = *self.tracing_state.estimated_error.read();
+            ui.label("Estimated error");
+            ui.label(if samples < 2 { "-".to_string() } else { format!("{:.2}%", error * 100.0) })
+                .on_hover_text("Relative error of the image, from how far the means of its odd and even samples are apart");
+            ui.end_row();
+
+            ui.label("Stop at error");
+            let mut target = *self.tracing_state.error_target.read() * 100.0;
+            if ui.add(egui::DragValue::new(&mut target).speed(0.05).clamp_range(0.0..=100.0).suffix("%"))
+                .on_hover_text("Stop rendering once the estimated error drops below this. Renders forever at 0.")
+                .changed()
+            {
+                *self.tracing_state.error_target.write() = target / 100.0;
+            }
+            ui.end_row();
+
+            ui.checkbox(&mut self.show_noise, "Show noise")
+                .on_hover_text("Display the relative error of each pixel, in place of the render");
+            ui.end_row();
+        });
+        ui.separator();
+
+        if self.reference.is_none() {
+            ui.label("Select a reference image to record convergence.");
+        }
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.convergence_log_scale, "Log-log scale");
+            if ui.button("Clear").clicked() {
+                self.convergence.clear();
+            }
+            if ui.add_enabled(!self.convergence.is_empty(), egui::Button::new("Export CSV")).clicked() {
+                self.export_convergence_csv();
+            }
+        });
+
+        let log_scale = self.convergence_log_scale;
+        let scale_x = |x: u32| if log_scale { (x as f64).log10() } else { x as f64 };
+        let mse_points: egui::plot::PlotPoints = self.convergence
+            .iter()
+            .map(|s| [scale_x(s.samples), if log_scale { (s.mse as f64).max(1e-12).log10() } else { s.mse as f64 }])
+            .collect();
+        let ssim_points: egui::plot::PlotPoints = self.convergence
+            .iter()
+            .map(|s| [scale_x(s.samples), s.ssim as f64])
+            .collect();
+
+        ui.label(if log_scale { "log10(MSE) vs. log10(samples)" } else { "MSE vs. samples" });
+        egui::plot::Plot::new("MSE plot")
+            .height(150.0)
+            .show(ui, |ui| {
+                ui.line(egui::plot::Line::new(mse_points).name("MSE"));
+            });
+        ui.label(if log_scale { "SSIM vs. log10(samples)" } else { "SSIM vs. samples" });
+        egui::plot::Plot::new("SSIM plot")
+            .height(150.0)
+            .show(ui, |ui| {
+                ui.line(egui::plot::Line::new(ssim_points).name("SSIM"));
+            });
+
+        if let Some(last) = self.convergence.last() {
+            ui.label(format!("Samples: {}, MSE: {:.6}, SSIM: {:.4}", last.samples, last.mse, last.ssim));
+        }
+    }
+
+    fn statistics_ui(&mut self, ui: &mut egui::Ui) {
+        let timings = *self.tracing_state.timings.read();
+        ui.label(format!("Pass timings ({}, CPU wall-clock)", if self.use_cpu { "CPU" } else { "GPU" }))
+            .on_hover_text("Measured on the CPU around each pass. On the GPU this includes waiting for the dispatch to finish, not only the time spent in the kernel.");
+        egui::Grid::new("TimingsGrid")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("Trace (per sample)");
+            ui.label(format!("{:.2} ms", timings.trace.as_secs_f64() * 1000.0));
+            ui.end_row();
+
+            ui.label("Resolve");
+            ui.label(format!("{:.2} ms", timings.resolve.as_secs_f64() * 1000.0));
+            ui.end_row();
+
+            ui.label("Denoise");
+            ui.label(format!("{:.2} ms", timings.denoise.as_secs_f64() * 1000.0));
+            ui.end_row();
+        });
+
+        ui.separator();
+        ui.label("Scene");
+        let Some(statistics) = self.tracing_state.scene_statistics.read().clone() else {
+            ui.label("No scene loaded.");
+            return;
+        };
+        egui::Grid::new("SceneGrid")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("Triangles");
+            ui.label(statistics.triangles.to_string());
+            ui.end_row();
+
+            if statistics.points > 0 {
+                ui.label("Points");
+                ui.label(statistics.points.to_string());
+                ui.end_row();
+            }
+
+            if statistics.sdfs > 0 {
+                ui.label("SDFs");
+                ui.label(statistics.sdfs.to_string());
+                ui.end_row();
+            }
+
+            ui.label("Vertices");
+            ui.label(statistics.vertices.to_string());
+            ui.end_row();
+
+            ui.label("Meshes");
+            ui.label(statistics.meshes.to_string());
+            ui.end_row();
+
+            ui.label("Materials");
+            ui.label(statistics.materials.to_string());
+            ui.end_row();
+
+            ui.label("Emissive triangles");
+            ui.label(statistics.emissive_triangles.to_string());
+            ui.end_row();
+
+            ui.label("BVH nodes");
+            ui.label(statistics.bvh_nodes.to_string());
+            ui.end_row();
+
+            ui.label("BVH depth");
+            ui.label(statistics.bvh_depth.to_string());
+            ui.end_row();
+
+            ui.label("Atlas occupancy");
+            ui.label(format!("{:.1}% ({} textures)", statistics.atlas_occupancy * 100.0, statistics.textures));
+            ui.end_row();
+
+            let size = statistics.bounds.1 - statistics.bounds.0;
+            ui.label("Size");
+            ui.label(format!("{:.2} x {:.2} x {:.2}", size.x, size.y, size.z));
+            ui.end_row();
+        });
+    }
+
+    fn resources_ui(&mut self, ui: &mut egui::Ui) {
+        let usage = self.tracing_state.memory_usage.read();
+        if usage.is_empty() {
+            ui.label("No scene loaded.");
+            return;
+        }
+
+        egui::Grid::new("ResourcesGrid")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.strong("Resource");
+            ui.strong("CPU");
+            ui.strong("GPU");
+            ui.end_row();
+
+            for resource in usage.iter() {
+                ui.label(resource.name);
+                ui.label(format_bytes(resource.cpu_bytes));
+                ui.label(format_bytes(resource.gpu_bytes));
+                ui.end_row();
+            }
+
+            ui.strong("Total");
+            ui.strong(format_bytes(usage.iter().map(|r| r.cpu_bytes).sum()));
+            ui.strong(format_bytes(usage.iter().map(|r| r.gpu_bytes).sum()));
+            ui.end_row();
+        });
+
+        ui.separator();
+        let largest = usage.iter().map(|r| r.gpu_bytes).max().unwrap_or(0);
+        let limit = GPU_LIMITS.max_storage_buffer_binding_size as u64;
+        ui.label(format!("Largest GPU buffer: {} of {} allowed", format_bytes(largest), format_bytes(limit)));
+    }
+
+    // Every vertex of the most recent debug path, as traced on the CPU
+    fn path_debugger_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Ctrl-click the viewport to trace a single path through a pixel. Disable jitter to trace through its center.");
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.draw_debug_path, "Draw path");
+            if ui.button("Clear").clicked() {
+                *self.tracing_state.debug_path.write() = None;
+            }
+        });
+
+        let debug_path = self.tracing_state.debug_path.read();
+        let Some(path) = debug_path.as_ref() else {
+            ui.label("No path traced.");
+            return;
+        };
+        ui.label(format!("Pixel ({}, {}), radiance {:.4} {:.4} {:.4}", path.pixel.x, path.pixel.y, path.radiance.x, path.radiance.y, path.radiance.z));
+        if !self.use_cpu {
+            ui.label("When rendering on the GPU, this is a random sample of the pixel, rather than its next one.");
+        }
+
+        let format_vec = |v: Vec3| format!("{:.3} {:.3} {:.3}", v.x, v.y, v.z);
+        egui::ScrollArea::both().show(ui, |ui| {
+            egui::Grid::new("PathDebuggerGrid")
+            .striped(true)
+            .show(ui, |ui| {
+                for heading in ["Bounce", "Event", "Position", "Normal", "Material", "Lobe", "PDF", "Cone width", "Throughput", "Radiance"] {
+                    ui.strong(heading);
+                }
+                ui.end_row();
+
+                let material_names = self.tracing_state.materials.read();
+                for vertex in path.vertices.iter() {
+                    let hit = vertex.event != PathEvent::Escaped;
+                    ui.label(vertex.bounce.to_string());
+                    ui.label(event_name(vertex.event));
+                    ui.label(if hit { format_vec(vertex.position) } else { "-".to_string() });
+                    ui.label(if vertex.normal != Vec3::ZERO { format_vec(vertex.normal) } else { "-".to_string() });
+                    ui.label(if hit {
+                        material_names.get(vertex.material_index as usize).map_or_else(|| vertex.material_index.to_string(), |material| material.name.clone())
+                    } else {
+                        "-".to_string()
+                    });
+                    ui.label(sampled_lobe(vertex));
+                    ui.label(if vertex.pdf > 0.0 { format!("{:.4}", vertex.pdf) } else { "-".to_string() });
+                    ui.label(if hit { format!("{:.4}", vertex.cone_width) } else { "-".to_string() });
+                    ui.label(format_vec(vertex.throughput));
+                    ui.label(format_vec(vertex.radiance));
+                    ui.end_row();
+                }
+            });
+        });
+    }
+
+    // Emission overrides, so lights can be tweaked without re-exporting the scene
+    fn materials_ui(&mut self, ui: &mut egui::Ui) {
+        let mut materials = self.tracing_state.materials.write();
+        if materials.is_empty() {
+            ui.label("No scene loaded.");
+            return;
+        }
+        let library = self.tracing_state.material_library.read().clone();
+        self.material_previews.update(ui.ctx(), library, &materials);
+        let previews = &self.material_previews;
+
+        let lights = materials
+            .iter()
+            .enumerate()
+            .filter(|(_, material)| material.emissive().truncate() != Vec3::ZERO)
+            .map(|(index, material)| (index, material.name.clone()))
+            .collect::<Vec<_>>();
+
+        let mut changed = false;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("MaterialsGrid")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("");
+                ui.strong("Material");
+                ui.strong("Emission");
+                ui.strong("Strength");
+                ui.strong("Lit by");
+                ui.strong("Visible to");
+                ui.strong("CSG");
+                ui.end_row();
+
+                for (index, material) in materials.iter_mut().enumerate() {
+                    match previews.thumbnail(index) {
+                        Some(thumbnail) => ui.image(thumbnail.id(), egui::vec2(MATERIAL_THUMBNAIL_SIZE, MATERIAL_THUMBNAIL_SIZE)),
+                        None => ui.spinner(),
+                    };
+                    ui.label(&material.name);
+                    let mut color = material.color.to_array();
+                    if ui.color_edit_button_rgb(&mut color).changed() {
+                        material.color = Vec3::from(color);
+                        // Picking a color for a material that doesn't emit should make it emit
+                        if material.strength == 0.0 {
+                            material.strength = 1.0;
+                        }
+                        changed = true;
+                    }
+                    changed |= ui.add(egui::DragValue::new(&mut material.strength).speed(0.1).clamp_range(0.0..=1000.0)).changed();
+                    // Light linking, for keeping a light off of some materials without moving it
+                    let unlinked = lights.iter().filter(|(index, _)| material.unlinked_lights.contains(index)).count();
+                    let label = match (unlinked, material.unlinked_environment) {
+                        (0, false) => "All lights".to_string(),
+                        (0, true) => "Only lights".to_string(),
+                        (_, false) => format!("{} of {} lights", lights.len() - unlinked, lights.len()),
+                        (_, true) => format!("{} of {} lights only", lights.len() - unlinked, lights.len()),
+                    };
+                    ui.menu_button(label, |ui| {
+                        let mut environment = !material.unlinked_environment;
+                        if ui.checkbox(&mut environment, "Environment").changed() {
+                            material.unlinked_environment = !environment;
+                            changed = true;
+                        }
+                        for (index, name) in lights.iter() {
+                            let mut linked = !material.unlinked_lights.contains(index);
+                            if ui.checkbox(&mut linked, name).changed() {
+                                if linked {
+                                    material.unlinked_lights.remove(index);
+                                } else {
+                                    material.unlinked_lights.insert(*index);
+                                }
+                                changed = true;
+                            }
                         }
-                        self.tracing_state.dirty.store(true, Ordering::Relaxed);
-                    }
-                    ui.end_row();
-
-                    if ui.add(egui::Slider::new(&mut config.specular_weight_clamp.y, 0.0..=1.0).text("Max specular")).changed() {
-                        if config.specular_weight_clamp.x > config.specular_weight_clamp.y {
-                            config.specular_weight_clamp.x = config.specular_weight_clamp.y;
+                    })
+                    .response
+                    .on_hover_text("Lights that shine on this material, and whether the environment does. Unlinked lights don't light it, and it doesn't see them in reflections.");
+                    // Visibility, for invisible emitters and objects that only show up in reflections
+                    let visibility = [(HIDDEN_FROM_CAMERA, "Camera"), (HIDDEN_FROM_SHADOWS, "Shadows"), (HIDDEN_FROM_INDIRECT, "Indirect")];
+                    let label = match visibility.iter().filter(|(flag, _)| material.hidden & flag == 0).count() {
+                        3 => "All rays".to_string(),
+                        0 => "No rays".to_string(),
+                        _ => visibility.iter().filter(|(flag, _)| material.hidden & flag == 0).map(|(_, name)| *name).collect::<Vec<_>>().join(", "),
+                    };
+                    ui.menu_button(label, |ui| {
+                        for (flag, name) in visibility {
+                            let mut visible = material.hidden & flag == 0;
+                            if ui.checkbox(&mut visible, name).changed() {
+                                material.hidden ^= flag;
+                                changed = true;
+                            }
                         }
-                        self.tracing_state.dirty.store(true, Ordering::Relaxed);
+                    })
+                    .response
+                    .on_hover_text("Which rays see this material. Camera rays make it visible, shadow rays let it cast shadows, and indirect rays make it show up in reflections and bounce light.");
+                    // Boolean operations between the closed meshes using each material, for cutaways
+                    let csg_name = |csg: Option<CsgOperation>| csg.map_or("None".to_string(), |operation| format!("{:?}", operation));
+                    let previous = material.csg;
+                    egui::ComboBox::from_id_source(("MaterialCsg", index))
+                        .selected_text(csg_name(material.csg))
+                        .show_ui(ui, |ui| {
+                            for option in [None, Some(CsgOperation::Union), Some(CsgOperation::Subtract), Some(CsgOperation::Intersect)] {
+                                ui.selectable_value(&mut material.csg, option, csg_name(option));
+                            }
+                        })
+                        .response
+                        .on_hover_text("Union meshes are solids, subtract meshes cut them away, and if there are any intersect meshes, the solids are only kept inside them. Only surfaces of the result show, so meshes have to be closed.");
+                    changed |= material.csg != previous;
+                    if ui.add_enabled(material.is_edited(), egui::Button::new("Reset")).clicked() {
+                        material.reset();
+                        changed = true;
                     }
                     ui.end_row();
                 }
+            });
+        });
 
-                egui::ComboBox::from_label("Tonemapping operator")
-                    .selected_text(format!("{:?}", self.tonemapping))
-                    .show_ui(ui, |ui| {
-                        ui.selectable_value(&mut self.tonemapping, Tonemapping::None, "None");
-                        ui.selectable_value(&mut self.tonemapping, Tonemapping::ACESNarkowicz, "ACES (Narkowicz)");
-                        ui.selectable_value(&mut self.tonemapping, Tonemapping::ACESNarkowiczOverexposed, "ACES (Narkowicz, overexposed)");
-                        ui.selectable_value(&mut self.tonemapping, Tonemapping::ACESHill, "ACES (Hill)");
-                        ui.selectable_value(&mut self.tonemapping, Tonemapping::Neutral, "Neutral");
-                        ui.selectable_value(&mut self.tonemapping, Tonemapping::Reinhard, "Reinhard");
-                        ui.selectable_value(&mut self.tonemapping, Tonemapping::Uncharted, "Uncharted");
-                    });
-                ui.end_row();
+        if changed {
+            self.tracing_state.mark_dirty(DirtyFlags::MATERIALS);
+        }
+    }
 
-                if ui.button("Environment settings").clicked() {
-                    self.show_environment_window = !self.show_environment_window;
-                }
-                ui.end_row();
+    fn panel_ui(&mut self, ui: &mut egui::Ui, panel: Panel) {
+        match panel {
+            Panel::Settings => self.settings_ui(ui),
+            Panel::Environment => self.environment_ui(ui),
+            Panel::Convergence => self.convergence_ui(ui),
+            Panel::Statistics => self.statistics_ui(ui),
+            Panel::Resources => self.resources_ui(ui),
+            Panel::Profiler => puffin_egui::profiler_ui(ui),
+            Panel::Log => self.log_ui(ui),
+            Panel::SceneBrowser => self.scene_browser_ui(ui),
+            Panel::Materials => self.materials_ui(ui),
+            Panel::PathDebugger => self.path_debugger_ui(ui),
+        }
+    }
 
-                ui.separator();
-                ui.end_row();
+    // Lets the user move a panel between the floating windows and the docked areas
+    fn panel_header_ui(&mut self, ui: &mut egui::Ui, panel: Panel) {
+        ui.horizontal(|ui| {
+            let mut dock = self.layout.dock(panel);
+            egui::ComboBox::from_id_source((panel, "Dock"))
+                .selected_text(format!("{:?}", dock))
+                .show_ui(ui, |ui| {
+                    for option in Dock::ALL {
+                        ui.selectable_value(&mut dock, option, format!("{:?}", option));
+                    }
+                });
+            ui.label("Dock");
+            self.layout.set_dock(panel, dock);
 
-                egui::ComboBox::from_label("Compute device")
-                    .selected_text(if self.use_cpu { "CPU" } else { "GPU" })
-                    .show_ui(ui, |ui| {
-                        if ui.selectable_label(!self.use_cpu, "GPU").clicked() { 
-                            self.use_cpu = false;
-                            self.restart_current_render(true);
-                        };
-                        if ui.selectable_label(self.use_cpu, "CPU").clicked() {
-                            self.use_cpu = true;
-                            self.restart_current_render(true);
-                        };
-                    });
-                ui.end_row();
+            // Floating windows have their own close button
+            if dock != Dock::Floating && panel.closable() && ui.button("Close").clicked() {
+                self.layout.set_open(panel, false);
+            }
+        });
+        ui.separator();
+    }
 
-                let mut sync_rate = self.tracing_state.sync_rate.load(Ordering::Relaxed);
-                if ui.add_enabled(!self.use_cpu, egui::Slider::new(&mut sync_rate, 1..=256).text("GPU sync rate")).changed() {
-                    self.tracing_state.sync_rate.store(sync_rate, Ordering::Relaxed);
-                }
-                ui.end_row();
-        
-                ui.label(format!(
-                    "Samples: {}",
-                    self.tracing_state.samples.load(Ordering::Relaxed)
-                ));
-                ui.end_row();
-            });
+    fn show_floating_panel(&mut self, egui_ctx: &egui::Context, panel: Panel) {
+        let mut open = true;
+        let window = egui::Window::new(panel.title());
+        let window = if panel.closable() { window.open(&mut open) } else { window };
+        window.show(egui_ctx, |ui| {
+            self.panel_header_ui(ui, panel);
+            self.panel_ui(ui, panel);
         });
+        if !open {
+            self.layout.set_open(panel, false);
+        }
     }
 
-    fn on_environment_gui(&mut self, egui_ctx: &egui::Context) {
-        let mut show_environment_window = self.show_environment_window;
-        egui::Window::new("Environment").open(&mut show_environment_window).show(egui_ctx, |ui| {
-            let mouse_down = ui.input().pointer.primary_down();
-            let sun_direction = self.tracing_state.config.read().sun_direction;
-            {
-                let skybox_name = self.selected_skybox.as_ref().map(|s| s.as_ref()).unwrap_or("Procedural");
-                ui.label(format!("Selected skybox: {}", skybox_name));
-            }
-            ui.horizontal(|ui| {
-                if ui.button("Select skybox").clicked() {
-                    if let Some(path) = tinyfiledialogs::open_file_dialog("Select skybox", "", None) {
-                        self.set_skybox(&path);
+    // Panels docked to the same side share an area, and are switched between with tabs
+    fn show_dock_area(&mut self, egui_ctx: &egui::Context, dock: Dock) {
+        let panels = self.layout.panels_in(dock);
+        if panels.is_empty() {
+            return;
+        }
+
+        let add_contents = |ui: &mut egui::Ui| {
+            let mut active = self.layout.active_tab(dock, &panels);
+            ui.horizontal_wrapped(|ui| {
+                for &panel in panels.iter() {
+                    if ui.selectable_label(panel == active, panel.title()).clicked() {
+                        active = panel;
                     }
                 }
-                if ui.button("Reset skybox").clicked() {
-                    self.clear_skybox();
-                }
             });
+            self.layout.set_active_tab(dock, active);
+            ui.separator();
 
-            let mut sun_intensity = sun_direction.w;
-            if ui.add(egui::Slider::new(&mut sun_intensity, 0.0..=50.0).text("Sun intensity")).changed() {
-                self.tracing_state.config.write().sun_direction.w = sun_intensity;
-                self.tracing_state.dirty.store(true, Ordering::Relaxed);
+            self.panel_header_ui(ui, active);
+            egui::ScrollArea::vertical()
+                .id_source(active)
+                .show(ui, |ui| self.panel_ui(ui, active));
+        };
+        match dock {
+            Dock::Left => {
+                egui::SidePanel::left("DockLeft").resizable(true).default_width(380.0).show(egui_ctx, add_contents);
             }
-            ui.end_row();
+            Dock::Right => {
+                egui::SidePanel::right("DockRight").resizable(true).default_width(380.0).show(egui_ctx, add_contents);
+            }
+            Dock::Bottom => {
+                egui::TopBottomPanel::bottom("DockBottom").resizable(true).default_height(250.0).show(egui_ctx, add_contents);
+            }
+            Dock::Floating => {}
+        }
+    }
 
-            egui::plot::Plot::new("Sun position")
-                .view_aspect(1.0)
-                .allow_drag(false)
-                .allow_zoom(false)
-                .allow_scroll(false)
-                .allow_boxed_zoom(false)
-                .height(250.0)
-                .show(ui, |ui| {
-                let n = 512;
-                let circle_points: egui::plot::PlotPoints = (0..=n)
-                    .map(|i| {
-                        let t = egui::remap(i as f64, 0.0..=(n as f64), 0.0..=6.28);
-                        let r = 1.0;
-                        [
-                            r * t.cos() + 0.0 as f64,
-                            r * t.sin() + 0.0 as f64,
-                        ]
-                    })
-                    .collect();
-                ui.line(egui::plot::Line::new(circle_points));
-
-                let sun_pos = [sun_direction.x as f64, sun_direction.z as f64];
-                ui.points(egui::plot::Points::new(vec![sun_pos])
-                    .color(egui::Color32::GOLD)
-                    .shape(egui::plot::MarkerShape::Asterisk)
-                    .radius(8.0)
-                    .name("Sun position"));
-                
-                let pointer = ui.pointer_coordinate();
-                if let Some(pointer) = pointer {
-                    if mouse_down && pointer.x.abs() <= 1.0 && pointer.y.abs() <= 1.0 {
-                        let mut new_pos = pointer.to_vec2();
-                        if new_pos.length() > 1.0 {
-                            new_pos = new_pos.normalized();
+    fn scene_browser_ui(&mut self, ui: &mut egui::Ui) {
+        self.scene_browser.update(ui.ctx());
+        if self.scene_browser.entries.is_empty() {
+            ui.label("No scenes found in the scenes directory.");
+            return;
+        }
+
+        let mut selected = None;
+        let size = egui::vec2(160.0, 90.0);
+        ui.horizontal_wrapped(|ui| {
+            for entry in self.scene_browser.entries.iter() {
+                ui.vertical(|ui| {
+                    let clicked = match entry.thumbnail.as_ref() {
+                        Some(thumbnail) => ui.add(egui::ImageButton::new(thumbnail.id(), size)).clicked(),
+                        None => ui.add_sized(size, egui::Button::new("Rendering...")).clicked(),
+                    };
+                    if clicked {
+                        selected = Some(entry.path.clone());
+                    }
+                    ui.label(entry.name.as_str());
+                });
+            }
+        });
+
+        if let Some(path) = selected {
+            self.set_scene(&path);
+        }
+    }
+
+    fn menu_bar_ui(&mut self, ui: &mut egui::Ui) {
+        egui::menu::bar(ui, |ui| {
+            ui.menu_button("File", |ui| {
+                if ui.button("Select scene...").clicked() {
+                    ui.close_menu();
+                    self.select_scene();
+                }
+                if ui.button("Select skybox...").clicked() {
+                    ui.close_menu();
+                    self.select_skybox();
+                }
+                if ui.button("Scene browser").clicked() {
+                    ui.close_menu();
+                    self.layout.set_open(Panel::SceneBrowser, true);
+                }
+                ui.separator();
+
+                ui.menu_button("Recent scenes", |ui| {
+                    let mut selected = None;
+                    for path in self.recent.scenes.iter() {
+                        if ui.button(path.as_str()).clicked() {
+                            selected = Some(path.clone());
+                        }
+                    }
+                    if let Some(path) = selected {
+                        ui.close_menu();
+                        self.set_scene(&path);
+                    }
+                });
+                ui.menu_button("Recent skyboxes", |ui| {
+                    let mut selected = None;
+                    for path in self.recent.skyboxes.iter() {
+                        if ui.button(path.as_str()).clicked() {
+                            selected = Some(path.clone());
                         }
-                        let new_pos_y = (1.0 - new_pos.x * new_pos.x - new_pos.y * new_pos.y).sqrt();
-                        let new_pos_vec = Vec3::new(new_pos.x as f32, new_pos_y as f32, new_pos.y as f32).normalize();
-                        
-                        self.tracing_state.config.write().sun_direction = new_pos_vec.extend(sun_direction.w);
-                        self.tracing_state.dirty.store(true, Ordering::Relaxed);
                     }
+                    if let Some(path) = selected {
+                        ui.close_menu();
+                        self.set_skybox(&path);
+                    }
+                });
+                ui.separator();
+
+                if ui.button("Save image").clicked() {
+                    ui.close_menu();
+                    self.save_image();
+                }
+                let motion_vectors = self.tracing_state.motion_vectors_enabled.load(Ordering::Relaxed);
+                if ui.add_enabled(motion_vectors, egui::Button::new("Save motion vectors")).clicked() {
+                    ui.close_menu();
+                    self.save_motion_vectors();
+                }
+                if ui.button("Save variance").on_hover_text("Save the variance of each pixel, estimated from its odd and even samples").clicked() {
+                    ui.close_menu();
+                    self.save_variance();
+                }
+                if ui.button("Save scene file").on_hover_text("Save the scene along with the camera, environment and render settings").clicked() {
+                    ui.close_menu();
+                    self.save_scene_file();
                 }
             });
         });
-        self.show_environment_window = show_environment_window;
+    }
+
+    fn log_ui(&mut self, ui: &mut egui::Ui) {
+        if ui.button("Clear").clicked() {
+            logging::clear();
+        }
+        ui.separator();
+        egui::ScrollArea::vertical()
+            .stick_to_bottom(true)
+            .max_height(300.0)
+            .show(ui, |ui| {
+                logging::with_entries(|entries| {
+                    for entry in entries.iter() {
+                        let color = match entry.level {
+                            tracing::Level::ERROR => egui::Color32::LIGHT_RED,
+                            tracing::Level::WARN => egui::Color32::GOLD,
+                            _ => ui.visuals().text_color(),
+                        };
+                        ui.colored_label(color, format!("[{}] {}", entry.level, entry.message))
+                            .on_hover_text(entry.target.as_str());
+                    }
+                });
+            });
     }
 
     fn handle_input(&mut self, ui: &egui::Ui) {
@@ -451,15 +2815,19 @@ impl App {
         }
     
         let mut config = self.tracing_state.config.write();
+        let previous_position = config.camera.cam_position;
+        let previous_rotation = config.camera.cam_rotation;
     
         let mut forward = Vec3::new(0.0, 0.0, 1.0);
         let mut right = Vec3::new(1.0, 0.0, 0.0);
         let euler_mat =
-            Mat3::from_rotation_y(config.cam_rotation.y) * Mat3::from_rotation_x(config.cam_rotation.x);
+            Mat3::from_rotation_y(config.camera.cam_rotation.y) * Mat3::from_rotation_x(config.camera.cam_rotation.x);
         forward = euler_mat * forward;
         right = euler_mat * right;
     
-        let speed = if ui.input().modifiers.shift {
+        // Tuned for scenes about 10 units across, and scaled to the size of the loaded model
+        let scene_size = self.tracing_state.scene_statistics.read().as_ref().map_or(10.0, |statistics| (statistics.model_bounds.1 - statistics.model_bounds.0).length());
+        let speed = scene_size / 10.0 * if ui.input().modifiers.shift {
             0.5
         } else if ui.input().modifiers.ctrl {
             0.01
@@ -467,32 +2835,308 @@ impl App {
             0.1
         };
 
-        if ui.input().key_down(egui::Key::W) {
-            config.cam_position += forward.extend(0.0) * speed;
+        // Don't fly around while typing into a text field, such as the command palette
+        if !ui.ctx().wants_keyboard_input() {
+            if ui.input().key_down(egui::Key::W) {
+                config.camera.cam_position += forward.extend(0.0) * speed;
+            }
+            if ui.input().key_down(egui::Key::S) {
+                config.camera.cam_position -= forward.extend(0.0) * speed;
+            }
+            if ui.input().key_down(egui::Key::D) {
+                config.camera.cam_position += right.extend(0.0) * speed;
+            }
+            if ui.input().key_down(egui::Key::A) {
+                config.camera.cam_position -= right.extend(0.0) * speed;
+            }
+            if ui.input().key_down(egui::Key::E) {
+                config.camera.cam_position.y += speed;
+            }
+            if ui.input().key_down(egui::Key::Q) {
+                config.camera.cam_position.y -= speed;
+            }
+        }
+
+        config.camera.cam_rotation.x += self.mouse_delta.1 * 0.005;
+        config.camera.cam_rotation.y += self.mouse_delta.0 * 0.005;
+        self.mouse_delta = (0.0, 0.0);
+
+        if config.camera.cam_position != previous_position || config.camera.cam_rotation != previous_rotation {
+            self.tracing_state.mark_dirty(DirtyFlags::CAMERA);
         }
-        if ui.input().key_down(egui::Key::S) {
-            config.cam_position -= forward.extend(0.0) * speed;
+    }
+
+    // Shift-clicking or dragging across the viewport points the sun along the camera ray under the cursor
+    fn place_sun(&mut self, ui: &egui::Ui, response: &egui::Response, rect: egui::Rect) {
+        if !ui.input().modifiers.shift || !ui.input().pointer.primary_down() {
+            return;
         }
-        if ui.input().key_down(egui::Key::D) {
-            config.cam_position += right.extend(0.0) * speed;
+        let Some(pos) = response.hover_pos().filter(|pos| rect.contains(*pos)) else {
+            return;
+        };
+
+        // Same as the camera setup in the kernel, minus the jitter
+        let mut config = self.tracing_state.config.write();
+        let pixel = pos - rect.min;
+        let uv = Vec2::new(pixel.x / rect.width(), 1.0 - pixel.y / rect.height()) * 2.0 - 1.0;
+        let uv = Vec2::new(uv.x, uv.y * config.render.height as f32 / config.render.width as f32);
+        let euler_mat = Mat3::from_rotation_y(config.camera.cam_rotation.y) * Mat3::from_rotation_x(config.camera.cam_rotation.x);
+        let direction = euler_mat * Vec3::new(uv.x, uv.y, 1.0).normalize();
+
+        config.environment.sun_direction = direction.extend(config.environment.sun_direction.w);
+        self.tracing_state.mark_dirty(DirtyFlags::LIGHTING);
+    }
+
+    // Ctrl-clicking the viewport traces a single path through the pixel under the cursor
+    fn pick_debug_pixel(&mut self, ui: &egui::Ui, response: &egui::Response, rect: egui::Rect, width: u32, height: u32) {
+        if !ui.input().modifiers.ctrl || !ui.input().pointer.primary_clicked() {
+            return;
         }
-        if ui.input().key_down(egui::Key::A) {
-            config.cam_position -= right.extend(0.0) * speed;
+        let Some(pos) = response.hover_pos().filter(|pos| rect.contains(*pos)) else {
+            return;
+        };
+
+        let pixel = pos - rect.min;
+        let x = ((pixel.x / rect.width() * width as f32) as u32).min(width - 1);
+        let y = ((pixel.y / rect.height() * height as f32) as u32).min(height - 1);
+        *self.tracing_state.debug_pixel.lock() = Some(glam::UVec2::new(x, y));
+        self.layout.set_open(Panel::PathDebugger, true);
+    }
+
+    // Alt-dragging across the viewport draws the region to denoise, the rest of the image stays raw
+    fn pick_denoise_region(&mut self, ui: &egui::Ui, response: &egui::Response, rect: egui::Rect, width: u32, height: u32) {
+        if !ui.input().modifiers.alt {
+            self.denoise_region_start = None;
+            return;
         }
-        if ui.input().key_down(egui::Key::E) {
-            config.cam_position.y += speed;
+        let to_pixel = |pos: egui::Pos2| {
+            let pixel = pos.clamp(rect.min, rect.max) - rect.min;
+            let x = ((pixel.x / rect.width() * width as f32) as u32).min(width - 1);
+            let y = ((pixel.y / rect.height() * height as f32) as u32).min(height - 1);
+            glam::UVec2::new(x, y)
+        };
+        if response.drag_started() {
+            self.denoise_region_start = response.interact_pointer_pos().filter(|pos| rect.contains(*pos)).map(to_pixel);
         }
-        if ui.input().key_down(egui::Key::Q) {
-            config.cam_position.y -= speed;
+        let (Some(start), Some(pos)) = (self.denoise_region_start, response.interact_pointer_pos()) else {
+            return;
+        };
+        if response.dragged_by(egui::PointerButton::Primary) {
+            let end = to_pixel(pos);
+            let (min, max) = (start.min(end), start.max(end));
+            *self.tracing_state.denoise_region.write() = Some(Tile { x: min.x, y: min.y, width: max.x - min.x + 1, height: max.y - min.y + 1 });
+        }
+    }
+
+    // Projects the debug path with the current camera, the inverse of the camera setup in the kernel
+    fn draw_debug_path(&self, ui: &egui::Ui, rect: egui::Rect, width: u32, height: u32) {
+        let debug_path = self.tracing_state.debug_path.read();
+        let Some(path) = debug_path.as_ref().filter(|_| self.draw_debug_path) else {
+            return;
+        };
+
+        let config = *self.tracing_state.config.read();
+        let euler_mat = Mat3::from_rotation_y(config.camera.cam_rotation.y) * Mat3::from_rotation_x(config.camera.cam_rotation.x);
+        let to_camera = euler_mat.transpose();
+        let aspect = height as f32 / width as f32;
+        let to_screen = |point: Vec3| {
+            let uv = Vec2::new(point.x / point.z, point.y / point.z / aspect);
+            rect.min + egui::vec2((uv.x + 1.0) / 2.0 * rect.width(), (1.0 - uv.y) / 2.0 * rect.height())
+        };
+
+        // Rays that escape are drawn as long as the scene is wide
+        let escape_length = self.tracing_state.scene_statistics.read().as_ref().map_or(10.0, |statistics| (statistics.bounds.1 - statistics.bounds.0).length());
+        let near = 1e-3;
+        for vertex in path.vertices.iter() {
+            let end = if vertex.event == PathEvent::Escaped {
+                vertex.origin + vertex.direction * escape_length
+            } else {
+                vertex.position
+            };
+            let mut a = to_camera * (vertex.origin - config.camera.cam_position.truncate());
+            let mut b = to_camera * (end - config.camera.cam_position.truncate());
+            if a.z < near && b.z < near {
+                continue;
+            }
+            // Clip against the near plane, so segments passing behind the camera don't flip
+            if a.z < near {
+                a = a.lerp(b, (near - a.z) / (b.z - a.z));
+            } else if b.z < near {
+                b = b.lerp(a, (near - b.z) / (a.z - b.z));
+            }
+            let color = if vertex.event == PathEvent::Escaped { egui::Color32::LIGHT_BLUE } else { egui::Color32::GOLD };
+            ui.painter().with_clip_rect(rect).line_segment([to_screen(a), to_screen(b)], egui::Stroke::new(1.5, color));
+            if vertex.event != PathEvent::Escaped {
+                ui.painter().with_clip_rect(rect).circle_filled(to_screen(b), 3.0, color);
+            }
+        }
+    }
+
+    // Draws each section plane as an outline with an arrow along its normal, and drags the plane
+    // along the arrow when its tip is dragged. Uses the same projection as draw_debug_path.
+    fn section_gizmos(&mut self, ui: &egui::Ui, rect: egui::Rect, width: u32, height: u32) {
+        let (center, scene_size) = match self.tracing_state.scene_statistics.read().as_ref() {
+            Some(statistics) => ((statistics.model_bounds.0 + statistics.model_bounds.1) / 2.0, (statistics.model_bounds.1 - statistics.model_bounds.0).length()),
+            None => return,
+        };
+        if !ui.input().pointer.primary_down() {
+            self.section_drag = None;
+        }
+
+        let mut config = self.tracing_state.config.write();
+        if config.camera.projection != PROJECTION_PERSPECTIVE {
+            return;
+        }
+        let euler_mat = Mat3::from_rotation_y(config.camera.cam_rotation.y) * Mat3::from_rotation_x(config.camera.cam_rotation.x);
+        let to_camera = euler_mat.transpose();
+        let camera_position = config.camera.cam_position.truncate();
+        let aspect = height as f32 / width as f32;
+        let near = 1e-3;
+        let to_screen = |point: Vec3| {
+            let point = to_camera * (point - camera_position);
+            let uv = Vec2::new(point.x / point.z, point.y / point.z / aspect);
+            (point.z > near).then(|| rect.min + egui::vec2((uv.x + 1.0) / 2.0 * rect.width(), (1.0 - uv.y) / 2.0 * rect.height()))
+        };
+
+        let length = scene_size * SECTION_GIZMO_SCALE;
+        let painter = ui.painter().with_clip_rect(rect);
+        let pointer = ui.input().pointer.interact_pos();
+        let mut changed = false;
+        let count = (config.render.section_plane_count as usize).min(MAX_SECTION_PLANES);
+        for (index, plane) in config.render.section_planes[..count].iter_mut().enumerate() {
+            let normal = plane.truncate();
+            let anchor = section_anchor(*plane, center);
+            let (tangent, bitangent) = normal.any_orthonormal_pair();
+            let corners = [tangent + bitangent, tangent - bitangent, -tangent - bitangent, -tangent + bitangent].map(|corner| to_screen(anchor + corner * length));
+            let (Some(base), Some(tip)) = (to_screen(anchor), to_screen(anchor + normal * length)) else {
+                continue;
+            };
+
+            let dragged = self.section_drag == Some(index);
+            let hovered = pointer.map_or(false, |pos| pos.distance(tip) < 8.0);
+            if hovered && ui.input().pointer.primary_pressed() && !ui.input().modifiers.any() {
+                self.section_drag = Some(index);
+            }
+            if dragged {
+                // Moves the plane by how far the pointer went along the arrow on screen
+                let arrow = tip - base;
+                let delta = ui.input().pointer.delta();
+                let moved = (delta.x * arrow.x + delta.y * arrow.y) / arrow.length_sq().max(1e-6) * length;
+                if moved != 0.0 {
+                    plane.w -= moved;
+                    changed = true;
+                }
+            }
+
+            let color = if dragged || hovered { egui::Color32::WHITE } else { egui::Color32::from_rgb(255, 120, 60) };
+            if let [Some(a), Some(b), Some(c), Some(d)] = corners {
+                painter.add(egui::Shape::closed_line(vec![a, b, c, d], egui::Stroke::new(1.0, color)));
+            }
+            painter.arrow(base, tip - base, egui::Stroke::new(2.0, color));
+            painter.circle_filled(tip, 5.0, color);
+        }
+
+        if changed {
+            self.tracing_state.mark_dirty(DirtyFlags::CAMERA);
+        }
+    }
+
+    // Outlines the region being denoised, see blend_denoise_region
+    fn draw_denoise_region(&self, ui: &egui::Ui, rect: egui::Rect, width: u32, height: u32) {
+        let state = &self.tracing_state;
+        let Some(region) = *state.denoise_region.read() else {
+            return;
+        };
+        if !state.denoise.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let scale = egui::vec2(rect.width() / width as f32, rect.height() / height as f32);
+        let region_rect = egui::Rect::from_min_size(
+            rect.min + egui::vec2(region.x as f32, region.y as f32) * scale,
+            egui::vec2(region.width as f32, region.height as f32) * scale,
+        );
+        ui.painter().rect_stroke(region_rect.intersect(rect), 0.0, egui::Stroke::new(1.0, egui::Color32::LIGHT_BLUE));
+    }
+
+    // Divides the raw and denoised halves of the image, see compose_denoise_comparison
+    fn draw_denoise_comparison(&self, ui: &egui::Ui, rect: egui::Rect) {
+        let state = &self.tracing_state;
+        if !state.denoise.load(Ordering::Relaxed) || !state.denoise_comparison.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let painter = ui.painter();
+        let x = rect.center().x;
+        painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())], egui::Stroke::new(1.0, egui::Color32::WHITE));
+        let font = egui::FontId::proportional(14.0);
+        painter.text(egui::pos2(x - 8.0, rect.top() + 8.0), egui::Align2::RIGHT_TOP, "Raw", font.clone(), egui::Color32::WHITE);
+        painter.text(egui::pos2(x + 8.0, rect.top() + 8.0), egui::Align2::LEFT_TOP, "Denoised", font, egui::Color32::WHITE);
+    }
+
+    // Outline the buckets of the current CPU pass, highlighting the ones being worked on
+    fn draw_tile_progress(&self, ui: &egui::Ui, rect: egui::Rect, width: u32, height: u32) {
+        let state = &self.tracing_state;
+        if !self.use_cpu || !state.bucket_rendering.load(Ordering::Relaxed) || !state.running.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let scale = egui::vec2(rect.width() / width as f32, rect.height() / height as f32);
+        let tile_rect = |tile: &Tile| {
+            egui::Rect::from_min_size(
+                rect.min + egui::vec2(tile.x as f32, tile.y as f32) * scale,
+                egui::vec2(tile.width as f32, tile.height as f32) * scale,
+            )
+        };
+        let progress = state.tile_progress.lock();
+        for tile in progress.finished.iter() {
+            ui.painter().rect_stroke(tile_rect(tile), 0.0, egui::Stroke::new(1.0, egui::Color32::from_white_alpha(16)));
+        }
+        for tile in progress.active.iter() {
+            ui.painter().rect_stroke(tile_rect(tile), 0.0, egui::Stroke::new(1.0, egui::Color32::GOLD));
         }
-    
-        config.cam_rotation.x += self.mouse_delta.1 * 0.005;
-        config.cam_rotation.y += self.mouse_delta.0 * 0.005;
-        self.mouse_delta = (0.0, 0.0);
+    }
+
+    // Upload the most recent frame to the display, unless it is already there
+    fn upload_framebuffer(&mut self, packed: bool) {
+        let generation = if self.show_noise {
+            self.tracing_state.variance.generation()
+        } else if packed {
+            self.tracing_state.packed_framebuffer.generation()
+        } else {
+            self.tracing_state.framebuffer.generation()
+        };
+        if self.uploaded_frame == Some((packed, self.show_noise, generation)) {
+            return;
+        }
+
+        let Some(resources) = self.egui_renderer.paint_callback_resources.get::<PaintCallbackResources>() else {
+            return;
+        };
+        if self.show_noise {
+            let framebuffer = self.tracing_state.framebuffer.read();
+            let variance = self.tracing_state.variance.read();
+            if variance.len() * 3 != framebuffer.len() {
+                return;
+            }
+            let noise = framebuffer
+                .chunks(3)
+                .zip(variance.iter())
+                .flat_map(|(color, &variance)| [pixel_relative_error(color, variance); 3])
+                .collect::<Vec<_>>();
+            resources.upload_framebuffer(&self.queue, bytemuck::cast_slice::<f32, u32>(&noise));
+        } else if packed {
+            resources.upload_framebuffer(&self.queue, &self.tracing_state.packed_framebuffer.read());
+        } else {
+            resources.upload_framebuffer(&self.queue, bytemuck::cast_slice::<f32, u32>(&self.tracing_state.framebuffer.read()));
+        }
+        self.uploaded_frame = Some((packed, self.show_noise, generation));
     }
 
     pub fn redraw(&mut self, platform: &mut Platform, start_time: &Instant) {
+        puffin::GlobalProfiler::lock().new_frame();
         platform.update_time(start_time.elapsed().as_secs_f64());
+        self.update_power_saving();
 
         let output_frame = match self.surface.get_current_texture() {
             Ok(frame) => frame,
@@ -508,21 +3152,42 @@ impl App {
         platform.begin_frame();
 
         // Render here
+        self.on_gui(&platform.context());
         egui::CentralPanel::default()
             .frame(egui::Frame::default().inner_margin(egui::Vec2::ZERO))
             .show(&platform.context(), |ui| {
-                self.on_gui(&platform.context());
                 self.handle_input(ui);
 
-                let rect = ui.allocate_exact_size(ui.available_size(), egui::Sense::drag()).0;
-                let framebuffer = self.tracing_state.framebuffer.read().clone(); // TODO: clone is slow
-                let width = self.tracing_state.config.read().width;
-                let height = self.tracing_state.config.read().height;
-                let tonemapping = self.tonemapping;
+                // Docked panels can leave the viewport with a different shape than the render, so letterbox it
+                let (available, response) = ui.allocate_exact_size(ui.available_size(), egui::Sense::drag());
+                let width = self.tracing_state.config.read().render.width;
+                let height = self.tracing_state.config.read().render.height;
+                let rect = fit_to_aspect(available, width as f32 / height as f32);
+                self.place_sun(ui, &response, rect);
+                self.pick_debug_pixel(ui, &response, rect, width, height);
+                self.pick_denoise_region(ui, &response, rect, width, height);
+                self.section_gizmos(ui, rect, width, height);
+                self.record_convergence(width, height);
+                let packed = !self.show_noise && !self.use_cpu && self.tracing_state.half_precision.load(Ordering::Relaxed);
+                self.upload_framebuffer(packed);
+                self.capture_timelapse_frame();
+                let uniforms = DisplayUniforms {
+                    width,
+                    height,
+                    tonemapping: self.tonemapping as u32,
+                    diff_mode: if self.reference.is_some() { self.diff_mode as u32 } else { DiffMode::None as u32 },
+                    diff_gain: self.diff_gain,
+                    packed: packed as u32,
+                    encode_srgb: (!self.hdr_output && !self.surface_format.describe().srgb) as u32,
+                    hdr_output: self.hdr_output as u32,
+                    hdr_white: self.hdr_white_nits / 80.0,
+                    hdr_peak: self.hdr_peak_nits / 80.0,
+                    _padding: [0; 2],
+                };
                 let cb = egui_wgpu::CallbackFn::new()
                     .prepare(move |_device, queue, _encoder, typemap| {
                         if let Some(resources) = typemap.get::<PaintCallbackResources>() {
-                            resources.prepare(queue, &framebuffer, width, height, tonemapping);
+                            resources.prepare(queue, &uniforms);
                         }
                         Default::default()
                     })
@@ -538,6 +3203,10 @@ impl App {
                 };
 
                 ui.painter().add(callback);
+                self.draw_tile_progress(ui, rect, width, height);
+                self.draw_denoise_region(ui, rect, width, height);
+                self.draw_denoise_comparison(ui, rect);
+                self.draw_debug_path(ui, rect, width, height);
             });
 
         // End the UI frame. We could now handle the output and draw the UI with the backend.
@@ -606,7 +3275,23 @@ impl App {
         }
     }
 
+    // Tells the render thread to save power while nobody is looking, or while on battery
+    fn update_power_saving(&mut self) {
+        let paused = (self.pause_when_minimized && self.minimized) || (self.pause_when_unfocused && !self.focused);
+        self.tracing_state.paused.store(paused, Ordering::Relaxed);
+        let low_power = self.low_power_on_battery && self.power.on_battery();
+        self.tracing_state.low_power.store(low_power, Ordering::Relaxed);
+    }
+
+    pub fn handle_focus(&mut self, focused: bool) {
+        self.focused = focused;
+        self.update_power_saving();
+    }
+
     pub fn handle_resize(&mut self, size: PhysicalSize<u32>) {
+        // Minimizing resizes the window to nothing on most platforms
+        self.minimized = size.width == 0 || size.height == 0;
+        self.update_power_saving();
         if size.width > 0 && size.height > 0 {
             self.surface_config.width = size.width;
             self.surface_config.height = size.height;
@@ -614,12 +3299,11 @@ impl App {
         }
     }
 
+    // Files dropped together arrive as separate events, so collect them and route the batch on the next frame
     pub fn handle_file_dropped(&mut self, path: &std::path::Path) {
-        let path_str = path.to_str().expect("Path was not valid utf8.");
-        if is_image(path_str) {
-            self.set_skybox(path_str);
-        } else {
-            self.set_scene(path_str);
+        match path.to_str() {
+            Some(path) => self.dropped_files.push(path.to_string()),
+            None => tracing::error!("Dropped file path '{}' is not valid UTF-8.", path.display()),
         }
     }
 }
@@ -629,23 +3313,27 @@ struct PaintCallbackResources {
     bind_group: wgpu::BindGroup,
     uniform_buffer: wgpu::Buffer,
     render_buffer: wgpu::Buffer,
+    reference_buffer: wgpu::Buffer,
 }
 
 impl PaintCallbackResources {
     fn prepare(
         &self,
         queue: &wgpu::Queue,
-        framebuffer: &Vec<f32>,
-        width: u32,
-        height: u32,
-        tonemapping: Tonemapping,
+        uniforms: &DisplayUniforms,
     ) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(uniforms));
+    }
+
+    fn upload_framebuffer(&self, queue: &wgpu::Queue, framebuffer: &[u32]) {
         queue.write_buffer(&self.render_buffer, 0, bytemuck::cast_slice(framebuffer));
-        queue.write_buffer(
-            &self.uniform_buffer,
-            0,
-            bytemuck::cast_slice(&[width, height, tonemapping as u32]),
-        );
+    }
+
+    fn set_reference(&self, queue: &wgpu::Queue, reference: Option<&[f32]>, width: u32, height: u32) {
+        match reference {
+            Some(reference) => queue.write_buffer(&self.reference_buffer, 0, bytemuck::cast_slice(reference)),
+            None => queue.write_buffer(&self.reference_buffer, 0, bytemuck::cast_slice(&vec![0.0f32; (width * height * 3) as usize])),
+        }
     }
 
     fn paint<'rpass>(&'rpass self, rpass: &mut wgpu::RenderPass<'rpass>) {
@@ -688,6 +3376,16 @@ impl PaintCallbackResources {
                     },
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
     
@@ -722,7 +3420,7 @@ impl PaintCallbackResources {
     
         let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
-            contents: bytemuck::cast_slice(&[0.0, 0.0, 0.0]),
+            contents: bytemuck::bytes_of(&DisplayUniforms::zeroed()),
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
         });
     
@@ -732,6 +3430,13 @@ impl PaintCallbackResources {
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
             mapped_at_creation: false,
         });
+
+        let reference_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (width * height * 3 * 4) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
     
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
@@ -745,6 +3450,10 @@ impl PaintCallbackResources {
                     binding: 1,
                     resource: render_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: reference_buffer.as_entire_binding(),
+                },
             ],
         });
     
@@ -753,93 +3462,7 @@ impl PaintCallbackResources {
             bind_group,
             uniform_buffer,
             render_buffer,
+            reference_buffer,
         }
     }
-
-    fn save_render(&self, texture_width: u32, texture_height: u32, format: wgpu::TextureFormat, device: &wgpu::Device, queue: &wgpu::Queue) {
-        let texture_desc = &wgpu::TextureDescriptor {
-            label: None,
-            size: wgpu::Extent3d {
-                width: texture_width,
-                height: texture_height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format,
-            usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::RENDER_ATTACHMENT,
-        };
-        let texture = device.create_texture(texture_desc);
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: None,
-                color_attachments: &[
-                    Some(wgpu::RenderPassColorAttachment {
-                        view: &texture_view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                            store: true,
-                        },
-                    }),
-                ],
-                depth_stencil_attachment: None,
-            });
-            self.paint(&mut render_pass);
-        }
-    
-        let u32_size = std::mem::size_of::<u32>() as u32;
-    
-        let output_buffer_size = (u32_size * texture_width * texture_height) as wgpu::BufferAddress;
-        let output_buffer_desc = wgpu::BufferDescriptor {
-            size: output_buffer_size,
-            usage: wgpu::BufferUsages::COPY_DST
-                // this tells wpgu that we want to read this buffer from the cpu
-                | wgpu::BufferUsages::MAP_READ,
-            label: None,
-            mapped_at_creation: false,
-        };
-        let output_buffer = device.create_buffer(&output_buffer_desc);
-        encoder.copy_texture_to_buffer(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                        texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            wgpu::ImageCopyBuffer {
-                buffer: &output_buffer,
-                layout: wgpu::ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: NonZeroU32::new(u32_size * texture_width),
-                    rows_per_image: NonZeroU32::new(texture_height),
-                },
-            },
-            texture_desc.size,
-        );
-        queue.submit(Some(encoder.finish()));
-    
-        {
-            let buffer_slice = output_buffer.slice(..);
-        
-            buffer_slice.map_async(wgpu::MapMode::Read, |_| {});
-            device.poll(wgpu::Maintain::Wait);
-            let mut data = buffer_slice.get_mapped_range().to_vec();
-            data.chunks_exact_mut(4).for_each(|c| c.swap(0, 2)); // BGRA -> RGBA swizzle
-            let Some(buffer) = image::ImageBuffer::<image::Rgba<u8>, _>::from_raw(texture_width, texture_height, data) else {
-                return;
-            };
-            let image = image::DynamicImage::ImageRgba8(buffer).into_rgba8();
-            if let Some(path) = tinyfiledialogs::save_file_dialog("Save render", "") {
-                let res = image.save(path);
-                if res.is_err() {
-                    #[cfg(debug_assertions)] println!("Failed to save image: {:?}", res.err());
-                }
-            }
-        }
-        output_buffer.unmap();
-    }
 }
\ No newline at end of file
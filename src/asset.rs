@@ -1,72 +1,183 @@
-use glam::{UVec4, Vec4, Mat4, Vec2, Vec3};
+use glam::{UVec2, UVec4, Vec4, Vec2, Vec3};
 use gpgpu::{GpuBuffer, BufOps, GpuConstImage, primitives::{pixels::{Rgba8UintNorm, Rgba32Float}, PixelInfo}, ImgOps};
 use image::DynamicImage;
-use russimp::{scene::{Scene, PostProcess::*}, node::Node, material::{DataContent, TextureType, Texture, Material, PropertyTypeInfo}};
-use shared_structs::{MaterialData, PerVertexData, LightPickEntry};
+use rayon::prelude::*;
+use shared_structs::{is_point, is_sdf, pack_udim_grid, point_corners, MaterialData, PerVertexData, LightPickEntry, PRIMITIVE_POINT, PRIMITIVE_SDF};
+use std::sync::atomic::{AtomicU32, Ordering};
 
-use crate::{bvh::{BVH, BVHBuilder, GpuBVH}, trace::FW, light_pick};
+use crate::{bvh::{BVH, BVHBuilder, GpuBVH, sort_triangles_morton}, trace::FW, light_pick, ground::{self, GroundSettings, SceneGeometry}, cancel::CancelToken, atlas, texture_cache::{TextureJob, resolve_texture}, scene::{CsgOperation, SceneDescription, TextureDescription, TextureTransform}, scene_file::{SceneFile, is_scene_file}, proxy::{GpuProxy, Proxy, PROXY_THRESHOLD, PROXY_TRIANGLES}, tangents::{vertex_normals, vertex_tangents}, point_cloud, sdf::{self, SdfShape}};
 
 pub struct World {
     pub bvh: BVH,
     pub per_vertex_buffer: Vec<PerVertexData>,
     pub index_buffer: Vec<UVec4>,
     pub atlas: DynamicImage,
+    pub texture_jobs: Vec<TextureJob>, // Textures not yet decoded, the atlas holds placeholders for them
     pub material_data_buffer: Vec<MaterialData>,  
     pub light_pick_buffer: Vec<LightPickEntry>,  
+    pub material_names: Vec<String>,
+    pub statistics: SceneStatistics,
+    pub proxy: Option<Proxy>, // Only for heavy scenes, see proxy::PROXY_THRESHOLD
 }
 
-pub struct GpuWorld<'fw> {
-    pub bvh: GpuBVH<'fw>,
-    pub per_vertex_buffer: GpuBuffer<'fw, PerVertexData>,
-    pub index_buffer: GpuBuffer<'fw, UVec4>,
-    pub atlas: GpuConstImage<'fw, Rgba8UintNorm>,
-    pub material_data_buffer: GpuBuffer<'fw, MaterialData>,
-    pub light_pick_buffer: GpuBuffer<'fw, LightPickEntry>,
+// Summary of what was imported, for display in the GUI
+#[derive(Clone, Default)]
+pub struct SceneStatistics {
+    pub triangles: usize,
+    pub points: usize, // Of point clouds, which are stored as triangles but aren't counted as them
+    pub sdfs: usize, // Likewise
+    pub vertices: usize,
+    pub meshes: usize, // As imported, before they are merged
+    pub materials: usize,
+    pub emissive_triangles: usize,
+    pub bvh_nodes: usize,
+    pub bvh_depth: usize,
+    pub textures: usize,
+    pub atlas_occupancy: f32, // Fraction of the atlas covered by textures
+    pub bounds: (Vec3, Vec3),
+    pub model_bounds: (Vec3, Vec3), // Of the imported geometry alone, as the ground is sized to be far larger
+}
+
+// Memory used by a single scene resource, when rendering on each backend
+#[derive(Clone)]
+pub struct ResourceUsage {
+    pub name: &'static str,
+    pub cpu_bytes: u64,
+    pub gpu_bytes: u64,
+}
+
+// Stages of loading a scene, in the order they run
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoadStage {
+    Import,
+    Textures,
+    Bvh,
+    Lights,
+    Proxy,
 }
 
-fn convert_texture(texture: &Texture) -> Option<DynamicImage> {
-    let image = match &texture.data {
-        DataContent::Texel(raw_data) => {
-            let image_data = raw_data.iter().flat_map(|c| [c.r, c.g, c.b, c.a]).collect::<Vec<_>>();
-            let image_buffer = image::RgbaImage::from_vec(texture.width, texture.height, image_data)?;
-            image::DynamicImage::ImageRgba8(image_buffer)
-        },
-        DataContent::Bytes(bytes) => {
-            image::io::Reader::new(std::io::Cursor::new(bytes)).with_guessed_format().ok()?.decode().ok()?
+impl LoadStage {
+    pub const ALL: [LoadStage; 5] = [LoadStage::Import, LoadStage::Textures, LoadStage::Bvh, LoadStage::Lights, LoadStage::Proxy];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LoadStage::Import => "Importing",
+            LoadStage::Textures => "Loading textures",
+            LoadStage::Bvh => "Building BVH",
+            LoadStage::Lights => "Building light table",
+            LoadStage::Proxy => "Simplifying proxy",
         }
-    };
+    }
+}
 
-    Some(image)
+// Shared between the thread loading a scene and whoever is waiting on it. The loader
+// reports how far along it is, and gives up once cancelled.
+#[derive(Default)]
+pub struct LoadProgress {
+    stage: AtomicU32,
+    fraction: AtomicU32, // f32 bits, progress within the current stage
+    cancel: CancelToken,
 }
 
-fn load_texture(material: &Material, texture_type: TextureType) -> Option<DynamicImage> {
-    material.textures.get(&texture_type).and_then(|texture| convert_texture(&texture.borrow()))
+impl LoadProgress {
+    pub fn set_stage(&self, stage: LoadStage) {
+        self.stage.store(stage as u32, Ordering::Relaxed);
+        self.fraction.store(0.0f32.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn set_fraction(&self, fraction: f32) {
+        self.fraction.store(fraction.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn stage(&self) -> LoadStage {
+        LoadStage::ALL[self.stage.load(Ordering::Relaxed) as usize]
+    }
+
+    // Progress over all stages, counting each as an equal share
+    pub fn overall(&self) -> f32 {
+        let fraction = f32::from_bits(self.fraction.load(Ordering::Relaxed));
+        (self.stage() as u32 as f32 + fraction) / LoadStage::ALL.len() as f32
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    // For the BVH and light table builds, which also give up partway through
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel
+    }
 }
 
-fn load_float_array(material: &Material, name: &str) -> Option<Vec<f32>> {
-    let prop = material.properties.iter().find(|p| p.key == name)?;
-    match &prop.data {
-        PropertyTypeInfo::FloatArray(col) => Some(col.clone()),
-        _ => None
+// Scene files, or anything assimp imports
+pub fn load_description(path: &str, scale: f32) -> Option<SceneDescription> {
+    if is_scene_file(path) {
+        SceneFile::load(path)?.import(scale)
+    } else if point_cloud::is_point_cloud(path) {
+        point_cloud::load(path, scale)
+    } else {
+        SceneDescription::import(path, scale)
     }
 }
 
+// Decodes textures into their slots in the atlas, stopping early if loading is cancelled
+fn decode_textures(atlas: &mut DynamicImage, jobs: &[TextureJob], progress: &LoadProgress) {
+    puffin::profile_function!();
+    let finished = AtomicU32::new(0);
+    let loaded = jobs
+        .par_iter()
+        .filter_map(|job| {
+            if progress.is_cancelled() {
+                return None;
+            }
+            let texture = resolve_texture(job);
+            let finished = finished.fetch_add(1, Ordering::Relaxed) + 1;
+            progress.set_fraction(finished as f32 / jobs.len() as f32);
+            texture
+        })
+        .collect::<Vec<_>>();
+    for texture in loaded {
+        texture.write_to_image(atlas);
+    }
+}
+
+pub struct GpuWorld<'fw> {
+    pub bvh: GpuBVH<'fw>,
+    pub per_vertex_buffer: GpuBuffer<'fw, PerVertexData>,
+    pub index_buffer: GpuBuffer<'fw, UVec4>,
+    pub atlas: GpuConstImage<'fw, Rgba8UintNorm>,
+    pub material_data_buffer: GpuBuffer<'fw, MaterialData>,
+    pub light_pick_buffer: GpuBuffer<'fw, LightPickEntry>,
+    pub proxy: Option<GpuProxy<'fw>>,
+}
+
 impl World {
-    pub fn from_path(path: &str) -> Option<Self> {
-        let blend = Scene::from_file(
-            path,
-            vec![
-                JoinIdenticalVertices,
-                Triangulate,
-                SortByPrimitiveType,
-                GenerateSmoothNormals,
-                GenerateUVCoords,
-                TransformUVCoords,
-                CalculateTangentSpace,
-                EmbedTextures,
-                ImproveCacheLocality,
-            ],
-        ).ok()?;
+    // Leaves the textures to be decoded later, see `load_textures`
+    pub fn from_path(path: &str, scale: f32, ground: &GroundSettings) -> Option<Self> {
+        Self::load(path, scale, ground, false, false, &LoadProgress::default())
+    }
+
+    // Loads a scene, reporting each stage to `progress`. Returns None if loading failed or was cancelled.
+    // Textures are decoded as part of loading if `decode_now` is set, otherwise the atlas only
+    // has placeholders for them. `sort_triangles` puts triangles in Morton order before building the BVH.
+    pub fn load(path: &str, scale: f32, ground: &GroundSettings, decode_now: bool, sort_triangles: bool, progress: &LoadProgress) -> Option<Self> {
+        progress.set_stage(LoadStage::Import);
+        let description = load_description(path, scale)?;
+        if progress.is_cancelled() {
+            return None;
+        }
+        let world = Self::from_description(&description, ground, decode_now, sort_triangles, progress)?;
+        tracing::info!("Loaded scene '{}' with {} triangles and {} materials.", path, world.statistics.triangles, world.statistics.materials);
+        Some(world)
+    }
+
+    // Builds everything needed to render a scene, however it was made. Takes the same options as `load`.
+    pub fn from_description(description: &SceneDescription, ground: &GroundSettings, decode_now: bool, sort_triangles: bool, progress: &LoadProgress) -> Option<Self> {
+        puffin::profile_function!();
 
         // Gather mesh data
         let mut vertices = Vec::new();
@@ -74,107 +185,174 @@ impl World {
         let mut normals = Vec::new();
         let mut tangents = Vec::new();
         let mut uvs = Vec::new();
-
-        fn walk_node_graph(
-            scene: &Scene,
-            node: &Node,
-            trs: Mat4,
-            vertices: &mut Vec<Vec4>,
-            indices: &mut Vec<UVec4>,
-            normals: &mut Vec<Vec4>,
-            tangents: &mut Vec<Vec4>,
-            uvs: &mut Vec<Vec2>
-        ) {
-            let node_trs = Mat4::from_cols_array_2d(&[
-                [node.transformation.a1, node.transformation.b1, node.transformation.c1, node.transformation.d1],
-                [node.transformation.a2, node.transformation.b2, node.transformation.c2, node.transformation.d2],
-                [node.transformation.a3, node.transformation.b3, node.transformation.c3, node.transformation.d3],
-                [node.transformation.a4, node.transformation.b4, node.transformation.c4, node.transformation.d4],
-            ]);
-            let new_trs = trs * node_trs;
-            let (node_scale,node_quat,_) = new_trs.to_scale_rotation_translation();
-
-            for mesh_idx in node.meshes.iter() {
-                let mesh = &scene.meshes[*mesh_idx as usize];
-                let triangle_offset = vertices.len() as u32;
-                for v in &mesh.vertices {
-                    let vert = new_trs.mul_vec4(Vec4::new(v.x, v.y, v.z, 1.0));
-                    vertices.push(Vec4::new(vert.x, vert.z, vert.y, 1.0));
-                }
-                for f in &mesh.faces {
-                    assert_eq!(f.0.len(), 3);
-                    indices.push(UVec4::new(triangle_offset + f.0[0], triangle_offset + f.0[2], triangle_offset + f.0[1], mesh.material_index));
-                }
-                for n in &mesh.normals {
-                    let norm = (node_quat.mul_vec3(Vec3::new(n.x, n.y, n.z) / node_scale)).normalize();
-                    normals.push(Vec4::new(norm.x, norm.z, norm.y, 0.0));
-                }
-                for t in &mesh.tangents {
-                    let tan = (node_quat.mul_vec3(Vec3::new(t.x, t.y, t.z) / node_scale)).normalize();
-                    tangents.push(Vec4::new(tan.x, tan.z, tan.y, 0.0));
-                }
-                if let Some(Some(uv_set)) = mesh.texture_coords.first() {
-                    for uv in uv_set {
-                        uvs.push(Vec2::new(uv.x, uv.y));
+        let mut grids = Vec::new();
+        let has_intersect = description.materials.iter().any(|material| material.csg == Some(CsgOperation::Intersect));
+        for mesh in description.meshes.iter() {
+            puffin::profile_scope!("Gather mesh");
+            let material = mesh.material.min(description.materials.len().saturating_sub(1) as u32);
+            let csg = description.materials.get(material as usize).map_or(0, |material| CsgOperation::triangle_flags(material.csg, has_intersect));
+            if let Some(sdf) = mesh.sdf.as_ref() {
+                let grid = grids.len();
+                if let SdfShape::Grid { path, resolution, .. } = &sdf.shape {
+                    match sdf::load_grid(path, *resolution) {
+                        Ok(distances) => grids.push(distances),
+                        Err(err) => {
+                            tracing::error!("Failed to load SDF grid '{}': {}", path.display(), err);
+                            continue;
+                        }
                     }
-                } else {
-                    uvs.resize(vertices.len(), Vec2::ZERO);
                 }
+                // The shape goes where triangles keep the normal and tangent of their first corner
+                let first = vertices.len() as u32;
+                let (normal, tangent) = sdf.encode(grid);
+                vertices.extend(sdf.corners().map(|corner| corner.extend(1.0)));
+                normals.extend([normal, Vec4::ZERO, Vec4::ZERO]);
+                tangents.extend([tangent, Vec4::ZERO, Vec4::ZERO]);
+                uvs.extend([Vec2::ZERO; 3]);
+                indices.push(UVec4::new(first, first + 1, first + 2, material | csg | PRIMITIVE_SDF));
+                continue;
             }
-
-            for child in node.children.borrow().iter() {
-                walk_node_graph(scene, child, new_trs, vertices, indices, normals, tangents, uvs);
+            if mesh.point_radius > 0.0 {
+                // Each point becomes its three corners, with its color where triangles keep their normal
+                for (index, position) in mesh.positions.iter().enumerate() {
+                    let first = vertices.len() as u32;
+                    let color = mesh.colors.get(index).copied().unwrap_or(Vec3::ONE);
+                    vertices.extend(point_corners(*position, mesh.point_radius).map(|corner| corner.extend(1.0)));
+                    normals.extend([color.extend(0.0); 3]);
+                    tangents.extend([Vec4::ZERO; 3]);
+                    uvs.extend([Vec2::ZERO; 3]);
+                    indices.push(UVec4::new(first, first + 1, first + 2, material | PRIMITIVE_POINT));
+                }
+                continue;
             }
+            let triangle_offset = vertices.len() as u32;
+            let vertex_count = mesh.positions.len();
+            vertices.extend(mesh.positions.iter().map(|p| p.extend(1.0)));
+            // Missing or broken normals and tangents would otherwise shade as NaN
+            let mesh_normals = vertex_normals(mesh);
+            tangents.extend(vertex_tangents(mesh, &mesh_normals).iter().map(|t| t.extend(0.0)));
+            normals.extend(mesh_normals.iter().map(|n| n.extend(0.0)));
+            uvs.extend_from_slice(&mesh.uvs[..mesh.uvs.len().min(vertex_count)]);
+            uvs.resize(vertices.len(), Vec2::ZERO);
+            indices.extend(mesh.triangles.iter().map(|t| UVec4::new(triangle_offset + t[0], triangle_offset + t[1], triangle_offset + t[2], material | csg)));
+        }
+
+        if indices.is_empty() || description.materials.is_empty() {
+            tracing::error!("Scene contains no triangles, nothing to render.");
+            return None;
         }
 
-        if let Some(root) = blend.root.as_ref() {
-            walk_node_graph(&blend, root, Mat4::IDENTITY, &mut vertices, &mut indices, &mut normals, &mut tangents, &mut uvs);
+        if progress.is_cancelled() {
+            return None;
         }
 
         // Gather material data
-        let mut material_datas = vec![MaterialData::default(); blend.materials.len()];
+        progress.set_stage(LoadStage::Textures);
+        let mut material_datas = vec![MaterialData::default(); description.materials.len()];
+        let mut material_names = description.materials.iter().map(|material| material.name.clone()).collect::<Vec<_>>();
 
         let mut textures = Vec::new();
-        for (material_index, material) in blend.materials.iter().enumerate() {
+        for (material_index, material) in description.materials.iter().enumerate() {
+            puffin::profile_scope!("Gather material");
             let current_material_data = &mut material_datas[material_index];
-            if let Some(texture) = load_texture(material, TextureType::Diffuse) {
-                // Albedo data is stored in gamma space, but we atlas it with all the other textures
-                // which are stored in linear. Therefore, we convert here.
-                let mut texture = texture.into_rgb8();
-                for pixel in texture.iter_mut() {
-                    *pixel = ((*pixel as f32 / 255.0).powf(2.2) * 255.0) as u8;
+            let mut source = |texture: &Option<TextureDescription>, name: &'static str| {
+                let (mut grid, mut tiles) = texture.as_ref()?.tiles()?;
+                if name == "emissive" && grid != UVec2::ONE {
+                    tracing::warn!("UDIM emissive textures aren't supported, using the first tile of '{}'.", material.name);
+                    grid = UVec2::ONE;
+                    tiles.truncate(1);
+                    tiles[0].0 = UVec2::ZERO;
                 }
-                textures.push(image::DynamicImage::ImageRgb8(texture));
+                textures.push((material_index, name, grid, tiles));
+                Some(pack_udim_grid(grid.x, grid.y))
+            };
+            // Patterned channels don't need their texture
+            if let Some(grid) = material.patterns.albedo.is_none().then(|| source(&material.textures.albedo, "albedo")).flatten() {
                 current_material_data.set_has_albedo_texture(true);
+                current_material_data.udim_grid.x = grid;
             }
-            if let Some(texture) = load_texture(material, TextureType::Metalness) {
-                textures.push(texture);
+            if let Some(grid) = material.patterns.metallic.is_none().then(|| source(&material.textures.metallic, "metallic")).flatten() {
                 current_material_data.set_has_metallic_texture(true);
+                current_material_data.udim_grid.y = grid;
             }
-            if let Some(texture) = load_texture(material, TextureType::Roughness) {
-                textures.push(texture);
+            if let Some(grid) = material.patterns.roughness.is_none().then(|| source(&material.textures.roughness, "roughness")).flatten() {
                 current_material_data.set_has_roughness_texture(true);
+                current_material_data.udim_grid.z = grid;
             }
-            if let Some(texture) = load_texture(material, TextureType::Normals) {
-                textures.push(texture);
+            if let Some(grid) = source(&material.textures.normal, "normal") {
                 current_material_data.set_has_normal_texture(true);
+                current_material_data.udim_grid.w = grid;
             }
-            if let Some(col) = load_float_array(material, "$clr.diffuse") {
-                current_material_data.albedo = Vec4::new(col[0], col[1], col[2], col[3]);
+            if source(&material.textures.emissive, "emissive").is_some() {
+                current_material_data.set_has_emissive_texture(true);
             }
-            if let Some(col) = load_float_array(material, "$clr.emissive") {
-                // HACK: Multiply by 15 since assimp 5.2.5 doesn't support emissive strength :(
-                current_material_data.emissive = Vec4::new(col[0], col[1], col[2], col[3]) * 15.0;
+            // Channels without a transform keep a zero scale, which the kernels skip
+            let transforms = &material.transforms;
+            let rotation = |transform: Option<TextureTransform>| transform.map_or(0.0, |transform| transform.rotation);
+            current_material_data.albedo_transform = transforms.albedo.map_or(Vec4::ZERO, TextureTransform::to_vec4);
+            current_material_data.metallic_transform = transforms.metallic.map_or(Vec4::ZERO, TextureTransform::to_vec4);
+            current_material_data.roughness_transform = transforms.roughness.map_or(Vec4::ZERO, TextureTransform::to_vec4);
+            current_material_data.normal_transform = transforms.normal.map_or(Vec4::ZERO, TextureTransform::to_vec4);
+            current_material_data.uv_rotation = Vec4::new(
+                rotation(transforms.albedo),
+                rotation(transforms.metallic),
+                rotation(transforms.roughness),
+                rotation(transforms.normal),
+            );
+            current_material_data.albedo = material.albedo;
+            current_material_data.emissive = material.emissive.extend(1.0);
+            current_material_data.set_double_sided(material.double_sided);
+            current_material_data.metallic = Vec4::splat(material.metallic);
+            current_material_data.roughness = Vec4::splat(material.roughness);
+
+            // Patterns blend from the constant to their own value, and need no atlas slot
+            if let Some(pattern) = material.patterns.albedo {
+                current_material_data.patterns.x = pattern.kind.to_u32();
+                current_material_data.pattern_scale.x = pattern.scale;
+                current_material_data.albedo_pattern = pattern.value;
             }
-            if let Some(col) = load_float_array(material, "$mat.metallicFactor") {
-                current_material_data.metallic = Vec4::splat(col[0]);
+            if let Some(pattern) = material.patterns.roughness {
+                current_material_data.patterns.y = pattern.kind.to_u32();
+                current_material_data.pattern_scale.y = pattern.scale;
+                current_material_data.roughness.y = pattern.value.x;
             }
-            if let Some(col) = load_float_array(material, "$mat.roughnessFactor") {
-                current_material_data.roughness = Vec4::splat(col[0]);
+            if let Some(pattern) = material.patterns.metallic {
+                current_material_data.patterns.z = pattern.kind.to_u32();
+                current_material_data.pattern_scale.z = pattern.scale;
+                current_material_data.metallic.y = pattern.value.x;
             }
         }
 
-        let (atlas_raw, mut sts) = crate::atlas::pack_textures(&textures, 4096, 4096);
+        // Textures are decoded later, so until then each slot shows the material's constant value
+        let slots = atlas::atlas_layout(textures.len(), 4096, 4096);
+        let mut atlas_raw = DynamicImage::new_rgba8(4096, 4096);
+        let mut texture_jobs = Vec::with_capacity(textures.len());
+        let mut sts = Vec::with_capacity(textures.len());
+        for ((material_index, name, grid, tiles), slot) in textures.into_iter().zip(slots.iter()) {
+            let material_data = &material_datas[material_index];
+            let placeholder = match name {
+                "albedo" => material_data.albedo.truncate(),
+                "metallic" => Vec3::splat(material_data.metallic.x),
+                "roughness" => Vec3::splat(material_data.roughness.x),
+                "emissive" => Vec3::ONE, // Multiplied with the emissive color
+                _ => Vec3::new(0.5, 0.5, 1.0), // Flat normal
+            };
+            let [r, g, b] = (placeholder.clamp(Vec3::ZERO, Vec3::ONE) * 255.0).to_array().map(|c| c as u8);
+            atlas::fill_slot(&mut atlas_raw, slot, [r, g, b, 255]);
+            // UDIM tiles share the slot, each in its own cell of the grid
+            for (tile, source) in tiles {
+                texture_jobs.push(TextureJob { source, slot: slot.grid_cell(grid, tile), srgb: name == "albedo" || name == "emissive", name });
+            }
+            sts.push(slot.grid_cell(grid, UVec2::ZERO).to_uvst(4096, 4096));
+        }
+        if decode_now {
+            decode_textures(&mut atlas_raw, &texture_jobs, progress);
+            texture_jobs.clear();
+        }
+        if progress.is_cancelled() {
+            return None;
+        }
+        let atlas_occupancy = slots.iter().map(|slot| slot.to_uvst(4096, 4096)).map(|st| st.z * st.w).sum::<f32>();
 
         for material_data in material_datas.iter_mut() {
             if material_data.has_albedo_texture() {
@@ -189,58 +367,201 @@ impl World {
             if material_data.has_normal_texture() {
                 material_data.normals = sts.remove(0);
             }
+            if material_data.has_emissive_texture() {
+                material_data.emissive_texture = sts.remove(0);
+            }
         }
 
+        let model_bounds = vertices.iter().fold((Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)), |(min, max), vertex| (min.min(vertex.truncate()), max.max(vertex.truncate())));
+        ground::add_ground(
+            ground,
+            SceneGeometry { vertices: &mut vertices, indices: &mut indices, normals: &mut normals, tangents: &mut tangents, uvs: &mut uvs },
+            &mut material_datas,
+            &mut material_names,
+        );
+
+        // Pack per-vertex data
+        let mut per_vertex_data = {
+            puffin::profile_scope!("Pack vertices");
+            (0..vertices.len())
+                .map(|i| PerVertexData {
+                    vertex: vertices[i],
+                    normal: *normals.get(i).unwrap_or(&Vec4::ZERO),
+                    tangent: *tangents.get(i).unwrap_or(&Vec4::ZERO),
+                    uv0: *uvs.get(i).unwrap_or(&Vec2::ZERO),
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>()
+        };
+
         // BVH building
+        progress.set_stage(LoadStage::Bvh);
         let now = std::time::Instant::now();
-        let bvh = BVHBuilder::new(&vertices, &mut indices).sah_samples(128).build();
-        #[cfg(debug_assertions)] println!("BVH build time: {:?}", now.elapsed());
+        let degenerate = clean_mesh(&mut per_vertex_data, &mut indices);
+        if degenerate > 0 {
+            tracing::info!("Dropped {} triangles without area.", degenerate);
+        }
+        if indices.is_empty() {
+            tracing::error!("Scene contains no triangles with any area, nothing to render.");
+            return None;
+        }
+        let mut vertices = per_vertex_data.iter().map(|data| data.vertex).collect::<Vec<_>>();
+        if sort_triangles {
+            sort_triangles_morton(&vertices, &mut indices);
+        }
+        let bvh = BVHBuilder::new(&vertices, &mut indices).sah_samples(128).cancel_token(progress.cancel_token().clone()).try_build()?;
+        // The BVH decides the order of the triangles, so the vertices follow it
+        optimize_vertex_order(&mut per_vertex_data, &mut indices);
+        vertices = per_vertex_data.iter().map(|data| data.vertex).collect();
+        tracing::debug!("BVH build time: {:?}", now.elapsed());
 
         // Build light pick table
+        progress.set_stage(LoadStage::Lights);
         let now = std::time::Instant::now();
         let emissive_mask = light_pick::compute_emissive_mask(&indices, &material_datas);
-        let light_pick_table = light_pick::build_light_pick_table(&vertices, &indices, &emissive_mask, &material_datas);
-        #[cfg(debug_assertions)] println!("Light pick table build time: {:?}", now.elapsed());
+        let light_pick_table = light_pick::try_build_light_pick_table(&vertices, &indices, &emissive_mask, &material_datas, progress.cancel_token())?;
+        tracing::debug!("Light pick table build time: {:?}", now.elapsed());
 
-        // Pack per-vertex data
-        let mut per_vertex_data = Vec::new();
-        for i in 0..vertices.len() {
-            per_vertex_data.push(PerVertexData {
-                vertex: *vertices.get(i).unwrap_or(&Vec4::ZERO),
-                normal: *normals.get(i).unwrap_or(&Vec4::ZERO),
-                tangent: *tangents.get(i).unwrap_or(&Vec4::ZERO),
-                uv0: *uvs.get(i).unwrap_or(&Vec2::ZERO),
-                ..Default::default()
-            });
-        }
+        // Build a simplified proxy to navigate heavy scenes with
+        let proxy = if indices.len() > PROXY_THRESHOLD {
+            progress.set_stage(LoadStage::Proxy);
+            let now = std::time::Instant::now();
+            let proxy = Proxy::build(&vertices, &indices, &material_datas, PROXY_TRIANGLES, progress.cancel_token())?;
+            tracing::debug!("Proxy build time: {:?}", now.elapsed());
+            Some(proxy)
+        } else {
+            None
+        };
+
+        let points = indices.iter().filter(|triangle| is_point(**triangle)).count();
+        let sdfs = indices.iter().filter(|triangle| is_sdf(**triangle)).count();
+        let vertex_count = per_vertex_data.len();
+        sdf::append_grids(&mut per_vertex_data, &indices, &grids);
+
+        let statistics = SceneStatistics {
+            triangles: indices.len() - points - sdfs,
+            points,
+            sdfs,
+            vertices: vertex_count,
+            meshes: description.meshes.len(),
+            materials: material_datas.len(),
+            emissive_triangles: emissive_mask.iter().filter(|&&emissive| emissive).count(),
+            bvh_nodes: bvh.nodes.len(),
+            bvh_depth: bvh.depth(),
+            textures: texture_jobs.len(),
+            atlas_occupancy,
+            bounds: bvh.bounds(),
+            model_bounds,
+        };
         Some(Self {
             bvh,
             per_vertex_buffer: per_vertex_data,
             index_buffer: indices,
             atlas: atlas_raw,
+            texture_jobs,
             material_data_buffer: material_datas,
             light_pick_buffer: light_pick_table,
+            material_names,
+            statistics,
+            proxy,
         })
     }
 
-    pub fn into_gpu<'fw>(self) -> GpuWorld<'fw> {
+    // Decodes every remaining texture into the atlas now, rather than streaming them in while rendering
+    pub fn load_textures(&mut self) {
+        puffin::profile_function!();
+        let jobs = std::mem::take(&mut self.texture_jobs);
+        decode_textures(&mut self.atlas, &jobs, &LoadProgress::default());
+    }
+
+    // Size in bytes of each buffer that is uploaded to the GPU
+    pub fn buffer_sizes(&self) -> [(&'static str, u64); 5] {
+        fn size_of_slice<T>(slice: &[T]) -> u64 {
+            std::mem::size_of_val(slice) as u64
+        }
+        [
+            ("per-vertex", size_of_slice(&self.per_vertex_buffer)),
+            ("index", size_of_slice(&self.index_buffer)),
+            ("BVH node", size_of_slice(&self.bvh.nodes)),
+            ("material", size_of_slice(&self.material_data_buffer)),
+            ("light pick", size_of_slice(&self.light_pick_buffer)),
+        ]
+    }
+
+    pub fn memory_usage(&self) -> Vec<ResourceUsage> {
+        let names = ["Vertices", "Indices", "BVH nodes", "Materials", "Light pick table"];
+        let mut usage = names
+            .into_iter()
+            .zip(self.buffer_sizes())
+            .map(|(name, (_, size))| ResourceUsage { name, cpu_bytes: size, gpu_bytes: size })
+            .collect::<Vec<_>>();
+
+        // The CPU backend expands the atlas to Vec4, the GPU backend uploads it as RGBA8
+        let atlas_pixels = self.atlas.width() as u64 * self.atlas.height() as u64;
+        usage.push(ResourceUsage {
+            name: "Texture atlas",
+            cpu_bytes: atlas_pixels * std::mem::size_of::<Vec4>() as u64,
+            gpu_bytes: atlas_pixels * 4,
+        });
+        if let Some(proxy) = &self.proxy {
+            usage.push(ResourceUsage { name: "Navigation proxy", cpu_bytes: proxy.buffer_bytes(), gpu_bytes: proxy.buffer_bytes() });
+        }
+        usage
+    }
+
+    // Borrows, so the render thread can keep the world around, see trace::WORLD_CACHE
+    pub fn to_gpu<'fw>(&self) -> GpuWorld<'fw> {
+        puffin::profile_function!();
+
         GpuWorld {
             per_vertex_buffer: GpuBuffer::from_slice(&FW, &self.per_vertex_buffer),
             index_buffer: GpuBuffer::from_slice(&FW, &self.index_buffer),
-            bvh: self.bvh.into_gpu(),
+            bvh: self.bvh.to_gpu(),
             atlas: GpuConstImage::from_bytes(&FW, &self.atlas.to_rgba8(), 4096, 4096),
             material_data_buffer: GpuBuffer::from_slice(&FW, &self.material_data_buffer),
             light_pick_buffer: GpuBuffer::from_slice(&FW, &self.light_pick_buffer),
+            proxy: self.proxy.as_ref().map(Proxy::to_gpu),
         }
     }
 }
 
+// Welds vertices that match in every attribute, and drops triangles without area, whose barycentrics
+// come out as NaN and show up as black pixels. Vertices no triangle uses are dropped too. Returns
+// how many triangles were dropped.
+pub fn clean_mesh(per_vertex_data: &mut Vec<PerVertexData>, indices: &mut Vec<UVec4>) -> usize {
+    puffin::profile_function!();
+    let flat = indices.iter().flat_map(|triangle| triangle.truncate().to_array()).collect::<Vec<_>>();
+    let (vertex_count, remap) = meshopt::generate_vertex_remap(per_vertex_data, Some(&flat));
+    *per_vertex_data = meshopt::remap_vertex_buffer(per_vertex_data, vertex_count, &remap);
+    let triangle_count = indices.len();
+    indices.retain_mut(|triangle| {
+        *triangle = UVec4::new(remap[triangle.x as usize], remap[triangle.y as usize], remap[triangle.z as usize], triangle.w);
+        let [a, b, c] = [triangle.x, triangle.y, triangle.z].map(|index| per_vertex_data[index as usize].vertex.truncate());
+        let area = (b - a).cross(c - a).length_squared();
+        area > 0.0 && area.is_finite()
+    });
+    triangle_count - indices.len()
+}
+
+// Orders vertices by when the triangles first use them, so neighbouring triangles read neighbouring vertices
+fn optimize_vertex_order(per_vertex_data: &mut Vec<PerVertexData>, indices: &mut [UVec4]) {
+    puffin::profile_function!();
+    let mut flat = indices.iter().flat_map(|triangle| triangle.truncate().to_array()).collect::<Vec<_>>();
+    *per_vertex_data = meshopt::optimize_vertex_fetch(&mut flat, per_vertex_data);
+    for (triangle, corners) in indices.iter_mut().zip(flat.chunks_exact(3)) {
+        *triangle = UVec4::new(corners[0], corners[1], corners[2], triangle.w);
+    }
+}
+
 pub fn load_dynamic_image(path: &str) -> Option<DynamicImage> {
     // Image crate does not by default decode .hdr images as HDR for some reason
     if path.ends_with(".hdr") {
-        let hdr_decoder = image::codecs::hdr::HdrDecoder::new(std::io::BufReader::new(
-            std::fs::File::open(&path).unwrap(),
-        )).ok()?;
+        let file = std::fs::File::open(&path)
+            .map_err(|err| tracing::error!("Failed to open image '{}': {}", path, err))
+            .ok()?;
+        let hdr_decoder = image::codecs::hdr::HdrDecoder::new(std::io::BufReader::new(file))
+            .map_err(|err| tracing::error!("Failed to decode image '{}': {}", path, err))
+            .ok()?;
         let width = hdr_decoder.metadata().width;
         let height = hdr_decoder.metadata().height;
         let buffer = hdr_decoder.read_image_hdr().ok()?;
@@ -251,7 +572,12 @@ pub fn load_dynamic_image(path: &str) -> Option<DynamicImage> {
         )?));
     }
 
-    image::io::Reader::open(path).ok()?.decode().ok()
+    image::io::Reader::open(path)
+        .map_err(|err| tracing::error!("Failed to open image '{}': {}", path, err))
+        .ok()?
+        .decode()
+        .map_err(|err| tracing::error!("Failed to decode image '{}': {}", path, err))
+        .ok()
 }
 
 pub fn dynamic_image_to_gpu_image<'fw, P: PixelInfo>(img: DynamicImage) -> GpuConstImage<'fw, P> {
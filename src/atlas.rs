@@ -1,6 +1,6 @@
 use std::{collections::VecDeque, num::NonZeroU32};
 
-use glam::Vec4;
+use glam::{UVec2, Vec4};
 use image::{DynamicImage, GenericImage};
 use fast_image_resize as fr;
 
@@ -23,7 +23,23 @@ impl PackingRect {
     }
 }
 
-pub fn pack_textures(textures: &[DynamicImage], atlas_width: u32, atlas_height: u32) -> (DynamicImage, Vec<Vec4>) {
+impl PackingRect {
+    // A cell of the slot split into a grid, for textures made of several tiles
+    pub fn grid_cell(&self, grid: UVec2, cell: UVec2) -> PackingRect {
+        let width = (self.width / grid.x).max(1);
+        let height = (self.height / grid.y).max(1);
+        PackingRect {
+            x: self.x + cell.x * width,
+            y: self.y + cell.y * height,
+            width,
+            height,
+        }
+    }
+}
+
+// Slots are handed out in order of size, and only depend on the number of textures, so
+// the layout can be decided before any texture is decoded.
+pub fn atlas_layout(texture_count: usize, atlas_width: u32, atlas_height: u32) -> Vec<PackingRect> {
     let root = PackingRect {
         x: 0,
         y: 0,
@@ -32,7 +48,7 @@ pub fn pack_textures(textures: &[DynamicImage], atlas_width: u32, atlas_height:
     };
     let mut queue = VecDeque::from([root]);
 
-    while queue.len() <= textures.len() {
+    while queue.len() <= texture_count {
         let node = queue.pop_front().expect("Texture packing queue was empty.");
         let half_width = node.width / 2;
         let half_height = node.height / 2;
@@ -66,27 +82,41 @@ pub fn pack_textures(textures: &[DynamicImage], atlas_width: u32, atlas_height:
 
     let mut leafs = queue.into_iter().collect::<Vec<_>>();
     leafs.sort_by(|a, b| b.width.cmp(&a.width));
-    leafs.truncate(textures.len());
+    leafs.truncate(texture_count);
+    leafs
+}
 
+// Resizes a texture to fill its slot, flipped to match the atlas
+pub fn fit_texture(texture: &DynamicImage, slot: &PackingRect) -> DynamicImage {
     let mut resizer = fr::Resizer::new(fr::ResizeAlg::Convolution(fr::FilterType::Lanczos3));
-    let mut atlas = DynamicImage::new_rgba8(atlas_width, atlas_height);
-    for (i, leaf) in leafs.iter().enumerate() {
-        let tex = &textures[i];
-        let width = NonZeroU32::new(tex.width()).unwrap();
-        let height = NonZeroU32::new(tex.height()).unwrap();
+    let width = NonZeroU32::new(texture.width()).unwrap();
+    let height = NonZeroU32::new(texture.height()).unwrap();
+
+    let desired_width = NonZeroU32::new(slot.width).unwrap();
+    let desired_height = NonZeroU32::new(slot.height).unwrap();
+    let fr_img_src = fr::Image::from_vec_u8(width, height, texture.to_rgba8().into_raw(), fr::PixelType::U8x4).unwrap();
+    let mut fr_img_dst = fr::Image::new(desired_width, desired_height, fr::PixelType::U8x4);
+    resizer.resize(&fr_img_src.view(), &mut fr_img_dst.view_mut()).unwrap();
 
-        let desired_width = NonZeroU32::new(leaf.width).unwrap();
-        let desired_height = NonZeroU32::new(leaf.height).unwrap();
-        let fr_img_src = fr::Image::from_vec_u8(width, height, tex.to_rgba8().into_raw(), fr::PixelType::U8x4).unwrap();
-        let mut fr_img_dst = fr::Image::new(desired_width, desired_height, fr::PixelType::U8x4);
-        resizer.resize(&fr_img_src.view(), &mut fr_img_dst.view_mut()).unwrap();
+    let resized_tex = DynamicImage::ImageRgba8(image::RgbaImage::from_raw(desired_width.get(), desired_height.get(), fr_img_dst.into_vec()).unwrap());
+    resized_tex.flipv()
+}
+
+// Fills a slot with a single color, for textures that haven't been decoded yet
+pub fn fill_slot(atlas: &mut DynamicImage, slot: &PackingRect, color: [u8; 4]) {
+    let solid = image::RgbaImage::from_pixel(slot.width, slot.height, image::Rgba(color));
+    atlas.copy_from(&solid, slot.x, slot.y).unwrap();
+}
+
+pub fn pack_textures(textures: &[DynamicImage], atlas_width: u32, atlas_height: u32) -> (DynamicImage, Vec<Vec4>) {
+    puffin::profile_function!();
 
-        let resized_tex = DynamicImage::ImageRgba8(image::RgbaImage::from_raw(desired_width.get(), desired_height.get(), fr_img_dst.into_vec()).unwrap());
-        atlas.copy_from(&resized_tex.flipv(), leaf.x, leaf.y).unwrap();
+    let leafs = atlas_layout(textures.len(), atlas_width, atlas_height);
+    let mut atlas = DynamicImage::new_rgba8(atlas_width, atlas_height);
+    for (tex, leaf) in textures.iter().zip(leafs.iter()) {
+        atlas.copy_from(&fit_texture(tex, leaf), leaf.x, leaf.y).unwrap();
     }
 
     let sts = leafs.iter().map(|x| x.to_uvst(atlas_width, atlas_height)).collect::<Vec<_>>();
     (atlas, sts)
 }
-
-
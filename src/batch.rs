@@ -0,0 +1,302 @@
+use serde::{Deserialize, Serialize};
+use parking_lot::Mutex;
+use shared_structs::TracingConfig;
+use std::{path::{Path, PathBuf}, sync::{atomic::{AtomicBool, AtomicU32, Ordering}, Arc}, time::{Duration, Instant}};
+
+use crate::{export::{save_image, ExportSettings, ImageFormat, RenderMetadata}, ground::GroundSettings, scene_file::{is_scene_file, SceneFile}, tonemap::Tonemapping, trace::{trace_cpu, trace_gpu_with_cpu_fallback, TracingState}};
+
+// Used when neither the job nor its scene file says
+const DEFAULT_WIDTH: u32 = 1280;
+const DEFAULT_HEIGHT: u32 = 720;
+const DEFAULT_SAMPLES: u32 = 256;
+const JPEG_QUALITY: u8 = 90;
+
+// A single render in a batch. Settings left out come from the scene file, if the scene is one,
+// and from the defaults above otherwise. Scenes without a camera are rendered from the default
+// camera position, since there is nobody to frame them.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatchJob {
+    pub scene: PathBuf,
+    pub output: Option<PathBuf>, // Defaults to the name of the scene, as a PNG in the output directory
+    pub skybox: Option<PathBuf>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub samples: Option<u32>,
+    pub cpu: bool,
+    pub tonemapping: Option<Tonemapping>,
+    pub footer: bool, // Burn the render settings into a strip below the image
+    pub time_lapse: Option<TimeLapse>,
+}
+
+// Renders a job once per frame, moving the time of day of the scene file's `solar` settings from
+// its own hour to `end_hour`. Frames are numbered after the job's output, from 1.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeLapse {
+    pub end_hour: f32,
+    pub frames: u32,
+}
+
+impl TimeLapse {
+    pub fn hour(&self, start_hour: f32, frame: u32) -> f32 {
+        if self.frames <= 1 {
+            return start_hour;
+        }
+        start_hour + (self.end_hour - start_hour) * frame as f32 / (self.frames - 1) as f32
+    }
+
+    pub fn frame_path(output: &Path, frame: u32) -> PathBuf {
+        let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+        let extension = output.extension().unwrap_or_default().to_string_lossy();
+        output.with_file_name(format!("{}_{:04}.{}", stem, frame + 1, extension))
+    }
+}
+
+// A list of jobs in RON or JSON. Paths are relative to the manifest.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatchManifest {
+    pub output_directory: Option<PathBuf>, // Defaults to the directory of the manifest
+    pub jobs: Vec<BatchJob>,
+}
+
+pub struct JobResult {
+    pub scene: PathBuf,
+    pub output: PathBuf,
+    pub samples: u32,
+    pub duration: Duration,
+    pub error: Option<String>,
+}
+
+impl BatchManifest {
+    pub fn parse(text: &str, json: bool) -> Result<Self, String> {
+        if json {
+            serde_json::from_str(text).map_err(|err| err.to_string())
+        } else {
+            ron::from_str(text).map_err(|err| err.to_string())
+        }
+    }
+
+    // A directory renders every scene file in it, in name order, next to the scene file.
+    // Anything else is read as a manifest.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if path.is_dir() {
+            let mut scenes = std::fs::read_dir(path)
+                .map_err(|err| err.to_string())?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file() && is_scene_file(&path.to_string_lossy()))
+                .collect::<Vec<_>>();
+            scenes.sort();
+            return Ok(Self {
+                output_directory: Some(path.to_path_buf()),
+                jobs: scenes.into_iter().map(|scene| BatchJob { scene, ..Default::default() }).collect(),
+            });
+        }
+
+        let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let json = path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("json"));
+        let mut manifest = Self::parse(&text, json)?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        manifest.output_directory = Some(dir.join(manifest.output_directory.as_deref().unwrap_or(Path::new(""))));
+        for job in manifest.jobs.iter_mut() {
+            job.scene = dir.join(&job.scene);
+            job.skybox = job.skybox.as_ref().map(|skybox| dir.join(skybox));
+        }
+        Ok(manifest)
+    }
+
+    // Where a job's image goes. Outputs are relative to the output directory.
+    pub fn output_path(&self, job: &BatchJob) -> PathBuf {
+        let dir = self.output_directory.as_deref().unwrap_or(Path::new(""));
+        match job.output.as_ref() {
+            Some(output) => dir.join(output),
+            None => {
+                let name = job.scene.file_stem().unwrap_or_default();
+                dir.join(name).with_extension("png")
+            }
+        }
+    }
+}
+
+// Traces until the state's sample limit, or until it stops running, without anything presenting
+// the frames. Returns how many samples were taken.
+pub fn render_headless(state: &Arc<TracingState>, scene: &str, skybox: Option<&str>, cpu: bool) -> u32 {
+    // Nothing presents the frames, so there is no reason to publish them early
+    state.frame_budget_ms.store(0, Ordering::Relaxed);
+    state.running.store(true, Ordering::Relaxed);
+    if cpu {
+        trace_cpu(scene, skybox, state.clone());
+    } else {
+        trace_gpu_with_cpu_fallback(scene, skybox, state.clone());
+    }
+    state.samples.load(Ordering::Relaxed)
+}
+
+// Shared between a thread rendering a sequence of images headlessly and whoever is waiting on it
+#[derive(Default)]
+pub struct RenderProgress {
+    finished: AtomicU32,
+    cancelled: AtomicBool,
+    current: Mutex<Option<Arc<TracingState>>>,
+}
+
+impl RenderProgress {
+    pub fn finished(&self) -> u32 {
+        self.finished.load(Ordering::Relaxed)
+    }
+
+    pub fn finish_one(&self) {
+        self.finished.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Stops the image being rendered, and skips the rest
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        if let Some(state) = self.current.lock().as_ref() {
+            state.running.store(false, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+// The scene open in the GUI, to render variations of it without disturbing the GUI's own render
+#[derive(Clone)]
+pub struct HeadlessScene {
+    pub scene: String,
+    pub skybox: Option<String>,
+    pub environment_clamp: Option<f32>,
+    pub scene_scale: f32,
+    pub ground: GroundSettings,
+    pub cpu: bool,
+}
+
+impl HeadlessScene {
+    // Renders to `samples` with the given config, and returns the linear framebuffer
+    pub fn render(&self, config: TracingConfig, samples: u32, progress: &RenderProgress) -> Result<Vec<f32>, String> {
+        let state = Arc::new(TracingState::new(config.render.width, config.render.height));
+        *state.config.write() = config;
+        *state.environment_clamp.write() = self.environment_clamp;
+        *state.scene_scale.write() = self.scene_scale;
+        *state.ground.write() = self.ground;
+        state.sample_limit.store(samples, Ordering::Relaxed);
+        *progress.current.lock() = Some(state.clone());
+        // The state may have been published after a cancel looked for it
+        if progress.is_cancelled() {
+            return Err("Cancelled".to_string());
+        }
+        let taken = render_headless(&state, &self.scene, self.skybox.as_deref(), self.cpu);
+        progress.current.lock().take();
+        if taken < samples {
+            return Err(format!("Render stopped after {} of {} samples", taken, samples));
+        }
+        let framebuffer = state.framebuffer.read().clone();
+        Ok(framebuffer)
+    }
+}
+
+// Renders a job, or one frame of a time-lapse, to completion and saves it, returning how many samples were taken
+fn run_job(job: &BatchJob, output: &Path, frame: u32) -> Result<u32, String> {
+    let scene = job.scene.to_string_lossy().into_owned();
+    let file = if is_scene_file(&scene) {
+        Some(SceneFile::load(&scene).ok_or("Failed to load scene file")?)
+    } else {
+        None
+    };
+    let file_render = file.as_ref().map(|file| file.render.clone()).unwrap_or_default();
+    let width = job.width.or(file_render.width).unwrap_or(DEFAULT_WIDTH);
+    let height = job.height.or(file_render.height).unwrap_or(DEFAULT_HEIGHT);
+    let samples = job.samples.or(file_render.samples).unwrap_or(DEFAULT_SAMPLES).max(1);
+    let skybox = job.skybox.as_ref().map(|path| path.to_string_lossy().into_owned()).or_else(|| file.as_ref().and_then(|file| file.skybox()));
+
+    let state = Arc::new(TracingState::new(width, height));
+    {
+        let mut config = state.config.write();
+        if let Some(file) = file.as_ref() {
+            file.apply_settings(&mut config);
+            *state.environment_clamp.write() = file.environment.clamp;
+        }
+        if let Some(time_lapse) = job.time_lapse {
+            let solar = file.as_ref().and_then(|file| file.environment.solar).ok_or("Time-lapses need a scene file with `solar` settings")?;
+            config.environment.sun_direction = solar.at_hour(time_lapse.hour(solar.hour, frame)).sun();
+        }
+        config.render.width = width;
+        config.render.height = height;
+        config.environment.has_skybox = skybox.is_some() as u32;
+    }
+    state.sample_limit.store(samples, Ordering::Relaxed);
+    let taken = render_headless(&state, &scene, skybox.as_deref(), job.cpu);
+    if taken < samples {
+        return Err(format!("Render stopped after {} of {} samples", taken, samples));
+    }
+
+    let config = *state.config.read();
+    let tonemapping = job.tonemapping.unwrap_or(Tonemapping::None);
+    let device = if job.cpu || state.gpu_failed.load(Ordering::Relaxed) { "CPU" } else { "GPU" };
+    let metadata = RenderMetadata {
+        entries: vec![
+            ("Software", format!("rust-path-tracer {}", env!("CARGO_PKG_VERSION"))),
+            ("Scene", scene.clone()),
+            ("Skybox", skybox.clone().unwrap_or_else(|| "Procedural".to_string())),
+            ("Samples", taken.to_string()),
+            ("Render time", format!("{:.1} s", state.render_time.lock().as_secs_f32())),
+            ("Resolution", format!("{}x{}", width, height)),
+            ("Device", device.to_string()),
+            ("Bounces", format!("{}-{}", config.render.min_bounces, config.render.max_bounces)),
+            ("Tonemapping", format!("{:?}", tonemapping)),
+            ("Seed", config.render.seed.to_string()),
+        ],
+    };
+    if let Some(dir) = output.parent() {
+        std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    }
+    let framebuffer = state.framebuffer.read();
+    let settings = ExportSettings { tonemapping, exposure: 0.0, format: ImageFormat::from_path(output), jpeg_quality: JPEG_QUALITY, footer: job.footer };
+    save_image(output, &framebuffer, width, height, &settings, &metadata)?;
+    Ok(taken)
+}
+
+// Renders every job in turn. A failing job is logged and skipped, so one broken scene
+// doesn't cost the rest of an overnight run.
+pub fn run_batch(manifest: &BatchManifest) -> Vec<JobResult> {
+    let mut results = Vec::new();
+    for (i, job) in manifest.jobs.iter().enumerate() {
+        let frames = job.time_lapse.map_or(1, |time_lapse| time_lapse.frames.max(1));
+        for frame in 0..frames {
+            let name = if job.time_lapse.is_some() {
+                format!("Job {}/{}, frame {}/{}", i + 1, manifest.jobs.len(), frame + 1, frames)
+            } else {
+                format!("Job {}/{}", i + 1, manifest.jobs.len())
+            };
+            let output = match job.time_lapse {
+                Some(_) => TimeLapse::frame_path(&manifest.output_path(job), frame),
+                None => manifest.output_path(job),
+            };
+            tracing::info!("{}: rendering '{}' to '{}'.", name, job.scene.display(), output.display());
+
+            let start = Instant::now();
+            let result = run_job(job, &output, frame);
+            let duration = start.elapsed();
+            match &result {
+                Ok(samples) => tracing::info!("{}: finished {} samples in {:.1}s.", name, samples, duration.as_secs_f32()),
+                Err(err) => tracing::error!("{}: failed to render '{}': {}", name, job.scene.display(), err),
+            }
+            results.push(JobResult {
+                scene: job.scene.clone(),
+                output,
+                samples: *result.as_ref().unwrap_or(&0),
+                duration,
+                error: result.err(),
+            });
+        }
+    }
+
+    let failed = results.iter().filter(|result| result.error.is_some()).count();
+    let total = results.iter().map(|result| result.duration).sum::<Duration>();
+    tracing::info!("Batch finished: {} of {} renders succeeded in {:.1}s.", results.len() - failed, results.len(), total.as_secs_f32());
+    results
+}
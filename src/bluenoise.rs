@@ -0,0 +1,128 @@
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use rayon::prelude::*;
+use shared_structs::{BLUE_NOISE_LAYERS, BLUE_NOISE_SIZE};
+
+// Width of the gaussian used to measure how clustered the pattern is, from the original paper
+const SIGMA: f32 = 1.9;
+// Fraction of pixels set in the initial binary pattern
+const INITIAL_DENSITY: f32 = 0.1;
+
+// Energy of every pixel, ie. how close it is to the set pixels, using the tiling distance
+struct EnergyField {
+    kernel: Vec<f32>,
+    energy: Vec<f32>,
+}
+
+impl EnergyField {
+    fn new() -> Self {
+        let size = BLUE_NOISE_SIZE as usize;
+        let mut kernel = vec![0.0; size * size];
+        for y in 0..size {
+            for x in 0..size {
+                let dx = x.min(size - x) as f32;
+                let dy = y.min(size - y) as f32;
+                kernel[y * size + x] = (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp();
+            }
+        }
+        Self {
+            kernel,
+            energy: vec![0.0; size * size],
+        }
+    }
+
+    fn splat(&mut self, index: usize, sign: f32) {
+        let size = BLUE_NOISE_SIZE as usize;
+        let (px, py) = (index % size, index / size);
+        for y in 0..size {
+            for x in 0..size {
+                let dx = (x + size - px) % size;
+                let dy = (y + size - py) % size;
+                self.energy[y * size + x] += sign * self.kernel[dy * size + dx];
+            }
+        }
+    }
+
+    // Set pixel with the most energy
+    fn tightest_cluster(&self, pattern: &[bool]) -> usize {
+        (0..pattern.len())
+            .filter(|&i| pattern[i])
+            .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap()
+    }
+
+    // Unset pixel with the least energy
+    fn largest_void(&self, pattern: &[bool]) -> usize {
+        (0..pattern.len())
+            .filter(|&i| !pattern[i])
+            .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap()
+    }
+}
+
+// Ulichney's void-and-cluster method. Returns the rank of each pixel, which is the order
+// in which it was added to the pattern.
+fn void_and_cluster(seed: u64) -> Vec<u32> {
+    let pixel_count = (BLUE_NOISE_SIZE * BLUE_NOISE_SIZE) as usize;
+    let initial_count = (pixel_count as f32 * INITIAL_DENSITY) as usize;
+
+    // Start from white noise, and move pixels from clusters into voids until it settles
+    let mut pattern = vec![false; pixel_count];
+    let mut field = EnergyField::new();
+    let mut order = (0..pixel_count).collect::<Vec<_>>();
+    order.shuffle(&mut StdRng::seed_from_u64(seed));
+    for &index in order.iter().take(initial_count) {
+        pattern[index] = true;
+        field.splat(index, 1.0);
+    }
+    loop {
+        let cluster = field.tightest_cluster(&pattern);
+        pattern[cluster] = false;
+        field.splat(cluster, -1.0);
+        let void = field.largest_void(&pattern);
+        pattern[void] = true;
+        field.splat(void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; pixel_count];
+
+    // Rank the initial pixels by removing them from the tightest clusters first
+    let mut removing = pattern.clone();
+    let mut removing_field = EnergyField { kernel: field.kernel.clone(), energy: field.energy.clone() };
+    for rank in (0..initial_count).rev() {
+        let cluster = removing_field.tightest_cluster(&removing);
+        removing[cluster] = false;
+        removing_field.splat(cluster, -1.0);
+        ranks[cluster] = rank as u32;
+    }
+
+    // And the rest by filling the largest voids
+    for rank in initial_count..pixel_count {
+        let void = field.largest_void(&pattern);
+        pattern[void] = true;
+        field.splat(void, 1.0);
+        ranks[void] = rank as u32;
+    }
+    ranks
+}
+
+// Independent blue noise textures, stored one after the other. Each pixel holds a value
+// spread over the full u32 range, to be used as an offset for a low discrepancy sequence.
+pub fn generate_blue_noise() -> Vec<u32> {
+    puffin::profile_function!();
+    let now = std::time::Instant::now();
+    let pixel_count = BLUE_NOISE_SIZE * BLUE_NOISE_SIZE;
+    let textures = (0..BLUE_NOISE_LAYERS)
+        .into_par_iter()
+        .flat_map(|layer| {
+            void_and_cluster(layer as u64)
+                .into_iter()
+                .map(|rank| ((rank as u64 * 2 + 1) * (1u64 << 32) / (pixel_count as u64 * 2)) as u32)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    tracing::debug!("Blue noise generation time: {:?}", now.elapsed());
+    textures
+}
@@ -0,0 +1,127 @@
+use std::sync::{atomic::Ordering, Arc};
+
+use parking_lot::Mutex;
+
+use crate::{import::is_scene, settings::Settings, trace::{setup_trace, trace_cpu}};
+
+const MAX_RECENT_FILES: usize = 10;
+
+const SCENES_DIRECTORY: &str = "scenes";
+const THUMBNAIL_WIDTH: u32 = 160;
+const THUMBNAIL_HEIGHT: u32 = 90;
+const THUMBNAIL_SAMPLES: u32 = 16;
+
+// Most recently used scenes and skyboxes, newest first. Stored in the settings file.
+#[derive(Default)]
+pub struct RecentFiles {
+    pub scenes: Vec<String>,
+    pub skyboxes: Vec<String>,
+}
+
+impl RecentFiles {
+    pub fn load() -> Self {
+        let settings = Settings::load();
+        Self { scenes: settings.recent_scenes, skyboxes: settings.recent_skyboxes }
+    }
+
+    fn save(&self) {
+        Settings::update(|settings| {
+            settings.recent_scenes = self.scenes.clone();
+            settings.recent_skyboxes = self.skyboxes.clone();
+        });
+    }
+
+    fn push(list: &mut Vec<String>, path: &str) {
+        list.retain(|p| p != path);
+        list.insert(0, path.to_string());
+        list.truncate(MAX_RECENT_FILES);
+    }
+
+    pub fn add_scene(&mut self, path: &str) {
+        Self::push(&mut self.scenes, path);
+        self.save();
+    }
+
+    pub fn add_skybox(&mut self, path: &str) {
+        Self::push(&mut self.skyboxes, path);
+        self.save();
+    }
+}
+
+pub struct SceneEntry {
+    pub path: String,
+    pub name: String,
+    pub thumbnail: Option<egui::TextureHandle>,
+}
+
+// Lists the scenes shipped in the scenes directory, with thumbnails that are
+// rendered on the CPU in the background the first time the browser is opened.
+pub struct SceneBrowser {
+    pub entries: Vec<SceneEntry>,
+    finished_thumbnails: Arc<Mutex<Vec<(usize, egui::ColorImage)>>>,
+    started: bool,
+}
+
+impl SceneBrowser {
+    pub fn scan() -> Self {
+        let mut entries = std::fs::read_dir(SCENES_DIRECTORY)
+            .map(|dir| {
+                dir.filter_map(|entry| entry.ok())
+                    .filter_map(|entry| entry.path().to_str().map(|path| path.to_string()))
+                    .filter(|path| is_scene(path))
+                    .map(|path| SceneEntry {
+                        name: std::path::Path::new(&path).file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string(),
+                        path,
+                        thumbnail: None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Self {
+            entries,
+            finished_thumbnails: Arc::new(Mutex::new(Vec::new())),
+            started: false,
+        }
+    }
+
+    // Starts rendering thumbnails if that hasn't happened yet, and uploads any that are done
+    pub fn update(&mut self, egui_ctx: &egui::Context) {
+        if !self.started {
+            self.started = true;
+            let paths = self.entries.iter().map(|entry| entry.path.clone()).collect::<Vec<_>>();
+            let finished_thumbnails = self.finished_thumbnails.clone();
+            let repaint_ctx = egui_ctx.clone();
+            std::thread::spawn(move || {
+                for (index, path) in paths.iter().enumerate() {
+                    let image = render_thumbnail(path);
+                    finished_thumbnails.lock().push((index, image));
+                    repaint_ctx.request_repaint();
+                }
+            });
+        }
+
+        for (index, image) in self.finished_thumbnails.lock().drain(..) {
+            let name = format!("Thumbnail {}", self.entries[index].path);
+            self.entries[index].thumbnail = Some(egui_ctx.load_texture(name, image, egui::TextureOptions::LINEAR));
+        }
+    }
+}
+
+fn render_thumbnail(path: &str) -> egui::ColorImage {
+    let state = setup_trace(THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT, THUMBNAIL_SAMPLES);
+    state.cpu_low_priority.store(true, Ordering::Relaxed);
+    trace_cpu(path, None, state.clone());
+
+    // Reinhard tonemapping and gamma correction, which is good enough for a preview
+    let framebuffer = state.framebuffer.read();
+    let pixels = framebuffer
+        .chunks(3)
+        .flat_map(|rgb| {
+            let [r, g, b] = [rgb[0], rgb[1], rgb[2]].map(|value| ((value / (1.0 + value)).powf(1.0 / 2.2) * 255.0) as u8);
+            [r, g, b, 255]
+        })
+        .collect::<Vec<_>>();
+    egui::ColorImage::from_rgba_unmultiplied([THUMBNAIL_WIDTH as usize, THUMBNAIL_HEIGHT as usize], &pixels)
+}
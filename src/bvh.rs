@@ -2,7 +2,7 @@ use glam::{UVec4, Vec3, Vec4, Vec4Swizzles};
 use gpgpu::{GpuBuffer, BufOps};
 use shared_structs::{BVHNode};
 
-use crate::trace::FW;
+use crate::{cancel::CancelToken, trace::FW};
 
 // TODO: Use triangle buffer directly instead of 2 indirections
 
@@ -37,12 +37,65 @@ pub struct BVH {
 }
 
 impl BVH {
-    pub fn into_gpu<'fw>(self) -> GpuBVH<'fw> {
+    // Number of nodes on the longest path from the root to a leaf
+    pub fn depth(&self) -> usize {
+        let mut max_depth = 0;
+        let mut stack = vec![(0, 1)];
+        while let Some((node_idx, depth)) = stack.pop() {
+            let node = &self.nodes[node_idx];
+            max_depth = max_depth.max(depth);
+            if !node.is_leaf() {
+                let left_idx = node.left_node_index() as usize;
+                stack.push((left_idx, depth + 1));
+                stack.push((left_idx + 1, depth + 1));
+            }
+        }
+        max_depth
+    }
+
+    // Bounding box of the whole scene, which is the one of the root node
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        (self.nodes[0].aabb_min(), self.nodes[0].aabb_max())
+    }
+
+    pub fn to_gpu<'fw>(&self) -> GpuBVH<'fw> {
         let nodes_buffer = GpuBuffer::from_slice(&FW, &self.nodes);
         GpuBVH { nodes_buffer }
     }
 }
 
+// Spreads the low 10 bits of x out to every third bit
+fn spread_bits_3d(mut x: u32) -> u32 {
+    x &= 0x3ff;
+    x = (x | (x << 16)) & 0x030000ff;
+    x = (x | (x << 8)) & 0x0300f00f;
+    x = (x | (x << 4)) & 0x030c30c3;
+    x = (x | (x << 2)) & 0x09249249;
+    x
+}
+
+// Sorts triangles along a Z-order curve through their centroids, so triangles that are close in
+// space are also close in memory. The BVH build only partitions, so its leaves keep most of this order.
+pub fn sort_triangles_morton(vertices: &[Vec4], indices: &mut [UVec4]) {
+    puffin::profile_function!();
+
+    let centroid = |ind: &UVec4| {
+        let v0 = vertices[ind.x as usize].xyz();
+        let v1 = vertices[ind.y as usize].xyz();
+        let v2 = vertices[ind.z as usize].xyz();
+        (v0 + v1 + v2) / 3.0
+    };
+    let (min, max) = indices.iter().map(centroid).fold(
+        (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+        |(min, max), c| (min.min(c), max.max(c)),
+    );
+    let extent = (max - min).max(Vec3::splat(f32::EPSILON));
+    indices.sort_by_cached_key(|ind| {
+        let cell = ((centroid(ind) - min) / extent * 1023.0).as_uvec3();
+        spread_bits_3d(cell.x) | (spread_bits_3d(cell.y) << 1) | (spread_bits_3d(cell.z) << 2)
+    });
+}
+
 pub struct GpuBVH<'fw> {
     pub nodes_buffer: GpuBuffer<'fw, BVHNode>,
 }
@@ -53,6 +106,7 @@ pub struct BVHBuilder<'a> {
     indices: &'a mut [UVec4],
     centroids: Vec<Vec3>,
     nodes: Vec<BVHNode>,
+    cancel: CancelToken,
 }
 
 impl<'a> BVHBuilder<'a> {
@@ -74,6 +128,7 @@ impl<'a> BVHBuilder<'a> {
             indices,
             centroids,
             nodes,
+            cancel: CancelToken::default(),
         }
     }
 
@@ -82,6 +137,12 @@ impl<'a> BVHBuilder<'a> {
         self
     }
 
+    // Checked between nodes, so a build can be abandoned partway. See `try_build`.
+    pub fn cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
     fn update_node_aabb(&mut self, node_idx: usize) {
         let node = &mut self.nodes[node_idx];
         let mut aabb_min = Vec3::splat(f32::INFINITY);
@@ -255,6 +316,13 @@ impl<'a> BVHBuilder<'a> {
     }
 
     pub fn build(&mut self) -> BVH {
+        self.try_build().expect("BVH build was cancelled.")
+    }
+
+    // Returns None if the build was cancelled, leaving the indices partially sorted
+    pub fn try_build(&mut self) -> Option<BVH> {
+        puffin::profile_function!();
+
         let mut node_count = 1;
 
         let root = &mut self.nodes[0];
@@ -264,6 +332,10 @@ impl<'a> BVHBuilder<'a> {
 
         let mut stack = vec![0];
         while !stack.is_empty() {
+            if self.cancel.is_cancelled() {
+                return None;
+            }
+
             // get the next root node
             let node_idx = stack.pop().expect("BVH build stack is empty.");
             let node = &self.nodes[node_idx];
@@ -318,8 +390,8 @@ impl<'a> BVHBuilder<'a> {
         }
 
         self.nodes.truncate(node_count);
-        BVH {
+        Some(BVH {
             nodes: self.nodes.clone(),
-        }
+        })
     }
 }
\ No newline at end of file
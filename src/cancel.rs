@@ -0,0 +1,16 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+
+// Lets long running work be abandoned from another thread. Clones share the same flag,
+// so a new token is needed to run the work again.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,173 @@
+use shared_structs::{NextEventEstimation, RenderSettings, TracingConfig};
+
+use crate::{batch::{HeadlessScene, RenderProgress}, export::{draw_text, encode_framebuffer, text_width, GLYPH_SIZE}, tonemap::Tonemapping};
+
+const BACKGROUND: [u8; 3] = [24, 24, 24];
+const LABEL: [u8; 3] = [230, 230, 230];
+const GAP: u32 = 4; // Between cells, and around the labels
+
+// A setting varied along the rows or columns of a contact sheet
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SweepAxis {
+    None,
+    NextEventEstimation,
+    Tonemapping,
+    MaxBounces,
+    PathSplits,
+    FireflyRejection,
+}
+
+impl SweepAxis {
+    pub const ALL: [SweepAxis; 6] = [SweepAxis::None, SweepAxis::NextEventEstimation, SweepAxis::Tonemapping, SweepAxis::MaxBounces, SweepAxis::PathSplits, SweepAxis::FireflyRejection];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SweepAxis::None => "None",
+            SweepAxis::NextEventEstimation => "Next event estimation",
+            SweepAxis::Tonemapping => "Tonemapping",
+            SweepAxis::MaxBounces => "Max bounces",
+            SweepAxis::PathSplits => "Path splits",
+            SweepAxis::FireflyRejection => "Firefly rejection",
+        }
+    }
+
+    pub fn values(self) -> Vec<SweepValue> {
+        match self {
+            SweepAxis::None => vec![SweepValue::Unchanged],
+            SweepAxis::NextEventEstimation => [NextEventEstimation::None, NextEventEstimation::MultipleImportanceSampling, NextEventEstimation::DirectLightSampling]
+                .into_iter()
+                .map(SweepValue::NextEventEstimation)
+                .collect(),
+            SweepAxis::Tonemapping => [Tonemapping::None, Tonemapping::Reinhard, Tonemapping::ACESNarkowicz, Tonemapping::ACESHill, Tonemapping::Neutral, Tonemapping::Uncharted]
+                .into_iter()
+                .map(SweepValue::Tonemapping)
+                .collect(),
+            SweepAxis::MaxBounces => [1, 2, 4, 8, 16].into_iter().map(SweepValue::MaxBounces).collect(),
+            SweepAxis::PathSplits => [1, 2, 4, 8].into_iter().map(SweepValue::PathSplits).collect(),
+            SweepAxis::FireflyRejection => [0.0, 16.0, 8.0, 4.0, 2.0].into_iter().map(SweepValue::FireflyRejection).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SweepValue {
+    Unchanged,
+    NextEventEstimation(NextEventEstimation),
+    Tonemapping(Tonemapping),
+    MaxBounces(u32),
+    PathSplits(u32),
+    FireflyRejection(f32),
+}
+
+impl SweepValue {
+    pub fn label(&self) -> String {
+        match self {
+            SweepValue::Unchanged => String::new(),
+            SweepValue::NextEventEstimation(nee) => format!("NEE {:?}", nee),
+            SweepValue::Tonemapping(tonemapping) => format!("{:?}", tonemapping),
+            SweepValue::MaxBounces(bounces) => format!("{} bounces", bounces),
+            SweepValue::PathSplits(splits) => format!("{} splits", splits),
+            SweepValue::FireflyRejection(threshold) if *threshold == 0.0 => "No rejection".to_string(),
+            SweepValue::FireflyRejection(threshold) => format!("Rejection {}", threshold),
+        }
+    }
+
+    pub fn apply(&self, render: &mut RenderSettings, tonemapping: &mut Tonemapping) {
+        match *self {
+            SweepValue::Unchanged => {}
+            SweepValue::NextEventEstimation(nee) => render.nee = nee.to_u32(),
+            SweepValue::Tonemapping(value) => *tonemapping = value,
+            SweepValue::MaxBounces(bounces) => {
+                render.max_bounces = bounces;
+                render.min_bounces = render.min_bounces.min(bounces);
+            }
+            SweepValue::PathSplits(splits) => render.path_splits = splits,
+            SweepValue::FireflyRejection(threshold) => render.firefly_rejection = threshold,
+        }
+    }
+}
+
+// Renders a scene once per combination of two settings, at a fixed sample count, and lays the
+// results out in a labeled grid. Everything else is taken as it was when the sheet was set up.
+#[derive(Clone)]
+pub struct ContactSheet {
+    pub scene: HeadlessScene,
+    pub config: TracingConfig, // Its size is that of each cell
+    pub tonemapping: Tonemapping,
+    pub rows: SweepAxis,
+    pub columns: SweepAxis,
+    pub samples: u32,
+}
+
+impl ContactSheet {
+    pub fn cell_count(&self) -> u32 {
+        (self.rows.values().len() * self.columns.values().len()) as u32
+    }
+
+    // Cells that only differ in tonemapping share a render
+    fn render_cell(&self, render: &RenderSettings, progress: &RenderProgress, renders: &mut Vec<(RenderSettings, Vec<f32>)>) -> Result<usize, String> {
+        if let Some(index) = renders.iter().position(|(settings, _)| bytemuck::bytes_of(settings) == bytemuck::bytes_of(render)) {
+            return Ok(index);
+        }
+        let framebuffer = self.scene.render(TracingConfig { render: *render, ..self.config }, self.samples, progress)?;
+        renders.push((*render, framebuffer));
+        Ok(renders.len() - 1)
+    }
+
+    // Returns the sRGB pixels of the sheet, and its size
+    pub fn render(&self, progress: &RenderProgress) -> Result<(Vec<u8>, u32, u32), String> {
+        let rows = self.rows.values();
+        let columns = self.columns.values();
+        let mut renders = Vec::new();
+        let mut cells = Vec::new();
+        for row in &rows {
+            for column in &columns {
+                let mut render = self.config.render;
+                let mut tonemapping = self.tonemapping;
+                row.apply(&mut render, &mut tonemapping);
+                column.apply(&mut render, &mut tonemapping);
+                let index = self.render_cell(&render, progress, &mut renders)?;
+                cells.push(encode_framebuffer(&renders[index].1, tonemapping, 0.0));
+                progress.finish_one();
+            }
+        }
+        let row_labels = rows.iter().map(SweepValue::label).collect::<Vec<_>>();
+        let column_labels = columns.iter().map(SweepValue::label).collect::<Vec<_>>();
+        let title = format!("{} spp", self.samples);
+        Ok(composite(&cells, self.config.render.width, self.config.render.height, &row_labels, &column_labels, &title))
+    }
+}
+
+// Lays cells out row by row, with the column labels above them and the row labels to their left.
+// Empty labels take no space. Returns the pixels and their size.
+pub fn composite(cells: &[Vec<u8>], cell_width: u32, cell_height: u32, row_labels: &[String], column_labels: &[String], title: &str) -> (Vec<u8>, u32, u32) {
+    let scale = (cell_width / 480).max(1);
+    let label_height = GLYPH_SIZE * scale + GAP * 2;
+    let left = row_labels.iter().map(|label| text_width(label, scale)).max().unwrap_or(0).max(text_width(title, scale)) + GAP * 2;
+    let top = label_height;
+    let width = left + column_labels.len() as u32 * (cell_width + GAP);
+    let height = top + row_labels.len() as u32 * (cell_height + GAP);
+    let mut pixels = BACKGROUND.repeat((width * height) as usize);
+
+    draw_text(&mut pixels, width, GAP, GAP, title, scale, LABEL);
+    for (column, label) in column_labels.iter().enumerate() {
+        let x = left + column as u32 * (cell_width + GAP);
+        draw_text(&mut pixels, width, x, GAP, label, scale, LABEL);
+    }
+    for (row, label) in row_labels.iter().enumerate() {
+        let y = top + row as u32 * (cell_height + GAP);
+        draw_text(&mut pixels, width, GAP, y + GAP, label, scale, LABEL);
+        for column in 0..column_labels.len() {
+            let Some(cell) = cells.get(row * column_labels.len() + column) else {
+                continue;
+            };
+            let x = left + column as u32 * (cell_width + GAP);
+            for cell_y in 0..cell_height {
+                let source = (cell_y * cell_width * 3) as usize;
+                let target = (((y + cell_y) * width + x) * 3) as usize;
+                pixels[target..target + (cell_width * 3) as usize].copy_from_slice(&cell[source..source + (cell_width * 3) as usize]);
+            }
+        }
+    }
+    (pixels, width, height)
+}
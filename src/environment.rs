@@ -0,0 +1,93 @@
+use glam::Vec3;
+use image::{imageops::{self, FilterType}, DynamicImage, Rgba32FImage};
+
+// Directional light standing in for the part of an environment map that was clamped away
+#[derive(Copy, Clone)]
+pub struct ExtractedLight {
+    pub direction: Vec3, // In the space of the environment map, before rotation
+    pub irradiance: Vec3,
+}
+
+fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+// Direction of a texel in an equirectangular map, matching how the kernel samples it
+fn texel_direction(x: u32, y: u32, width: u32, height: u32) -> Vec3 {
+    let u = (x as f32 + 0.5) / width as f32;
+    let v = (y as f32 + 0.5) / height as f32;
+    let phi = (u - 0.5) * 2.0 * std::f32::consts::PI;
+    let latitude = (0.5 - v) * std::f32::consts::PI;
+    Vec3::new(latitude.cos() * phi.cos(), latitude.sin(), latitude.cos() * phi.sin())
+}
+
+// A tiny, very bright sun in an HDRI is rarely hit by BSDF samples, and shows up as fireflies
+// when it is. Clamps the map to `threshold`, and gathers the energy that was removed into a
+// directional light, which can be sampled directly and still casts sharp shadows.
+pub fn clamp_environment(image: &mut DynamicImage, threshold: f32) -> Option<ExtractedLight> {
+    puffin::profile_function!();
+
+    let mut pixels = image.to_rgba32f();
+    let (width, height) = pixels.dimensions();
+    let texel_solid_angle = (2.0 * std::f32::consts::PI / width as f32) * (std::f32::consts::PI / height as f32);
+
+    let mut irradiance = Vec3::ZERO;
+    let mut weighted_direction = Vec3::ZERO;
+    for (x, y, pixel) in pixels.enumerate_pixels_mut() {
+        let color = Vec3::new(pixel[0], pixel[1], pixel[2]);
+        let brightest = color.max_element();
+        if brightest <= threshold {
+            continue;
+        }
+
+        let clamped = color * (threshold / brightest);
+        let removed = color - clamped;
+        pixel[0] = clamped.x;
+        pixel[1] = clamped.y;
+        pixel[2] = clamped.z;
+
+        let direction = texel_direction(x, y, width, height);
+        // Texels shrink towards the poles, by the cosine of the latitude
+        let solid_angle = texel_solid_angle * (1.0 - direction.y * direction.y).max(0.0).sqrt();
+        irradiance += removed * solid_angle;
+        weighted_direction += direction * luminance(removed) * solid_angle;
+    }
+    *image = DynamicImage::ImageRgba32F(pixels);
+
+    if irradiance == Vec3::ZERO {
+        return None;
+    }
+    let light = ExtractedLight {
+        direction: weighted_direction.normalize_or_zero(),
+        irradiance,
+    };
+    tracing::info!("Extracted a directional light with irradiance {:?} from the environment map.", light.irradiance);
+    Some(light)
+}
+
+
+// Deepest level generated for blurred backgrounds, where the sky is reduced to a few colors
+pub const MAX_SKYBOX_MIPS: u32 = 9;
+
+// Packs a mip chain of the environment map into one image, since the kernels only get a single
+// level. The full image is on top, and the smaller levels are lined up below it, each to the right
+// of the previous one. Returns the packed image and how many levels it holds.
+pub fn pack_skybox_mips(image: &DynamicImage) -> (DynamicImage, u32) {
+    puffin::profile_function!();
+
+    let base = image.to_rgba32f();
+    let (width, height) = base.dimensions();
+    let mut packed = Rgba32FImage::new(width, height + height / 2);
+    imageops::replace(&mut packed, &base, 0, 0);
+
+    let mut level = base;
+    let mut levels = 1;
+    let mut x = 0;
+    while levels < MAX_SKYBOX_MIPS && width >> levels > 0 && height >> levels > 0 {
+        level = imageops::resize(&level, width >> levels, height >> levels, FilterType::Triangle);
+        imageops::replace(&mut packed, &level, x as i64, height as i64);
+        x += width >> levels;
+        levels += 1;
+    }
+    (DynamicImage::ImageRgba32F(packed), levels)
+}
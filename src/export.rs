@@ -0,0 +1,231 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use font8x8::UnicodeFonts;
+use glam::{Vec2, Vec3};
+use image::{codecs::jpeg::JpegEncoder, ColorType};
+
+use crate::tonemap::{linear_to_srgb, tonemap, Tonemapping};
+
+// Settings that produced a render, embedded in exported images so they can be reproduced
+pub struct RenderMetadata {
+    pub entries: Vec<(&'static str, String)>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Self {
+        if is_jpeg(path) { ImageFormat::Jpeg } else { ImageFormat::Png }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+// How the linear framebuffer is turned into an image file. Picked when saving, so it can differ from the viewport.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ExportSettings {
+    pub tonemapping: Tonemapping,
+    pub exposure: f32, // In stops, applied before tonemapping
+    pub format: ImageFormat,
+    pub jpeg_quality: u8,
+    pub footer: bool, // Write the metadata onto a strip below the image, see burn_in_footer
+}
+
+fn encode_pixel(rgb: &[f32], tonemapping: Tonemapping, scale: f32) -> [u8; 3] {
+    let color = tonemap(Vec3::new(rgb[0], rgb[1], rgb[2]) * scale, tonemapping);
+    color.to_array().map(|c| (linear_to_srgb(c.clamp(0.0, 1.0)) * 255.0 + 0.5) as u8)
+}
+
+// Tonemaps and sRGB encodes the linear framebuffer, the same way the display pass does on an sRGB surface
+pub fn encode_framebuffer(framebuffer: &[f32], tonemapping: Tonemapping, exposure: f32) -> Vec<u8> {
+    let scale = exposure.exp2();
+    framebuffer
+        .chunks_exact(3)
+        .flat_map(|rgb| encode_pixel(rgb, tonemapping, scale))
+        .collect()
+}
+
+// Encodes a nearest neighbour downscale of the framebuffer, at most `max_size` on either side,
+// to preview export settings without encoding the whole image. Returns the pixels and their size.
+pub fn encode_preview(framebuffer: &[f32], width: u32, height: u32, max_size: u32, tonemapping: Tonemapping, exposure: f32) -> (Vec<u8>, u32, u32) {
+    if width == 0 || height == 0 || framebuffer.len() < (width * height * 3) as usize {
+        return (Vec::new(), 0, 0);
+    }
+    let factor = (width.max(height) as f32 / max_size as f32).max(1.0);
+    let preview_width = ((width as f32 / factor) as u32).max(1);
+    let preview_height = ((height as f32 / factor) as u32).max(1);
+    let scale = exposure.exp2();
+    let mut pixels = Vec::with_capacity((preview_width * preview_height * 3) as usize);
+    for y in 0..preview_height {
+        for x in 0..preview_width {
+            let source_x = ((x as f32 + 0.5) * factor) as u32;
+            let source_y = ((y as f32 + 0.5) * factor) as u32;
+            let index = (source_y.min(height - 1) * width + source_x.min(width - 1)) as usize * 3;
+            pixels.extend(encode_pixel(&framebuffer[index..index + 3], tonemapping, scale));
+        }
+    }
+    (pixels, preview_width, preview_height)
+}
+
+fn save_png(path: &Path, pixels: &[u8], width: u32, height: u32, metadata: &RenderMetadata) -> Result<(), String> {
+    let file = File::create(path).map_err(|err| err.to_string())?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_srgb(png::SrgbRenderingIntent::Perceptual);
+    for (key, value) in metadata.entries.iter() {
+        encoder.add_text_chunk(key.to_string(), value.clone()).map_err(|err| err.to_string())?;
+    }
+    let mut writer = encoder.write_header().map_err(|err| err.to_string())?;
+    writer.write_image_data(pixels).map_err(|err| err.to_string())
+}
+
+// The JPEG encoder can't write EXIF, so the metadata goes into a comment (COM) segment instead
+fn save_jpeg(path: &Path, pixels: &[u8], width: u32, height: u32, metadata: &RenderMetadata, quality: u8) -> Result<(), String> {
+    let mut bytes = Vec::new();
+    JpegEncoder::new_with_quality(&mut bytes, quality)
+        .encode(pixels, width, height, ColorType::Rgb8)
+        .map_err(|err| err.to_string())?;
+
+    let comment = metadata
+        .entries
+        .iter()
+        .map(|(key, value)| format!("{}: {}", key, value))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let comment = &comment.as_bytes()[..comment.len().min(u16::MAX as usize - 2)];
+    let mut segment = vec![0xFF, 0xFE];
+    segment.extend_from_slice(&(comment.len() as u16 + 2).to_be_bytes());
+    segment.extend_from_slice(comment);
+
+    // Right after the SOI marker
+    bytes.splice(2..2, segment);
+    std::fs::write(path, bytes).map_err(|err| err.to_string())
+}
+
+pub fn is_jpeg(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg"))
+}
+
+const FOOTER_BACKGROUND: [u8; 3] = [24, 24, 24];
+const FOOTER_TEXT: [u8; 3] = [230, 230, 230];
+pub const GLYPH_SIZE: u32 = 8;
+
+// Greedily fits the metadata entries into lines of `columns` characters, breaking only between
+// entries. Entries that don't fit on a line of their own are cut short.
+pub fn footer_lines(metadata: &RenderMetadata, columns: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for (key, value) in metadata.entries.iter() {
+        let entry = format!("{}: {}", key, value);
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 3 + entry.chars().count() <= columns => {
+                line.push_str("   ");
+                line.push_str(&entry);
+            }
+            _ => lines.push(entry.chars().take(columns).collect()),
+        }
+    }
+    lines
+}
+
+// Appends a strip below the encoded image with the metadata written on it, so renders shared
+// without their files still say how they were made. Returns the new height.
+pub fn burn_in_footer(pixels: &mut Vec<u8>, width: u32, height: u32, metadata: &RenderMetadata) -> u32 {
+    // Text scales with the image, so it stays readable on large renders
+    let scale = (width / 960).max(1);
+    let padding = GLYPH_SIZE / 2 * scale;
+    let line_height = (GLYPH_SIZE + 2) * scale;
+    let columns = (width.saturating_sub(padding * 2) / (GLYPH_SIZE * scale)) as usize;
+    let lines = footer_lines(metadata, columns);
+    if columns == 0 || lines.is_empty() {
+        return height;
+    }
+
+    let footer_height = lines.len() as u32 * line_height + padding * 2 - 2 * scale;
+    pixels.extend(FOOTER_BACKGROUND.repeat((width * footer_height) as usize));
+    for (row, line) in lines.iter().enumerate() {
+        draw_text(pixels, width, padding, height + padding + row as u32 * line_height, line, scale, FOOTER_TEXT);
+    }
+    height + footer_height
+}
+
+// Writes a line of text into RGB pixels, with its top left corner at `x`, `y` and each glyph
+// pixel blown up to `scale` pixels. Characters past the right edge are cut off.
+pub fn draw_text(pixels: &mut [u8], width: u32, x: u32, y: u32, text: &str, scale: u32, color: [u8; 3]) {
+    let height = pixels.len() as u32 / 3 / width.max(1);
+    for (column, character) in text.chars().enumerate() {
+        let glyph = font8x8::BASIC_FONTS.get(character).or_else(|| font8x8::BASIC_FONTS.get('?')).unwrap_or_default();
+        let left = x + column as u32 * GLYPH_SIZE * scale;
+        for (glyph_y, &bits) in glyph.iter().enumerate() {
+            for glyph_x in 0..GLYPH_SIZE {
+                if bits >> glyph_x & 1 == 0 {
+                    continue;
+                }
+                for offset_y in 0..scale {
+                    for offset_x in 0..scale {
+                        let pixel_x = left + glyph_x * scale + offset_x;
+                        let pixel_y = y + glyph_y as u32 * scale + offset_y;
+                        if pixel_x >= width || pixel_y >= height {
+                            continue;
+                        }
+                        let index = ((pixel_y * width + pixel_x) * 3) as usize;
+                        pixels[index..index + 3].copy_from_slice(&color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn text_width(text: &str, scale: u32) -> u32 {
+    text.chars().count() as u32 * GLYPH_SIZE * scale
+}
+
+// Saves in the format of the settings, whatever the extension of the path
+pub fn save_image(path: &Path, framebuffer: &[f32], width: u32, height: u32, settings: &ExportSettings, metadata: &RenderMetadata) -> Result<(), String> {
+    let mut pixels = encode_framebuffer(framebuffer, settings.tonemapping, settings.exposure);
+    if pixels.len() != (width * height * 3) as usize {
+        return Err("Framebuffer doesn't match the render size".to_string());
+    }
+    let height = if settings.footer { burn_in_footer(&mut pixels, width, height, metadata) } else { height };
+    save_pixels(path, &pixels, width, height, settings, metadata)
+}
+
+// Saves an already encoded sRGB image, such as a contact sheet
+pub fn save_pixels(path: &Path, pixels: &[u8], width: u32, height: u32, settings: &ExportSettings, metadata: &RenderMetadata) -> Result<(), String> {
+    match settings.format {
+        ImageFormat::Jpeg => save_jpeg(path, pixels, width, height, metadata, settings.jpeg_quality),
+        ImageFormat::Png => save_png(path, pixels, width, height, metadata),
+    }
+}
+
+// Motion vectors are stored in pixels, in the red and green channels of a float EXR
+pub fn save_motion_vectors(path: &Path, motion_vectors: &[Vec2], width: u32, height: u32) -> Result<(), String> {
+    if motion_vectors.len() != (width * height) as usize {
+        return Err("Motion vectors don't match the render size".to_string());
+    }
+    let pixels = motion_vectors.iter().flat_map(|motion| [motion.x, motion.y, 0.0]).collect::<Vec<_>>();
+    let image = image::Rgb32FImage::from_raw(width, height, pixels).ok_or("Invalid motion vector buffer")?;
+    image.save(path).map_err(|err| err.to_string())
+}
+
+// Variance of the mean luminance of each pixel, in all three channels of a float EXR
+pub fn save_variance(path: &Path, variance: &[f32], width: u32, height: u32) -> Result<(), String> {
+    if variance.len() != (width * height) as usize {
+        return Err("Variance doesn't match the render size".to_string());
+    }
+    let pixels = variance.iter().flat_map(|&variance| [variance; 3]).collect::<Vec<_>>();
+    let image = image::Rgb32FImage::from_raw(width, height, pixels).ok_or("Invalid variance buffer")?;
+    image.save(path).map_err(|err| err.to_string())
+}
@@ -0,0 +1,31 @@
+use glam::Vec4;
+
+// Weights of a 3x3 tent, which spreads a pixel's rejected energy over its neighbours
+const SPREAD_WEIGHTS: [f32; 3] = [0.25, 0.5, 0.25];
+
+// Adds the energy taken off by kernels::reject_firefly back into an RGB image, as a soft blur
+// around the pixels it was taken from. Weights falling outside the image go to the pixels that
+// remain, so no energy is lost at the edges, and the image keeps an unbiased mean.
+pub fn spread_rejected_energy(image: &mut [f32], outliers: &[Vec4], width: u32, height: u32) {
+    let (width, height) = (width as i32, height as i32);
+    for (index, outlier) in outliers.iter().enumerate() {
+        let rejected = outlier.truncate();
+        if rejected.max_element() <= 0.0 {
+            continue;
+        }
+        let (x, y) = (index as i32 % width, index as i32 / width);
+        let neighbours = || {
+            (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+                .filter(move |&(dx, dy)| (0..width).contains(&(x + dx)) && (0..height).contains(&(y + dy)))
+                .map(move |(dx, dy)| (((y + dy) * width + x + dx) as usize * 3, SPREAD_WEIGHTS[(dx + 1) as usize] * SPREAD_WEIGHTS[(dy + 1) as usize]))
+        };
+        let total = neighbours().map(|(_, weight)| weight).sum::<f32>();
+        for (target, weight) in neighbours() {
+            let share = rejected * (weight / total);
+            image[target] += share.x;
+            image[target + 1] += share.y;
+            image[target + 2] += share.z;
+        }
+    }
+}
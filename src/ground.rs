@@ -0,0 +1,120 @@
+use glam::{UVec4, Vec2, Vec3, Vec4, Vec4Swizzles};
+use shared_structs::MaterialData;
+
+// Number of segments used for the curve between the floor and the wall of the cyclorama
+const CURVE_SEGMENTS: u32 = 16;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GroundShape {
+    None,
+    Plane,
+    Cyclorama,
+}
+
+// Procedural floor placed under the scene, so single models don't float in the sky
+#[derive(Copy, Clone, PartialEq)]
+pub struct GroundSettings {
+    pub shape: GroundShape,
+    pub albedo: Vec3,
+    pub roughness: f32,
+    pub metallic: f32,
+}
+
+impl Default for GroundSettings {
+    fn default() -> Self {
+        Self {
+            shape: GroundShape::None,
+            albedo: Vec3::splat(0.8),
+            roughness: 0.5,
+            metallic: 0.0,
+        }
+    }
+}
+
+// Geometry of a loaded scene, before the BVH is built
+pub struct SceneGeometry<'a> {
+    pub vertices: &'a mut Vec<Vec4>,
+    pub indices: &'a mut Vec<UVec4>,
+    pub normals: &'a mut Vec<Vec4>,
+    pub tangents: &'a mut Vec<Vec4>,
+    pub uvs: &'a mut Vec<Vec2>,
+}
+
+// Cross section of the ground along Z, as (z, y, normal) points. The camera looks
+// down +Z by default, so the cyclorama curves up behind the scene.
+fn profile(settings: &GroundSettings, min: Vec3, max: Vec3, extent: f32) -> Vec<(f32, f32, Vec3)> {
+    let floor = min.y - extent * 1e-3;
+    match settings.shape {
+        GroundShape::None => Vec::new(),
+        GroundShape::Plane => vec![
+            (min.z - extent * 5.0, floor, Vec3::Y),
+            (max.z + extent * 5.0, floor, Vec3::Y),
+        ],
+        GroundShape::Cyclorama => {
+            let back = max.z + extent * 0.5;
+            let radius = extent;
+            let mut points = vec![(min.z - extent * 3.0, floor, Vec3::Y)];
+            for i in 0..=CURVE_SEGMENTS {
+                let angle = i as f32 / CURVE_SEGMENTS as f32 * std::f32::consts::FRAC_PI_2;
+                points.push((
+                    back + radius * angle.sin(),
+                    floor + radius * (1.0 - angle.cos()),
+                    Vec3::new(0.0, angle.cos(), -angle.sin()),
+                ));
+            }
+            points.push((back + radius, floor + extent * 3.0, Vec3::NEG_Z));
+            points
+        }
+    }
+}
+
+// Appends the ground to the scene, sized to its bounds, using a new material
+pub fn add_ground(settings: &GroundSettings, geometry: SceneGeometry, material_datas: &mut Vec<MaterialData>, material_names: &mut Vec<String>) {
+    if settings.shape == GroundShape::None || geometry.vertices.is_empty() {
+        return;
+    }
+
+    let min = geometry.vertices.iter().fold(Vec3::splat(f32::MAX), |acc, v| acc.min(v.xyz()));
+    let max = geometry.vertices.iter().fold(Vec3::splat(f32::MIN), |acc, v| acc.max(v.xyz()));
+    let extent = (max - min).max_element().max(1.0);
+    let center = (min + max) / 2.0;
+    let half_width = extent * if settings.shape == GroundShape::Plane { 5.0 } else { 3.0 };
+
+    let material_index = material_datas.len() as u32;
+    let mut material = MaterialData::default();
+    material.albedo = settings.albedo.extend(1.0);
+    material.roughness = Vec4::splat(settings.roughness);
+    material.metallic = Vec4::splat(settings.metallic);
+    material_datas.push(material);
+    material_names.push("Ground".to_string());
+
+    // Meshes without normals, tangents or UVs leave these short, so pad them to line up with the new vertices
+    let vertex_count = geometry.vertices.len();
+    geometry.normals.resize(vertex_count, Vec4::ZERO);
+    geometry.tangents.resize(vertex_count, Vec4::ZERO);
+    geometry.uvs.resize(vertex_count, Vec2::ZERO);
+
+    // A strip of quads along the profile, spanning the scene in X
+    let mut distance = 0.0;
+    let mut previous: Option<Vec2> = None;
+    for (i, (z, y, normal)) in profile(settings, min, max, extent).into_iter().enumerate() {
+        let point = Vec2::new(z, y);
+        distance += previous.map_or(0.0, |previous| previous.distance(point));
+        previous = Some(point);
+
+        let offset = geometry.vertices.len() as u32;
+        for x in [center.x - half_width, center.x + half_width] {
+            geometry.vertices.push(Vec4::new(x, y, z, 1.0));
+            geometry.normals.push(normal.extend(0.0));
+            geometry.tangents.push(Vec4::new(1.0, 0.0, 0.0, 0.0));
+            geometry.uvs.push(Vec2::new(x, distance) / extent);
+        }
+        if i > 0 {
+            // Wound so the faces point along the normals, which matters if the ground is made emissive
+            let (left, right) = (offset - 2, offset - 1);
+            let (next_left, next_right) = (offset, offset + 1);
+            geometry.indices.push(UVec4::new(left, next_left, right, material_index));
+            geometry.indices.push(UVec4::new(right, next_left, next_right, material_index));
+        }
+    }
+}
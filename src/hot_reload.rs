@@ -0,0 +1,65 @@
+use std::{path::PathBuf, time::{Duration, Instant, SystemTime}};
+
+// Where the build script writes the compiled kernels. Running `cargo build` while the app is
+// open rebuilds them in place, which is all hot reloading needs.
+const KERNEL_PATH: &str = env!("kernels.spv");
+
+// SPIR-V modules start with this word
+const SPIRV_MAGIC: u32 = 0x07230203;
+
+// Polling a single file is cheap, but there's no need to do it every sample
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Watches a compiled kernel on disk, for iterating on the kernels without restarting.
+// The path can be overridden with the RUSTIC_KERNEL_PATH environment variable, to load
+// kernels built some other way.
+pub struct KernelWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+}
+
+impl KernelWatcher {
+    pub fn new() -> Self {
+        let path = std::env::var_os("RUSTIC_KERNEL_PATH").map_or_else(|| PathBuf::from(KERNEL_PATH), PathBuf::from);
+        let modified = std::fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+        Self {
+            path,
+            modified,
+            last_poll: Instant::now(),
+        }
+    }
+
+    // Returns the kernel if it changed since the last call. The build script writes it in one go,
+    // but a half written or foreign file is skipped until it is replaced again.
+    pub fn poll(&mut self) -> Option<Vec<u8>> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+
+        let modified = std::fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok()?;
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+
+        let bytes = match std::fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                tracing::error!("Failed to read kernel '{}': {}", self.path.display(), err);
+                return None;
+            }
+        };
+        if !is_spirv(&bytes) {
+            tracing::error!("'{}' is not a SPIR-V module, keeping the current kernel.", self.path.display());
+            return None;
+        }
+        tracing::info!("Reloading kernel from '{}'.", self.path.display());
+        Some(bytes)
+    }
+}
+
+pub fn is_spirv(bytes: &[u8]) -> bool {
+    bytes.len() >= 20 && bytes.len() % 4 == 0 && bytes[..4] == SPIRV_MAGIC.to_le_bytes()
+}
@@ -0,0 +1,78 @@
+use std::path::Path;
+
+// Formats assimp is commonly used for. Anything else might still load, but we can't tell.
+const SCENE_EXTENSIONS: [&str; 11] = ["glb", "gltf", "fbx", "obj", "dae", "blend", "3ds", "ply", "stl", "x3d", "3mf"];
+// Scene files that reference one of the above, see scene_file
+const SCENE_FILE_EXTENSIONS: [&str; 2] = ["ron", "json"];
+// HDR images are almost always environment maps
+const ENVIRONMENT_EXTENSIONS: [&str; 2] = ["hdr", "exr"];
+// LDR images could be a skybox or a reference render
+const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "bmp", "tga"];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ImportKind {
+    Scene,
+    Skybox,
+    Reference,
+    Ignore,
+}
+
+impl ImportKind {
+    pub const ALL: [ImportKind; 4] = [ImportKind::Scene, ImportKind::Skybox, ImportKind::Reference, ImportKind::Ignore];
+}
+
+fn extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+pub fn is_scene(path: &str) -> bool {
+    let extension = extension(path);
+    SCENE_EXTENSIONS.contains(&extension.as_str()) || SCENE_FILE_EXTENSIONS.contains(&extension.as_str())
+}
+
+// What a file most likely is, or None if the extension doesn't tell
+fn guess_kind(path: &str) -> Option<ImportKind> {
+    if is_scene(path) {
+        Some(ImportKind::Scene)
+    } else if ENVIRONMENT_EXTENSIONS.contains(&extension(path).as_str()) {
+        Some(ImportKind::Skybox)
+    } else {
+        None
+    }
+}
+
+// Only one file can be used for each role at a time
+pub fn find_conflict(kinds: &[ImportKind]) -> Option<String> {
+    for kind in [ImportKind::Scene, ImportKind::Skybox, ImportKind::Reference] {
+        if kinds.iter().filter(|&&k| k == kind).count() > 1 {
+            return Some(format!("Only one file can be imported as {:?}.", kind));
+        }
+    }
+    None
+}
+
+// Picks a kind for each of a batch of files, and reports whether any of the choices
+// were guesses that the user should confirm.
+pub fn route_files(paths: &[String]) -> (Vec<ImportKind>, bool) {
+    let guesses = paths.iter().map(|path| guess_kind(path)).collect::<Vec<_>>();
+    let has_skybox = guesses.contains(&Some(ImportKind::Skybox));
+    let kinds = paths
+        .iter()
+        .zip(guesses.iter())
+        .map(|(path, guess)| match guess {
+            Some(kind) => *kind,
+            // An LDR image next to an HDR one is more likely to be a reference than a second skybox
+            None if IMAGE_EXTENSIONS.contains(&extension(path).as_str()) => {
+                if has_skybox { ImportKind::Reference } else { ImportKind::Skybox }
+            }
+            None => ImportKind::Scene,
+        })
+        .collect::<Vec<_>>();
+
+    let ambiguous = guesses.contains(&None) || find_conflict(&kinds).is_some();
+    (kinds, ambiguous)
+}
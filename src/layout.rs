@@ -0,0 +1,167 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::settings::{PanelLayout, Settings};
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Panel {
+    Settings,
+    Environment,
+    Convergence,
+    Statistics,
+    Resources,
+    Profiler,
+    Log,
+    SceneBrowser,
+    Materials,
+    PathDebugger,
+}
+
+impl Panel {
+    pub const ALL: [Panel; 10] = [
+        Panel::Settings,
+        Panel::Environment,
+        Panel::Convergence,
+        Panel::Statistics,
+        Panel::Resources,
+        Panel::Profiler,
+        Panel::Log,
+        Panel::SceneBrowser,
+        Panel::Materials,
+        Panel::PathDebugger,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Panel::Settings => "Settings",
+            Panel::Environment => "Environment",
+            Panel::Convergence => "Convergence",
+            Panel::Statistics => "Statistics",
+            Panel::Resources => "Resources",
+            Panel::Profiler => "Profiler",
+            Panel::Log => "Log",
+            Panel::SceneBrowser => "Scenes",
+            Panel::Materials => "Materials",
+            Panel::PathDebugger => "Path debugger",
+        }
+    }
+
+    // The settings panel holds the controls to reopen everything else, so it can't be closed
+    pub fn closable(self) -> bool {
+        self != Panel::Settings
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Dock {
+    Floating,
+    Left,
+    Right,
+    Bottom,
+}
+
+impl Dock {
+    pub const ALL: [Dock; 4] = [Dock::Floating, Dock::Left, Dock::Right, Dock::Bottom];
+}
+
+fn parse<T: std::fmt::Debug + Copy>(values: &[T], name: &str) -> Option<T> {
+    values.iter().copied().find(|value| format!("{:?}", value) == name)
+}
+
+// Where each panel lives and whether it is open. Panels docked to the same side
+// of the viewport are shown as tabs. Changes are written to the settings file
+// immediately, so the arrangement survives restarts.
+pub struct Layout {
+    docks: HashMap<Panel, Dock>,
+    open: HashSet<Panel>,
+    active_tabs: HashMap<Dock, Panel>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            docks: HashMap::new(),
+            open: HashSet::from([Panel::Settings]),
+            active_tabs: HashMap::new(),
+        }
+    }
+}
+
+impl Layout {
+    pub fn load() -> Self {
+        let mut layout = Self::default();
+        for entry in Settings::load().layout {
+            let (Some(panel), Some(dock)) = (parse(&Panel::ALL, &entry.panel), parse(&Dock::ALL, &entry.dock)) else {
+                tracing::warn!("Ignoring unknown panel layout entry '{} {}'.", entry.panel, entry.dock);
+                continue;
+            };
+            layout.docks.insert(panel, dock);
+            if entry.open || !panel.closable() {
+                layout.open.insert(panel);
+            } else {
+                layout.open.remove(&panel);
+            }
+        }
+        layout
+    }
+
+    fn save(&self) {
+        let entries = Panel::ALL
+            .iter()
+            .map(|&panel| PanelLayout { panel: format!("{:?}", panel), dock: format!("{:?}", self.dock(panel)), open: self.is_open(panel) })
+            .collect();
+        Settings::update(|settings| settings.layout = entries);
+    }
+
+    pub fn dock(&self, panel: Panel) -> Dock {
+        self.docks.get(&panel).copied().unwrap_or(Dock::Floating)
+    }
+
+    pub fn set_dock(&mut self, panel: Panel, dock: Dock) {
+        if self.dock(panel) == dock {
+            return;
+        }
+        self.docks.insert(panel, dock);
+        self.active_tabs.insert(dock, panel);
+        self.save();
+    }
+
+    pub fn is_open(&self, panel: Panel) -> bool {
+        self.open.contains(&panel)
+    }
+
+    pub fn set_open(&mut self, panel: Panel, open: bool) {
+        if self.is_open(panel) == open || (!open && !panel.closable()) {
+            return;
+        }
+        if open {
+            self.open.insert(panel);
+            self.active_tabs.insert(self.dock(panel), panel);
+        } else {
+            self.open.remove(&panel);
+        }
+        self.save();
+    }
+
+    pub fn toggle(&mut self, panel: Panel) {
+        self.set_open(panel, !self.is_open(panel));
+    }
+
+    // Open panels docked to the given side, in a stable order
+    pub fn panels_in(&self, dock: Dock) -> Vec<Panel> {
+        Panel::ALL
+            .into_iter()
+            .filter(|&panel| self.is_open(panel) && self.dock(panel) == dock)
+            .collect()
+    }
+
+    pub fn active_tab(&self, dock: Dock, panels: &[Panel]) -> Panel {
+        match self.active_tabs.get(&dock) {
+            Some(panel) if panels.contains(panel) => *panel,
+            _ => panels[0],
+        }
+    }
+
+    pub fn set_active_tab(&mut self, dock: Dock, panel: Panel) {
+        self.active_tabs.insert(dock, panel);
+    }
+}
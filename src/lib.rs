@@ -5,4 +5,42 @@ pub mod trace;
 pub mod bvh;
 pub mod atlas;
 pub mod asset;
-pub mod light_pick;
\ No newline at end of file
+pub mod light_pick;
+pub mod reference;
+pub mod logging;
+pub mod layout;
+pub mod settings;
+pub mod shortcuts;
+pub mod import;
+pub mod browser;
+pub mod tonemap;
+pub mod export;
+pub mod ground;
+pub mod environment;
+pub mod bluenoise;
+pub mod path_debug;
+pub mod parity;
+pub mod texture_cache;
+pub mod cancel;
+pub mod hot_reload;
+pub mod scene;
+pub mod scene_file;
+pub mod batch;
+pub mod procedural;
+pub mod solar;
+pub mod variance;
+pub mod firefly;
+pub mod power;
+pub mod upscale;
+pub mod proxy;
+pub mod tangents;
+pub mod markers;
+pub mod contact_sheet;
+pub mod turntable;
+pub mod material_preview;
+pub mod probe;
+pub mod lightmap;
+pub mod surface_maps;
+pub mod vertex_bake;
+pub mod point_cloud;
+pub mod sdf;
@@ -1,6 +1,8 @@
 use glam::{UVec4, Vec3, Vec4, Vec4Swizzles};
 use rand::Rng;
-use shared_structs::{LightPickEntry, MaterialData};
+use shared_structs::{is_point, is_sdf, triangle_material_index, LightPickEntry, MaterialData, PerVertexData};
+
+use crate::cancel::CancelToken;
 
 fn triangle_area(a: Vec3, b: Vec3, c: Vec3) -> f32 {
     let side_a = b - a;
@@ -13,7 +15,8 @@ fn triangle_area(a: Vec3, b: Vec3, c: Vec3) -> f32 {
 pub fn compute_emissive_mask(indices: &[UVec4], material_datas: &[MaterialData]) -> Vec<bool> {
     let mut emissive_mask = vec![false; indices.len()];
     for i in 0..indices.len() {
-        if material_datas[indices[i].w as usize].emissive.xyz() != Vec3::ZERO {
+        // Points and SDFs have no triangle to sample light from
+        if material_datas[triangle_material_index(indices[i]) as usize].emissive.xyz() != Vec3::ZERO && !is_point(indices[i]) && !is_sdf(indices[i]) {
             emissive_mask[i] = true;
         }
     }
@@ -27,12 +30,29 @@ pub fn build_light_pick_table(
     mask: &[bool],
     material_datas: &[MaterialData],
 ) -> Vec<LightPickEntry> {
+    try_build_light_pick_table(vertices, indices, mask, material_datas, &CancelToken::default()).expect("Light pick table build was cancelled.")
+}
+
+// Checks for cancellation every so many triangles, returning None if cancelled
+pub fn try_build_light_pick_table(
+    vertices: &[Vec4],
+    indices: &[UVec4],
+    mask: &[bool],
+    material_datas: &[MaterialData],
+    cancel: &CancelToken,
+) -> Option<Vec<LightPickEntry>> {
+    puffin::profile_function!();
+    const CANCEL_CHECK_INTERVAL: usize = 4096;
+
     // Calculate areas and probabilities of picking each triangle
     let mut triangle_areas = vec![0.0; indices.len()];
     let mut triangle_powers = vec![0.0; indices.len()];
     let mut total_power = 0.0;
     let mut total_tris = 0;
     for i in 0..indices.len() {
+        if i % CANCEL_CHECK_INTERVAL == 0 && cancel.is_cancelled() {
+            return None;
+        }
         if !mask[i] {
             continue;
         }
@@ -46,22 +66,24 @@ pub fn build_light_pick_table(
         let triangle_area = triangle_area(a, b, c);
         triangle_areas[i] = triangle_area;
 
-        let triangle_power = material_datas[triangle.w as usize].emissive.xyz().dot(Vec3::ONE) * triangle_area;
+        // Double-sided lights emit from both faces, so they are picked as if they had twice the area
+        let material = &material_datas[triangle_material_index(triangle) as usize];
+        let sides = if material.double_sided() { 2.0 } else { 1.0 };
+        let triangle_power = material.emissive.xyz().dot(Vec3::ONE) * triangle_area * sides;
         triangle_powers[i] = triangle_power;
         total_power += triangle_power;
     }
-    if total_tris == 0 {
+    if total_tris == 0 || total_power <= 0.0 {
         // If there are 0 entries, put in a stupid sentinel value
-        return vec![LightPickEntry {
+        return Some(vec![LightPickEntry {
             ratio: -1.0,
             ..Default::default()
-        }];
+        }]);
     }
     let mut triangle_probabilities = vec![0.0; indices.len()];
     for i in 0..indices.len() {
         triangle_probabilities[i] = triangle_powers[i] / total_power;
     }
-    let average_probability = triangle_probabilities.iter().sum::<f32>() / total_tris as f32;
     // Build histogram bins. Each entry contains 2 discrete outcomes.
     #[derive(Debug)]
     struct TriangleBin {
@@ -79,30 +101,31 @@ pub fn build_light_pick_table(
             index_b: 0,
             probability_b: 0.0,
         })
-        .filter(|x| x.probability_a != 0.0)
+        .filter(|x| x.probability_a > 0.0)
         .collect::<Vec<_>>();
-    bins.sort_by(|a, b| {
-        a.probability_a
-            .partial_cmp(&b.probability_a)
-            .unwrap_or(std::cmp::Ordering::Equal)
-    });
 
-    // Robin hood - take from the most probable and give to the least probable
-    let num_bins = bins.len();
-    let mut most_probable = num_bins - 1;
-    for i in 0..num_bins {
-        let needed = average_probability - bins[i].probability_a;
-        if needed <= 0.0 {
-            break;
+    // Robin hood - take from the more probable and give to the less probable. Bins that give
+    // away enough to drop below the average need topping up themselves (Vose's alias method).
+    // Triangles with no area or emission get no bin, so the average is over the bins.
+    let average_probability = 1.0 / bins.len() as f32;
+    let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..bins.len()).partition(|&i| bins[i].probability_a < average_probability);
+    let mut steps = 0;
+    while let (Some(&less_probable), Some(&more_probable)) = (small.last(), large.last()) {
+        steps += 1;
+        if steps % CANCEL_CHECK_INTERVAL == 0 && cancel.is_cancelled() {
+            return None;
         }
-
-        bins[i].index_b = bins[most_probable].index_a;
-        bins[i].probability_b = needed;
-        bins[most_probable].probability_a -= needed;
-        if bins[most_probable].probability_a <= average_probability {
-            most_probable -= 1;
+        small.pop();
+        let needed = average_probability - bins[less_probable].probability_a;
+        bins[less_probable].index_b = bins[more_probable].index_a;
+        bins[less_probable].probability_b = needed;
+        bins[more_probable].probability_a -= needed;
+        if bins[more_probable].probability_a < average_probability {
+            large.pop();
+            small.push(more_probable);
         }
     }
+    // Whatever is left over is at the average, give or take rounding, so always picks its own triangle.
 
     // Build the table
     let table = bins
@@ -114,11 +137,23 @@ pub fn build_light_pick_table(
             triangle_area_a: triangle_areas[x.index_a],
             triangle_area_b: triangle_areas[x.index_b],
             triangle_pick_pdf_b: triangle_probabilities[x.index_b],
-            ratio: x.probability_a / (x.probability_a + x.probability_b),
+            ratio: if x.probability_b > 0.0 { x.probability_a / (x.probability_a + x.probability_b) } else { 1.0 },
         })
         .collect::<Vec<_>>();
 
-    table
+    Some(table)
+}
+
+// For when emission is edited after loading. Also returns how many triangles now emit light.
+pub fn rebuild_light_pick_table(
+    per_vertex_data: &[PerVertexData],
+    indices: &[UVec4],
+    material_datas: &[MaterialData],
+) -> (Vec<LightPickEntry>, usize) {
+    let vertices = per_vertex_data.iter().map(|data| data.vertex).collect::<Vec<_>>();
+    let emissive_mask = compute_emissive_mask(indices, material_datas);
+    let table = build_light_pick_table(&vertices, indices, &emissive_mask, material_datas);
+    (table, emissive_mask.iter().filter(|&&emissive| emissive).count())
 }
 
 // Just for reference
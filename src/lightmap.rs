@@ -0,0 +1,237 @@
+use glam::{UVec2, Vec2, Vec3, Vec4};
+use image::Rgb32FImage;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use shared_structs::{CpuImage, TracingConfig};
+use std::{f32::consts::PI, path::{Path, PathBuf}, sync::Arc};
+
+use crate::{
+    asset::{dynamic_image_to_cpu_buffer, fallback_cpu_buffer, load_description, LoadProgress, World},
+    batch::{HeadlessScene, RenderProgress},
+    scene::{MeshDescription, SceneDescription},
+    tangents::vertex_normals,
+    trace::{load_skybox, scene_ray_offset, TracingState, BLUE_NOISE},
+};
+
+pub const DILATION_PASSES: u32 = 4; // Texels grown around each chart, so bilinear filtering doesn't pull in black
+
+// Where a texel of a lightmap lies on its mesh
+#[derive(Clone, Copy)]
+pub struct SurfaceTexel {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub triangle: usize,
+    pub weights: Vec3, // Barycentric, for interpolating other vertex attributes
+}
+
+// Finds the point on the mesh under the center of each texel of a `size` square map, going by
+// one of its UV sets. Where charts overlap, the last triangle wins.
+pub fn rasterize(mesh: &MeshDescription, uvs: &[Vec2], normals: &[Vec3], size: u32) -> Vec<Option<SurfaceTexel>> {
+    let mut texels = vec![None; (size * size) as usize];
+    if uvs.len() < mesh.positions.len() {
+        return texels;
+    }
+    for (triangle_index, triangle) in mesh.triangles.iter().enumerate() {
+        let uv = triangle.map(|index| uvs[index as usize] * size as f32);
+        let area = (uv[1] - uv[0]).perp_dot(uv[2] - uv[0]);
+        if area.abs() < 1e-12 {
+            continue;
+        }
+        let min = uv[0].min(uv[1]).min(uv[2]).floor().max(Vec2::ZERO);
+        let max = uv[0].max(uv[1]).max(uv[2]).ceil().min(Vec2::splat(size as f32));
+        for y in min.y as u32..max.y as u32 {
+            for x in min.x as u32..max.x as u32 {
+                let center = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let weights = Vec3::new(
+                    (uv[2] - uv[1]).perp_dot(center - uv[1]),
+                    (uv[0] - uv[2]).perp_dot(center - uv[2]),
+                    (uv[1] - uv[0]).perp_dot(center - uv[0]),
+                ) / area;
+                if weights.min_element() < -1e-4 {
+                    continue;
+                }
+                let [a, b, c] = triangle.map(|index| index as usize);
+                let position = mesh.positions[a] * weights.x + mesh.positions[b] * weights.y + mesh.positions[c] * weights.z;
+                let normal = (normals[a] * weights.x + normals[b] * weights.y + normals[c] * weights.z).normalize_or_zero();
+                if normal != Vec3::ZERO {
+                    texels[(y * size + x) as usize] = Some(SurfaceTexel { position, normal, triangle: triangle_index, weights });
+                }
+            }
+        }
+    }
+    texels
+}
+
+// Fills empty texels next to baked ones with the mean of their baked neighbours, a ring per pass
+pub fn dilate(texels: &mut [Vec3], covered: &mut [bool], size: u32, passes: u32) {
+    for _ in 0..passes {
+        let previous = covered.to_vec();
+        for y in 0..size as i32 {
+            for x in 0..size as i32 {
+                let index = (y * size as i32 + x) as usize;
+                if previous[index] {
+                    continue;
+                }
+                let mut sum = Vec3::ZERO;
+                let mut count = 0;
+                for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (-1, 1), (1, 1)] {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx >= size as i32 || ny >= size as i32 {
+                        continue;
+                    }
+                    let neighbour = (ny * size as i32 + nx) as usize;
+                    if previous[neighbour] {
+                        sum += texels[neighbour];
+                        count += 1;
+                    }
+                }
+                if count > 0 {
+                    texels[index] = sum / count as f32;
+                    covered[index] = true;
+                }
+            }
+        }
+    }
+}
+
+// Cosine-weighted direction around `normal`
+pub fn cosine_direction(normal: Vec3, xi: Vec2) -> Vec3 {
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+    let radius = xi.x.sqrt();
+    let phi = 2.0 * PI * xi.y;
+    (tangent * radius * phi.cos() + bitangent * radius * phi.sin() + normal * (1.0 - xi.x).max(0.0).sqrt()).normalize()
+}
+
+// A scene loaded for tracing rays that start on its surfaces, on the CPU
+pub struct BakeScene {
+    pub description: SceneDescription,
+    pub world: World,
+    pub config: TracingConfig,
+    atlas: (Vec<Vec4>, u32, u32),
+    skybox: (Vec<Vec4>, u32, u32),
+}
+
+impl BakeScene {
+    // The skybox is loaded, and clamped, the same way renders do it
+    pub fn load(scene: &HeadlessScene, config: TracingConfig) -> Result<Self, String> {
+        let description = load_description(&scene.scene, scene.scene_scale).ok_or("Failed to load scene")?;
+        let world = World::from_description(&description, &scene.ground, true, false, &LoadProgress::default()).ok_or("Failed to build scene")?;
+
+        let state = Arc::new(TracingState::new(1, 1));
+        *state.config.write() = config;
+        *state.environment_clamp.write() = scene.environment_clamp;
+        let skybox = match load_skybox(&state, scene.skybox.as_deref()) {
+            Some(skybox) => {
+                let (width, height) = (skybox.width(), skybox.height());
+                (dynamic_image_to_cpu_buffer(skybox), width, height)
+            }
+            None => (fallback_cpu_buffer(), 2, 2),
+        };
+        let atlas = (dynamic_image_to_cpu_buffer(world.atlas.clone()), world.atlas.width(), world.atlas.height());
+
+        let mut config = *state.config.read();
+        config.render.ray_offset = scene_ray_offset(world.statistics.model_bounds);
+        // Blue noise is laid out over the screen, which bake samples aren't
+        config.render.use_blue_noise = 0;
+        config.render.path_splits = 1;
+        Ok(Self { description, world, config, atlas, skybox })
+    }
+
+    // Where rays leave a surface from, far enough to not hit it again
+    pub fn ray_origin(&self, position: Vec3, normal: Vec3) -> Vec3 {
+        position + normal * self.config.render.ray_offset
+    }
+
+    // Radiance arriving at `origin` from `direction`, and the RNG state for the next sample
+    pub fn radiance(&self, id: UVec2, rng: UVec2, origin: Vec3, direction: Vec3) -> (Vec3, UVec2) {
+        let (radiance, rng) = kernels::trace_ray(
+            id.extend(1),
+            &self.config,
+            rng,
+            origin,
+            direction,
+            &self.world.per_vertex_buffer,
+            &self.world.index_buffer,
+            &self.world.bvh.nodes,
+            &self.world.material_data_buffer,
+            &self.world.light_pick_buffer,
+            &shared_structs::Sampler,
+            &CpuImage::new(&self.atlas.0, self.atlas.1, self.atlas.2),
+            &CpuImage::new(&self.skybox.0, self.skybox.1, self.skybox.2),
+            &BLUE_NOISE,
+        );
+        // A NaN would spread over the whole map once dilated
+        (if radiance.is_finite() { radiance.truncate() } else { Vec3::ZERO }, rng)
+    }
+}
+
+// Path traces a scene in texture space, using each mesh's lightmap UVs, and saves a lightmap per
+// mesh. Texels hold irradiance divided by pi, so diffuse lighting is the albedo times the map,
+// and include everything that reaches the surface, direct light and bounces alike.
+#[derive(Clone)]
+pub struct LightmapBake {
+    pub scene: HeadlessScene,
+    pub config: TracingConfig,
+    pub resolution: u32, // Width and height of every lightmap
+    pub samples: u32, // Per texel
+}
+
+impl LightmapBake {
+    // Saves the lightmap of mesh N as `<stem>_N.exr` next to `path`, and returns the paths it saved to
+    pub fn bake(&self, path: &Path, progress: &RenderProgress) -> Result<Vec<PathBuf>, String> {
+        let scene = BakeScene::load(&self.scene, self.config)?;
+        let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_else(|| "lightmap".to_string());
+        let mut saved = Vec::new();
+        for (mesh_index, mesh) in scene.description.meshes.iter().enumerate() {
+            if progress.is_cancelled() {
+                return Err("Cancelled".to_string());
+            }
+            if mesh.lightmap_uvs.is_empty() {
+                tracing::warn!("Mesh {} has no lightmap UVs, so no lightmap was baked for it.", mesh_index);
+                progress.finish_one();
+                continue;
+            }
+
+            let size = self.resolution;
+            let surface = rasterize(mesh, &mesh.lightmap_uvs, &vertex_normals(mesh), size);
+            let mut texels = surface
+                .par_iter()
+                .enumerate()
+                .map(|(texel_index, texel)| {
+                    let Some(texel) = texel else {
+                        return Vec3::ZERO;
+                    };
+                    if progress.is_cancelled() {
+                        return Vec3::ZERO;
+                    }
+                    let mut random = StdRng::seed_from_u64((mesh_index as u64) << 32 | texel_index as u64);
+                    let mut rng = UVec2::new(0, random.gen());
+                    let id = UVec2::new(texel_index as u32 % size, texel_index as u32 / size);
+                    let origin = scene.ray_origin(texel.position, texel.normal);
+                    let mut sum = Vec3::ZERO;
+                    for _ in 0..self.samples {
+                        // With cosine-weighted directions, the mean radiance is irradiance over pi
+                        let direction = cosine_direction(texel.normal, Vec2::new(random.gen(), random.gen()));
+                        let (radiance, next_rng) = scene.radiance(id, rng, origin, direction);
+                        rng = next_rng;
+                        sum += radiance;
+                    }
+                    sum / self.samples.max(1) as f32
+                })
+                .collect::<Vec<_>>();
+            if progress.is_cancelled() {
+                return Err("Cancelled".to_string());
+            }
+            let mut covered = surface.iter().map(Option::is_some).collect::<Vec<_>>();
+            dilate(&mut texels, &mut covered, size, DILATION_PASSES);
+
+            let output = path.with_file_name(format!("{}_{}.exr", stem, mesh_index));
+            let data = texels.iter().flat_map(|texel| texel.to_array()).collect();
+            let image = Rgb32FImage::from_raw(size, size, data).ok_or("Invalid lightmap")?;
+            image.save(&output).map_err(|err| format!("Failed to save '{}': {}", output.display(), err))?;
+            saved.push(output);
+            progress.finish_one();
+        }
+        Ok(saved)
+    }
+}
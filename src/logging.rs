@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use parking_lot::Mutex;
+use tracing::{field::{Field, Visit}, Event, Level, Subscriber};
+use tracing_subscriber::{filter::Targets, layer::Context, prelude::*, Layer};
+
+const MAX_LOG_ENTRIES: usize = 1024;
+
+pub struct LogEntry {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+lazy_static::lazy_static! {
+    static ref LOG_ENTRIES: Mutex<VecDeque<LogEntry>> = Mutex::new(VecDeque::new());
+}
+static ERROR_COUNT: AtomicU32 = AtomicU32::new(0);
+
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{:?}{}", value, self.0);
+        } else {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+}
+
+// Collects events into an in-memory ring buffer, so they can be shown in the GUI
+struct ConsoleLayer;
+
+impl<S: Subscriber> Layer<S> for ConsoleLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor(String::new());
+        event.record(&mut visitor);
+
+        let level = *event.metadata().level();
+        if level == Level::ERROR {
+            ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut entries = LOG_ENTRIES.lock();
+        if entries.len() >= MAX_LOG_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(LogEntry {
+            level,
+            target: event.metadata().target().to_string(),
+            message: visitor.0,
+        });
+    }
+}
+
+pub fn init() {
+    let level = if cfg!(debug_assertions) { Level::DEBUG } else { Level::INFO };
+    let filter = Targets::new()
+        .with_target("rustic", level)
+        .with_default(Level::WARN);
+    let _ = tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .with(ConsoleLayer)
+        .with(filter)
+        .try_init();
+}
+
+// Number of errors logged since startup. Used to notice new errors without scanning the log.
+pub fn error_count() -> u32 {
+    ERROR_COUNT.load(Ordering::Relaxed)
+}
+
+pub fn with_entries(f: impl FnOnce(&VecDeque<LogEntry>)) {
+    f(&LOG_ENTRIES.lock());
+}
+
+pub fn clear() {
+    LOG_ENTRIES.lock().clear();
+}
@@ -6,6 +6,27 @@ use rustic::app::App;
 use winit::event_loop::ControlFlow;
 
 fn main() {
+    rustic::logging::init();
+
+    // `rustic --batch <directory or manifest>` renders without a window, and exits
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(index) = args.iter().position(|arg| arg == "--batch") {
+        let Some(path) = args.get(index + 1) else {
+            eprintln!("Usage: rustic --batch <directory or manifest>");
+            std::process::exit(2);
+        };
+        let manifest = match rustic::batch::BatchManifest::load(std::path::Path::new(path)) {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                tracing::error!("Failed to load batch '{}': {}", path, err);
+                std::process::exit(2);
+            }
+        };
+        let results = rustic::batch::run_batch(&manifest);
+        let failed = results.iter().any(|result| result.error.is_some());
+        std::process::exit(if failed { 1 } else { 0 });
+    }
+
     let width = 1280;
     let height = 720;
 
@@ -60,6 +81,9 @@ fn main() {
                 winit::event::WindowEvent::DroppedFile(path) => {
                     app.handle_file_dropped(&path);
                 }
+                winit::event::WindowEvent::Focused(focused) => {
+                    app.handle_focus(focused);
+                }
                 _ => {}
             },
             _ => (),
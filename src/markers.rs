@@ -0,0 +1,123 @@
+use glam::{Vec3, Vec4};
+use std::f32::consts::PI;
+
+use crate::scene::{MaterialDescription, MeshDescription};
+
+// Marker radius as a fraction of the scene's diagonal, small enough to not be in the way
+const MARKER_SCALE: f32 = 0.002;
+const SPHERE_SUBDIVISIONS: u32 = 4;
+const CYLINDER_SIDES: u32 = 8;
+
+pub enum MarkerShape {
+    Point(Vec3),
+    Line(Vec3, Vec3),
+}
+
+// An emitter without any area, from a point or line primitive or an analytic light. Since there
+// is no area to spread it over, emission is given as radiant intensity, per unit length for lines.
+pub struct Marker {
+    pub name: String,
+    pub shape: MarkerShape,
+    pub intensity: Vec3,
+}
+
+// Subdivided octahedron pushed out to a sphere
+fn sphere(center: Vec3, radius: f32, material: u32) -> MeshDescription {
+    let n = SPHERE_SUBDIVISIONS;
+    let mut mesh = MeshDescription { material, ..Default::default() };
+    for octant in 0..8 {
+        let sign = |bit: u32| if octant & bit == 0 { 1.0 } else { -1.0 };
+        let corners = [Vec3::X * sign(1), Vec3::Y * sign(2), Vec3::Z * sign(4)];
+        let offset = mesh.positions.len() as u32;
+        let mut row_starts = Vec::new();
+        for i in 0..=n {
+            row_starts.push(mesh.positions.len() as u32 - offset);
+            for j in 0..=n - i {
+                let weights = Vec3::new((n - i - j) as f32, i as f32, j as f32) / n as f32;
+                let normal = (corners[0] * weights.x + corners[1] * weights.y + corners[2] * weights.z).normalize();
+                mesh.positions.push(center + normal * radius);
+                mesh.normals.push(normal);
+            }
+        }
+        let index = |i: u32, j: u32| offset + row_starts[i as usize] + j;
+        for i in 0..n {
+            for j in 0..n - i {
+                mesh.triangles.push([index(i, j), index(i + 1, j), index(i, j + 1)]);
+                if j + 1 < n - i {
+                    mesh.triangles.push([index(i + 1, j), index(i + 1, j + 1), index(i, j + 1)]);
+                }
+            }
+        }
+    }
+    orient_outwards(&mut mesh, |_| center);
+    mesh
+}
+
+// Open tube from `a` to `b`, the ends are too small to matter
+fn cylinder(a: Vec3, b: Vec3, radius: f32, material: u32) -> MeshDescription {
+    let axis = (b - a).normalize();
+    let (u, v) = axis.any_orthonormal_pair();
+    let mut mesh = MeshDescription { material, ..Default::default() };
+    for side in 0..CYLINDER_SIDES {
+        let angle = side as f32 / CYLINDER_SIDES as f32 * 2.0 * PI;
+        let normal = u * angle.cos() + v * angle.sin();
+        mesh.positions.extend([a + normal * radius, b + normal * radius]);
+        mesh.normals.extend([normal, normal]);
+        let next = (side + 1) % CYLINDER_SIDES;
+        mesh.triangles.push([side * 2, next * 2, side * 2 + 1]);
+        mesh.triangles.push([next * 2, next * 2 + 1, side * 2 + 1]);
+    }
+    orient_outwards(&mut mesh, |point| a + axis * axis.dot(point - a));
+    mesh
+}
+
+// Flips triangles facing towards `inside`, rather than keeping track of winding while generating them
+fn orient_outwards(mesh: &mut MeshDescription, inside: impl Fn(Vec3) -> Vec3) {
+    for triangle in &mut mesh.triangles {
+        let [a, b, c] = triangle.map(|index| mesh.positions[index as usize]);
+        let centroid = (a + b + c) / 3.0;
+        if (b - a).cross(c - a).dot(centroid - inside(centroid)) < 0.0 {
+            triangle.swap(1, 2);
+        }
+    }
+}
+
+// Turns markers into small emissive meshes, each with its own material. The radiance is picked so
+// the emitted power matches the intensity no matter how large the markers end up.
+pub fn add_markers(meshes: &mut Vec<MeshDescription>, materials: &mut Vec<MaterialDescription>, markers: &[Marker]) {
+    if markers.is_empty() {
+        return;
+    }
+
+    let mut min = Vec3::splat(f32::INFINITY);
+    let mut max = Vec3::splat(f32::NEG_INFINITY);
+    let marker_points = markers.iter().flat_map(|marker| match marker.shape {
+        MarkerShape::Point(point) => [point, point],
+        MarkerShape::Line(a, b) => [a, b],
+    });
+    for point in meshes.iter().flat_map(|mesh| mesh.positions.iter().copied()).chain(marker_points) {
+        min = min.min(point);
+        max = max.max(point);
+    }
+    let diagonal = (max - min).length();
+    let radius = if diagonal > 0.0 { diagonal * MARKER_SCALE } else { 0.01 };
+
+    for marker in markers {
+        let material = materials.len() as u32;
+        let (mesh, radiance) = match marker.shape {
+            // A sphere looks like a disk from every direction
+            MarkerShape::Point(point) => (sphere(point, radius, material), marker.intensity / (PI * radius * radius)),
+            // And a tube like a strip of its diameter, seen from the side
+            MarkerShape::Line(a, b) if a.distance(b) > 0.0 => (cylinder(a, b, radius, material), marker.intensity / (2.0 * radius)),
+            MarkerShape::Line(..) => continue,
+        };
+        meshes.push(mesh);
+        materials.push(MaterialDescription {
+            name: marker.name.clone(),
+            albedo: Vec4::new(0.0, 0.0, 0.0, 1.0),
+            emissive: radiance,
+            ..Default::default()
+        });
+    }
+    tracing::info!("Added {} emissive markers with a radius of {}.", markers.len(), radius);
+}
@@ -0,0 +1,203 @@
+use glam::{Vec2, Vec3, Vec4};
+use image::DynamicImage;
+use parking_lot::Mutex;
+use shared_structs::MaterialData;
+use std::{collections::BTreeMap, f32::consts::PI, sync::{atomic::Ordering, mpsc, Arc}};
+
+use crate::{
+    asset::{LoadProgress, World},
+    export::encode_framebuffer,
+    ground::GroundSettings,
+    light_pick,
+    scene::{MaterialDescription, MaterialPatterns, MeshDescription, PatternDescription, PatternKind, SceneDescription},
+    tonemap::Tonemapping,
+    trace::{setup_trace, trace_cpu_world, MaterialEdits},
+    turntable::orbit_camera,
+};
+
+const PREVIEW_SIZE: u32 = 64;
+const PREVIEW_SAMPLES: u32 = 32;
+const PREVIEW_ATLAS_SIZE: u32 = 1024; // Longest side of the atlas copy that previews sample textures from
+const SPHERE_RINGS: u32 = 24;
+const SPHERE_SEGMENTS: u32 = 48;
+
+// The materials of the loaded scene, with a downscaled copy of its atlas. Materials address the
+// atlas with normalized coordinates, so they work the same on the smaller copy.
+pub struct MaterialLibrary {
+    pub names: Vec<String>,
+    pub materials: Vec<MaterialData>,
+    pub atlas: DynamicImage,
+}
+
+impl MaterialLibrary {
+    pub fn from_world(world: &World) -> Self {
+        let atlas = if world.atlas.width().max(world.atlas.height()) > PREVIEW_ATLAS_SIZE {
+            world.atlas.resize(PREVIEW_ATLAS_SIZE, PREVIEW_ATLAS_SIZE, image::imageops::FilterType::Triangle)
+        } else {
+            world.atlas.clone()
+        };
+        Self {
+            names: world.material_names.clone(),
+            materials: world.material_data_buffer.clone(),
+            atlas,
+        }
+    }
+
+    // Restarting the render on the same scene shouldn't copy the atlas again
+    pub fn matches(&self, world: &World) -> bool {
+        self.names == world.material_names && self.materials.len() == world.material_data_buffer.len()
+    }
+}
+
+// Latitude-longitude sphere, so textures wrap around it the way they would in a DCC's material ball
+fn uv_sphere(material: u32) -> MeshDescription {
+    let mut mesh = MeshDescription { material, ..Default::default() };
+    for ring in 0..=SPHERE_RINGS {
+        let theta = ring as f32 / SPHERE_RINGS as f32 * PI;
+        for segment in 0..=SPHERE_SEGMENTS {
+            let phi = segment as f32 / SPHERE_SEGMENTS as f32 * 2.0 * PI;
+            let normal = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+            mesh.positions.push(normal);
+            mesh.normals.push(normal);
+            mesh.uvs.push(Vec2::new(segment as f32 / SPHERE_SEGMENTS as f32, ring as f32 / SPHERE_RINGS as f32));
+        }
+    }
+    let row = SPHERE_SEGMENTS + 1;
+    for ring in 0..SPHERE_RINGS {
+        for segment in 0..SPHERE_SEGMENTS {
+            let corner = ring * row + segment;
+            // Counter-clockwise seen from outside, the triangles at the poles are dropped on import
+            mesh.triangles.push([corner, corner + 1, corner + row]);
+            mesh.triangles.push([corner + 1, corner + row + 1, corner + row]);
+        }
+    }
+    mesh
+}
+
+// A sphere on a checkered floor. The sphere uses material 0, which is replaced by the previewed material.
+fn shader_ball() -> SceneDescription {
+    let floor = MeshDescription {
+        positions: vec![Vec3::new(-20.0, -1.0, -20.0), Vec3::new(20.0, -1.0, -20.0), Vec3::new(20.0, -1.0, 20.0), Vec3::new(-20.0, -1.0, 20.0)],
+        normals: vec![Vec3::Y; 4],
+        uvs: vec![Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(1.0, 1.0), Vec2::new(0.0, 1.0)],
+        triangles: vec![[0, 2, 1], [0, 3, 2]],
+        material: 1,
+        ..Default::default()
+    };
+    let checker = PatternDescription { kind: PatternKind::Checker, value: Vec4::new(0.2, 0.2, 0.2, 1.0), scale: 40.0 };
+    let floor_material = MaterialDescription {
+        name: "Floor".to_string(),
+        albedo: Vec4::new(0.6, 0.6, 0.6, 1.0),
+        roughness: 0.8,
+        patterns: MaterialPatterns { albedo: Some(checker), ..Default::default() },
+        ..Default::default()
+    };
+    SceneDescription {
+        meshes: vec![uv_sphere(0), floor],
+        materials: vec![MaterialDescription { name: "Preview".to_string(), ..Default::default() }, floor_material],
+        camera: None,
+    }
+}
+
+// Renders one material of the library on the shader ball, with the given emission
+pub fn render_preview(library: &MaterialLibrary, index: usize, emissive: Vec4) -> Option<egui::ColorImage> {
+    let mut world = World::from_description(&shader_ball(), &GroundSettings::default(), true, false, &LoadProgress::default())?;
+    let mut material = *library.materials.get(index)?;
+    material.emissive = emissive;
+    // Light linking refers to lights of the scene, which aren't here
+    material.light_group = 0;
+    material.light_exclude = 0;
+    world.material_data_buffer[0] = material;
+    world.atlas = library.atlas.clone();
+    world.light_pick_buffer = light_pick::rebuild_light_pick_table(&world.per_vertex_buffer, &world.index_buffer, &world.material_data_buffer).0;
+
+    let state = setup_trace(PREVIEW_SIZE, PREVIEW_SIZE, PREVIEW_SAMPLES);
+    state.cpu_low_priority.store(true, Ordering::Relaxed);
+    {
+        let mut config = state.config.write();
+        let (position, rotation) = orbit_camera(Vec3::ZERO, 2.6, 0.0, 0.25);
+        config.camera.cam_position = position.extend(config.camera.cam_position.w);
+        config.camera.cam_rotation = rotation.extend(0.0);
+    }
+    trace_cpu_world(world, None, state.clone());
+
+    let pixels = encode_framebuffer(&state.framebuffer.read(), Tonemapping::ACESNarkowicz, 0.0);
+    Some(egui::ColorImage::from_rgb([PREVIEW_SIZE as usize, PREVIEW_SIZE as usize], &pixels))
+}
+
+type FinishedPreview = (usize, Vec4, egui::ColorImage);
+
+// Thumbnails of each material of the loaded scene, rendered on the CPU in the background, only
+// once someone asks for them. A thumbnail is redrawn when its material's emission is edited.
+#[derive(Default)]
+pub struct MaterialPreviews {
+    library: Option<Arc<MaterialLibrary>>,
+    thumbnails: Vec<Option<egui::TextureHandle>>,
+    requested: Vec<Option<Vec4>>, // Emission each thumbnail was last requested with
+    requests: Option<mpsc::Sender<(usize, Vec4)>>,
+    finished: Arc<Mutex<Vec<FinishedPreview>>>,
+}
+
+impl MaterialPreviews {
+    // Starts over when another scene was loaded, requests thumbnails that are out of date, and
+    // uploads any that are done
+    pub fn update(&mut self, egui_ctx: &egui::Context, library: Option<Arc<MaterialLibrary>>, materials: &[MaterialEdits]) {
+        let same_library = match (self.library.as_ref(), library.as_ref()) {
+            (Some(current), Some(library)) => Arc::ptr_eq(current, library),
+            (None, None) => true,
+            _ => false,
+        };
+        if !same_library {
+            // Dropping the sender stops the worker of the previous library
+            *self = Self { library: library.clone(), ..Default::default() };
+            if let Some(library) = library {
+                self.thumbnails = vec![None; library.materials.len()];
+                self.requested = vec![None; library.materials.len()];
+                self.requests = Some(spawn_worker(library, self.finished.clone(), egui_ctx.clone()));
+            }
+        }
+        let Some(requests) = self.requests.as_ref() else {
+            return;
+        };
+
+        if materials.len() == self.requested.len() {
+            for (index, material) in materials.iter().enumerate() {
+                let emissive = material.emissive();
+                if self.requested[index] != Some(emissive) {
+                    self.requested[index] = Some(emissive);
+                    let _ = requests.send((index, emissive));
+                }
+            }
+        }
+
+        for (index, emissive, image) in self.finished.lock().drain(..) {
+            // Edited again since, so another one is on the way
+            if self.requested.get(index) == Some(&Some(emissive)) {
+                let name = format!("Material preview {}", index);
+                self.thumbnails[index] = Some(egui_ctx.load_texture(name, image, egui::TextureOptions::LINEAR));
+            }
+        }
+    }
+
+    pub fn thumbnail(&self, index: usize) -> Option<&egui::TextureHandle> {
+        self.thumbnails.get(index)?.as_ref()
+    }
+}
+
+fn spawn_worker(library: Arc<MaterialLibrary>, finished: Arc<Mutex<Vec<FinishedPreview>>>, repaint_ctx: egui::Context) -> mpsc::Sender<(usize, Vec4)> {
+    let (sender, receiver) = mpsc::channel::<(usize, Vec4)>();
+    std::thread::spawn(move || {
+        while let Ok(first) = receiver.recv() {
+            // Dragging an emission slider sends a request every frame, only the latest one matters
+            let mut pending = BTreeMap::from([first]);
+            pending.extend(receiver.try_iter());
+            for (index, emissive) in pending {
+                if let Some(image) = render_preview(&library, index, emissive) {
+                    finished.lock().push((index, emissive, image));
+                    repaint_ctx.request_repaint();
+                }
+            }
+        }
+    });
+    sender
+}
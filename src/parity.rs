@@ -0,0 +1,98 @@
+use std::sync::{atomic::Ordering, Arc};
+
+use crate::trace::{trace_cpu, trace_gpu, TracingState};
+
+// Per-pixel differences between a CPU and a GPU render of the same scene. Both backends
+// run the same kernel code with the same seeds, so any difference comes from how the
+// math is compiled for each target.
+pub struct ParityReport {
+    pub width: u32,
+    pub height: u32,
+    pub abs_differences: Vec<f32>, // Per pixel, the largest over the color channels
+    pub ulp_differences: Vec<u32>, // Likewise, in units in the last place
+    pub max_abs: f32,
+    pub max_ulp: u32,
+    pub worst_pixel: (u32, u32), // Where the largest absolute difference is
+}
+
+impl ParityReport {
+    // Pixels whose paths went somewhere else entirely, usually because a branch flipped
+    pub fn diverged_pixels(&self, tolerance: f32) -> usize {
+        self.abs_differences.iter().filter(|&&difference| difference > tolerance).count()
+    }
+
+    pub fn mean_abs(&self) -> f32 {
+        self.abs_differences.iter().sum::<f32>() / self.abs_differences.len().max(1) as f32
+    }
+}
+
+// Distance between two floats in representable values, so 0 means bit-identical
+pub fn ulp_distance(a: f32, b: f32) -> u32 {
+    if a.is_nan() || b.is_nan() {
+        return if a.is_nan() == b.is_nan() { 0 } else { u32::MAX };
+    }
+    // Map the sign-magnitude bits onto a line, so consecutive floats are consecutive integers
+    let ordered = |value: f32| {
+        let bits = value.to_bits() as i32;
+        if bits < 0 { i32::MIN.wrapping_sub(bits) as i64 } else { bits as i64 }
+    };
+    (ordered(a) - ordered(b)).unsigned_abs().min(u32::MAX as u64) as u32
+}
+
+// Compares two RGB framebuffers of the given size
+pub fn compare_framebuffers(width: u32, height: u32, cpu: &[f32], gpu: &[f32]) -> ParityReport {
+    assert_eq!(cpu.len(), gpu.len());
+    let mut report = ParityReport {
+        width,
+        height,
+        abs_differences: Vec::with_capacity(cpu.len() / 3),
+        ulp_differences: Vec::with_capacity(cpu.len() / 3),
+        max_abs: 0.0,
+        max_ulp: 0,
+        worst_pixel: (0, 0),
+    };
+    for (i, (cpu, gpu)) in cpu.chunks(3).zip(gpu.chunks(3)).enumerate() {
+        let abs = cpu.iter().zip(gpu).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+        let ulp = cpu.iter().zip(gpu).map(|(&a, &b)| ulp_distance(a, b)).max().unwrap_or(0);
+        if abs > report.max_abs {
+            report.max_abs = abs;
+            report.worst_pixel = (i as u32 % width, i as u32 / width);
+        }
+        report.max_ulp = report.max_ulp.max(ulp);
+        report.abs_differences.push(abs);
+        report.ulp_differences.push(ulp);
+    }
+    report
+}
+
+fn render(use_cpu: bool, scene: &str, skybox: Option<&str>, width: u32, height: u32, samples: u32) -> Vec<f32> {
+    let state = Arc::new(TracingState::new(width, height));
+    state.sample_limit.store(samples, Ordering::Relaxed);
+    state.running.store(true, Ordering::Relaxed);
+    if use_cpu {
+        trace_cpu(scene, skybox, state.clone());
+    } else {
+        trace_gpu(scene, skybox, state.clone());
+        if state.gpu_failed.load(Ordering::Relaxed) {
+            tracing::warn!("The GPU render fell back to the CPU, so the parity report compares the CPU against itself.");
+        }
+    }
+    let frame = state.framebuffer.read().clone();
+    frame
+}
+
+// Renders the scene for exactly `samples` samples on both backends, and compares the results
+pub fn render_parity(scene: &str, skybox: Option<&str>, width: u32, height: u32, samples: u32) -> ParityReport {
+    let cpu = render(true, scene, skybox, width, height, samples);
+    let gpu = render(false, scene, skybox, width, height, samples);
+    let report = compare_framebuffers(width, height, &cpu, &gpu);
+    tracing::info!(
+        "CPU/GPU parity for '{}': max difference {} ({} ULP) at {:?}, mean difference {}",
+        scene,
+        report.max_abs,
+        report.max_ulp,
+        report.worst_pixel,
+        report.mean_abs(),
+    );
+    report
+}
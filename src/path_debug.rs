@@ -0,0 +1,144 @@
+use glam::{UVec2, UVec3, UVec4, Vec3, Vec4, Vec4Swizzles};
+use image::DynamicImage;
+use kernels::{LobeType, PathEvent, PathRecorder, PathVertex};
+use shared_structs::{BVHNode, CpuImage, LightPickEntry, MaterialData, PerVertexData, TracingConfig};
+
+use crate::{asset::{dynamic_image_to_cpu_buffer, fallback_cpu_buffer}, trace::BLUE_NOISE};
+
+// A single path through one pixel, traced on the CPU with every vertex recorded
+#[derive(Clone, Default)]
+pub struct DebugPath {
+    pub pixel: UVec2,
+    pub vertices: Vec<PathVertex>,
+    pub radiance: Vec3,
+}
+
+struct VertexLog(Vec<PathVertex>);
+
+impl PathRecorder for VertexLog {
+    fn record(&mut self, vertex: &PathVertex) {
+        self.0.push(*vertex);
+    }
+}
+
+// Scene data the debug tracer reads, borrowed from whichever backend is rendering
+pub struct DebugScene<'a> {
+    pub per_vertex_buffer: &'a [PerVertexData],
+    pub index_buffer: &'a [UVec4],
+    pub nodes: &'a [BVHNode],
+    pub material_data_buffer: &'a [MaterialData],
+    pub light_pick_buffer: &'a [LightPickEntry],
+    pub atlas: &'a CpuImage<'a>,
+    pub skybox: &'a CpuImage<'a>,
+}
+
+// The GPU backend only keeps its textures on the GPU. Most sessions never trace a debug
+// path, so the CPU copies are only converted when the first one is requested.
+pub struct DebugTextures {
+    atlas_source: Option<DynamicImage>,
+    skybox_source: Option<DynamicImage>,
+    atlas: (Vec<Vec4>, u32, u32),
+    skybox: (Vec<Vec4>, u32, u32),
+}
+
+impl DebugTextures {
+    pub fn new(atlas: DynamicImage, skybox: Option<DynamicImage>) -> Self {
+        Self {
+            atlas_source: Some(atlas),
+            skybox_source: skybox,
+            atlas: (fallback_cpu_buffer(), 2, 2),
+            skybox: (fallback_cpu_buffer(), 2, 2),
+        }
+    }
+
+    // Textures streamed in after the first debug path replace the atlas, which is converted again when next needed
+    pub fn set_atlas(&mut self, atlas: DynamicImage) {
+        self.atlas_source = Some(atlas);
+    }
+
+    pub fn images(&mut self) -> (CpuImage, CpuImage) {
+        if let Some(atlas) = self.atlas_source.take() {
+            self.atlas = (Vec::new(), atlas.width(), atlas.height());
+            self.atlas.0 = dynamic_image_to_cpu_buffer(atlas);
+        }
+        if let Some(skybox) = self.skybox_source.take() {
+            self.skybox = (Vec::new(), skybox.width(), skybox.height());
+            self.skybox.0 = dynamic_image_to_cpu_buffer(skybox);
+        }
+        (
+            CpuImage::new(&self.atlas.0, self.atlas.1, self.atlas.2),
+            CpuImage::new(&self.skybox.0, self.skybox.1, self.skybox.2),
+        )
+    }
+}
+
+// Only vertices that bounced sampled a lobe
+pub fn sampled_lobe(vertex: &PathVertex) -> &'static str {
+    if !matches!(vertex.event, PathEvent::Bounced | PathEvent::RussianRoulette | PathEvent::GlossyTerminated) {
+        return "-";
+    }
+    match vertex.lobe {
+        LobeType::DiffuseReflection => "Diffuse reflection",
+        LobeType::SpecularReflection => "Specular reflection",
+        LobeType::DiffuseTransmission => "Diffuse transmission",
+        LobeType::SpecularTransmission => "Specular transmission",
+    }
+}
+
+pub fn event_name(event: PathEvent) -> &'static str {
+    match event {
+        PathEvent::Bounced => "Bounced",
+        PathEvent::Escaped => "Escaped",
+        PathEvent::Emitter => "Hit light",
+        PathEvent::EmitterBackface => "Hit back of light",
+        PathEvent::EmitterUnlinked => "Hit unlinked light",
+        PathEvent::RussianRoulette => "Russian roulette",
+        PathEvent::GlossyTerminated => "Glossy cone terminated",
+        PathEvent::Section => "Hit section",
+    }
+}
+
+// Traces the path the renderer would take through `pixel` with the given RNG state,
+// and logs every vertex along it.
+pub fn trace_debug_path(scene: &DebugScene, config: &TracingConfig, pixel: UVec2, rng: UVec2) -> DebugPath {
+    let mut log = VertexLog(Vec::new());
+    let (radiance, _) = kernels::trace_pixel(
+        UVec3::new(pixel.x, pixel.y, 1),
+        config,
+        rng,
+        scene.per_vertex_buffer,
+        scene.index_buffer,
+        scene.nodes,
+        scene.material_data_buffer,
+        scene.light_pick_buffer,
+        &shared_structs::Sampler,
+        scene.atlas,
+        scene.skybox,
+        &BLUE_NOISE,
+        &mut log,
+    );
+
+    tracing::info!("Debug path through pixel ({}, {}):", pixel.x, pixel.y);
+    for vertex in log.0.iter() {
+        tracing::info!(
+            "  Bounce {}: {} at {:?}, material {}, normal {:?}, lobe {}, pdf {:.4}, cone width {:.4}, throughput {:?}, radiance {:?}",
+            vertex.bounce,
+            event_name(vertex.event),
+            vertex.position,
+            vertex.material_index,
+            vertex.normal,
+            sampled_lobe(vertex),
+            vertex.pdf,
+            vertex.cone_width,
+            vertex.throughput,
+            vertex.radiance,
+        );
+    }
+    tracing::info!("  Radiance: {:?}", radiance.xyz());
+
+    DebugPath {
+        pixel,
+        vertices: log.0,
+        radiance: radiance.xyz(),
+    }
+}
@@ -0,0 +1,261 @@
+use glam::{Vec3, Vec4};
+use std::{io::Read, path::Path};
+
+use crate::scene::{MaterialDescription, MeshDescription, SceneDescription};
+
+const HEADER_LIMIT: u64 = 64 * 1024; // Bytes read to find the end of the header, PLY headers are a few lines
+const POINT_SPACING_SCALE: f32 = 0.5; // Of the spacing estimated from the bounds, see point_radius
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "char" | "int8" => Some(Scalar::I8),
+            "uchar" | "uint8" => Some(Scalar::U8),
+            "short" | "int16" => Some(Scalar::I16),
+            "ushort" | "uint16" => Some(Scalar::U16),
+            "int" | "int32" => Some(Scalar::I32),
+            "uint" | "uint32" => Some(Scalar::U32),
+            "float" | "float32" => Some(Scalar::F32),
+            "double" | "float64" => Some(Scalar::F64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    fn decode(self, bytes: &[u8], big_endian: bool) -> f64 {
+        let mut array = [0u8; 8];
+        array[..bytes.len()].copy_from_slice(bytes);
+        if big_endian {
+            array[..bytes.len()].reverse();
+        }
+        let [a, b, c, d, ..] = array;
+        match self {
+            Scalar::I8 => a as i8 as f64,
+            Scalar::U8 => a as f64,
+            Scalar::I16 => i16::from_le_bytes([a, b]) as f64,
+            Scalar::U16 => u16::from_le_bytes([a, b]) as f64,
+            Scalar::I32 => i32::from_le_bytes([a, b, c, d]) as f64,
+            Scalar::U32 => u32::from_le_bytes([a, b, c, d]) as f64,
+            Scalar::F32 => f32::from_le_bytes([a, b, c, d]) as f64,
+            Scalar::F64 => f64::from_le_bytes(array),
+        }
+    }
+
+    // Colors stored as integers span the whole range of their type
+    fn color_scale(self) -> f64 {
+        match self {
+            Scalar::U8 => 1.0 / u8::MAX as f64,
+            Scalar::U16 => 1.0 / u16::MAX as f64,
+            _ => 1.0,
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    scalar: Scalar,
+    list_count: Option<Scalar>, // Lists, like the indices of faces, start with their length
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    ascii: bool,
+    big_endian: bool,
+    elements: Vec<Element>,
+    body: usize, // Offset of the data after the header
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Self, String> {
+        if !data.starts_with(b"ply") {
+            return Err("Not a PLY file".to_string());
+        }
+        let end = data.windows(10).position(|window| window == b"end_header").ok_or("PLY header has no end")?;
+        let body = data[end..].iter().position(|&byte| byte == b'\n').map_or(data.len(), |newline| end + newline + 1);
+        let text = std::str::from_utf8(&data[..end]).map_err(|_| "PLY header isn't text")?;
+
+        let mut header = Header { ascii: true, big_endian: false, elements: Vec::new(), body };
+        for line in text.lines().skip(1) {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                ["format", "ascii", ..] => header.ascii = true,
+                ["format", "binary_little_endian", ..] => header.ascii = false,
+                ["format", "binary_big_endian", ..] => {
+                    header.ascii = false;
+                    header.big_endian = true;
+                }
+                ["element", name, count] => header.elements.push(Element {
+                    name: name.to_string(),
+                    count: count.parse().map_err(|_| format!("Invalid count of PLY element '{}'", name))?,
+                    properties: Vec::new(),
+                }),
+                ["property", "list", count, scalar, name] => {
+                    let element = header.elements.last_mut().ok_or("PLY property outside of an element")?;
+                    let count = Scalar::parse(count).ok_or_else(|| format!("Unknown PLY type '{}'", count))?;
+                    let scalar = Scalar::parse(scalar).ok_or_else(|| format!("Unknown PLY type '{}'", scalar))?;
+                    element.properties.push(Property { name: name.to_string(), scalar, list_count: Some(count) });
+                }
+                ["property", scalar, name] => {
+                    let element = header.elements.last_mut().ok_or("PLY property outside of an element")?;
+                    let scalar = Scalar::parse(scalar).ok_or_else(|| format!("Unknown PLY type '{}'", scalar))?;
+                    element.properties.push(Property { name: name.to_string(), scalar, list_count: None });
+                }
+                _ => {} // Comments and obj_info
+            }
+        }
+        Ok(header)
+    }
+
+    fn faces(&self) -> usize {
+        self.elements.iter().filter(|element| element.name == "face").map(|element| element.count).sum()
+    }
+}
+
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], offset: usize, big_endian: bool },
+}
+
+impl Body<'_> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, String> {
+        match self {
+            Body::Ascii(tokens) => tokens.next().and_then(|token| token.parse().ok()).ok_or_else(|| "Invalid or truncated PLY data".to_string()),
+            Body::Binary { data, offset, big_endian } => {
+                let bytes = data.get(*offset..*offset + scalar.size()).ok_or("Truncated PLY data")?;
+                *offset += scalar.size();
+                Ok(scalar.decode(bytes, *big_endian))
+            }
+        }
+    }
+}
+
+// Positions and linear colors of a point cloud. Colors are white if the file has none.
+pub struct PointCloud {
+    pub positions: Vec<Vec3>,
+    pub colors: Vec<Vec3>,
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+// Reads the vertices of an ASCII or binary PLY file, ignoring any faces
+pub fn parse_ply(data: &[u8]) -> Result<PointCloud, String> {
+    let header = Header::parse(data)?;
+    let mut body = if header.ascii {
+        let text = std::str::from_utf8(&data[header.body..]).map_err(|_| "ASCII PLY data isn't text")?;
+        Body::Ascii(text.split_ascii_whitespace())
+    } else {
+        Body::Binary { data, offset: header.body, big_endian: header.big_endian }
+    };
+
+    for element in header.elements.iter() {
+        let find = |names: &[&str]| element.properties.iter().position(|property| property.list_count.is_none() && names.contains(&property.name.as_str()));
+        if element.name != "vertex" {
+            // Elements before the vertices still have to be read past
+            for _ in 0..element.count {
+                for property in element.properties.iter() {
+                    let length = match property.list_count {
+                        Some(count) => body.read(count)? as usize,
+                        None => 1,
+                    };
+                    for _ in 0..length {
+                        body.read(property.scalar)?;
+                    }
+                }
+            }
+            continue;
+        }
+
+        let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+        let [Some(x), Some(y), Some(z)] = position else {
+            return Err("PLY vertices have no position".to_string());
+        };
+        let color = [find(&["red", "r", "diffuse_red"]), find(&["green", "g", "diffuse_green"]), find(&["blue", "b", "diffuse_blue"])];
+
+        let mut cloud = PointCloud { positions: Vec::with_capacity(element.count), colors: Vec::with_capacity(element.count) };
+        let mut values = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            for (value, property) in values.iter_mut().zip(element.properties.iter()) {
+                *value = match property.list_count {
+                    Some(count) => {
+                        let length = body.read(count)? as usize;
+                        for _ in 0..length {
+                            body.read(property.scalar)?;
+                        }
+                        0.0
+                    }
+                    None => body.read(property.scalar)?,
+                };
+            }
+            cloud.positions.push(Vec3::new(values[x] as f32, values[y] as f32, values[z] as f32));
+            let channel = |index: Option<usize>| index.map_or(1.0, |index| (values[index] * element.properties[index].scalar.color_scale()) as f32);
+            cloud.colors.push(Vec3::new(channel(color[0]), channel(color[1]), channel(color[2])).clamp(Vec3::ZERO, Vec3::ONE).to_array().map(srgb_to_linear).into());
+        }
+        return Ok(cloud);
+    }
+    Err("PLY file has no vertices".to_string())
+}
+
+// PLY files without faces. Those with faces are meshes, and imported by assimp.
+pub fn is_point_cloud(path: &str) -> bool {
+    let extension = Path::new(path).extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_lowercase();
+    if extension != "ply" {
+        return false;
+    }
+    let mut data = Vec::new();
+    let read = std::fs::File::open(path).and_then(|file| file.take(HEADER_LIMIT).read_to_end(&mut data));
+    read.is_ok() && Header::parse(&data).map_or(false, |header| header.faces() == 0)
+}
+
+// Roughly the spacing of the points if they were spread over a surface the size of the cloud, so
+// that neighbouring discs overlap without bloating the cloud much
+pub fn point_radius(positions: &[Vec3]) -> f32 {
+    let (min, max) = positions.iter().fold((Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)), |(min, max), position| (min.min(*position), max.max(*position)));
+    let diagonal = (max - min).length();
+    if !diagonal.is_finite() || diagonal <= 0.0 {
+        return 0.01;
+    }
+    diagonal / (positions.len() as f32).sqrt() * POINT_SPACING_SCALE
+}
+
+// A scene of a single point cloud, with a white diffuse material so the points show their own colors
+pub fn load(path: &str, scale: f32) -> Option<SceneDescription> {
+    let data = std::fs::read(path)
+        .map_err(|err| tracing::error!("Failed to open point cloud '{}': {}", path, err))
+        .ok()?;
+    let cloud = parse_ply(&data)
+        .map_err(|err| tracing::error!("Failed to read point cloud '{}': {}", path, err))
+        .ok()?;
+    let positions = cloud.positions.iter().map(|position| *position * scale).collect::<Vec<_>>();
+    let point_radius = point_radius(&positions);
+    tracing::info!("Loaded {} points with a radius of {}.", positions.len(), point_radius);
+    let mesh = MeshDescription { positions, colors: cloud.colors, point_radius, ..Default::default() };
+    let material = MaterialDescription { name: "Point cloud".to_string(), albedo: Vec4::ONE, roughness: 1.0, ..Default::default() };
+    Some(SceneDescription { meshes: vec![mesh], materials: vec![material], camera: None })
+}
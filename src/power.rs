@@ -0,0 +1,46 @@
+use std::time::{Duration, Instant};
+
+// Asking the OS about batteries is slow enough to show up in frame times, so it's only done every few seconds
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// Tracks whether the machine is running off a battery. Machines without one, or where the
+// platform can't tell, always count as plugged in.
+pub struct PowerMonitor {
+    manager: Option<battery::Manager>,
+    on_battery: bool,
+    last_check: Option<Instant>,
+}
+
+impl PowerMonitor {
+    pub fn new() -> Self {
+        let manager = battery::Manager::new()
+            .map_err(|err| tracing::warn!("Failed to query batteries, assuming the machine is plugged in: {}", err))
+            .ok();
+        Self {
+            manager,
+            on_battery: false,
+            last_check: None,
+        }
+    }
+
+    pub fn on_battery(&mut self) -> bool {
+        if self.last_check.map_or(true, |last| last.elapsed() >= CHECK_INTERVAL) {
+            self.last_check = Some(Instant::now());
+            self.on_battery = self.manager.as_ref().map_or(false, is_discharging);
+        }
+        self.on_battery
+    }
+}
+
+impl Default for PowerMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_discharging(manager: &battery::Manager) -> bool {
+    manager
+        .batteries()
+        .map(|batteries| batteries.flatten().any(|battery| battery.state() == battery::State::Discharging))
+        .unwrap_or(false)
+}
@@ -0,0 +1,322 @@
+use glam::{Vec2, Vec3};
+use image::Rgb32FImage;
+use kernels::half::f32_to_f16_bits;
+use rayon::prelude::*;
+use shared_structs::{TracingConfig, PROJECTION_EQUIRECTANGULAR};
+use std::{f32::consts::PI, path::{Path, PathBuf}};
+
+use crate::batch::{HeadlessScene, RenderProgress};
+
+const IRRADIANCE_SIZE: u32 = 32; // Irradiance is smooth enough that more is wasted
+const SPECULAR_SAMPLES: u32 = 128; // Per texel, filtered importance sampling keeps this low
+const SPECULAR_SMALLEST_MIP: u32 = 4; // Smaller faces can't hold the lobes of the roughest levels anyway
+
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+const VK_FORMAT_R16G16B16A16_SFLOAT: u32 = 97;
+const KTX2_HEADER_SIZE: usize = 80; // Identifier, header and index, up to the level index
+
+// Linear RGB, laid out like skybox images, see environment::texel_direction
+pub struct Panorama {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Panorama {
+    pub fn from_framebuffer(framebuffer: &[f32], width: u32, height: u32) -> Self {
+        let pixels = framebuffer.chunks_exact(3).map(Vec3::from_slice).collect();
+        Self { width, height, pixels }
+    }
+
+    fn texel(&self, x: i32, y: i32) -> Vec3 {
+        let x = x.rem_euclid(self.width as i32) as u32;
+        let y = y.clamp(0, self.height as i32 - 1) as u32;
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    // Bilinear, wrapping around horizontally
+    pub fn sample(&self, direction: Vec3) -> Vec3 {
+        let uv = direction_uv(direction) * Vec2::new(self.width as f32, self.height as f32) - 0.5;
+        let base = uv.floor();
+        let t = uv - base;
+        let (x, y) = (base.x as i32, base.y as i32);
+        let top = self.texel(x, y).lerp(self.texel(x + 1, y), t.x);
+        let bottom = self.texel(x, y + 1).lerp(self.texel(x + 1, y + 1), t.x);
+        top.lerp(bottom, t.y)
+    }
+
+    fn downsample(&self) -> Self {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let pixels = (0..width * height)
+            .map(|index| {
+                let (x, y) = ((index % width * 2) as i32, (index / width * 2) as i32);
+                (self.texel(x, y) + self.texel(x + 1, y) + self.texel(x, y + 1) + self.texel(x + 1, y + 1)) / 4.0
+            })
+            .collect();
+        Self { width, height, pixels }
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let data = self.pixels.iter().flat_map(|pixel| pixel.to_array()).collect();
+        let image = Rgb32FImage::from_raw(self.width, self.height, data).ok_or("Invalid panorama")?;
+        image.save(path).map_err(|err| err.to_string())
+    }
+}
+
+// Where a direction lands in a panorama, from 0 to 1. The inverse of environment::texel_direction.
+pub fn direction_uv(direction: Vec3) -> Vec2 {
+    let direction = direction.normalize();
+    Vec2::new(0.5 + direction.z.atan2(direction.x) / (2.0 * PI), 0.5 - direction.y.clamp(-1.0, 1.0).asin() / PI)
+}
+
+// Direction through a point of a cube face, with texel coordinates from -1 to 1 going right and
+// down. Faces are in the order +X, -X, +Y, -Y, +Z, -Z, as Vulkan and KTX lay cubemaps out.
+pub fn cube_direction(face: u32, s: f32, t: f32) -> Vec3 {
+    match face {
+        0 => Vec3::new(1.0, -t, -s),
+        1 => Vec3::new(-1.0, -t, s),
+        2 => Vec3::new(s, 1.0, t),
+        3 => Vec3::new(s, -1.0, -t),
+        4 => Vec3::new(s, -t, 1.0),
+        _ => Vec3::new(-s, -t, -1.0),
+    }
+    .normalize()
+}
+
+// Each level holds its six faces one after the other, row by row
+pub struct Cubemap {
+    pub size: u32, // Of the first level
+    pub levels: Vec<Vec<Vec3>>,
+}
+
+impl Cubemap {
+    // Evaluates `texel` at the center of every texel of every level
+    fn from_fn(size: u32, level_count: u32, texel: impl Fn(u32, Vec3) -> Vec3 + Sync) -> Self {
+        let levels = (0..level_count)
+            .map(|level| {
+                let level_size = (size >> level).max(1);
+                (0..6 * level_size * level_size)
+                    .into_par_iter()
+                    .map(|index| {
+                        let (face, texel_index) = (index / (level_size * level_size), index % (level_size * level_size));
+                        let s = ((texel_index % level_size) as f32 + 0.5) / level_size as f32 * 2.0 - 1.0;
+                        let t = ((texel_index / level_size) as f32 + 0.5) / level_size as f32 * 2.0 - 1.0;
+                        texel(level, cube_direction(face, s, t))
+                    })
+                    .collect()
+            })
+            .collect();
+        Self { size, levels }
+    }
+}
+
+// Weights that convolve spherical harmonics of radiance with the cosine lobe, per coefficient, and
+// divide by pi. Evaluating the result gives irradiance over pi, the diffuse lighting of a white surface.
+pub const COSINE_LOBE_BANDS: [f32; 9] = [1.0, 2.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0, 0.25, 0.25, 0.25, 0.25, 0.25];
+
+// Real spherical harmonics up to the second band
+pub fn sh9(d: Vec3) -> [f32; 9] {
+    [
+        0.282095,
+        0.488603 * d.y,
+        0.488603 * d.z,
+        0.488603 * d.x,
+        1.092548 * d.x * d.y,
+        1.092548 * d.y * d.z,
+        0.315392 * (3.0 * d.z * d.z - 1.0),
+        1.092548 * d.x * d.z,
+        0.546274 * (d.x * d.x - d.y * d.y),
+    ]
+}
+
+// Irradiance divided by pi, so diffuse lighting is the albedo times the map. Projected onto
+// spherical harmonics, which hold cosine-convolved lighting with next to no error.
+pub fn irradiance_cubemap(panorama: &Panorama, size: u32) -> Cubemap {
+    let mut coefficients = [Vec3::ZERO; 9];
+    let texel_angle = (2.0 * PI / panorama.width as f32) * (PI / panorama.height as f32);
+    for y in 0..panorama.height {
+        for x in 0..panorama.width {
+            let u = (x as f32 + 0.5) / panorama.width as f32;
+            let latitude = (0.5 - (y as f32 + 0.5) / panorama.height as f32) * PI;
+            let phi = (u - 0.5) * 2.0 * PI;
+            let direction = Vec3::new(latitude.cos() * phi.cos(), latitude.sin(), latitude.cos() * phi.sin());
+            let radiance = panorama.pixels[(y * panorama.width + x) as usize] * texel_angle * latitude.cos();
+            for (coefficient, basis) in coefficients.iter_mut().zip(sh9(direction)) {
+                *coefficient += radiance * basis;
+            }
+        }
+    }
+    Cubemap::from_fn(size, 1, |_, normal| {
+        let basis = sh9(normal);
+        (0..9).map(|i| coefficients[i] * COSINE_LOBE_BANDS[i] * basis[i]).sum::<Vec3>().max(Vec3::ZERO)
+    })
+}
+
+fn hammersley(index: u32, count: u32) -> Vec2 {
+    Vec2::new(index as f32 / count as f32, index.reverse_bits() as f32 * 2.328_306_4e-10)
+}
+
+// Trilinear lookup in a chain of ever smaller panoramas
+fn sample_mips(mips: &[Panorama], direction: Vec3, lod: f32) -> Vec3 {
+    let lod = lod.clamp(0.0, (mips.len() - 1) as f32);
+    let lower = lod.floor() as usize;
+    let upper = (lower + 1).min(mips.len() - 1);
+    mips[lower].sample(direction).lerp(mips[upper].sample(direction), lod - lower as f32)
+}
+
+// GGX lobe around `normal`, with the view direction along it as in the split sum approximation.
+// Samples read blurrier mips the less likely they are, as in filtered importance sampling.
+fn prefilter_specular(mips: &[Panorama], normal: Vec3, roughness: f32) -> Vec3 {
+    if roughness == 0.0 {
+        return mips[0].sample(normal);
+    }
+    let alpha2 = (roughness * roughness).powi(2);
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+    let texel_angle = 4.0 * PI / (mips[0].width * mips[0].height) as f32;
+    let mut color = Vec3::ZERO;
+    let mut weight = 0.0;
+    for index in 0..SPECULAR_SAMPLES {
+        let xi = hammersley(index, SPECULAR_SAMPLES);
+        let cos_theta = ((1.0 - xi.y) / (1.0 + (alpha2 - 1.0) * xi.y)).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * xi.x;
+        let half = tangent * sin_theta * phi.cos() + bitangent * sin_theta * phi.sin() + normal * cos_theta;
+        let light = 2.0 * normal.dot(half) * half - normal;
+        let n_dot_l = normal.dot(light);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+        // With the view along the normal, the pdf of the light direction is D / 4
+        let d = alpha2 / (PI * ((alpha2 - 1.0) * cos_theta * cos_theta + 1.0).powi(2));
+        let sample_angle = 4.0 / (SPECULAR_SAMPLES as f32 * d);
+        let lod = 0.5 * (sample_angle / texel_angle).log2() + 1.0;
+        color += sample_mips(mips, light, lod) * n_dot_l;
+        weight += n_dot_l;
+    }
+    if weight > 0.0 { color / weight } else { Vec3::ZERO }
+}
+
+// Roughness goes from 0 at the first level to 1 at the last, which is SPECULAR_SMALLEST_MIP wide
+pub fn specular_cubemap(panorama: &Panorama, size: u32) -> Cubemap {
+    let size = size.max(SPECULAR_SMALLEST_MIP).next_power_of_two();
+    let level_count = (size / SPECULAR_SMALLEST_MIP).trailing_zeros() + 1;
+    let mut mips = vec![Panorama { width: panorama.width, height: panorama.height, pixels: panorama.pixels.clone() }];
+    while mips.last().map_or(false, |mip| mip.width > 1 && mip.height > 1) {
+        let next = mips.last().unwrap().downsample();
+        mips.push(next);
+    }
+    Cubemap::from_fn(size, level_count, |level, direction| {
+        let roughness = if level_count > 1 { level as f32 / (level_count - 1) as f32 } else { 0.0 };
+        prefilter_specular(&mips, direction, roughness)
+    })
+}
+
+// Data format descriptor of RGBA16F, as the KTX2 spec requires even for plain Vulkan formats
+fn rgba16f_dfd() -> Vec<u32> {
+    let mut block = vec![
+        0, // Khronos vendor, basic descriptor type
+        2 | (24 + 16 * 4) << 16, // Version 2, and the size of the block with 4 samples
+        1 | 1 << 8 | 1 << 16, // RGBSDA color model, BT.709 primaries, linear transfer, straight alpha
+        0, // 1x1x1 texel blocks
+        8, // Bytes in the first plane
+        0,
+    ];
+    for (index, channel) in [0u32, 1, 2, 15].into_iter().enumerate() {
+        // 16 bits at a 16 bit stride, signed float, from -1 to 1 as floats are described
+        block.extend([(index as u32 * 16) | 15 << 16 | (channel | 0xC0) << 24, 0, 0xBF80_0000, 0x3F80_0000]);
+    }
+    let mut dfd = vec![(block.len() as u32 + 1) * 4];
+    dfd.extend(block);
+    dfd
+}
+
+// A KTX2 cubemap in RGBA16F with the levels of the cubemap as its mips, without supercompression
+pub fn encode_ktx2(cubemap: &Cubemap) -> Vec<u8> {
+    let level_count = cubemap.levels.len();
+    let dfd = rgba16f_dfd();
+    let dfd_offset = KTX2_HEADER_SIZE + level_count * 24;
+    let dfd_length = dfd.len() * 4;
+
+    // Levels are stored from the smallest up, each aligned to the 8 bytes of a texel
+    let mut data = Vec::new();
+    let mut level_index = vec![(0u64, 0u64); level_count];
+    let data_start = (dfd_offset + dfd_length + 7) / 8 * 8;
+    for (level, texels) in cubemap.levels.iter().enumerate().rev() {
+        while (data_start + data.len()) % 8 != 0 {
+            data.push(0);
+        }
+        let offset = data_start + data.len();
+        for texel in texels {
+            for value in [texel.x, texel.y, texel.z, 1.0] {
+                data.extend_from_slice(&(f32_to_f16_bits(value, 0xFFF) as u16).to_le_bytes());
+            }
+        }
+        level_index[level] = (offset as u64, (data_start + data.len() - offset) as u64);
+    }
+
+    let mut bytes = KTX2_IDENTIFIER.to_vec();
+    // Format, type size, size, depth, layers, faces, levels, no supercompression
+    let header = [VK_FORMAT_R16G16B16A16_SFLOAT, 2, cubemap.size, cubemap.size, 0, 0, 6, level_count as u32, 0];
+    for value in header {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    // Data format descriptor, no key/value data, no supercompression global data
+    for value in [dfd_offset as u32, dfd_length as u32, 0, 0] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    for (offset, length) in level_index {
+        for value in [offset, length, length] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    for value in dfd {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.resize(data_start, 0);
+    bytes.extend(data);
+    bytes
+}
+
+// Renders a 360 degree panorama from a point in the scene, and prefilters it into the cubemaps
+// real-time engines light with: diffuse irradiance, and GGX specular for the split sum approximation
+#[derive(Clone)]
+pub struct ProbeBake {
+    pub scene: HeadlessScene,
+    pub config: TracingConfig,
+    pub position: Vec3,
+    pub resolution: u32, // Height of the panorama, which is twice as wide
+    pub samples: u32,
+    pub specular_size: u32, // Of the sharpest level
+}
+
+impl ProbeBake {
+    pub fn render(&self, progress: &RenderProgress) -> Result<Panorama, String> {
+        let mut config = self.config;
+        config.render.width = self.resolution * 2;
+        config.render.height = self.resolution;
+        config.camera.projection = PROJECTION_EQUIRECTANGULAR;
+        config.camera.cam_position = self.position.extend(config.camera.cam_position.w);
+        config.camera.prev_cam_position = config.camera.cam_position;
+        let framebuffer = self.scene.render(config, self.samples, progress)?;
+        Ok(Panorama::from_framebuffer(&framebuffer, config.render.width, config.render.height))
+    }
+
+    // Writes the panorama to `path`, as EXR, and the cubemaps next to it. Returns the paths of the cubemaps.
+    pub fn bake(&self, path: &Path, progress: &RenderProgress) -> Result<(PathBuf, PathBuf), String> {
+        let panorama = self.render(progress)?;
+        panorama.save(&path.with_extension("exr"))?;
+        progress.finish_one();
+
+        let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_else(|| "probe".to_string());
+        let irradiance_path = path.with_file_name(format!("{}_irradiance.ktx2", stem));
+        let specular_path = path.with_file_name(format!("{}_specular.ktx2", stem));
+        let irradiance = irradiance_cubemap(&panorama, IRRADIANCE_SIZE);
+        std::fs::write(&irradiance_path, encode_ktx2(&irradiance)).map_err(|err| err.to_string())?;
+        let specular = specular_cubemap(&panorama, self.specular_size);
+        std::fs::write(&specular_path, encode_ktx2(&specular)).map_err(|err| err.to_string())?;
+        progress.finish_one();
+        Ok((irradiance_path, specular_path))
+    }
+}
@@ -0,0 +1,101 @@
+use glam::{Vec2, Vec4};
+use shared_structs::{MaterialData, ProceduralRegion, PATTERN_NONE, PROCEDURAL_SPACING};
+use std::sync::Arc;
+
+// Computes a texel from a UV. Only the CPU backend can call back into Rust, so these are
+// ignored when rendering on the GPU.
+pub type ProceduralTexture = Arc<dyn Fn(Vec2) -> Vec4 + Send + Sync>;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TextureChannel {
+    Albedo,
+    Roughness, // read from the red channel
+    Metallic, // likewise
+}
+
+// Replaces a texture of every material with the given name
+#[derive(Clone)]
+pub struct ProceduralBinding {
+    pub material: String,
+    pub channel: TextureChannel,
+    pub texture: ProceduralTexture,
+}
+
+// Alternates between two values on a grid of `scale` squares per unit of UV
+pub fn checker(a: Vec4, b: Vec4, scale: f32) -> ProceduralTexture {
+    Arc::new(move |uv: Vec2| {
+        let cell = (uv * scale).floor();
+        if (cell.x + cell.y).rem_euclid(2.0) < 1.0 { a } else { b }
+    })
+}
+
+// Blends from `a` to `b` along U, repeating every unit
+pub fn gradient(a: Vec4, b: Vec4) -> ProceduralTexture {
+    Arc::new(move |uv: Vec2| a.lerp(b, uv.x.rem_euclid(1.0)))
+}
+
+fn hash(x: i32, y: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6b343) ^ (y as u32).wrapping_mul(0xd8163841);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1e995);
+    h ^= h >> 15;
+    h as f32 / u32::MAX as f32
+}
+
+// Smoothly interpolated value noise with `scale` cells per unit of UV, blending from `a` to `b`
+pub fn noise(a: Vec4, b: Vec4, scale: f32) -> ProceduralTexture {
+    Arc::new(move |uv: Vec2| {
+        let p = uv * scale;
+        let cell = p.floor();
+        let t = p - cell;
+        let t = t * t * (Vec2::splat(3.0) - 2.0 * t);
+        let (x, y) = (cell.x as i32, cell.y as i32);
+        let top = hash(x, y) + (hash(x + 1, y) - hash(x, y)) * t.x;
+        let bottom = hash(x, y + 1) + (hash(x + 1, y + 1) - hash(x, y + 1)) * t.x;
+        a.lerp(b, top + (bottom - top) * t.y)
+    })
+}
+
+// Points the bound material channels at the procedural textures, and returns where each
+// texture is placed, to be handed to the CPU atlas with CpuImage::with_procedural.
+pub fn bind_procedural_textures(bindings: &[ProceduralBinding], names: &[String], materials: &mut [MaterialData]) -> Vec<(Vec2, ProceduralTexture)> {
+    let mut placed = Vec::new();
+    for binding in bindings {
+        let offset = Vec2::new(PROCEDURAL_SPACING * (placed.len() + 1) as f32, 0.0);
+        let location = Vec4::new(offset.x, offset.y, 1.0, 1.0);
+        let mut found = false;
+        for (material, _) in materials.iter_mut().zip(names).filter(|(_, name)| **name == binding.material) {
+            found = true;
+            match binding.channel {
+                TextureChannel::Albedo => {
+                    material.albedo = location;
+                    material.set_has_albedo_texture(true);
+                    material.patterns.x = PATTERN_NONE;
+                }
+                TextureChannel::Roughness => {
+                    material.roughness = location;
+                    material.set_has_roughness_texture(true);
+                    material.patterns.y = PATTERN_NONE;
+                }
+                TextureChannel::Metallic => {
+                    material.metallic = location;
+                    material.set_has_metallic_texture(true);
+                    material.patterns.z = PATTERN_NONE;
+                }
+            }
+        }
+        if found {
+            placed.push((offset, binding.texture.clone()));
+        } else {
+            tracing::warn!("Procedural texture is bound to material '{}', which the scene doesn't have.", binding.material);
+        }
+    }
+    placed
+}
+
+pub fn procedural_regions(placed: &[(Vec2, ProceduralTexture)]) -> Vec<ProceduralRegion> {
+    placed
+        .iter()
+        .map(|(offset, texture)| ProceduralRegion { offset: *offset, texture: texture.as_ref() })
+        .collect()
+}
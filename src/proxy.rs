@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+
+use glam::{UVec4, Vec4};
+use gpgpu::{BufOps, GpuBuffer};
+use shared_structs::{LightPickEntry, MaterialData, PerVertexData, CSG_MASK, PRIMITIVE_POINT, PRIMITIVE_SDF};
+
+use crate::{bvh::{BVHBuilder, GpuBVH, BVH}, cancel::CancelToken, light_pick, trace::FW};
+
+// Scenes with more triangles than this get a proxy to navigate with
+pub const PROXY_THRESHOLD: usize = 2_000_000;
+// Roughly how many triangles the proxy is simplified down to
+pub const PROXY_TRIANGLES: usize = 500_000;
+// Triangles sharing a material are simplified together, and groups smaller than this are kept as they are
+const MIN_SIMPLIFIED_GROUP: usize = 1024;
+// Relative to the size of each group, how far the simplified surface may stray from the original
+const SIMPLIFY_ERROR: f32 = 0.02;
+// Points and SDFs are corners of boxes rather than surfaces, and CSG needs closed meshes, so these are kept as they are
+const UNSIMPLIFIED_FLAGS: u32 = PRIMITIVE_POINT | PRIMITIVE_SDF | CSG_MASK;
+
+// Simplified geometry of a heavy scene, traced instead of the full scene while the camera is being
+// moved, see trace::InteractiveTarget. It shares the vertices of the full scene, so only its
+// triangles, BVH and light pick table are its own.
+pub struct Proxy {
+    pub bvh: BVH,
+    pub index_buffer: Vec<UVec4>,
+    pub light_pick_buffer: Vec<LightPickEntry>,
+}
+
+pub struct GpuProxy<'fw> {
+    pub bvh: GpuBVH<'fw>,
+    pub index_buffer: GpuBuffer<'fw, UVec4>,
+    pub light_pick_buffer: GpuBuffer<'fw, LightPickEntry>,
+}
+
+impl Proxy {
+    // Simplifies each material's triangles on their own, so every triangle of the proxy keeps the
+    // material, and visibility, of the ones it replaces. Returns None if cancelled.
+    pub fn build(vertices: &[Vec4], indices: &[UVec4], material_datas: &[MaterialData], target_triangles: usize, cancel: &CancelToken) -> Option<Self> {
+        puffin::profile_function!();
+        let mut groups = BTreeMap::<u32, Vec<u32>>::new();
+        for triangle in indices {
+            groups.entry(triangle.w).or_default().extend_from_slice(&triangle.truncate().to_array());
+        }
+        let adapter = meshopt::VertexDataAdapter::new(bytemuck::cast_slice(vertices), std::mem::size_of::<Vec4>(), 0).ok()?;
+        let ratio = target_triangles as f32 / indices.len() as f32;
+        let mut proxy_indices = Vec::new();
+        for (w, group) in groups {
+            if cancel.is_cancelled() {
+                return None;
+            }
+            let triangles = group.len() / 3;
+            let simplified = if triangles < MIN_SIMPLIFIED_GROUP || w & UNSIMPLIFIED_FLAGS != 0 {
+                group
+            } else {
+                let target = ((triangles as f32 * ratio) as usize).max(MIN_SIMPLIFIED_GROUP) * 3;
+                meshopt::simplify(&group, &adapter, target, SIMPLIFY_ERROR)
+            };
+            proxy_indices.extend(simplified.chunks_exact(3).map(|t| UVec4::new(t[0], t[1], t[2], w)));
+        }
+
+        let bvh = BVHBuilder::new(vertices, &mut proxy_indices).sah_samples(128).cancel_token(cancel.clone()).try_build()?;
+        let emissive_mask = light_pick::compute_emissive_mask(&proxy_indices, material_datas);
+        let light_pick_buffer = light_pick::try_build_light_pick_table(vertices, &proxy_indices, &emissive_mask, material_datas, cancel)?;
+        tracing::info!("Simplified {} triangles to a proxy of {} for navigation.", indices.len(), proxy_indices.len());
+        Some(Self {
+            bvh,
+            index_buffer: proxy_indices,
+            light_pick_buffer,
+        })
+    }
+
+    // Follows emission edits of the full scene
+    pub fn rebuild_lights(&mut self, per_vertex_data: &[PerVertexData], material_datas: &[MaterialData]) {
+        self.light_pick_buffer = light_pick::rebuild_light_pick_table(per_vertex_data, &self.index_buffer, material_datas).0;
+    }
+
+    pub fn buffer_bytes(&self) -> u64 {
+        (std::mem::size_of_val(self.index_buffer.as_slice()) + std::mem::size_of_val(self.bvh.nodes.as_slice()) + std::mem::size_of_val(self.light_pick_buffer.as_slice())) as u64
+    }
+
+    pub fn to_gpu<'fw>(&self) -> GpuProxy<'fw> {
+        GpuProxy {
+            bvh: self.bvh.to_gpu(),
+            index_buffer: GpuBuffer::from_slice(&FW, &self.index_buffer),
+            light_pick_buffer: GpuBuffer::from_slice(&FW, &self.light_pick_buffer),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use image::DynamicImage;
+
+use crate::asset::load_dynamic_image;
+
+// Loads an image to compare against, resized to match the framebuffer and laid out the same way (linear RGB f32).
+pub fn load_reference_image(path: &str, width: u32, height: u32) -> Option<Vec<f32>> {
+    let image = load_dynamic_image(path)?;
+
+    // HDR formats are already linear, everything else is assumed to be stored in gamma space.
+    let is_linear = matches!(image, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_));
+    let image = image.resize_exact(width, height, image::imageops::FilterType::Triangle).into_rgb32f();
+
+    let mut buffer = image.into_raw();
+    if !is_linear {
+        for value in buffer.iter_mut() {
+            *value = value.powf(2.2);
+        }
+    }
+    Some(buffer)
+}
+
+pub fn mean_squared_error(image: &[f32], reference: &[f32]) -> f32 {
+    let sum = image
+        .iter()
+        .zip(reference.iter())
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f32>();
+    sum / image.len().max(1) as f32
+}
+
+fn luminance(rgb: &[f32]) -> f32 {
+    (0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]).clamp(0.0, 1.0)
+}
+
+// Mean SSIM of the luminance channel over non-overlapping 8x8 windows.
+// https://www.cns.nyu.edu/pub/eero/wang03-reprint.pdf
+pub fn structural_similarity(image: &[f32], reference: &[f32], width: u32, height: u32) -> f32 {
+    const WINDOW: usize = 8;
+    const C1: f32 = 0.01 * 0.01;
+    const C2: f32 = 0.03 * 0.03;
+
+    let width = width as usize;
+    let height = height as usize;
+    let mut total = 0.0;
+    let mut windows = 0u32;
+    for window_y in (0..height).step_by(WINDOW) {
+        for window_x in (0..width).step_by(WINDOW) {
+            let mut mean_a = 0.0;
+            let mut mean_b = 0.0;
+            let mut count = 0;
+            for y in window_y..(window_y + WINDOW).min(height) {
+                for x in window_x..(window_x + WINDOW).min(width) {
+                    let index = (y * width + x) * 3;
+                    mean_a += luminance(&image[index..index + 3]);
+                    mean_b += luminance(&reference[index..index + 3]);
+                    count += 1;
+                }
+            }
+            mean_a /= count as f32;
+            mean_b /= count as f32;
+
+            let mut variance_a = 0.0;
+            let mut variance_b = 0.0;
+            let mut covariance = 0.0;
+            for y in window_y..(window_y + WINDOW).min(height) {
+                for x in window_x..(window_x + WINDOW).min(width) {
+                    let index = (y * width + x) * 3;
+                    let a = luminance(&image[index..index + 3]) - mean_a;
+                    let b = luminance(&reference[index..index + 3]) - mean_b;
+                    variance_a += a * a;
+                    variance_b += b * b;
+                    covariance += a * b;
+                }
+            }
+            variance_a /= count as f32;
+            variance_b /= count as f32;
+            covariance /= count as f32;
+
+            let numerator = (2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2);
+            let denominator = (mean_a * mean_a + mean_b * mean_b + C1) * (variance_a + variance_b + C2);
+            total += numerator / denominator;
+            windows += 1;
+        }
+    }
+    total / windows.max(1) as f32
+}
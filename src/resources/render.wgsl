@@ -7,13 +7,23 @@ struct Uniforms {
     width: u32,
     height: u32,
     tonemapping: u32,
+    diff_mode: u32,
+    diff_gain: f32,
+    packed: u32,
+    encode_srgb: u32, // Set when the surface doesn't apply the sRGB transfer function itself
+    hdr_output: u32, // Set when presenting to a scRGB surface
+    hdr_white: f32, // Scene radiance of 1 maps to this, in scRGB units of 80 nits
+    hdr_peak: f32,
 };
 
 @group(0) @binding(0)
 var<uniform> uniforms: Uniforms;
 
 @group(0) @binding(1)
-var<storage> render_buffer: array<f32>;
+var<storage> render_buffer: array<u32>; // RGB f32, or RGBA16F if packed
+
+@group(0) @binding(2)
+var<storage> reference_buffer: array<f32>;
 
 var<private> v_positions: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
     vec2<f32>(-1.0, -1.0),
@@ -116,6 +126,19 @@ fn uncharted(v: vec3<f32>) -> vec3<f32> {
     return curr * white_scale;
 }
 
+fn linear_to_srgb(x: vec3<f32>) -> vec3<f32> {
+    let c = clamp(x, vec3<f32>(0.0), vec3<f32>(1.0));
+    return select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3<f32>(0.0031308));
+}
+
+// Exported images are always sRGB encoded, so encode here too when the surface won't, to make the viewport match them
+fn encode_output(color: vec3<f32>) -> vec4<f32> {
+    if (uniforms.encode_srgb != 0u) {
+        return vec4<f32>(linear_to_srgb(color), 1.0);
+    }
+    return vec4<f32>(color, 1.0);
+}
+
 @fragment
 fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
     var uv = in.uv;
@@ -123,9 +146,34 @@ fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
     var puv: vec2<u32> = vec2<u32>(uv * vec2<f32>(f32(uniforms.width), f32(uniforms.height)));
     var idx: u32 = (puv.y*u32(uniforms.width)+puv.x);
     var color: vec4<f32> = vec4<f32>(0.0, 0.0, 0.0, 0.0);
-    color.r = render_buffer[idx*3u+0u];
-    color.g = render_buffer[idx*3u+1u];
-    color.b = render_buffer[idx*3u+2u];
+    if (uniforms.packed != 0u) {
+        color = vec4<f32>(
+            unpack2x16float(render_buffer[idx*2u+0u]),
+            unpack2x16float(render_buffer[idx*2u+1u])
+        );
+    } else {
+        color.r = bitcast<f32>(render_buffer[idx*3u+0u]);
+        color.g = bitcast<f32>(render_buffer[idx*3u+1u]);
+        color.b = bitcast<f32>(render_buffer[idx*3u+2u]);
+    }
+
+    if (uniforms.diff_mode != 0u) {
+        var reference = vec3<f32>(
+            reference_buffer[idx*3u+0u],
+            reference_buffer[idx*3u+1u],
+            reference_buffer[idx*3u+2u]
+        );
+        var diff = abs(color.rgb - reference);
+        if (uniforms.diff_mode == 2u) { // Relative
+            diff = diff / (reference + 0.01);
+        }
+        return encode_output(diff * uniforms.diff_gain);
+    }
+
+    // scRGB is linear and unbounded, so skip tonemapping and only clip to what the display can show
+    if (uniforms.hdr_output != 0u) {
+        return vec4<f32>(min(color.rgb * uniforms.hdr_white, vec3<f32>(uniforms.hdr_peak)), 1.0);
+    }
 
     var tonemapped = color.rgb;
     switch (uniforms.tonemapping) {
@@ -152,5 +200,5 @@ fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
         }
     }
 
-    return vec4<f32>(tonemapped, 1.0);
+    return encode_output(tonemapped);
 }
\ No newline at end of file
@@ -0,0 +1,597 @@
+use glam::{Mat4, UVec2, Vec2, Vec3, Vec4};
+use image::DynamicImage;
+use russimp::{scene::{Scene, PostProcess::*}, node::Node, light::LightSourceType, material::{DataContent, TextureType, Material, PropertyTypeInfo}, metadata::MetadataType};
+use serde::{Deserialize, Serialize};
+use shared_structs::{CSG_HAS_INTERSECT, CSG_INTERSECT, CSG_MASK, CSG_SUBTRACT, CSG_UNION, PATTERN_CHECKER, PATTERN_GRADIENT, PATTERN_NOISE};
+use std::{path::{Path, PathBuf}, sync::Arc};
+
+use crate::{texture_cache::TextureSource, markers::{add_markers, Marker, MarkerShape}, sdf::SdfDescription};
+
+// A scene as plain data, independent of the format it was loaded from. Loaders produce one of
+// these, and World::from_description turns it into something that can be rendered, so scenes
+// built in code go down the same path as imported ones. There are no analytic lights, any mesh
+// with an emissive material is a light. Imported lights become small emissive meshes, see markers.rs.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SceneDescription {
+    pub meshes: Vec<MeshDescription>,
+    pub materials: Vec<MaterialDescription>,
+    pub camera: Option<CameraDescription>,
+}
+
+// Triangles in world space, with Y up. Normals, tangents and UVs are either empty or one per position.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MeshDescription {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub tangents: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+    #[serde(default)]
+    pub lightmap_uvs: Vec<Vec2>, // second UV set, unique per texel, for baking lightmaps
+    pub triangles: Vec<[u32; 3]>, // counter-clockwise when seen from the front
+    pub material: u32, // index into SceneDescription::materials
+    #[serde(default)]
+    pub point_radius: f32, // above 0, this is a point cloud, each position drawn as a disc facing the viewer, and has no triangles
+    #[serde(default)]
+    pub colors: Vec<Vec3>, // linear, one per point of a point cloud, multiplied with the material's albedo
+    #[serde(default)]
+    pub sdf: Option<SdfDescription>, // traced in place of the positions and triangles, which are then empty
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MaterialDescription {
+    pub name: String,
+    pub albedo: Vec4,
+    pub emissive: Vec3, // radiance, zero for materials that aren't lights
+    pub metallic: f32,
+    pub roughness: f32,
+    pub double_sided: bool, // whether emissive triangles emit from their back faces too
+    pub textures: MaterialTextures,
+    pub patterns: MaterialPatterns,
+    pub transforms: MaterialTransforms,
+    #[serde(default)]
+    pub csg: Option<CsgOperation>, // of the meshes using it, which have to be closed
+}
+
+impl Default for MaterialDescription {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            albedo: Vec4::ONE,
+            emissive: Vec3::ZERO,
+            metallic: 0.0,
+            roughness: 0.0,
+            double_sided: false,
+            textures: MaterialTextures::default(),
+            patterns: MaterialPatterns::default(),
+            transforms: MaterialTransforms::default(),
+            csg: None,
+        }
+    }
+}
+
+// Textures replace the constant of the same name, except the emissive texture, which is multiplied with it
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MaterialTextures {
+    pub albedo: Option<TextureDescription>,
+    pub metallic: Option<TextureDescription>,
+    pub roughness: Option<TextureDescription>,
+    pub normal: Option<TextureDescription>,
+    pub emissive: Option<TextureDescription>,
+}
+
+// Patterns are computed in the kernels, so they take no room in the atlas. They replace the
+// texture of the same channel, if there is one.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct MaterialPatterns {
+    pub albedo: Option<PatternDescription>,
+    pub roughness: Option<PatternDescription>,
+    pub metallic: Option<PatternDescription>,
+}
+
+// UV transforms of the textured channels, as in glTF's KHR_texture_transform. Emissive textures
+// always use the mesh's UVs.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct MaterialTransforms {
+    pub albedo: Option<TextureTransform>,
+    pub metallic: Option<TextureTransform>,
+    pub roughness: Option<TextureTransform>,
+    pub normal: Option<TextureTransform>,
+}
+
+// Scales the UV, rotates it counter-clockwise around the origin, then offsets it
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextureTransform {
+    pub offset: Vec2,
+    pub scale: Vec2,
+    pub rotation: f32, // radians
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
+        Self {
+            offset: Vec2::ZERO,
+            scale: Vec2::ONE,
+            rotation: 0.0,
+        }
+    }
+}
+
+impl TextureTransform {
+    // Offset in xy and scale in zw, as the kernels expect it. A zero scale would mean no transform
+    // to them, so it is nudged off zero.
+    pub fn to_vec4(self) -> Vec4 {
+        let scale = Vec2::select(self.scale.cmpeq(Vec2::ZERO), Vec2::splat(f32::EPSILON), self.scale);
+        self.offset.extend(scale.x).extend(scale.y)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum PatternKind {
+    Checker,
+    Noise,
+    Gradient,
+}
+
+impl PatternKind {
+    pub fn to_u32(self) -> u32 {
+        match self {
+            PatternKind::Checker => PATTERN_CHECKER,
+            PatternKind::Noise => PATTERN_NOISE,
+            PatternKind::Gradient => PATTERN_GRADIENT,
+        }
+    }
+}
+
+// Boolean operations between closed meshes, tagged by their material. Cutaways of a model are
+// solids with a cutter subtracted, without having to re-author the model.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum CsgOperation {
+    Union,
+    Subtract,
+    Intersect,
+}
+
+impl CsgOperation {
+    pub fn to_u32(self) -> u32 {
+        match self {
+            CsgOperation::Union => CSG_UNION,
+            CsgOperation::Subtract => CSG_SUBTRACT,
+            CsgOperation::Intersect => CSG_INTERSECT,
+        }
+    }
+
+    pub fn from_u32(flags: u32) -> Option<Self> {
+        match flags & CSG_MASK {
+            CSG_UNION => Some(CsgOperation::Union),
+            CSG_SUBTRACT => Some(CsgOperation::Subtract),
+            CSG_INTERSECT => Some(CsgOperation::Intersect),
+            _ => None,
+        }
+    }
+
+    // Flags of the triangles of a material, given whether any material intersects, see shared_structs::CSG_HAS_INTERSECT
+    pub fn triangle_flags(operation: Option<Self>, has_intersect: bool) -> u32 {
+        match operation {
+            Some(operation) if has_intersect => operation.to_u32() | CSG_HAS_INTERSECT,
+            Some(operation) => operation.to_u32(),
+            None => 0,
+        }
+    }
+}
+
+// Blends from the material's own value of the channel to `value`. Roughness and metallic use
+// the first component.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct PatternDescription {
+    pub kind: PatternKind,
+    pub value: Vec4,
+    pub scale: f32, // cells per unit of UV
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum TextureDescription {
+    File(PathBuf), // Absolute, or relative to the working directory
+    Encoded { key: String, bytes: Vec<u8> }, // An image file held in memory, the key identifies it in the texture cache
+    Pixels { width: u32, height: u32, rgba: Vec<u8> }, // Uncompressed RGBA8 texels
+    Udim(Vec<UdimTile>), // A set of files, one per unit square of UV space
+}
+
+// Tile 1001 covers UVs from 0 to 1, 1002 the square to its right, and so on for 10 columns,
+// after which 1011 starts the next row up.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UdimTile {
+    pub u: u32,
+    pub v: u32,
+    pub path: PathBuf,
+}
+
+impl TextureDescription {
+    // UDIM sets have no single source, see tiles()
+    pub fn to_source(&self) -> Option<TextureSource> {
+        match self {
+            TextureDescription::File(path) => Some(TextureSource::File(path.clone())),
+            TextureDescription::Encoded { key, bytes } => Some(TextureSource::Embedded { key: key.clone(), bytes: Arc::new(bytes.clone()) }),
+            TextureDescription::Pixels { width, height, rgba } => {
+                let image = image::RgbaImage::from_vec(*width, *height, rgba.clone())?;
+                Some(TextureSource::Raw(Arc::new(DynamicImage::ImageRgba8(image))))
+            }
+            TextureDescription::Udim(_) => None,
+        }
+    }
+
+    // The size of the tile grid, and the position of each tile in it. Anything but a UDIM set is a single tile.
+    pub fn tiles(&self) -> Option<(UVec2, Vec<(UVec2, TextureSource)>)> {
+        match self {
+            TextureDescription::Udim(tiles) if !tiles.is_empty() => {
+                let grid = tiles.iter().fold(UVec2::ONE, |grid, tile| grid.max(UVec2::new(tile.u + 1, tile.v + 1)));
+                let sources = tiles.iter().map(|tile| (UVec2::new(tile.u, tile.v), TextureSource::File(tile.path.clone()))).collect();
+                Some((grid, sources))
+            }
+            TextureDescription::Udim(_) => None,
+            _ => Some((UVec2::ONE, vec![(UVec2::ZERO, self.to_source()?)])),
+        }
+    }
+}
+
+const UDIM_FIRST: u32 = 1001;
+const UDIM_LAST: u32 = 1100;
+const UDIM_TOKEN: &str = "<UDIM>";
+
+// Splits a file name around its UDIM number, or the <UDIM> token some tools write instead
+fn udim_pattern(file_name: &str) -> Option<(&str, &str)> {
+    if let Some(start) = file_name.find(UDIM_TOKEN) {
+        return Some((&file_name[..start], &file_name[start + UDIM_TOKEN.len()..]));
+    }
+    let bytes = file_name.as_bytes();
+    (0..bytes.len().saturating_sub(3)).rev().find_map(|start| {
+        let digits = &bytes[start..start + 4];
+        let isolated = (start == 0 || !bytes[start - 1].is_ascii_digit()) && bytes.get(start + 4).map_or(true, |c| !c.is_ascii_digit());
+        if !isolated || !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let number = digits.iter().fold(0, |number, c| number * 10 + (c - b'0') as u32);
+        (UDIM_FIRST..=UDIM_LAST).contains(&number).then(|| (&file_name[..start], &file_name[start + 4..]))
+    })
+}
+
+// Finds the other tiles of a UDIM set next to a texture. A lone numbered file is more likely
+// to just have a number in its name, so a set needs at least two tiles, or the <UDIM> token.
+pub fn udim_tiles(path: &Path) -> Option<Vec<UdimTile>> {
+    let file_name = path.file_name()?.to_str()?;
+    let (prefix, suffix) = udim_pattern(file_name)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let listing = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+
+    let mut tiles = std::fs::read_dir(listing)
+        .ok()?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            let digits = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            let number = digits.parse::<u32>().ok().filter(|_| digits.len() == 4)?;
+            let tile = (UDIM_FIRST..=UDIM_LAST).contains(&number).then_some(number - UDIM_FIRST)?;
+            Some(UdimTile { u: tile % 10, v: tile / 10, path: dir.join(&name) })
+        })
+        .collect::<Vec<_>>();
+    tiles.sort_by_key(|tile| (tile.v, tile.u));
+    let token = file_name.contains(UDIM_TOKEN);
+    (tiles.len() > 1 || (token && !tiles.is_empty())).then_some(tiles)
+}
+
+// Same conventions as TracingConfig: rotation is pitch around X, then yaw around Y, in radians
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct CameraDescription {
+    pub position: Vec3,
+    pub rotation: Vec2,
+}
+
+// Finds where a texture's data lives. Textures stored in the scene file come through the
+// material, while textures stored next to it are only referenced by path, and are decoded later.
+fn texture_description(material: &Material, texture_type: TextureType, scene_dir: &Path, key: String) -> Option<TextureDescription> {
+    if let Some(texture) = material.textures.get(&texture_type) {
+        let texture = texture.borrow();
+        return match &texture.data {
+            DataContent::Texel(raw_data) => Some(TextureDescription::Pixels {
+                width: texture.width,
+                height: texture.height,
+                rgba: raw_data.iter().flat_map(|c| [c.r, c.g, c.b, c.a]).collect(),
+            }),
+            DataContent::Bytes(bytes) => Some(TextureDescription::Encoded { key, bytes: bytes.clone() }),
+        };
+    }
+
+    let prop = material.properties.iter().find(|p| p.key == "$tex.file" && p.semantic == texture_type)?;
+    match &prop.data {
+        // Paths starting with '*' refer to embedded textures, which were handled above
+        PropertyTypeInfo::String(file) if !file.starts_with('*') => {
+            let path = scene_dir.join(file);
+            Some(udim_tiles(&path).map_or(TextureDescription::File(path), TextureDescription::Udim))
+        }
+        _ => None,
+    }
+}
+
+// Assimp stores a texture's transform as an aiUVTransform: translation, scaling and a rotation
+// around the middle of the texture. That is turned into a rotation around the origin here.
+fn load_texture_transform(material: &Material, texture_type: TextureType) -> Option<TextureTransform> {
+    let prop = material.properties.iter().find(|p| p.key == "$tex.uvtrafo" && p.semantic == texture_type)?;
+    let values = match &prop.data {
+        PropertyTypeInfo::FloatArray(values) => values.clone(),
+        PropertyTypeInfo::Buffer(bytes) => bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect(),
+        _ => return None,
+    };
+    let [tx, ty, sx, sy, rotation] = values.get(..5)?.try_into().ok()?;
+    let (sin, cos) = rotation.sin_cos();
+    let center = Vec2::splat(0.5);
+    let rotated_center = Vec2::new(cos * center.x - sin * center.y, sin * center.x + cos * center.y);
+    let transform = TextureTransform {
+        offset: Vec2::new(tx, ty) + center - rotated_center,
+        scale: Vec2::new(sx, sy),
+        rotation,
+    };
+    (transform != TextureTransform::default()).then_some(transform)
+}
+
+fn load_float_array(material: &Material, name: &str) -> Option<Vec<f32>> {
+    let prop = material.properties.iter().find(|p| p.key == name)?;
+    match &prop.data {
+        PropertyTypeInfo::FloatArray(col) => Some(col.clone()),
+        _ => None
+    }
+}
+
+// Assimp stores flags as either an integer or a single byte, depending on the importer
+fn load_bool(material: &Material, name: &str) -> Option<bool> {
+    let prop = material.properties.iter().find(|p| p.key == name)?;
+    match &prop.data {
+        PropertyTypeInfo::IntegerArray(values) => Some(*values.first()? != 0),
+        PropertyTypeInfo::Buffer(bytes) => Some(*bytes.first()? != 0),
+        _ => None
+    }
+}
+
+fn load_string(material: &Material, name: &str) -> Option<String> {
+    let prop = material.properties.iter().find(|p| p.key == name)?;
+    match &prop.data {
+        PropertyTypeInfo::String(value) => Some(value.clone()),
+        _ => None
+    }
+}
+
+// Meters per scene unit, for formats that say. FBX stores its unit in centimeters.
+fn unit_scale(scene: &Scene) -> f32 {
+    let Some(metadata) = scene.metadata.as_ref() else {
+        return 1.0;
+    };
+    let unit = metadata
+        .keys
+        .iter()
+        .zip(metadata.values.iter())
+        .find(|(key, _)| key.as_str() == "UnitScaleFactor")
+        .and_then(|(_, entry)| match entry.data {
+            MetadataType::Double(value) => Some(value as f32),
+            MetadataType::Float(value) => Some(value),
+            _ => None,
+        });
+    match unit {
+        Some(unit) if unit > 0.0 => {
+            tracing::info!("Scene units are {} cm, scaling to meters.", unit);
+            unit / 100.0
+        }
+        _ => 1.0,
+    }
+}
+
+// Every instance of a mesh in the node graph becomes its own mesh, transformed to world space.
+// Point and line primitives are collected separately, along with the index of their material.
+fn walk_node_graph(scene: &Scene, node: &Node, trs: Mat4, meshes: &mut Vec<MeshDescription>, primitives: &mut Vec<(MarkerShape, u32)>) {
+    let node_trs = Mat4::from_cols_array_2d(&[
+        [node.transformation.a1, node.transformation.b1, node.transformation.c1, node.transformation.d1],
+        [node.transformation.a2, node.transformation.b2, node.transformation.c2, node.transformation.d2],
+        [node.transformation.a3, node.transformation.b3, node.transformation.c3, node.transformation.d3],
+        [node.transformation.a4, node.transformation.b4, node.transformation.c4, node.transformation.d4],
+    ]);
+    let new_trs = trs * node_trs;
+    let (node_scale, node_quat, _) = new_trs.to_scale_rotation_translation();
+
+    // Y and Z are swapped on the way in, which also flips the winding of the triangles
+    for mesh_idx in node.meshes.iter() {
+        let mesh = &scene.meshes[*mesh_idx as usize];
+        let positions = mesh
+            .vertices
+            .iter()
+            .map(|v| {
+                let vert = new_trs.mul_vec4(Vec4::new(v.x, v.y, v.z, 1.0));
+                Vec3::new(vert.x, vert.z, vert.y)
+            })
+            .collect::<Vec<_>>();
+        let mut triangles = Vec::new();
+        for face in &mesh.faces {
+            match face.0[..] {
+                [a, b, c] => triangles.push([a, c, b]),
+                [a, b] => primitives.push((MarkerShape::Line(positions[a as usize], positions[b as usize]), mesh.material_index)),
+                [a] => primitives.push((MarkerShape::Point(positions[a as usize]), mesh.material_index)),
+                _ => {}
+            }
+        }
+        if triangles.is_empty() {
+            continue;
+        }
+        let normals = mesh
+            .normals
+            .iter()
+            .map(|n| {
+                let norm = (node_quat.mul_vec3(Vec3::new(n.x, n.y, n.z) / node_scale)).normalize();
+                Vec3::new(norm.x, norm.z, norm.y)
+            })
+            .collect();
+        let tangents = mesh
+            .tangents
+            .iter()
+            .map(|t| {
+                let tan = (node_quat.mul_vec3(Vec3::new(t.x, t.y, t.z) / node_scale)).normalize();
+                Vec3::new(tan.x, tan.z, tan.y)
+            })
+            .collect();
+        let uvs = match mesh.texture_coords.first() {
+            Some(Some(uv_set)) => uv_set.iter().map(|uv| Vec2::new(uv.x, uv.y)).collect(),
+            _ => Vec::new(),
+        };
+        let lightmap_uvs = match mesh.texture_coords.get(1) {
+            Some(Some(uv_set)) => uv_set.iter().map(|uv| Vec2::new(uv.x, uv.y)).collect(),
+            _ => Vec::new(),
+        };
+        meshes.push(MeshDescription {
+            positions,
+            normals,
+            tangents,
+            uvs,
+            lightmap_uvs,
+            triangles,
+            material: mesh.material_index,
+            ..Default::default()
+        });
+    }
+
+    for child in node.children.borrow().iter() {
+        walk_node_graph(scene, child, new_trs, meshes, primitives);
+    }
+}
+
+// Lights are placed by the node of the same name
+fn find_node_transform(node: &Node, name: &str, trs: Mat4) -> Option<Mat4> {
+    let node_trs = trs * Mat4::from_cols_array_2d(&[
+        [node.transformation.a1, node.transformation.b1, node.transformation.c1, node.transformation.d1],
+        [node.transformation.a2, node.transformation.b2, node.transformation.c2, node.transformation.d2],
+        [node.transformation.a3, node.transformation.b3, node.transformation.c3, node.transformation.d3],
+        [node.transformation.a4, node.transformation.b4, node.transformation.c4, node.transformation.d4],
+    ]);
+    if node.name == name {
+        return Some(node_trs);
+    }
+    node.children.borrow().iter().find_map(|child| find_node_transform(child, name, node_trs))
+}
+
+// Point and spot lights become emissive markers, with the color taken as radiant intensity.
+// Spot lights lose their cone, and lights without a position have nothing to become.
+fn light_markers(scene: &Scene, root_trs: Mat4) -> Vec<Marker> {
+    let mut markers = Vec::new();
+    for light in &scene.lights {
+        if !matches!(light.light_source_type, LightSourceType::Point | LightSourceType::Spot) {
+            tracing::warn!("Light '{}' is a {:?} light, only point and spot lights are imported.", light.name, light.light_source_type);
+            continue;
+        }
+        let trs = scene
+            .root
+            .as_ref()
+            .and_then(|root| find_node_transform(root, &light.name, root_trs))
+            .unwrap_or(root_trs);
+        let pos = trs.transform_point3(Vec3::new(light.pos.x, light.pos.y, light.pos.z));
+        markers.push(Marker {
+            name: light.name.clone(),
+            shape: MarkerShape::Point(Vec3::new(pos.x, pos.z, pos.y)),
+            intensity: Vec3::new(light.color_diffuse.r, light.color_diffuse.g, light.color_diffuse.b),
+        });
+    }
+    markers
+}
+
+fn material_description(material: &Material, index: usize, path: &str) -> MaterialDescription {
+    let scene_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let texture = |texture_type: TextureType, name: &str| {
+        texture_description(material, texture_type, scene_dir, format!("{}#{}#{}", path, index, name))
+    };
+    let mut description = MaterialDescription {
+        name: load_string(material, "?mat.name").unwrap_or_else(|| format!("Material {}", index)),
+        albedo: Vec4::ZERO, // Black unless the file says otherwise, as it always was
+        textures: MaterialTextures {
+            albedo: texture(TextureType::Diffuse, "albedo"),
+            metallic: texture(TextureType::Metalness, "metallic"),
+            roughness: texture(TextureType::Roughness, "roughness"),
+            normal: texture(TextureType::Normals, "normal"),
+            emissive: texture(TextureType::Emissive, "emissive"),
+        },
+        transforms: MaterialTransforms {
+            albedo: load_texture_transform(material, TextureType::Diffuse),
+            metallic: load_texture_transform(material, TextureType::Metalness),
+            roughness: load_texture_transform(material, TextureType::Roughness),
+            normal: load_texture_transform(material, TextureType::Normals),
+        },
+        ..Default::default()
+    };
+    if let Some(col) = load_float_array(material, "$clr.diffuse") {
+        description.albedo = Vec4::new(col[0], col[1], col[2], col[3]);
+    }
+    if let Some(col) = load_float_array(material, "$clr.emissive") {
+        // HACK: Multiply by 15 since assimp 5.2.5 doesn't support emissive strength :(
+        description.emissive = Vec3::new(col[0], col[1], col[2]) * 15.0;
+    }
+    if let Some(double_sided) = load_bool(material, "$mat.twosided") {
+        description.double_sided = double_sided;
+    }
+    if let Some(col) = load_float_array(material, "$mat.metallicFactor") {
+        description.metallic = col[0];
+    }
+    if let Some(col) = load_float_array(material, "$mat.roughnessFactor") {
+        description.roughness = col[0];
+    }
+    description
+}
+
+impl SceneDescription {
+    // Imports any format assimp supports, scaled by `scale` on top of the units stored in the file
+    pub fn import(path: &str, scale: f32) -> Option<Self> {
+        puffin::profile_function!();
+
+        let scene = Scene::from_file(
+            path,
+            vec![
+                JoinIdenticalVertices,
+                Triangulate,
+                SortByPrimitiveType,
+                GenerateSmoothNormals,
+                GenerateUVCoords,
+                CalculateTangentSpace,
+                ImproveCacheLocality,
+            ],
+        )
+        .map_err(|err| tracing::error!("Failed to import scene '{}': {:?}. Make sure the file exists and is in a format supported by assimp.", path, err))
+        .ok()?;
+
+        let mut meshes = Vec::new();
+        let mut primitives = Vec::new();
+        let root_trs = Mat4::from_scale(Vec3::splat(scale * unit_scale(&scene)));
+        if let Some(root) = scene.root.as_ref() {
+            puffin::profile_scope!("Gather meshes");
+            walk_node_graph(&scene, root, root_trs, &mut meshes, &mut primitives);
+        }
+
+        let mut materials: Vec<_> = scene
+            .materials
+            .iter()
+            .enumerate()
+            .map(|(index, material)| material_description(material, index, path))
+            .collect();
+
+        // Points and lines have no area to render, so only emissive ones are kept, as lights
+        let mut markers = light_markers(&scene, root_trs);
+        let candidates = markers.len() + primitives.len();
+        for (shape, material) in primitives {
+            let Some(material) = materials.get(material as usize) else { continue };
+            if material.emissive != Vec3::ZERO {
+                markers.push(Marker { name: material.name.clone(), shape, intensity: material.emissive });
+            }
+        }
+        if markers.len() < candidates {
+            tracing::warn!("Skipped {} point and line primitives without emissive materials.", candidates - markers.len());
+        }
+        add_markers(&mut meshes, &mut materials, &markers);
+
+        // Assimp cameras are not imported, new scenes are framed automatically instead
+        Some(Self { meshes, materials, camera: None })
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.meshes.iter().map(|mesh| mesh.triangles.len()).sum()
+    }
+}
@@ -0,0 +1,332 @@
+use glam::{Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+use shared_structs::{TracingConfig, MAX_SECTION_PLANES};
+use std::path::{Path, PathBuf};
+
+use crate::{scene::{CameraDescription, CsgOperation, MaterialDescription, MaterialTransforms, MeshDescription, PatternDescription, SceneDescription, TextureTransform}, sdf::{SdfDescription, SdfShape}, solar::SolarDescription};
+
+// A render job in a single text file, in RON or JSON depending on the extension. It references a
+// model, and changes whatever should differ from how the model imports, so the file can be
+// committed next to the model and rendered the same way again. Paths are relative to the file.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneFile {
+    pub scene: PathBuf,
+    pub scale: Option<f32>, // On top of the units in the model, and the scale set in the GUI
+    pub materials: Vec<MaterialOverride>,
+    pub sdfs: Vec<SdfInstance>, // Shapes added to the model
+    pub camera: Option<CameraDescription>, // The scene is framed automatically when this is missing
+    pub environment: EnvironmentDescription,
+    pub render: RenderDescription,
+}
+
+// Replaces properties of every material with the given name. Anything left out keeps its imported value.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaterialOverride {
+    pub name: String,
+    pub albedo: Option<Vec4>,
+    pub emissive: Option<Vec3>,
+    pub metallic: Option<f32>,
+    pub roughness: Option<f32>,
+    pub double_sided: Option<bool>,
+    pub albedo_pattern: Option<PatternDescription>,
+    pub roughness_pattern: Option<PatternDescription>,
+    pub metallic_pattern: Option<PatternDescription>,
+    pub texture_transform: Option<TextureTransform>, // Replaces the UV transform of every textured channel
+    pub csg: Option<CsgOperation>, // Of the closed meshes using it, see scene::CsgOperation
+}
+
+// A signed distance field added to the model. It uses the material with the given name, which is
+// added as a plain white one if the model has none, so `materials` can override it like any other.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SdfInstance {
+    pub shape: SdfShape,
+    pub position: Vec3,
+    pub material: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnvironmentDescription {
+    pub skybox: Option<PathBuf>, // The procedural sky is used when this is missing
+    pub clamp: Option<f32>, // Skybox values above this are turned into a directional light
+    pub sun: Option<SunDescription>,
+    pub solar: Option<SolarDescription>, // Places the sun by location and time instead, replacing `sun`
+    pub background_blur: Option<f32>, // Mip level of the skybox seen behind the scene, lighting is unaffected
+    pub sky_weight: Option<f32>, // How much of the procedural sky is added on top of the skybox
+    pub haze: Option<f32>, // Density of the air between the camera and the scene, relative to real air
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct SunDescription {
+    pub direction: Vec3, // Towards the sun
+    pub intensity: f32,
+}
+
+// Settings left out keep whatever they were. The GUI renders at the size of its window, so
+// width and height only apply to renders without one.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderDescription {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub samples: Option<u32>, // Stop after this many samples
+    pub min_bounces: Option<u32>,
+    pub max_bounces: Option<u32>,
+    pub nee: Option<u32>, // 0 for none, 1 for NEE, 2 for NEE with MIS
+    pub seed: Option<u32>,
+    pub blue_noise: Option<bool>,
+    pub sections: Option<Vec<SectionDescription>>, // Up to MAX_SECTION_PLANES, cutting away what the camera sees
+    pub section_fill: Option<Vec3>, // Flat color the cuts through closed meshes are filled with, left open if missing
+}
+
+// Camera rays pass through everything on the side of the plane the normal points to
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct SectionDescription {
+    pub normal: Vec3,
+    pub point: Vec3, // Anywhere on the plane
+}
+
+impl SectionDescription {
+    pub fn to_plane(self) -> Vec4 {
+        let normal = self.normal.normalize_or_zero();
+        normal.extend(-normal.dot(self.point))
+    }
+
+    pub fn from_plane(plane: Vec4) -> Self {
+        Self { normal: plane.truncate(), point: plane.truncate() * -plane.w }
+    }
+}
+
+pub fn is_scene_file(path: &str) -> bool {
+    let extension = Path::new(path).extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_lowercase();
+    extension == "ron" || extension == "json"
+}
+
+impl SceneFile {
+    pub fn parse(text: &str, json: bool) -> Result<Self, String> {
+        if json {
+            serde_json::from_str(text).map_err(|err| err.to_string())
+        } else {
+            ron::from_str(text).map_err(|err| err.to_string())
+        }
+    }
+
+    // Reads the file, and makes its paths absolute so they don't depend on the working directory
+    pub fn load(path: &str) -> Option<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| tracing::error!("Failed to read scene file '{}': {}", path, err))
+            .ok()?;
+        let json = path.to_lowercase().ends_with(".json");
+        let mut file = Self::parse(&text, json)
+            .map_err(|err| tracing::error!("Failed to parse scene file '{}': {}", path, err))
+            .ok()?;
+
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        file.scene = dir.join(&file.scene);
+        if let Some(skybox) = file.environment.skybox.as_mut() {
+            *skybox = dir.join(&skybox);
+        }
+        for instance in file.sdfs.iter_mut() {
+            if let SdfShape::Grid { path, .. } = &mut instance.shape {
+                *path = dir.join(&path);
+            }
+        }
+        Some(file)
+    }
+
+    pub fn skybox(&self) -> Option<String> {
+        self.environment.skybox.as_ref().map(|path| path.to_string_lossy().into_owned())
+    }
+
+    // Imports the referenced model, adds the SDFs, and applies the material overrides to it
+    pub fn import(&self, scale: f32) -> Option<SceneDescription> {
+        let mut description = SceneDescription::import(&self.scene.to_string_lossy(), scale * self.scale.unwrap_or(1.0))?;
+        self.add_sdfs(&mut description);
+        self.apply_materials(&mut description);
+        if self.camera.is_some() {
+            description.camera = self.camera;
+        }
+        Some(description)
+    }
+
+    pub fn add_sdfs(&self, description: &mut SceneDescription) {
+        for instance in self.sdfs.iter() {
+            let material = match description.materials.iter().position(|material| material.name == instance.material) {
+                Some(index) => index,
+                None => {
+                    description.materials.push(MaterialDescription { name: instance.material.clone(), roughness: 1.0, ..Default::default() });
+                    description.materials.len() - 1
+                }
+            };
+            description.meshes.push(MeshDescription {
+                sdf: Some(SdfDescription { shape: instance.shape.clone(), position: instance.position }),
+                material: material as u32,
+                ..Default::default()
+            });
+        }
+    }
+
+    pub fn apply_materials(&self, description: &mut SceneDescription) {
+        for material_override in self.materials.iter() {
+            let mut found = false;
+            for material in description.materials.iter_mut().filter(|material| material.name == material_override.name) {
+                found = true;
+                if let Some(albedo) = material_override.albedo {
+                    material.albedo = albedo;
+                    material.textures.albedo = None;
+                }
+                if let Some(emissive) = material_override.emissive {
+                    material.emissive = emissive;
+                }
+                if let Some(metallic) = material_override.metallic {
+                    material.metallic = metallic;
+                    material.textures.metallic = None;
+                }
+                if let Some(roughness) = material_override.roughness {
+                    material.roughness = roughness;
+                    material.textures.roughness = None;
+                }
+                if let Some(double_sided) = material_override.double_sided {
+                    material.double_sided = double_sided;
+                }
+                if let Some(pattern) = material_override.albedo_pattern {
+                    material.patterns.albedo = Some(pattern);
+                }
+                if let Some(pattern) = material_override.roughness_pattern {
+                    material.patterns.roughness = Some(pattern);
+                }
+                if let Some(pattern) = material_override.metallic_pattern {
+                    material.patterns.metallic = Some(pattern);
+                }
+                if let Some(transform) = material_override.texture_transform {
+                    material.transforms = MaterialTransforms {
+                        albedo: Some(transform),
+                        metallic: Some(transform),
+                        roughness: Some(transform),
+                        normal: Some(transform),
+                    };
+                }
+                if let Some(csg) = material_override.csg {
+                    material.csg = Some(csg);
+                }
+            }
+            if !found {
+                tracing::warn!("Scene file overrides material '{}', which the scene doesn't have.", material_override.name);
+            }
+        }
+    }
+
+    // The opposite of apply_settings, for saving what is being rendered as a scene file
+    pub fn capture_settings(&mut self, config: &TracingConfig, skybox: Option<&str>, clamp: Option<f32>) {
+        self.camera = Some(CameraDescription {
+            position: config.camera.cam_position.truncate(),
+            rotation: config.camera.cam_rotation.truncate().truncate(),
+        });
+        self.environment = EnvironmentDescription {
+            skybox: skybox.map(PathBuf::from),
+            clamp,
+            sun: Some(SunDescription {
+                direction: config.environment.sun_direction.truncate(),
+                intensity: config.environment.sun_direction.w,
+            }),
+            solar: None,
+            background_blur: Some(config.environment.background_lod),
+            sky_weight: Some(config.environment.sky_weight),
+            haze: Some(config.environment.haze_density),
+        };
+        self.render = RenderDescription {
+            width: Some(config.render.width),
+            height: Some(config.render.height),
+            samples: self.render.samples,
+            min_bounces: Some(config.render.min_bounces),
+            max_bounces: Some(config.render.max_bounces),
+            nee: Some(config.render.nee),
+            seed: Some(config.render.seed),
+            blue_noise: Some(config.render.use_blue_noise != 0),
+            sections: Some(config.render.section_planes[..config.render.section_plane_count as usize].iter().map(|plane| SectionDescription::from_plane(*plane)).collect()),
+            section_fill: (config.render.section_color.w != 0.0).then(|| config.render.section_color.truncate()),
+        };
+    }
+
+    // Paths are written relative to the file where possible, so the file and its assets can move together
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let absolute = |file: &Path| std::fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        let dir = absolute(Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new(".")));
+        let relative = |file: &Path| {
+            let file = absolute(file);
+            file.strip_prefix(&dir).map(Path::to_path_buf).unwrap_or(file)
+        };
+        let mut file = self.clone();
+        file.scene = relative(&self.scene);
+        file.environment.skybox = self.environment.skybox.as_deref().map(relative);
+
+        let text = if path.to_lowercase().ends_with(".json") {
+            serde_json::to_string_pretty(&file).map_err(|err| err.to_string())?
+        } else {
+            ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::default()).map_err(|err| err.to_string())?
+        };
+        std::fs::write(path, text).map_err(|err| err.to_string())
+    }
+
+    // Applies the camera, sun and render settings. The skybox is loaded by the renderer, see skybox().
+    pub fn apply_settings(&self, config: &mut TracingConfig) {
+        if let Some(camera) = self.camera {
+            config.camera.cam_position = camera.position.extend(config.camera.cam_position.w);
+            config.camera.cam_rotation = Vec4::new(camera.rotation.x, camera.rotation.y, 0.0, 0.0);
+        }
+        config.environment.has_skybox = self.environment.skybox.is_some() as u32;
+        if let Some(sun) = self.environment.sun {
+            config.environment.sun_direction = sun.direction.normalize_or_zero().extend(sun.intensity);
+        }
+        if let Some(solar) = self.environment.solar {
+            config.environment.sun_direction = solar.sun();
+        }
+        if let Some(blur) = self.environment.background_blur {
+            config.environment.background_lod = blur.max(0.0);
+        }
+        if let Some(weight) = self.environment.sky_weight {
+            config.environment.sky_weight = weight.max(0.0);
+        }
+        if let Some(haze) = self.environment.haze {
+            config.environment.haze_density = haze.max(0.0);
+        }
+
+        let render = &self.render;
+        if let Some(width) = render.width {
+            config.render.width = width;
+        }
+        if let Some(height) = render.height {
+            config.render.height = height;
+        }
+        if let Some(min_bounces) = render.min_bounces {
+            config.render.min_bounces = min_bounces;
+        }
+        if let Some(max_bounces) = render.max_bounces {
+            config.render.max_bounces = max_bounces;
+        }
+        if let Some(nee) = render.nee {
+            config.render.nee = nee.min(2);
+        }
+        if let Some(seed) = render.seed {
+            config.render.seed = seed;
+        }
+        if let Some(blue_noise) = render.blue_noise {
+            config.render.use_blue_noise = blue_noise as u32;
+        }
+        if let Some(sections) = render.sections.as_ref() {
+            if sections.len() > MAX_SECTION_PLANES {
+                tracing::warn!("Scene file has {} section planes, only the first {} are used.", sections.len(), MAX_SECTION_PLANES);
+            }
+            let planes = sections.iter().map(|section| section.to_plane()).filter(|plane| plane.truncate() != Vec3::ZERO).take(MAX_SECTION_PLANES).collect::<Vec<_>>();
+            config.render.section_planes[..planes.len()].copy_from_slice(&planes);
+            config.render.section_plane_count = planes.len() as u32;
+            config.render.section_color.w = 0.0;
+        }
+        if let Some(fill) = render.section_fill {
+            config.render.section_color = fill.extend(1.0);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use glam::{UVec3, UVec4, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+use shared_structs::{box_corners, is_sdf, PerVertexData, SDF_BOX, SDF_CAPSULE, SDF_GRID, SDF_SPHERE, SDF_TORUS};
+use std::{collections::BTreeSet, path::{Path, PathBuf}};
+
+// Shapes the kernels sphere trace, for what triangles can't express well. Sizes are in scene units.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum SdfShape {
+    Sphere { radius: f32 },
+    Box { half_extents: Vec3, rounding: f32 }, // Rounding eats into the box rather than growing it
+    Torus { radius: f32, thickness: f32 }, // Lying flat, around Y
+    Capsule { half_length: f32, radius: f32 }, // Along Y
+    Grid { path: PathBuf, resolution: UVec3, size: Vec3 }, // Baked distances, see load_grid
+}
+
+// A signed distance field in place of triangles, centered on `position`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SdfDescription {
+    pub shape: SdfShape,
+    pub position: Vec3,
+}
+
+impl SdfDescription {
+    fn half_extents(&self) -> Vec3 {
+        match &self.shape {
+            SdfShape::Sphere { radius } => Vec3::splat(*radius),
+            SdfShape::Box { half_extents, .. } => *half_extents,
+            SdfShape::Torus { radius, thickness } => Vec3::new(radius + thickness, *thickness, radius + thickness),
+            SdfShape::Capsule { half_length, radius } => Vec3::new(*radius, half_length + radius, *radius),
+            SdfShape::Grid { size, .. } => *size * 0.5,
+        }
+    }
+
+    // Corners whose bounds are the shape's, see shared_structs::PRIMITIVE_SDF
+    pub fn corners(&self) -> [Vec3; 3] {
+        let half_extents = self.half_extents().max(Vec3::splat(f32::EPSILON));
+        box_corners(self.position - half_extents, self.position + half_extents)
+    }
+
+    // Normal and tangent of the first corner, see shared_structs::SDF_SPHERE. Grids are given
+    // by their index until they are placed, see append_grids.
+    pub fn encode(&self, grid: usize) -> (Vec4, Vec4) {
+        match &self.shape {
+            SdfShape::Sphere { radius } => (Vec4::new(SDF_SPHERE as f32, *radius, 0.0, 0.0), Vec4::ZERO),
+            SdfShape::Box { half_extents, rounding } => {
+                let rounding = rounding.clamp(0.0, half_extents.min_element());
+                (Vec4::new(SDF_BOX as f32, half_extents.x, half_extents.y, half_extents.z), Vec4::new(rounding, 0.0, 0.0, 0.0))
+            }
+            SdfShape::Torus { radius, thickness } => (Vec4::new(SDF_TORUS as f32, *radius, *thickness, 0.0), Vec4::ZERO),
+            SdfShape::Capsule { half_length, radius } => (Vec4::new(SDF_CAPSULE as f32, *half_length, *radius, 0.0), Vec4::ZERO),
+            SdfShape::Grid { resolution, .. } => {
+                let resolution = resolution.as_vec3();
+                (Vec4::new(SDF_GRID as f32, resolution.x, resolution.y, resolution.z), Vec4::new(0.0, grid as f32, 0.0, 0.0))
+            }
+        }
+    }
+}
+
+// Raw little-endian f32 distances in scene units, X varying fastest, then Y, then Z. Samples sit
+// on a lattice reaching from one corner of the grid's box to the other.
+pub fn load_grid(path: &Path, resolution: UVec3) -> Result<Vec<f32>, String> {
+    if resolution.min_element() < 2 {
+        return Err("SDF grids need at least 2 samples along each axis".to_string());
+    }
+    let data = std::fs::read(path).map_err(|err| err.to_string())?;
+    let expected = (resolution.x * resolution.y * resolution.z) as usize;
+    if data.len() != expected * 4 {
+        return Err(format!("Expected {} distances for a {}x{}x{} grid, found {} bytes", expected, resolution.x, resolution.y, resolution.z, data.len()));
+    }
+    Ok(data.chunks_exact(4).map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).collect())
+}
+
+// Samples a distance function, given around the center of the grid, in the layout load_grid reads
+pub fn bake_grid(resolution: UVec3, size: Vec3, distance: impl Fn(Vec3) -> f32) -> Vec<f32> {
+    let cells = (resolution.max(UVec3::splat(2)) - 1).as_vec3();
+    let mut distances = Vec::with_capacity((resolution.x * resolution.y * resolution.z) as usize);
+    for z in 0..resolution.z {
+        for y in 0..resolution.y {
+            for x in 0..resolution.x {
+                let position = (UVec3::new(x, y, z).as_vec3() / cells - 0.5) * size;
+                distances.push(distance(position));
+            }
+        }
+    }
+    distances
+}
+
+pub fn save_grid(path: &Path, distances: &[f32]) -> Result<(), String> {
+    let data = distances.iter().flat_map(|distance| distance.to_le_bytes()).collect::<Vec<_>>();
+    std::fs::write(path, data).map_err(|err| format!("Failed to save '{}': {}", path.display(), err))
+}
+
+// Packs the distances of each grid after every vertex, 4 per vertex, where reordering vertices
+// can't move them, and points the SDFs using them at where they ended up
+pub fn append_grids(per_vertex_data: &mut Vec<PerVertexData>, indices: &[UVec4], grids: &[Vec<f32>]) {
+    let mut offsets = Vec::with_capacity(grids.len());
+    for grid in grids {
+        offsets.push(per_vertex_data.len());
+        per_vertex_data.extend(grid.chunks(4).map(|chunk| {
+            let mut packed = [0.0; 4];
+            packed[..chunk.len()].copy_from_slice(chunk);
+            PerVertexData { vertex: Vec4::from_array(packed), ..Default::default() }
+        }));
+    }
+    // Identical SDFs may share their first corner, which must only be pointed once
+    let first_corners = indices.iter().filter(|triangle| is_sdf(**triangle)).map(|triangle| triangle.x as usize).collect::<BTreeSet<_>>();
+    for corner in first_corners {
+        let data = &mut per_vertex_data[corner];
+        if data.normal.x as u32 == SDF_GRID {
+            data.tangent.y = offsets[data.tangent.y as usize] as f32;
+        }
+    }
+}
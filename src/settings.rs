@@ -0,0 +1,79 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+const APP_DIRECTORY: &str = "rustic";
+const SETTINGS_FILE: &str = "settings.ron";
+
+// Where a panel lives and whether it is open. Names are kept as text, so a panel that no
+// longer exists only loses its own entry rather than the whole file.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PanelLayout {
+    pub panel: String,
+    pub dock: String,
+    pub open: bool,
+}
+
+// Everything the GUI remembers between runs, in one RON file in the per-user config directory.
+// Missing fields keep their defaults, so files written by older versions still load.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub layout: Vec<PanelLayout>,
+    pub shortcuts: BTreeMap<String, String>, // Action to shortcut, like "SaveImage": "Ctrl+B", or "None" to unbind it
+    pub recent_scenes: Vec<String>,          // Newest first
+    pub recent_skyboxes: Vec<String>,
+    pub hdr_output: bool, // Takes effect on the next start, when the surface is created
+}
+
+// The per-user config directory of the platform. Only the environment is consulted, so no
+// extra dependency is needed for it.
+fn config_directory() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    let base = if cfg!(target_os = "windows") {
+        var("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        var("XDG_CONFIG_HOME").or_else(|| var("HOME").map(|home| home.join(".config")))
+    };
+    base.map(|base| base.join(APP_DIRECTORY))
+}
+
+pub fn settings_path() -> Option<PathBuf> {
+    config_directory().map(|directory| directory.join(SETTINGS_FILE))
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let Some(path) = settings_path() else {
+            return Self::default();
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            tracing::warn!("Ignoring invalid settings file '{}': {}", path.display(), err);
+            Self::default()
+        })
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = settings_path().ok_or("no config directory, set HOME or APPDATA")?;
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory).map_err(|err| err.to_string())?;
+        }
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(|err| err.to_string())?;
+        std::fs::write(path, text).map_err(|err| err.to_string())
+    }
+
+    // Changes part of the settings on disk, keeping whatever else is stored there. Each part of
+    // the GUI owns its own fields, so they never have to share a copy of the whole file.
+    pub fn update(change: impl FnOnce(&mut Self)) {
+        let mut settings = Self::load();
+        change(&mut settings);
+        if let Err(err) = settings.save() {
+            tracing::warn!("Failed to save settings: {}", err);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use egui::Key;
+
+use crate::{layout::Panel, settings::Settings};
+
+// Keys that can be bound. Letters used for camera movement are left out, since
+// they are held down while flying around.
+const BINDABLE_KEYS: [Key; 42] = [
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9, Key::F10, Key::F11, Key::F12,
+    Key::B, Key::C, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M, Key::N, Key::O, Key::P,
+    Key::R, Key::T, Key::U, Key::V, Key::X, Key::Y, Key::Z,
+    Key::Num0, Key::Num1, Key::Num2, Key::Num3, Key::Num4, Key::Num5, Key::Num6, Key::Num7, Key::Num8, Key::Num9,
+];
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    CommandPalette,
+    ToggleRender,
+    SelectScene,
+    SaveImage,
+    #[cfg(feature = "oidn")]
+    ToggleDenoise,
+    SwitchDevice,
+    SelectSkybox,
+    ResetSkybox,
+    SelectReference,
+    ClearReference,
+    FrameScene,
+    TogglePanel(Panel),
+}
+
+impl Action {
+    pub fn all() -> Vec<Action> {
+        let mut actions = vec![
+            Action::CommandPalette,
+            Action::ToggleRender,
+            Action::SelectScene,
+            Action::SaveImage,
+            #[cfg(feature = "oidn")]
+            Action::ToggleDenoise,
+            Action::SwitchDevice,
+            Action::SelectSkybox,
+            Action::ResetSkybox,
+            Action::SelectReference,
+            Action::ClearReference,
+            Action::FrameScene,
+        ];
+        actions.extend(Panel::ALL.into_iter().filter(|panel| panel.closable()).map(Action::TogglePanel));
+        actions
+    }
+
+    pub fn title(self) -> String {
+        match self {
+            Action::CommandPalette => "Command palette".to_string(),
+            Action::ToggleRender => "Start/stop render".to_string(),
+            Action::SelectScene => "Select scene".to_string(),
+            Action::SaveImage => "Save image".to_string(),
+            #[cfg(feature = "oidn")]
+            Action::ToggleDenoise => "Toggle denoising".to_string(),
+            Action::SwitchDevice => "Switch compute device".to_string(),
+            Action::SelectSkybox => "Select skybox".to_string(),
+            Action::ResetSkybox => "Reset skybox".to_string(),
+            Action::SelectReference => "Select reference".to_string(),
+            Action::ClearReference => "Clear reference".to_string(),
+            Action::FrameScene => "Frame scene".to_string(),
+            Action::TogglePanel(panel) => format!("Show/hide {} panel", panel.title().to_lowercase()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Shortcut {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub key: Key,
+}
+
+impl Shortcut {
+    const fn new(key: Key) -> Self {
+        Self { ctrl: false, shift: false, alt: false, key }
+    }
+
+    const fn ctrl(key: Key) -> Self {
+        Self { ctrl: true, ..Self::new(key) }
+    }
+
+    // Parses shortcuts written like "Ctrl+Shift+P"
+    fn parse(text: &str) -> Option<Self> {
+        let mut shortcut = Self::new(Key::F1);
+        let mut key = None;
+        for part in text.split('+') {
+            match part {
+                "Ctrl" => shortcut.ctrl = true,
+                "Shift" => shortcut.shift = true,
+                "Alt" => shortcut.alt = true,
+                _ => key = BINDABLE_KEYS.into_iter().find(|k| format!("{:?}", k) == part),
+            }
+        }
+        shortcut.key = key?;
+        Some(shortcut)
+    }
+
+    // The first twelve bindable keys are F1-F12, the others type characters
+    fn is_text(&self) -> bool {
+        !self.ctrl && !self.alt && !BINDABLE_KEYS[..12].contains(&self.key)
+    }
+
+    pub fn pressed(&self, input: &egui::InputState) -> bool {
+        input.events.iter().any(|event| match event {
+            egui::Event::Key { key, pressed: true, modifiers, .. } => {
+                *key == self.key
+                    && (modifiers.ctrl || modifiers.mac_cmd) == self.ctrl
+                    && modifiers.shift == self.shift
+                    && modifiers.alt == self.alt
+            }
+            _ => false,
+        })
+    }
+}
+
+impl std::fmt::Display for Shortcut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.ctrl {
+            write!(f, "Ctrl+")?;
+        }
+        if self.shift {
+            write!(f, "Shift+")?;
+        }
+        if self.alt {
+            write!(f, "Alt+")?;
+        }
+        write!(f, "{:?}", self.key)
+    }
+}
+
+// Key bindings for actions. The defaults can be overridden in the shortcuts of the settings
+// file, mapping an action to its shortcut, or to "None" to unbind it.
+pub struct Shortcuts {
+    bindings: HashMap<Action, Shortcut>,
+}
+
+impl Default for Shortcuts {
+    fn default() -> Self {
+        let bindings = HashMap::from([
+            (Action::CommandPalette, Shortcut::ctrl(Key::P)),
+            (Action::ToggleRender, Shortcut::new(Key::F5)),
+            (Action::SelectScene, Shortcut::ctrl(Key::O)),
+            (Action::SaveImage, Shortcut::new(Key::F12)),
+            #[cfg(feature = "oidn")]
+            (Action::ToggleDenoise, Shortcut::new(Key::F9)),
+            (Action::SwitchDevice, Shortcut::new(Key::F8)),
+            (Action::FrameScene, Shortcut::new(Key::F)),
+        ]);
+        Self { bindings }
+    }
+}
+
+impl Shortcuts {
+    pub fn load() -> Self {
+        let mut shortcuts = Self::default();
+        let actions = Action::all();
+        for (action, shortcut) in Settings::load().shortcuts {
+            let Some(action) = actions.iter().copied().find(|a| format!("{:?}", a) == action) else {
+                tracing::warn!("Ignoring shortcut for unknown action '{}'.", action);
+                continue;
+            };
+            if shortcut == "None" {
+                shortcuts.bindings.remove(&action);
+            } else if let Some(parsed) = Shortcut::parse(&shortcut) {
+                shortcuts.bindings.insert(action, parsed);
+            } else {
+                tracing::warn!("Ignoring invalid shortcut '{}' for {:?}.", shortcut, action);
+            }
+        }
+        shortcuts
+    }
+
+    pub fn get(&self, action: Action) -> Option<Shortcut> {
+        self.bindings.get(&action).copied()
+    }
+
+    // Actions whose shortcut was pressed this frame. While typing, plain letter and
+    // number keys are text rather than shortcuts.
+    pub fn triggered(&self, input: &egui::InputState, typing: bool) -> Vec<Action> {
+        self.bindings
+            .iter()
+            .filter(|(_, shortcut)| shortcut.pressed(input) && !(typing && shortcut.is_text()))
+            .map(|(action, _)| *action)
+            .collect()
+    }
+}
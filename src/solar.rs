@@ -0,0 +1,103 @@
+use glam::{Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+
+// Degrees below the horizon at which the sun has fully faded out, the end of civil twilight
+const TWILIGHT_DEGREES: f32 = 6.0;
+
+// A place and a moment, from which the sun is placed with NOAA's solar position equations.
+// Scenes are assumed to face north along -Z, with east along +X.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SolarDescription {
+    pub latitude: f32, // Degrees, north is positive
+    pub longitude: f32, // Degrees, east is positive
+    pub year: i32,
+    pub month: u32, // 1 to 12
+    pub day: u32, // 1 to 31
+    pub hour: f32, // Local time, 13.5 is half past one in the afternoon
+    pub utc_offset: f32, // Hours ahead of UTC of the local time
+    pub intensity: f32, // Sun intensity while it is above the horizon
+}
+
+impl Default for SolarDescription {
+    fn default() -> Self {
+        Self {
+            latitude: 51.48,
+            longitude: 0.0,
+            year: 2024,
+            month: 6,
+            day: 21,
+            hour: 12.0,
+            utc_offset: 0.0,
+            intensity: 15.0,
+        }
+    }
+}
+
+// Where the sun is in the sky, in radians. Azimuth is clockwise from north.
+#[derive(Clone, Copy, Debug)]
+pub struct SunPosition {
+    pub elevation: f32,
+    pub azimuth: f32,
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+impl SolarDescription {
+    // 1 for the first of January
+    pub fn day_of_year(&self) -> u32 {
+        const DAYS_BEFORE_MONTH: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+        let month = self.month.clamp(1, 12);
+        let leap_day = (month > 2 && is_leap_year(self.year)) as u32;
+        DAYS_BEFORE_MONTH[month as usize - 1] + self.day.clamp(1, 31) + leap_day
+    }
+
+    pub fn position(&self) -> SunPosition {
+        let days_in_year = if is_leap_year(self.year) { 366.0 } else { 365.0 };
+        let gamma = 2.0 * std::f32::consts::PI / days_in_year * (self.day_of_year() as f32 - 1.0 + (self.hour - 12.0) / 24.0);
+
+        // Equation of time in minutes, and declination in radians
+        let equation_of_time = 229.18
+            * (0.000075 + 0.001868 * gamma.cos() - 0.032077 * gamma.sin() - 0.014615 * (2.0 * gamma).cos() - 0.040849 * (2.0 * gamma).sin());
+        let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin() - 0.006758 * (2.0 * gamma).cos()
+            + 0.000907 * (2.0 * gamma).sin()
+            - 0.002697 * (3.0 * gamma).cos()
+            + 0.00148 * (3.0 * gamma).sin();
+
+        // True solar time in minutes, and the hour angle, which is zero at solar noon
+        let time_offset = equation_of_time + 4.0 * self.longitude - 60.0 * self.utc_offset;
+        let solar_time = self.hour * 60.0 + time_offset;
+        let hour_angle = (solar_time / 4.0 - 180.0).to_radians();
+
+        let latitude = self.latitude.to_radians();
+        let sin_elevation = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+        let elevation = sin_elevation.clamp(-1.0, 1.0).asin();
+        // Measured from south, then turned around to be from north
+        let azimuth = hour_angle.sin().atan2(hour_angle.cos() * latitude.sin() - declination.tan() * latitude.cos()) + std::f32::consts::PI;
+        SunPosition { elevation, azimuth }
+    }
+
+    // Towards the sun, in scene space
+    pub fn direction(&self) -> Vec3 {
+        let SunPosition { elevation, azimuth } = self.position();
+        Vec3::new(azimuth.sin() * elevation.cos(), elevation.sin(), -azimuth.cos() * elevation.cos())
+    }
+
+    // The atmosphere already reddens and dims a low sun, this only fades it out through twilight
+    pub fn sun_intensity(&self) -> f32 {
+        let elevation = self.position().elevation.to_degrees();
+        self.intensity * ((elevation + TWILIGHT_DEGREES) / TWILIGHT_DEGREES).clamp(0.0, 1.0)
+    }
+
+    // As stored in EnvironmentSettings::sun_direction
+    pub fn sun(&self) -> Vec4 {
+        self.direction().extend(self.sun_intensity())
+    }
+
+    // The same place and day at another time
+    pub fn at_hour(&self, hour: f32) -> Self {
+        Self { hour, ..*self }
+    }
+}
@@ -0,0 +1,142 @@
+use glam::{Vec2, Vec3};
+use image::GrayImage;
+use kernels::intersection::BVHReference;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use shared_structs::HIDDEN_FROM_SHADOWS;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    asset::{load_description, LoadProgress, World},
+    batch::{HeadlessScene, RenderProgress},
+    ground::GroundSettings,
+    lightmap::{cosine_direction, dilate, rasterize, DILATION_PASSES},
+    scene::MeshDescription,
+    tangents::vertex_normals,
+    trace::scene_ray_offset,
+};
+
+const AO_DISTANCE_SCALE: f32 = 0.1; // Of the scene's diagonal, when no distance is given
+const CURVATURE_PERCENTILE: f32 = 0.98; // Curvature that maps to black and white, so a few sharp corners don't flatten the rest
+
+// Mean curvature at each vertex, from how the normals bend along its edges: positive on convex
+// parts, negative in creases. Vertices are only connected through shared indices, so hard edges
+// that split vertices read as flat.
+pub fn vertex_curvature(mesh: &MeshDescription, normals: &[Vec3]) -> Vec<f32> {
+    let mut sums = vec![0.0; mesh.positions.len()];
+    let mut counts = vec![0u32; mesh.positions.len()];
+    for triangle in &mesh.triangles {
+        let [a, b, c] = triangle.map(|index| index as usize);
+        for (from, to) in [(a, b), (b, c), (c, a)] {
+            let edge = mesh.positions[to] - mesh.positions[from];
+            let length_squared = edge.length_squared();
+            if length_squared <= 0.0 {
+                continue;
+            }
+            let curvature = (normals[to] - normals[from]).dot(edge) / length_squared;
+            for vertex in [from, to] {
+                sums[vertex] += curvature;
+                counts[vertex] += 1;
+            }
+        }
+    }
+    sums.iter().zip(counts).map(|(sum, count)| if count > 0 { sum / count as f32 } else { 0.0 }).collect()
+}
+
+// Maps curvature to grey, with flat surfaces at half intensity
+fn curvature_scale(curvature: &[f32]) -> f32 {
+    let mut magnitudes = curvature.iter().map(|value| value.abs()).filter(|value| value.is_finite()).collect::<Vec<_>>();
+    if magnitudes.is_empty() {
+        return 1.0;
+    }
+    magnitudes.sort_by(f32::total_cmp);
+    let scale = magnitudes[((magnitudes.len() - 1) as f32 * CURVATURE_PERCENTILE) as usize];
+    if scale > 0.0 { scale } else { 1.0 }
+}
+
+fn save_gray(values: &[Vec3], size: u32, path: &Path) -> Result<(), String> {
+    let data = values.iter().map(|value| (value.x.clamp(0.0, 1.0) * 255.0).round() as u8).collect();
+    let image = GrayImage::from_raw(size, size, data).ok_or("Invalid map")?;
+    image.save(path).map_err(|err| format!("Failed to save '{}': {}", path.display(), err))
+}
+
+// Bakes ambient occlusion and curvature of each mesh into maps laid out by its texture UVs, the
+// way texturing tools expect them. Occlusion counts the whole scene, without the ground.
+#[derive(Clone)]
+pub struct SurfaceMapBake {
+    pub scene: HeadlessScene,
+    pub resolution: u32, // Width and height of every map
+    pub ao_samples: u32, // Per texel
+    pub ao_distance: f32, // Hits further away than this don't occlude. 0 scales it to the scene.
+}
+
+impl SurfaceMapBake {
+    // Saves `<stem>_N_ao.png` and `<stem>_N_curvature.png` next to `path` for mesh N, and returns the paths it saved to
+    pub fn bake(&self, path: &Path, progress: &RenderProgress) -> Result<Vec<PathBuf>, String> {
+        let description = load_description(&self.scene.scene, self.scene.scene_scale).ok_or("Failed to load scene")?;
+        let world = World::from_description(&description, &GroundSettings::default(), false, false, &LoadProgress::default()).ok_or("Failed to build scene")?;
+        let (min, max) = world.statistics.bounds;
+        let distance = if self.ao_distance > 0.0 { self.ao_distance } else { (max - min).length() * AO_DISTANCE_SCALE };
+        let offset = scene_ray_offset(world.statistics.model_bounds);
+        let bvh = BVHReference { nodes: &world.bvh.nodes, min_t: offset };
+
+        let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_else(|| "maps".to_string());
+        let size = self.resolution;
+        let mut saved = Vec::new();
+        for (mesh_index, mesh) in description.meshes.iter().enumerate() {
+            if progress.is_cancelled() {
+                return Err("Cancelled".to_string());
+            }
+            if mesh.uvs.is_empty() {
+                tracing::warn!("Mesh {} has no UVs, so no maps were baked for it.", mesh_index);
+                progress.finish_one();
+                continue;
+            }
+
+            let normals = vertex_normals(mesh);
+            let surface = rasterize(mesh, &mesh.uvs, &normals, size);
+            let mut occlusion = surface
+                .par_iter()
+                .enumerate()
+                .map(|(texel_index, texel)| {
+                    let Some(texel) = texel else {
+                        return Vec3::ZERO;
+                    };
+                    let mut random = StdRng::seed_from_u64((mesh_index as u64) << 32 | texel_index as u64);
+                    let origin = texel.position + texel.normal * offset;
+                    let open = (0..self.ao_samples)
+                        .filter(|_| {
+                            let direction = cosine_direction(texel.normal, Vec2::new(random.gen(), random.gen()));
+                            !bvh.intersect_any(&world.per_vertex_buffer, &world.index_buffer, origin, direction, distance, HIDDEN_FROM_SHADOWS).hit
+                        })
+                        .count();
+                    Vec3::splat(open as f32 / self.ao_samples.max(1) as f32)
+                })
+                .collect::<Vec<_>>();
+
+            let curvature = vertex_curvature(mesh, &normals);
+            let scale = curvature_scale(&curvature);
+            let mut bends = surface
+                .iter()
+                .map(|texel| {
+                    let Some(texel) = texel else {
+                        return Vec3::ZERO;
+                    };
+                    let [a, b, c] = mesh.triangles[texel.triangle].map(|index| curvature[index as usize]);
+                    let value = a * texel.weights.x + b * texel.weights.y + c * texel.weights.z;
+                    Vec3::splat(0.5 + 0.5 * (value / scale).clamp(-1.0, 1.0))
+                })
+                .collect::<Vec<_>>();
+
+            let covered = surface.iter().map(Option::is_some).collect::<Vec<_>>();
+            for (name, texels) in [("ao", &mut occlusion), ("curvature", &mut bends)] {
+                dilate(texels, &mut covered.clone(), size, DILATION_PASSES);
+                let output = path.with_file_name(format!("{}_{}_{}.png", stem, mesh_index, name));
+                save_gray(texels, size, &output)?;
+                saved.push(output);
+            }
+            progress.finish_one();
+        }
+        Ok(saved)
+    }
+}
@@ -0,0 +1,93 @@
+use glam::{Vec2, Vec3};
+
+use crate::scene::MeshDescription;
+
+// Area weighted vertex normals, for meshes that come without any. Triangles are counter-clockwise
+// seen from the front, so the cross product of their edges points out of it.
+fn generate_normals(mesh: &MeshDescription) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; mesh.positions.len()];
+    for triangle in &mesh.triangles {
+        let [a, b, c] = triangle.map(|index| mesh.positions[index as usize]);
+        let weighted = (b - a).cross(c - a);
+        for &index in triangle {
+            normals[index as usize] += weighted;
+        }
+    }
+    normals.into_iter().map(|normal| normal.try_normalize().unwrap_or(Vec3::Y)).collect()
+}
+
+// The mesh's own normals, or generated ones if it has none, or they are broken
+pub fn vertex_normals(mesh: &MeshDescription) -> Vec<Vec3> {
+    let valid = mesh.normals.len() == mesh.positions.len() && mesh.normals.iter().all(|normal| normal.is_finite() && normal.length_squared() > 0.0);
+    if valid {
+        return mesh.normals.clone();
+    }
+    if !mesh.normals.is_empty() {
+        tracing::warn!("A mesh has missing or invalid normals, generating smooth ones.");
+    }
+    generate_normals(mesh)
+}
+
+struct MikkTSpaceMesh<'a> {
+    mesh: &'a MeshDescription,
+    normals: &'a [Vec3],
+    tangents: Vec<Vec3>,
+}
+
+impl mikktspace::Geometry for MikkTSpaceMesh<'_> {
+    fn num_faces(&self) -> usize {
+        self.mesh.triangles.len()
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.mesh.positions[self.mesh.triangles[face][vert] as usize].to_array()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.normals[self.mesh.triangles[face][vert] as usize].to_array()
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.mesh.uvs.get(self.mesh.triangles[face][vert] as usize).copied().unwrap_or(Vec2::ZERO).to_array()
+    }
+
+    // Vertices shared between faces get the same tangent, unless the mesh has UV seams that
+    // weren't split, in which case the last face wins
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.tangents[self.mesh.triangles[face][vert] as usize] = Vec3::new(tangent[0], tangent[1], tangent[2]);
+    }
+}
+
+// Gram-Schmidt against the normal. Tangents that are missing, or parallel to it, come out as None.
+fn orthonormalize(tangent: Vec3, normal: Vec3) -> Option<Vec3> {
+    (tangent - normal * normal.dot(tangent)).try_normalize()
+}
+
+// Tangents for normal mapping, orthonormal to `normals`. Imported tangents are kept if every one of
+// them is valid, otherwise the mesh gets MikkTSpace tangents, the way normal maps are usually baked.
+// Vertices MikkTSpace can't give a tangent, such as on meshes without UVs, get any perpendicular
+// direction, so normal mapping at least doesn't produce NaNs.
+pub fn vertex_tangents(mesh: &MeshDescription, normals: &[Vec3]) -> Vec<Vec3> {
+    if mesh.tangents.len() == mesh.positions.len() {
+        let imported = mesh.tangents.iter().zip(normals).map(|(&tangent, &normal)| orthonormalize(tangent, normal)).collect::<Option<Vec<_>>>();
+        if let Some(imported) = imported {
+            return imported;
+        }
+        tracing::warn!("A mesh has invalid tangents, generating them with MikkTSpace.");
+    }
+
+    let mut generated = MikkTSpaceMesh { mesh, normals, tangents: vec![Vec3::ZERO; mesh.positions.len()] };
+    if mesh.uvs.len() == mesh.positions.len() && !mikktspace::generate_tangents(&mut generated) {
+        tracing::warn!("MikkTSpace couldn't generate tangents for a mesh.");
+    }
+    generated
+        .tangents
+        .iter()
+        .zip(normals)
+        .map(|(&tangent, &normal)| orthonormalize(tangent, normal).unwrap_or_else(|| normal.any_orthonormal_vector()))
+        .collect()
+}
@@ -0,0 +1,206 @@
+use std::{collections::HashMap, path::PathBuf, sync::{mpsc, Arc}};
+
+use glam::Vec4;
+use image::{DynamicImage, GenericImage};
+use parking_lot::Mutex;
+use rayon::prelude::*;
+
+use crate::{asset::load_dynamic_image, atlas::{fit_texture, PackingRect}, trace::{DirtyFlags, TracingState}};
+
+// Decoded textures are kept up to this many bytes, so reopening a scene doesn't decode them again
+pub const TEXTURE_CACHE_CAPACITY: usize = 1024 * 1024 * 1024;
+
+lazy_static::lazy_static! {
+    pub static ref TEXTURE_CACHE: Mutex<TextureCache> = Mutex::new(TextureCache::new(TEXTURE_CACHE_CAPACITY));
+}
+
+// Where the data of a texture lives, before it is decoded
+#[derive(Clone)]
+pub enum TextureSource {
+    File(PathBuf), // Referenced by the scene, already resolved against the scene's directory
+    Embedded { key: String, bytes: Arc<Vec<u8>> }, // Compressed data stored in the scene file itself
+    Raw(Arc<DynamicImage>), // Uncompressed texels stored in the scene file itself
+}
+
+impl TextureSource {
+    fn cache_key(&self) -> Option<String> {
+        match self {
+            TextureSource::File(path) => Some(path.to_string_lossy().into_owned()),
+            TextureSource::Embedded { key, .. } => Some(key.clone()),
+            TextureSource::Raw(_) => None,
+        }
+    }
+
+    fn decode(&self) -> Option<DynamicImage> {
+        match self {
+            TextureSource::File(path) => load_dynamic_image(path.to_str()?),
+            TextureSource::Embedded { bytes, .. } => {
+                image::io::Reader::new(std::io::Cursor::new(bytes.as_slice())).with_guessed_format().ok()?.decode().ok()
+            }
+            TextureSource::Raw(image) => Some((**image).clone()),
+        }
+    }
+}
+
+// A texture waiting to be decoded into its slot in the atlas
+#[derive(Clone)]
+pub struct TextureJob {
+    pub source: TextureSource,
+    pub slot: PackingRect,
+    pub srgb: bool, // Albedo and emission are stored in gamma space, everything else in linear
+    pub name: &'static str,
+}
+
+// A decoded texture, already fitted to its slot
+pub struct LoadedTexture {
+    pub slot: PackingRect,
+    pub image: DynamicImage,
+}
+
+impl LoadedTexture {
+    pub fn write_to_image(&self, atlas: &mut DynamicImage) {
+        atlas.copy_from(&self.image, self.slot.x, self.slot.y).unwrap();
+    }
+
+    // The CPU backend keeps the atlas expanded to Vec4
+    pub fn write_to_cpu_buffer(&self, buffer: &mut [Vec4], atlas_width: u32) {
+        for (x, y, pixel) in self.image.to_rgb8().enumerate_pixels() {
+            let index = ((self.slot.y + y) * atlas_width + self.slot.x + x) as usize;
+            buffer[index] = Vec4::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32, 255.0) / 255.0;
+        }
+    }
+}
+
+struct CacheEntry {
+    image: Arc<DynamicImage>,
+    last_used: u64,
+}
+
+// Decoded textures by path, evicting the least recently used ones when over capacity
+pub struct TextureCache {
+    entries: HashMap<String, CacheEntry>,
+    bytes: usize,
+    capacity: usize,
+    clock: u64,
+}
+
+impl TextureCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            bytes: 0,
+            capacity,
+            clock: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<Arc<DynamicImage>> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = self.clock;
+        Some(entry.image.clone())
+    }
+
+    // Images larger than the whole cache aren't kept
+    pub fn insert(&mut self, key: String, image: Arc<DynamicImage>) {
+        let size = image.as_bytes().len();
+        if size > self.capacity {
+            return;
+        }
+        if let Some(old) = self.entries.remove(&key) {
+            self.bytes -= old.image.as_bytes().len();
+        }
+        while self.bytes + size > self.capacity {
+            let Some(oldest) = self.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(key, _)| key.clone()) else {
+                break;
+            };
+            let evicted = self.entries.remove(&oldest).unwrap();
+            self.bytes -= evicted.image.as_bytes().len();
+        }
+        self.clock += 1;
+        self.bytes += size;
+        self.entries.insert(key, CacheEntry { image, last_used: self.clock });
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
+}
+
+fn gamma_to_linear(image: &DynamicImage) -> DynamicImage {
+    let mut texture = image.to_rgb8();
+    for pixel in texture.iter_mut() {
+        *pixel = ((*pixel as f32 / 255.0).powf(2.2) * 255.0) as u8;
+    }
+    DynamicImage::ImageRgb8(texture)
+}
+
+// Decodes a texture, going through the cache, and fits it to its slot. If it can't be
+// decoded, the slot keeps its placeholder.
+pub fn resolve_texture(job: &TextureJob) -> Option<LoadedTexture> {
+    puffin::profile_function!();
+
+    let key = job.source.cache_key();
+    let cached = key.as_ref().and_then(|key| TEXTURE_CACHE.lock().get(key));
+    let image = match cached {
+        Some(image) => image,
+        None => {
+            // Decoded without holding the lock, so textures decode in parallel
+            let Some(image) = job.source.decode() else {
+                tracing::warn!("Failed to decode {} texture, falling back to the material's constant value.", job.name);
+                return None;
+            };
+            let image = Arc::new(image);
+            if let Some(key) = key {
+                TEXTURE_CACHE.lock().insert(key, image.clone());
+            }
+            image
+        }
+    };
+
+    // Albedo data is stored in gamma space, but we atlas it with all the other textures
+    // which are stored in linear. Therefore, we convert here.
+    let image = if job.srgb {
+        fit_texture(&gamma_to_linear(&image), &job.slot)
+    } else {
+        fit_texture(&image, &job.slot)
+    };
+    Some(LoadedTexture { slot: job.slot, image })
+}
+
+// Decodes textures in the background, sending each one to the render thread as it finishes.
+// Stops early if the render thread goes away, such as when another scene is opened.
+pub fn spawn_texture_loader(jobs: Vec<TextureJob>, state: Arc<TracingState>) -> mpsc::Receiver<LoadedTexture> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("Texture loader".to_string())
+        .spawn(move || {
+            let start = std::time::Instant::now();
+            let count = jobs.len();
+            let result = jobs.into_par_iter().try_for_each_with(sender, |sender, job| {
+                if let Some(texture) = resolve_texture(&job) {
+                    sender.send(texture).map_err(|_| ())?;
+                    state.mark_dirty(DirtyFlags::TEXTURES);
+                }
+                Ok(())
+            });
+            if result.is_ok() {
+                tracing::debug!("Loaded {} textures in {:?}", count, start.elapsed());
+            }
+        })
+        .expect("Failed to spawn texture loader thread.");
+    receiver
+}
@@ -0,0 +1,115 @@
+use std::fmt::Debug;
+
+use glam::{Mat3, Vec3};
+use serde::{Deserialize, Serialize};
+
+// CPU versions of the tonemapping operators in render.wgsl, used when exporting images.
+// Keep the two in sync.
+
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Tonemapping {
+    None,
+    Reinhard,
+    ACESNarkowicz,
+    ACESNarkowiczOverexposed,
+    ACESHill,
+    Neutral,
+    Uncharted,
+}
+
+impl Debug for Tonemapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Tonemapping::None => write!(f, "None"),
+            Tonemapping::Reinhard => write!(f, "Reinhard"),
+            Tonemapping::ACESNarkowicz => write!(f, "ACES (N)"),
+            Tonemapping::ACESNarkowiczOverexposed => write!(f, "ACES (N, O)"),
+            Tonemapping::ACESHill => write!(f, "ACES (H)"),
+            Tonemapping::Neutral => write!(f, "Neutral"),
+            Tonemapping::Uncharted => write!(f, "Uncharted"),
+        }
+    }
+}
+
+// Narkowicz ACES https://knarkowicz.wordpress.com/2016/01/06/aces-filmic-tone-mapping-curve/
+fn aces_narkowicz(x: Vec3) -> Vec3 {
+    let a: f32 = 2.51;
+    let b: f32 = 0.03;
+    let c: f32 = 2.43;
+    let d: f32 = 0.59;
+    let e: f32 = 0.14;
+    ((x * (a * x + b)) / (x * (c * x + d) + e)).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+// Hill ACES https://github.com/TheRealMJP/BakingLab/blob/master/BakingLab/ACES.hlsl
+fn aces_hill(x: Vec3) -> Vec3 {
+    let aces_input = Mat3::from_cols(
+        Vec3::new(0.59719, 0.35458, 0.04823),
+        Vec3::new(0.07600, 0.90834, 0.01566),
+        Vec3::new(0.02840, 0.13383, 0.83777),
+    ).transpose();
+    let aces_output = Mat3::from_cols(
+        Vec3::new(1.60475, -0.53108, -0.07367),
+        Vec3::new(-0.10208, 1.10813, -0.00605),
+        Vec3::new(-0.00327, -0.07276, 1.07602),
+    ).transpose();
+
+    let color = aces_input * x;
+    let a = color * (color + 0.0245786) - 0.000090537;
+    let b = color * (0.983729 * color + 0.4329510) + 0.238081;
+    (aces_output * (a / b)).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+fn reinhard(x: Vec3) -> Vec3 {
+    x / (x + 1.0)
+}
+
+fn neutral_curve(x: Vec3, a: f32, b: f32, c: f32, d: f32, e: f32, f: f32) -> Vec3 {
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn neutral(x: Vec3) -> Vec3 {
+    let (a, b, c, d, e, f) = (0.2, 0.29, 0.24, 0.272, 0.02, 0.3);
+    let white_level: f32 = 5.3;
+    let white_clip: f32 = 1.0;
+
+    let white_scale = Vec3::ONE / neutral_curve(Vec3::splat(white_level), a, b, c, d, e, f);
+    let x = neutral_curve(x * white_scale, a, b, c, d, e, f) * white_scale;
+
+    // Post-curve white point adjustment
+    x / white_clip
+}
+
+fn uncharted_partial(x: Vec3) -> Vec3 {
+    let (a, b, c, d, e, f) = (0.15f32, 0.50f32, 0.10f32, 0.20f32, 0.02f32, 0.30f32);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn uncharted(v: Vec3) -> Vec3 {
+    let exposure_bias: f32 = 2.0;
+    let curr = uncharted_partial(v * exposure_bias);
+    let white_scale = Vec3::ONE / uncharted_partial(Vec3::splat(11.2));
+    curr * white_scale
+}
+
+pub fn tonemap(color: Vec3, tonemapping: Tonemapping) -> Vec3 {
+    match tonemapping {
+        Tonemapping::None => color,
+        Tonemapping::Reinhard => reinhard(color),
+        Tonemapping::ACESNarkowicz => aces_narkowicz(color * 0.6),
+        Tonemapping::ACESNarkowiczOverexposed => aces_narkowicz(color),
+        Tonemapping::ACESHill => aces_hill(color),
+        Tonemapping::Neutral => neutral(color),
+        Tonemapping::Uncharted => uncharted(color),
+    }
+}
+
+// The sRGB transfer function, applied by the display when presenting to an sRGB surface
+pub fn linear_to_srgb(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
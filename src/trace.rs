@@ -1,52 +1,726 @@
 const KERNEL: &[u8] = include_bytes!(env!("kernels.spv"));
-const BLUE_BYTES: &[u8] = include_bytes!("resources/bluenoise.png");
 lazy_static::lazy_static! {
-    pub static ref FW: gpgpu::Framework = make_framework();
-    pub static ref BLUE_TEXTURE: RgbaImage = Reader::new(Cursor::new(BLUE_BYTES)).with_guessed_format().unwrap().decode().unwrap().into_rgba8();
+    static ref GPU: (gpgpu::Framework, wgpu::Limits) = make_framework();
+    pub static ref FW: &'static gpgpu::Framework = &GPU.0;
+    pub static ref GPU_LIMITS: wgpu::Limits = GPU.1.clone();
+    pub static ref BLUE_NOISE: Vec<u32> = generate_blue_noise();
+    static ref WORLD_CACHE: Mutex<Option<CachedWorld>> = Mutex::new(None);
 }
 
-use glam::{UVec2, Vec4, UVec3};
+use glam::{UVec2, UVec4, Vec2, Vec3, Vec4, UVec3};
 use gpgpu::{
     BufOps, DescriptorSet, GpuBuffer, GpuBufferUsage, GpuUniformBuffer, Kernel, Program, Shader, Sampler, SamplerWrapMode, SamplerFilterMode, GpuConstImage, primitives::pixels::Rgba32Float
 };
-use image::{RgbaImage, io::Reader, GenericImageView};
-use parking_lot::RwLock;
+use image::DynamicImage;
+use kernels::{half::{pack_half2x16, unpack_half2x16}, morton};
+use parking_lot::{Mutex, MutexGuard, RwLock};
 use pollster::FutureExt;
-use shared_structs::CpuImage;
+use shared_structs::{is_point, kernel_features, triangle_material_index, BVHNode, CameraUniform, CpuImage, EnvironmentSettings, FirstHit, LightPickEntry, MaterialData, PerVertexData, RenderSettings, ENVIRONMENT_LIGHT_GROUP, PRIMITIVE_POINT, PRIMITIVE_SDF};
 pub use shared_structs::TracingConfig;
 use std::{sync::{
-    atomic::{Ordering, AtomicBool, AtomicU32},
-    Arc,
-}, io::Cursor};
+    atomic::{Ordering, AtomicBool, AtomicU32, AtomicU64},
+    mpsc, Arc,
+}, borrow::Cow, cell::Cell, collections::BTreeSet, panic::AssertUnwindSafe, time::{Duration, Instant}};
 use rayon::prelude::*;
 
-use crate::{asset::{World, GpuWorld, dynamic_image_to_cpu_buffer, load_dynamic_image, dynamic_image_to_gpu_image, fallback_gpu_image, fallback_cpu_buffer}};
+use crate::{scene::CsgOperation, hot_reload::KernelWatcher, procedural::{ProceduralBinding, bind_procedural_textures, procedural_regions}, texture_cache::{LoadedTexture, TEXTURE_CACHE, spawn_texture_loader}, bluenoise::generate_blue_noise, path_debug::{DebugPath, DebugScene, DebugTextures, trace_debug_path}, bvh::BVH, light_pick, proxy::Proxy, ground::GroundSettings, environment::{clamp_environment, pack_skybox_mips}, variance::{estimate_variance, odd_means_from_image, relative_error}, firefly::spread_rejected_energy, upscale::upscale_bilinear, material_preview::MaterialLibrary, asset::{World, GpuWorld, LoadProgress, ResourceUsage, SceneStatistics, dynamic_image_to_cpu_buffer, load_dynamic_image, dynamic_image_to_gpu_image, fallback_gpu_image, fallback_cpu_buffer}};
 
-fn make_framework() -> gpgpu::Framework {
+fn make_adapter() -> wgpu::Adapter {
     let backend = wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY);
     let power_preference = wgpu::util::power_preference_from_env()
         .unwrap_or(wgpu::PowerPreference::HighPerformance);
     let instance = wgpu::Instance::new(backend);
-    let adapter = instance
+    instance
         .request_adapter(&wgpu::RequestAdapterOptions {
             power_preference,
             ..Default::default()
         })
         .block_on()
-        .expect("Failed at adapter creation.");
-    gpgpu::Framework::new(adapter, std::time::Duration::from_millis(1)).block_on()
+        .expect("Failed at adapter creation.")
+}
+
+// Limits are read off the adapter the framework is made from, as another adapter may differ
+fn make_framework() -> (gpgpu::Framework, wgpu::Limits) {
+    let adapter = make_adapter();
+    let limits = adapter.limits();
+    (gpgpu::Framework::new(adapter, std::time::Duration::from_millis(1)).block_on(), limits)
+}
+
+// Publish the memory used by the scene and the per-pixel state, for the resources panel
+fn record_memory_usage(state: &TracingState, world: &World, skybox_size: (u32, u32), pixel_count: u64) {
+    let half_precision = state.half_precision.load(Ordering::Relaxed);
+    let skybox_pixels = skybox_size.0 as u64 * skybox_size.1 as u64;
+    let mut usage = world.memory_usage();
+    usage.push(ResourceUsage {
+        name: "Skybox",
+        cpu_bytes: skybox_pixels * std::mem::size_of::<Vec4>() as u64,
+        gpu_bytes: skybox_pixels * std::mem::size_of::<Vec4>() as u64,
+    });
+    usage.push(ResourceUsage {
+        name: "Accumulation",
+        cpu_bytes: pixel_count * std::mem::size_of::<Vec4>() as u64,
+        gpu_bytes: pixel_count * AccumulationBuffer::texel_size(half_precision),
+    });
+    usage.push(ResourceUsage {
+        name: "Odd samples",
+        cpu_bytes: pixel_count * std::mem::size_of::<f32>() as u64,
+        gpu_bytes: pixel_count * std::mem::size_of::<f32>() as u64,
+    });
+    usage.push(ResourceUsage {
+        name: "Firefly rejection",
+        cpu_bytes: pixel_count * std::mem::size_of::<Vec4>() as u64,
+        gpu_bytes: pixel_count * std::mem::size_of::<Vec4>() as u64,
+    });
+    usage.push(ResourceUsage {
+        name: "RNG state",
+        cpu_bytes: pixel_count * std::mem::size_of::<UVec2>() as u64,
+        gpu_bytes: pixel_count * std::mem::size_of::<UVec2>() as u64,
+    });
+    usage.push(ResourceUsage {
+        name: "First hits",
+        cpu_bytes: 0,
+        gpu_bytes: pixel_count * std::mem::size_of::<FirstHit>() as u64,
+    });
+    usage.push(ResourceUsage {
+        name: "Blue noise",
+        cpu_bytes: (BLUE_NOISE.len() * std::mem::size_of::<u32>()) as u64,
+        gpu_bytes: (BLUE_NOISE.len() * std::mem::size_of::<u32>()) as u64,
+    });
+    usage.push(texture_cache_usage());
+    *state.memory_usage.write() = usage;
+}
+
+// Decoded textures are shared between scenes, and only live on the CPU
+fn texture_cache_usage() -> ResourceUsage {
+    ResourceUsage {
+        name: "Texture cache",
+        cpu_bytes: TEXTURE_CACHE.lock().size_bytes() as u64,
+        gpu_bytes: 0,
+    }
+}
+
+// The cache fills up as textures stream in, so its entry is refreshed as they arrive
+fn update_texture_cache_usage(state: &TracingState) {
+    let current = texture_cache_usage();
+    if let Some(usage) = state.memory_usage.write().iter_mut().find(|usage| usage.name == current.name) {
+        *usage = current;
+    }
+}
+
+// Starts streaming in any textures that weren't decoded while loading
+fn start_texture_loading(world: &mut World, state: &Arc<TracingState>) -> Option<mpsc::Receiver<LoadedTexture>> {
+    if world.texture_jobs.is_empty() {
+        return None;
+    }
+    Some(spawn_texture_loader(std::mem::take(&mut world.texture_jobs), state.clone()))
+}
+
+// Loads the scene on a thread of its own, reporting progress through the tracing state. If
+// rendering is stopped meanwhile, this returns right away and the loader gives up on its own,
+// so stopping never has to wait for a long import to finish.
+pub fn load_world(scene_path: &str, state: &Arc<TracingState>) -> Option<World> {
+    let ground = *state.ground.read();
+    let scene_scale = *state.scene_scale.read();
+    let decode_now = !state.async_textures.load(Ordering::Relaxed);
+    let sort_triangles = state.config.read().render.morton_order != 0;
+    let progress = Arc::new(LoadProgress::default());
+    *state.load_progress.write() = Some(progress.clone());
+    // Loads are profiled whether or not the profiler is open, so slow ones can be looked into after the fact
+    puffin::set_scopes_on(true);
+
+    let (sender, receiver) = mpsc::channel();
+    {
+        let path = scene_path.to_string();
+        let progress = progress.clone();
+        std::thread::Builder::new()
+            .name("Scene loader".to_string())
+            .spawn(move || {
+                let _ = sender.send(World::load(&path, scene_scale, &ground, decode_now, sort_triangles, &progress));
+            })
+            .expect("Failed to spawn scene loader thread.");
+    }
+
+    let world = loop {
+        match receiver.recv_timeout(Duration::from_millis(10)) {
+            Ok(world) => break world,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if !state.running.load(Ordering::Relaxed) {
+                    tracing::info!("Cancelled loading scene '{}'.", scene_path);
+                    progress.cancel();
+                    break None;
+                }
+            }
+            // The loader panicked, which it has already reported
+            Err(mpsc::RecvTimeoutError::Disconnected) => break None,
+        }
+    };
+    *state.load_progress.write() = None;
+    world
+}
+
+// Everything a loaded world depends on. Restarting with the same key can reuse the world.
+#[derive(Clone, PartialEq)]
+struct WorldKey {
+    scene_path: String,
+    modified: Option<std::time::SystemTime>,
+    scene_scale: f32,
+    ground: GroundSettings,
+    sort_triangles: bool,
+}
+
+impl WorldKey {
+    fn new(scene_path: &str, state: &TracingState) -> Self {
+        Self {
+            scene_path: scene_path.to_string(),
+            modified: std::fs::metadata(scene_path).and_then(|metadata| metadata.modified()).ok(),
+            scene_scale: *state.scene_scale.read(),
+            ground: *state.ground.read(),
+            sort_triangles: state.config.read().render.morton_order != 0,
+        }
+    }
+}
+
+// The world of the last GPU render, on both sides, with the material edits it was rendered with.
+// Kept after the render stops, so restarting on the same scene, after a resize for example, skips
+// loading and uploading it again, and only the per-pixel buffers are made anew.
+struct CachedWorld {
+    key: WorldKey,
+    world: World,
+    gpu: GpuWorld<'static>,
+}
+
+// Takes the cached world if it matches. Edits are carried over by comparing against the state's
+// materials, so the world is only reused if those are of the same scene, see sync_material_emission.
+fn take_cached_world(key: &WorldKey, state: &TracingState) -> Option<(World, GpuWorld<'static>)> {
+    let cached = WORLD_CACHE.lock().take()?;
+    let reusable = cached.key == *key && is_same_scene(&state.materials.read(), &cached.world.material_names);
+    reusable.then_some((cached.world, cached.gpu))
+}
+
+// Checks that every buffer we are about to upload fits within the device limits,
+// since wgpu only reports violations with an opaque validation error.
+fn validate_gpu_limits(world: &World, pixel_count: u64, half_precision: bool) -> Result<(), String> {
+    let max_size = GPU_LIMITS.max_storage_buffer_binding_size as u64;
+    let per_pixel_sizes = [
+        ("output", pixel_count * AccumulationBuffer::texel_size(half_precision)),
+        ("RNG", pixel_count * std::mem::size_of::<UVec2>() as u64),
+        ("first hit", pixel_count * std::mem::size_of::<FirstHit>() as u64),
+    ];
+    for (name, size) in world.buffer_sizes().into_iter().chain(per_pixel_sizes) {
+        if size > max_size {
+            return Err(format!(
+                "The {} buffer requires {:.1} MiB, but the GPU only supports storage buffers up to {:.1} MiB.",
+                name,
+                size as f64 / (1024.0 * 1024.0),
+                max_size as f64 / (1024.0 * 1024.0),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Wall-clock time spent in each pass of the most recent sync, measured on the CPU. Trace time is per sample.
+#[derive(Default, Clone, Copy)]
+pub struct PassTimings {
+    pub trace: Duration,
+    pub resolve: Duration,
+    pub denoise: Duration,
+}
+
+// Time spent in each pass, summed over every sync since the render started
+#[derive(Default, Clone, Copy)]
+pub struct TimingTotals {
+    pub trace: Duration,
+    pub resolve: Duration,
+    pub denoise: Duration,
+    pub samples: u32,
+}
+
+impl TimingTotals {
+    fn add(&mut self, timings: &PassTimings, samples: u32) {
+        self.trace += timings.trace * samples;
+        self.resolve += timings.resolve;
+        self.denoise += timings.denoise;
+        self.samples += samples;
+    }
+}
+
+// Triple buffered handoff of finished frames from the render thread to the GUI. The render
+// thread owns the back buffer and swaps it in when done, so neither side ever waits on a
+// copy. Every pixel changes each sample, so dirty tracking is per frame via a generation.
+pub struct FrameBuffer<T> {
+    latest: Mutex<Vec<T>>,
+    front: Mutex<Vec<T>>,
+    fresh: AtomicBool,
+    generation: AtomicU64,
+}
+
+impl<T: Clone> FrameBuffer<T> {
+    pub fn new(frame: Vec<T>) -> Self {
+        Self {
+            latest: Mutex::new(frame.clone()),
+            front: Mutex::new(frame),
+            fresh: AtomicBool::new(false),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self, frame: Vec<T>) {
+        let mut front = self.front.lock();
+        *self.latest.lock() = frame.clone();
+        *front = frame;
+        self.fresh.store(false, Ordering::Release);
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    // Swap a finished frame in. `frame` gets back a stale buffer of the same size to render into.
+    pub fn publish(&self, frame: &mut Vec<T>) {
+        std::mem::swap(frame, &mut *self.latest.lock());
+        self.fresh.store(true, Ordering::Release);
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    // Incremented whenever the contents change, so readers can skip frames they have already seen.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    // Whether a reader has taken the latest frame.
+    pub fn is_taken(&self) -> bool {
+        !self.fresh.load(Ordering::Acquire)
+    }
+
+    // The most recently published frame.
+    pub fn read(&self) -> MutexGuard<'_, Vec<T>> {
+        let mut front = self.front.lock();
+        if self.fresh.swap(false, Ordering::Acquire) {
+            std::mem::swap(&mut *front, &mut *self.latest.lock());
+        }
+        front
+    }
+}
+
+// What changed since the last sync, so the render thread only resets what it has to.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct DirtyFlags(u32);
+
+impl DirtyFlags {
+    pub const CAMERA: Self = Self(1 << 0); // Camera moved, accumulation is reset
+    pub const LIGHTING: Self = Self(1 << 1); // Sun or light transport settings changed, accumulation is reset
+    pub const SAMPLING: Self = Self(1 << 2); // Random sequence changed, accumulation and RNG state are reset
+    pub const DISPLAY: Self = Self(1 << 3); // Post-processing changed, the current image is resolved again
+    pub const MATERIALS: Self = Self(1 << 4); // Emission, light linking or visibility was edited, lights are rebuilt and accumulation is reset
+    pub const SEED: Self = Self(1 << 5); // Seed was re-rolled, sampling continues with it without resetting accumulation
+    pub const TEXTURES: Self = Self(1 << 6); // Textures finished loading in the background, the atlas is updated and accumulation is reset
+
+    pub fn contains(&self, flag: DirtyFlags) -> bool {
+        self.0 & flag.0 != 0
+    }
+
+    pub fn resets_accumulation(&self) -> bool {
+        self.contains(Self::CAMERA) || self.contains(Self::LIGHTING) || self.contains(Self::SAMPLING) || self.contains(Self::MATERIALS) || self.contains(Self::TEXTURES)
+    }
+
+    // Lighting tweaks are blended over the previous frame. Camera motion isn't, as it would smear.
+    fn wants_preview(&self) -> bool {
+        (self.contains(Self::LIGHTING) || self.contains(Self::MATERIALS)) && !self.contains(Self::CAMERA)
+    }
+}
+
+// While a setting is being scrubbed, each sync only has a sample or two of the new state.
+// Rather than flicker between noisy frames, blend them into the previously shown frame,
+// handing over to the real accumulation as it gathers samples.
+const PREVIEW_BLEND: f32 = 0.3;
+const PREVIEW_SAMPLES: u32 = 8;
+
+struct PreviewBuffer {
+    image: Vec<f32>,
+    active: bool,
+}
+
+impl PreviewBuffer {
+    fn new(len: usize) -> Self {
+        Self {
+            image: vec![0.0; len],
+            active: false,
+        }
+    }
+
+    fn apply(&mut self, image_buffer: &mut [f32], samples: u32) {
+        if !self.active || samples >= PREVIEW_SAMPLES {
+            self.active = false;
+            self.image.copy_from_slice(image_buffer);
+            return;
+        }
+
+        let weight = PREVIEW_BLEND.max(samples as f32 / PREVIEW_SAMPLES as f32);
+        for (preview, value) in self.image.iter_mut().zip(image_buffer.iter_mut()) {
+            *preview += (*value - *preview) * weight;
+            *value = *preview;
+        }
+    }
+}
+
+// Size of the buckets used by the CPU backend in bucket mode
+const TILE_SIZE: u32 = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Buckets of the current CPU pass, for drawing progress in the viewport
+#[derive(Default)]
+pub struct TileProgress {
+    pub active: Vec<Tile>,
+    pub finished: Vec<Tile>,
+}
+
+impl TileProgress {
+    fn clear(&mut self) {
+        self.active.clear();
+        self.finished.clear();
+    }
+
+    fn finish(&mut self, tile: Tile) {
+        self.active.retain(|t| *t != tile);
+        self.finished.push(tile);
+    }
+}
+
+fn make_tiles(width: u32, height: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(TILE_SIZE as usize) {
+        for x in (0..width).step_by(TILE_SIZE as usize) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: TILE_SIZE.min(width - x),
+                height: TILE_SIZE.min(height - y),
+            });
+        }
+    }
+    tiles
+}
+
+// Pixel indices in the order the GPU traces them with Morton order enabled, skipping those off screen
+fn morton_pixel_order(width: u32, height: u32) -> Vec<u32> {
+    let mut order = Vec::with_capacity((width * height) as usize);
+    for tile in 0..morton::tile_count(width, height) {
+        for y in 0..morton::TILE_SIZE {
+            for x in 0..morton::TILE_SIZE {
+                let pixel = morton::morton_pixel(tile, UVec2::new(x, y), width);
+                if pixel.x < width && pixel.y < height {
+                    order.push(pixel.y * width + pixel.x);
+                }
+            }
+        }
+    }
+    order
+}
+
+// Every pixel starts at sample 0, and keeps its index to seed the RNG with
+fn initial_rng_state(width: u32, height: u32) -> Vec<UVec2> {
+    (0..width * height).map(|pixel_index| UVec2::new(0, pixel_index)).collect()
+}
+
+// Loads the skybox, clamping it and extracting a directional light from it if enabled
+pub fn load_skybox(state: &TracingState, skybox_path: Option<&str>) -> Option<DynamicImage> {
+    let mut skybox = skybox_path.and_then(load_dynamic_image);
+    let threshold = *state.environment_clamp.read();
+    let light = match (skybox.as_mut(), threshold) {
+        (Some(skybox), Some(threshold)) => clamp_environment(skybox, threshold),
+        _ => None,
+    };
+    let mut config = state.config.write();
+    config.environment.environment_light_direction = light.map_or(Vec4::ZERO, |light| light.direction.extend(0.0));
+    config.environment.environment_light_irradiance = light.map_or(Vec4::ZERO, |light| light.irradiance.extend(0.0));
+    // Mips are only needed, and only worth their memory, for a blurred background
+    config.environment.skybox_mips = 0;
+    if config.environment.background_lod > 0.0 {
+        if let Some(image) = skybox.as_mut() {
+            let (packed, levels) = pack_skybox_mips(image);
+            *image = packed;
+            config.environment.skybox_mips = levels;
+        }
+    }
+    skybox
+}
+
+// Self-intersection offsets have to follow the scene size. A fixed one causes acne in
+// big scenes and light leaks in small ones. 1e-4 of the diagonal is 0.001 for a 10m scene.
+// Takes the bounds of the model, as the ground would make it about 10 times bigger.
+pub fn scene_ray_offset(bounds: (Vec3, Vec3)) -> f32 {
+    ((bounds.1 - bounds.0).length() * 1e-4).max(1e-6)
+}
+
+// Emission, light linking, visibility and CSG of a material as loaded, and as edited in the GUI. The
+// emissive color is split into a color and a strength, so either can be edited without touching the other.
+#[derive(Clone)]
+pub struct MaterialEdits {
+    pub name: String,
+    pub loaded: Vec4,
+    pub color: Vec3,
+    pub strength: f32,
+    pub unlinked_lights: BTreeSet<usize>, // emissive materials whose direct light doesn't reach this one
+    pub unlinked_environment: bool, // whether the environment doesn't light this one, for studio setups
+    pub hidden: u32, // rays that pass through this material, see shared_structs::HIDDEN_FROM_CAMERA
+    pub csg: Option<CsgOperation>,
+}
+
+impl MaterialEdits {
+    fn new(name: &str, loaded: Vec4, csg: Option<CsgOperation>) -> Self {
+        let strength = loaded.truncate().max_element().max(0.0);
+        Self {
+            name: name.to_string(),
+            loaded,
+            color: if strength > 0.0 { loaded.truncate() / strength } else { Vec3::ONE },
+            strength,
+            unlinked_lights: BTreeSet::new(),
+            unlinked_environment: false,
+            hidden: 0,
+            csg,
+        }
+    }
+
+    // Splitting and recombining isn't exact, so unedited materials keep the loaded value
+    pub fn emissive(&self) -> Vec4 {
+        let emissive = (self.color * self.strength).extend(self.loaded.w);
+        if emissive.abs_diff_eq(self.loaded, 1e-4) { self.loaded } else { emissive }
+    }
+
+    pub fn is_edited(&self) -> bool {
+        self.emissive() != self.loaded
+    }
+
+    // Only resets the emission
+    pub fn reset(&mut self) {
+        let unlinked_lights = std::mem::take(&mut self.unlinked_lights);
+        *self = Self { unlinked_lights, unlinked_environment: self.unlinked_environment, hidden: self.hidden, ..Self::new(&self.name, self.loaded, self.csg) };
+    }
+}
+
+// Gives each emissive material a light group, and each material a mask of the groups unlinked
+// from it. The masks have room for 31 groups, so beyond that emitters share them. The last bit is
+// the environment's, see ENVIRONMENT_LIGHT_GROUP.
+fn light_linking_masks(materials: &[MaterialEdits], material_datas: &[MaterialData]) -> Vec<(u32, u32)> {
+    let mut light_groups = vec![0u32; material_datas.len()];
+    let mut next_group = 0;
+    for (light_group, data) in light_groups.iter_mut().zip(material_datas) {
+        if data.emissive.truncate() != Vec3::ZERO {
+            *light_group = 1 << (next_group % 31);
+            next_group += 1;
+        }
+    }
+    materials
+        .iter()
+        .zip(&light_groups)
+        .map(|(material, &light_group)| {
+            let environment = if material.unlinked_environment { ENVIRONMENT_LIGHT_GROUP } else { 0 };
+            let light_exclude = material.unlinked_lights.iter().fold(environment, |mask, &light| mask | light_groups[light]);
+            (light_group, light_exclude)
+        })
+        .collect()
+}
+
+fn is_same_scene(materials: &[MaterialEdits], names: &[String]) -> bool {
+    materials.len() == names.len() && materials.iter().zip(names).all(|(material, name)| &material.name == name)
+}
+
+// What a round of material edits has to update. Light linking only changes the materials, while
+// emission changes the power of the lights too, so the light pick table has to be rebuilt.
+struct MaterialUpdate {
+    edited: Vec<usize>,
+    light_pick_table: Option<Vec<LightPickEntry>>,
+}
+
+// Publishes the materials of a freshly loaded scene, or, if the render was restarted on the
+// same scene, carries the edits over into it. Returns the indices of the edited materials, and whether any emission changed.
+fn sync_material_emission(state: &TracingState, names: &[String], indices: &[UVec4], material_datas: &mut [MaterialData]) -> (Vec<usize>, bool) {
+    let mut materials = state.materials.write();
+    if !is_same_scene(&materials, names) {
+        // CSG operations given by the scene are only kept on its triangles
+        let mut operations = vec![None; names.len()];
+        for triangle in indices {
+            if let Some(operation) = operations.get_mut(triangle_material_index(*triangle) as usize) {
+                *operation = CsgOperation::from_u32(triangle.w);
+            }
+        }
+        *materials = names
+            .iter()
+            .zip(material_datas.iter())
+            .zip(operations)
+            .map(|((name, data), csg)| MaterialEdits::new(name, data.emissive, csg))
+            .collect();
+        return (Vec::new(), false);
+    }
+
+    let mut edited = vec![false; material_datas.len()];
+    let mut emission_changed = false;
+    for ((material, data), edited) in materials.iter().zip(material_datas.iter_mut()).zip(edited.iter_mut()) {
+        if data.emissive != material.emissive() {
+            data.emissive = material.emissive();
+            emission_changed = true;
+            *edited = true;
+        }
+    }
+    for (((light_group, light_exclude), data), edited) in light_linking_masks(&materials, material_datas).into_iter().zip(material_datas.iter_mut()).zip(edited.iter_mut()) {
+        if data.light_group != light_group || data.light_exclude != light_exclude {
+            data.light_group = light_group;
+            data.light_exclude = light_exclude;
+            *edited = true;
+        }
+    }
+    let edited = edited.iter().enumerate().filter(|(_, edited)| **edited).map(|(index, _)| index).collect();
+    (edited, emission_changed)
+}
+
+// Writes the visibility and CSG flags of each material into the triangles using it. Returns whether any changed.
+fn sync_material_visibility(state: &TracingState, indices: &mut [UVec4]) -> bool {
+    let materials = state.materials.read();
+    let has_intersect = materials.iter().any(|material| material.csg == Some(CsgOperation::Intersect));
+    indices
+        .par_iter_mut()
+        .map(|triangle| {
+            let material_index = triangle_material_index(*triangle);
+            let (hidden, csg) = materials.get(material_index as usize).map_or((0, 0), |material| (material.hidden, CsgOperation::triangle_flags(material.csg, has_intersect)));
+            // Points don't enclose anything to take part in CSG
+            let csg = if is_point(*triangle) { 0 } else { csg };
+            let flags = material_index | hidden | csg | (triangle.w & (PRIMITIVE_POINT | PRIMITIVE_SDF));
+            let changed = triangle.w != flags;
+            triangle.w = flags;
+            changed
+        })
+        .reduce(|| false, |a, b| a | b)
+}
+
+// Applies emission and light linking edits made in the GUI. Returns what has to be updated if anything changed,
+// only rebuilding the light pick table, which walks every triangle, if the emission did.
+fn apply_material_edits(
+    state: &TracingState,
+    names: &[String],
+    per_vertex_data: &[PerVertexData],
+    indices: &[UVec4],
+    material_datas: &mut [MaterialData],
+) -> Option<MaterialUpdate> {
+    let (edited, emission_changed) = sync_material_emission(state, names, indices, material_datas);
+    if edited.is_empty() {
+        return None;
+    }
+    if !emission_changed {
+        return Some(MaterialUpdate { edited, light_pick_table: None });
+    }
+    let (table, emissive_triangles) = light_pick::rebuild_light_pick_table(per_vertex_data, indices, material_datas);
+    if let Some(statistics) = state.scene_statistics.write().as_mut() {
+        statistics.emissive_triangles = emissive_triangles;
+    }
+    Some(MaterialUpdate { edited, light_pick_table: Some(table) })
+}
+
+// Uploads only the edited materials, each at its own offset, so an edit doesn't stall on the whole buffer.
+fn upload_materials(buffer: &GpuBuffer<'_, MaterialData>, material_datas: &[MaterialData], edited: &[usize]) {
+    for &index in edited {
+        let _ = buffer.write_at(index as u64, &material_datas[index..index + 1]);
+    }
+}
+
+// Carries material edits over to the navigation proxy. Returns whether it changed.
+fn apply_proxy_edits(state: &TracingState, proxy: &mut Proxy, per_vertex_data: &[PerVertexData], material_datas: &[MaterialData], lights_edited: bool) -> bool {
+    if lights_edited {
+        proxy.rebuild_lights(per_vertex_data, material_datas);
+    }
+    sync_material_visibility(state, &mut proxy.index_buffer) || lights_edited
+}
+
+// Remembers where the camera was, for motion vectors. Pass the previous config if the camera
+// moved since then, any other reset holds the image still.
+fn update_previous_camera(config: &mut TracingConfig, moved_from: Option<&TracingConfig>) {
+    let (position, rotation) = match moved_from {
+        Some(previous) => (previous.camera.cam_position, previous.camera.cam_rotation),
+        None => (config.camera.cam_position, config.camera.cam_rotation),
+    };
+    config.camera.prev_cam_position = position;
+    config.camera.prev_cam_rotation = rotation;
+}
+
+// Traces the motion vector AOV at the center of each pixel. It only changes along with the
+// camera, so both backends refresh it on the CPU after a reset, rather than every sample.
+fn publish_motion_vectors(state: &TracingState, config: &TracingConfig, per_vertex_data: &[PerVertexData], indices: &[UVec4], nodes: &[BVHNode]) {
+    puffin::profile_function!();
+    let mut motion_vectors = (0..config.render.width * config.render.height)
+        .into_par_iter()
+        .map(|i| kernels::pixel_motion(config, UVec2::new(i % config.render.width, i / config.render.width), per_vertex_data, indices, nodes))
+        .collect::<Vec<_>>();
+    state.motion_vectors.publish(&mut motion_vectors);
+}
+
+// Publishes the variance AOV, and the error of the image estimated from it. Must be given the
+// image before it is denoised or previewed.
+fn publish_variance(state: &TracingState, image_buffer: &[f32], odd_means: &[f32], variance: &mut Vec<f32>) {
+    puffin::profile_function!();
+    estimate_variance(image_buffer, odd_means, state.samples.load(Ordering::Relaxed), variance);
+    *state.estimated_error.write() = relative_error(image_buffer, variance);
+    state.variance.publish(variance);
+}
+
+const MIN_ERROR_TARGET_SAMPLES: u32 = 16;
+
+// Rendering until the image is clean enough. A handful of samples can agree by chance, so the
+// estimate isn't trusted before MIN_ERROR_TARGET_SAMPLES.
+fn reached_error_target(state: &TracingState) -> bool {
+    let target = *state.error_target.read();
+    target > 0.0 && state.samples.load(Ordering::Relaxed) >= MIN_ERROR_TARGET_SAMPLES && *state.estimated_error.read() <= target
 }
 
 pub struct TracingState {
-    pub framebuffer: RwLock<Vec<f32>>,
+    pub framebuffer: FrameBuffer<f32>,
     pub running: AtomicBool,
     pub samples: AtomicU32,
     pub denoise: AtomicBool,
+    pub denoise_comparison: AtomicBool, // Leave the left half of the denoised image raw
+    pub denoise_region: RwLock<Option<Tile>>, // Only denoise inside this rectangle of pixels, if set
     pub sync_rate: AtomicU32,
-    pub use_blue_noise: AtomicBool,
+    pub frame_budget_ms: AtomicU32, // Publish after dispatching for this long, even if sync_rate isn't reached, or never if 0
+    pub burst_samples: AtomicU32, // Once the camera stops, trace this many samples in one sync before settling to sync_rate, or none if 0
     pub interacting: AtomicBool,
-    pub dirty: AtomicBool,
+    pub paused: AtomicBool, // Hold off on rendering, such as while the window is minimized
+    pub low_power: AtomicBool, // Idle between syncs for as long as they took, such as while on battery
+    dirty: AtomicU32,
     pub config: RwLock<TracingConfig>,
+    pub timings: RwLock<PassTimings>,
+    pub timing_totals: Mutex<TimingTotals>,
+    pub render_time: Mutex<Duration>, // Spent on the current image, reset along with the samples
+    pub gpu_failed: AtomicBool,
+    pub half_precision: AtomicBool,
+    pub interactive_preview: AtomicBool,
+    pub interactive_upscale: AtomicBool, // Trace a quarter of the pixels while the camera is being moved, see InteractiveTarget
+    pub bucket_rendering: AtomicBool,
+    pub cpu_threads: AtomicU32,
+    pub cpu_low_priority: AtomicBool,
+    pub tile_progress: Mutex<TileProgress>,
+    pub packed_framebuffer: FrameBuffer<u32>, // RGBA16F, only written when using half precision
+    pub memory_usage: RwLock<Vec<ResourceUsage>>,
+    pub scene_statistics: RwLock<Option<SceneStatistics>>,
+    pub material_library: RwLock<Option<Arc<MaterialLibrary>>>, // Copy of the loaded materials, for previews in the GUI
+    pub materials: RwLock<Vec<MaterialEdits>>,
+    pub ground: RwLock<GroundSettings>,
+    pub scene_scale: RwLock<f32>, // Applied on import, on top of the units in the file
+    pub environment_clamp: RwLock<Option<f32>>, // Skybox values above this are turned into a directional light
+    pub debug_pixel: Mutex<Option<UVec2>>, // Pixel to trace a debug path through, picked up by the render thread
+    pub debug_path: RwLock<Option<DebugPath>>,
+    pub sample_limit: AtomicU32, // Stop on our own after this many samples, or never if 0
+    pub async_textures: AtomicBool, // Stream textures in while rendering, rather than decoding them all before the first sample
+    pub load_progress: RwLock<Option<Arc<LoadProgress>>>, // Set while a scene is loading
+    pub motion_vectors_enabled: AtomicBool, // Trace the motion vector AOV whenever the camera moves
+    pub motion_vectors: FrameBuffer<Vec2>, // One per pixel, see kernels::pixel_motion
+    pub hot_reload: AtomicBool, // Reload the GPU kernels when they are rebuilt, see hot_reload::KernelWatcher
+    pub procedural_textures: RwLock<Vec<ProceduralBinding>>, // Only used by the CPU backend, applied when a scene loads
+    pub variance: FrameBuffer<f32>, // Variance of the mean luminance of each pixel, see variance::split_variance
+    pub estimated_error: RwLock<f32>, // Relative error of the image, estimated from the variance
+    pub error_target: RwLock<f32>, // Stop on our own once the estimated error drops below this, or never if 0
 }
 
 impl TracingState {
@@ -55,11 +729,9 @@ impl TracingState {
         height: u32,
         config: Option<TracingConfig>,
     ) -> (TracingConfig, Vec<f32>) {
-        let config = TracingConfig {
-            width,
-            height,
-            ..config.unwrap_or_default()
-        };
+        let mut config = config.unwrap_or_default();
+        config.render.width = width;
+        config.render.height = height;
         let data_size = width as usize * height as usize * 3;
         let framebuffer = vec![0.0; data_size];
         (config, framebuffer)
@@ -68,69 +740,531 @@ impl TracingState {
     pub fn new (width: u32, height: u32) -> Self {
         let (config, framebuffer) = Self::make_view_dependent_state(width, height, None);
         let config = RwLock::new(config);
-        let framebuffer = RwLock::new(framebuffer);
+        let framebuffer = FrameBuffer::new(framebuffer);
         let running = AtomicBool::new(false);
         let samples = AtomicU32::new(0);
         let denoise = AtomicBool::new(false);
+        let denoise_comparison = AtomicBool::new(false);
+        let denoise_region = RwLock::new(None);
         let sync_rate = AtomicU32::new(32);
-        let use_blue_noise = AtomicBool::new(true);
+        let frame_budget_ms = AtomicU32::new(0); // Only worth it when something presents the frames, see App
+        let burst_samples = AtomicU32::new(16);
         let interacting = AtomicBool::new(false);
-        let dirty = AtomicBool::new(false);
+        let paused = AtomicBool::new(false);
+        let low_power = AtomicBool::new(false);
+        let dirty = AtomicU32::new(0);
+        let timings = RwLock::new(PassTimings::default());
+        let timing_totals = Mutex::new(TimingTotals::default());
+        let render_time = Mutex::new(Duration::ZERO);
+        let gpu_failed = AtomicBool::new(false);
+        let half_precision = AtomicBool::new(false);
+        let interactive_preview = AtomicBool::new(true);
+        let interactive_upscale = AtomicBool::new(true);
+        let bucket_rendering = AtomicBool::new(false);
+        let cpu_threads = AtomicU32::new(0);
+        let cpu_low_priority = AtomicBool::new(false);
+        let tile_progress = Mutex::new(TileProgress::default());
+        let packed_framebuffer = FrameBuffer::new(Vec::new());
+        let memory_usage = RwLock::new(Vec::new());
+        let scene_statistics = RwLock::new(None);
+        let material_library = RwLock::new(None);
+        let materials = RwLock::new(Vec::new());
+        let ground = RwLock::new(GroundSettings::default());
+        let scene_scale = RwLock::new(1.0);
+        let environment_clamp = RwLock::new(None);
+        let debug_pixel = Mutex::new(None);
+        let debug_path = RwLock::new(None);
+        let sample_limit = AtomicU32::new(0);
+        let async_textures = AtomicBool::new(false);
+        let load_progress = RwLock::new(None);
+        let motion_vectors_enabled = AtomicBool::new(false);
+        let motion_vectors = FrameBuffer::new(Vec::new());
+        let hot_reload = AtomicBool::new(false);
+        let procedural_textures = RwLock::new(Vec::new());
+        let variance = FrameBuffer::new(Vec::new());
+        let estimated_error = RwLock::new(0.0);
+        let error_target = RwLock::new(0.0);
         
         Self {
             framebuffer,
             running,
             samples,
             denoise,
+            denoise_comparison,
+            denoise_region,
             sync_rate,
-            use_blue_noise,
+            frame_budget_ms,
+            burst_samples,
             interacting,
+            paused,
+            low_power,
             dirty,
             config,
+            timings,
+            timing_totals,
+            render_time,
+            gpu_failed,
+            half_precision,
+            interactive_preview,
+            interactive_upscale,
+            bucket_rendering,
+            cpu_threads,
+            cpu_low_priority,
+            tile_progress,
+            packed_framebuffer,
+            memory_usage,
+            scene_statistics,
+            material_library,
+            materials,
+            ground,
+            scene_scale,
+            environment_clamp,
+            debug_pixel,
+            debug_path,
+            sample_limit,
+            async_textures,
+            load_progress,
+            motion_vectors_enabled,
+            motion_vectors,
+            hot_reload,
+            procedural_textures,
+            variance,
+            estimated_error,
+            error_target,
         }
     }
+
+    pub fn mark_dirty(&self, flag: DirtyFlags) {
+        self.dirty.fetch_or(flag.0, Ordering::Relaxed);
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Relaxed) != 0
+    }
+
+    // Returns everything that changed since the last call, and clears it.
+    pub fn take_dirty(&self) -> DirtyFlags {
+        DirtyFlags(self.dirty.swap(0, Ordering::Relaxed))
+    }
+}
+
+// Both formats accumulate a running mean of samples, either as Vec4 or packed
+// as RGBA16F. The second field is scratch space for readback.
+enum AccumulationBuffer<'fw> {
+    Full(GpuBuffer<'fw, Vec4>, Vec<Vec4>),
+    Half(GpuBuffer<'fw, UVec2>, Vec<UVec2>),
 }
 
-struct PathTracingKernel<'fw>(Kernel<'fw>);
+impl<'fw> AccumulationBuffer<'fw> {
+    fn new(half_precision: bool, framebuffer: &[f32]) -> Self {
+        if half_precision {
+            let init = framebuffer
+                .chunks(3)
+                .map(|c| UVec2::new(pack_half2x16(Vec2::new(c[0], c[1])), pack_half2x16(Vec2::new(c[2], 1.0))))
+                .collect::<Vec<_>>();
+            Self::Half(GpuBuffer::from_slice(&FW, &init), init)
+        } else {
+            let init = framebuffer
+                .chunks(3)
+                .map(|c| Vec4::new(c[0], c[1], c[2], 1.0))
+                .collect::<Vec<_>>();
+            Self::Full(GpuBuffer::from_slice(&FW, &init), init)
+        }
+    }
+
+    fn texel_size(half_precision: bool) -> u64 {
+        if half_precision {
+            std::mem::size_of::<UVec2>() as u64
+        } else {
+            std::mem::size_of::<Vec4>() as u64
+        }
+    }
+
+    fn clear(&self) {
+        match self {
+            Self::Full(buffer, scratch) => { let _ = buffer.write(&vec![Vec4::ZERO; scratch.len()]); }
+            Self::Half(buffer, scratch) => { let _ = buffer.write(&vec![UVec2::ZERO; scratch.len()]); }
+        }
+    }
+
+    // Read back the mean of all accumulated samples as RGB
+    fn read_mean(&mut self, image_buffer: &mut [f32]) {
+        match self {
+            Self::Full(buffer, scratch) => {
+                let _ = buffer.read_blocking(scratch);
+                for (i, col) in scratch.iter().enumerate() {
+                    image_buffer[i * 3] = col.x;
+                    image_buffer[i * 3 + 1] = col.y;
+                    image_buffer[i * 3 + 2] = col.z;
+                }
+            }
+            Self::Half(buffer, scratch) => {
+                let _ = buffer.read_blocking(scratch);
+                for (i, col) in scratch.iter().enumerate() {
+                    let rg = unpack_half2x16(col.x);
+                    image_buffer[i * 3] = rg.x;
+                    image_buffer[i * 3 + 1] = rg.y;
+                    image_buffer[i * 3 + 2] = unpack_half2x16(col.y).x;
+                }
+            }
+        }
+    }
+}
+
+// Pack an RGB f32 image into RGBA16F, halving the size of the upload to the display
+fn pack_framebuffer(image_buffer: &[f32], packed: &mut Vec<u32>) {
+    packed.resize(image_buffer.len() / 3 * 2, 0);
+    for (i, col) in image_buffer.chunks(3).enumerate() {
+        packed[i * 2] = pack_half2x16(Vec2::new(col[0], col[1]));
+        packed[i * 2 + 1] = pack_half2x16(Vec2::new(col[2], 1.0));
+    }
+}
+
+// TracingConfig on the GPU. Each block has its own uniform buffer, and is only uploaded when it
+// changed, so the sample count written every dispatch doesn't send the whole config along.
+struct ConfigBuffers<'fw> {
+    camera: GpuUniformBuffer<'fw, CameraUniform>,
+    render: GpuUniformBuffer<'fw, RenderSettings>,
+    environment: GpuUniformBuffer<'fw, EnvironmentSettings>,
+    uploaded: Cell<TracingConfig>, // the kernels borrow the buffers, so this is updated through a shared reference
+}
+
+impl<'fw> ConfigBuffers<'fw> {
+    fn new(config: &TracingConfig) -> Self {
+        Self {
+            camera: GpuUniformBuffer::from_slice(&FW, &[config.camera]),
+            render: GpuUniformBuffer::from_slice(&FW, &[config.render]),
+            environment: GpuUniformBuffer::from_slice(&FW, &[config.environment]),
+            uploaded: Cell::new(*config),
+        }
+    }
+
+    fn write(&self, config: &TracingConfig) {
+        let uploaded = self.uploaded.get();
+        if bytemuck::bytes_of(&config.camera) != bytemuck::bytes_of(&uploaded.camera) {
+            let _ = self.camera.write(&[config.camera]);
+        }
+        if bytemuck::bytes_of(&config.render) != bytemuck::bytes_of(&uploaded.render) {
+            let _ = self.render.write(&[config.render]);
+        }
+        if bytemuck::bytes_of(&config.environment) != bytemuck::bytes_of(&uploaded.environment) {
+            let _ = self.environment.write(&[config.environment]);
+        }
+        self.uploaded.set(*config);
+    }
+}
+
+// Each sample is traced in two dispatches. The primary kernel finds the first hit of every
+// camera ray, and the path tracing kernel continues the paths from there. With the primary
+// pass turned off in the render settings, the path tracing kernel traces camera rays itself.
+struct PathTracingKernel<'fw> {
+    primary: Kernel<'fw>,
+    trace: Kernel<'fw>,
+}
 
 impl<'fw> PathTracingKernel<'fw> {
     fn new(
-        config_buffer: &GpuUniformBuffer<'fw, TracingConfig>,
+        kernel: &[u8],
+        features: u32,
+        config_buffers: &ConfigBuffers<'fw>,
         rng_buffer: &GpuBuffer<'fw, UVec2>,
-        output_buffer: &GpuBuffer<'fw, Vec4>,
+        output_buffer: &AccumulationBuffer<'fw>,
+        odd_buffer: &GpuBuffer<'fw, f32>,
+        outlier_buffer: &GpuBuffer<'fw, Vec4>,
         world: &GpuWorld<'fw>,
         skybox: &GpuConstImage<'fw, Rgba32Float>,
+        blue_noise_buffer: &GpuBuffer<'fw, u32>,
+        first_hit_buffer: &GpuBuffer<'fw, FirstHit>,
+        use_proxy: bool, // Trace the world's navigation proxy, if it has one
     ) -> Self {
-        let shader = Shader::from_spirv_bytes(&FW, KERNEL, Some("compute"));
+        let shader = Shader::from_spirv_bytes(&FW, kernel, Some("compute"));
         let sampler = Sampler::new(&FW, SamplerWrapMode::ClampToEdge, SamplerFilterMode::Linear);
-        let bindings = DescriptorSet::default()
-            .bind_uniform_buffer(config_buffer)
-            .bind_buffer(rng_buffer, GpuBufferUsage::ReadWrite)
-            .bind_buffer(output_buffer, GpuBufferUsage::ReadWrite)
-            .bind_buffer(&world.per_vertex_buffer, GpuBufferUsage::ReadOnly)
-            .bind_buffer(&world.index_buffer, GpuBufferUsage::ReadOnly)
-            .bind_buffer(&world.bvh.nodes_buffer, GpuBufferUsage::ReadOnly)
-            .bind_buffer(&world.material_data_buffer, GpuBufferUsage::ReadOnly)
-            .bind_buffer(&world.light_pick_buffer, GpuBufferUsage::ReadOnly)
-            .bind_sampler(&sampler)
-            .bind_const_image(&world.atlas)
-            .bind_const_image(&skybox);
-        let program = Program::new(&shader, "trace_kernel").add_descriptor_set(bindings);
-        let kernel = Kernel::new(&FW, program);
+        // The proxy shares the vertices and materials, and brings its own triangles
+        let (index_buffer, nodes_buffer, light_pick_buffer) = match &world.proxy {
+            Some(proxy) if use_proxy => (&proxy.index_buffer, &proxy.bvh.nodes_buffer, &proxy.light_pick_buffer),
+            _ => (&world.index_buffer, &world.bvh.nodes_buffer, &world.light_pick_buffer),
+        };
+        // Both kernels share the same layout, the primary kernel just leaves most of it unused
+        let bindings = || {
+            let bindings = DescriptorSet::default()
+                .bind_uniform_buffer(&config_buffers.camera)
+                .bind_buffer(rng_buffer, GpuBufferUsage::ReadWrite);
+            let bindings = match output_buffer {
+                AccumulationBuffer::Full(buffer, _) => bindings.bind_buffer(buffer, GpuBufferUsage::ReadWrite),
+                AccumulationBuffer::Half(buffer, _) => bindings.bind_buffer(buffer, GpuBufferUsage::ReadWrite),
+            };
+            bindings
+                .bind_buffer(&world.per_vertex_buffer, GpuBufferUsage::ReadOnly)
+                .bind_buffer(index_buffer, GpuBufferUsage::ReadOnly)
+                .bind_buffer(nodes_buffer, GpuBufferUsage::ReadOnly)
+                .bind_buffer(&world.material_data_buffer, GpuBufferUsage::ReadOnly)
+                .bind_buffer(light_pick_buffer, GpuBufferUsage::ReadOnly)
+                .bind_sampler(&sampler)
+                .bind_const_image(&world.atlas)
+                .bind_const_image(skybox)
+                .bind_buffer(blue_noise_buffer, GpuBufferUsage::ReadOnly)
+                .bind_buffer(first_hit_buffer, GpuBufferUsage::ReadWrite)
+                .bind_uniform_buffer(&config_buffers.render)
+                .bind_uniform_buffer(&config_buffers.environment)
+                .bind_buffer(odd_buffer, GpuBufferUsage::ReadWrite)
+                .bind_buffer(outlier_buffer, GpuBufferUsage::ReadWrite)
+        };
+        let half_precision = matches!(output_buffer, AccumulationBuffer::Half(..));
+        let entry_point = kernels::kernel_entry_point(features, half_precision);
+        let primary = Kernel::new(&FW, Program::new(&shader, "primary_kernel").add_descriptor_set(bindings()));
+        let trace = Kernel::new(&FW, Program::new(&shader, &entry_point).add_descriptor_set(bindings()));
+
+        Self { primary, trace }
+    }
 
-        Self(kernel)
+    // Traces one sample of every pixel
+    fn enqueue(&self, x: u32, y: u32, z: u32, primary_pass: bool) {
+        if primary_pass {
+            self.primary.enqueue(x, y, z);
+        }
+        self.trace.enqueue(x, y, z);
     }
 }
 
+// A quarter resolution accumulation, traced instead of the full one while the camera is being
+// moved, so navigation stays smooth at any output resolution. It is upscaled for display, and the
+// full resolution accumulation takes over again once the camera is still. Heavy scenes are traced
+// through their simplified proxy here, see proxy::Proxy.
+struct InteractiveTarget<'fw> {
+    config: TracingConfig,
+    config_buffers: ConfigBuffers<'fw>,
+    rng_buffer: GpuBuffer<'fw, UVec2>,
+    output_buffer: AccumulationBuffer<'fw>,
+    odd_buffer: GpuBuffer<'fw, f32>,
+    outlier_buffer: GpuBuffer<'fw, Vec4>,
+    first_hit_buffer: GpuBuffer<'fw, FirstHit>,
+    kernel: Option<PathTracingKernel<'fw>>, // Made when first traced, and dropped whenever the full resolution kernel is replaced
+    image: Vec<f32>,
+    samples: u32,
+}
+
+impl<'fw> InteractiveTarget<'fw> {
+    fn new(half_precision: bool, config: &TracingConfig) -> Self {
+        let mut config = *config;
+        config.render.width = config.render.width.div_ceil(2);
+        config.render.height = config.render.height.div_ceil(2);
+        let pixel_count = (config.render.width * config.render.height) as usize;
+        let image = vec![0.0; pixel_count * 3];
+        Self {
+            config_buffers: ConfigBuffers::new(&config),
+            rng_buffer: GpuBuffer::from_slice(&FW, &initial_rng_state(config.render.width, config.render.height)),
+            output_buffer: AccumulationBuffer::new(half_precision, &image),
+            odd_buffer: GpuBuffer::from_slice(&FW, &vec![0.0; pixel_count]),
+            outlier_buffer: GpuBuffer::from_slice(&FW, &vec![Vec4::ZERO; pixel_count]),
+            first_hit_buffer: GpuBuffer::from_slice(&FW, &vec![FirstHit::default(); pixel_count]),
+            kernel: None,
+            image,
+            samples: 0,
+            config,
+        }
+    }
+
+    fn memory_usage(&self, half_precision: bool) -> ResourceUsage {
+        let pixel_count = (self.config.render.width * self.config.render.height) as u64;
+        let texel_size = AccumulationBuffer::texel_size(half_precision)
+            + (std::mem::size_of::<f32>() + std::mem::size_of::<Vec4>() + std::mem::size_of::<UVec2>() + std::mem::size_of::<FirstHit>()) as u64;
+        ResourceUsage {
+            name: "Interactive accumulation",
+            cpu_bytes: pixel_count * std::mem::size_of::<Vec4>() as u64,
+            gpu_bytes: pixel_count * texel_size,
+        }
+    }
+
+    // Starts accumulating again, from the current full resolution config
+    fn reset(&mut self, config: &TracingConfig) {
+        self.config = TracingConfig { render: RenderSettings { width: self.config.render.width, height: self.config.render.height, ..config.render }, ..*config };
+        self.config_buffers.write(&self.config);
+        self.samples = 0;
+        self.output_buffer.clear();
+        let pixel_count = (self.config.render.width * self.config.render.height) as usize;
+        let _ = self.odd_buffer.write(&vec![0.0; pixel_count]);
+        let _ = self.outlier_buffer.write(&vec![Vec4::ZERO; pixel_count]);
+    }
+
+    // Traces one more sample of every pixel
+    fn trace(&mut self, source: &[u8], features: u32, world: &GpuWorld<'fw>, skybox: &GpuConstImage<'fw, Rgba32Float>, blue_noise_buffer: &GpuBuffer<'fw, u32>) {
+        let kernel = self.kernel.get_or_insert_with(|| {
+            PathTracingKernel::new(source, features, &self.config_buffers, &self.rng_buffer, &self.output_buffer, &self.odd_buffer, &self.outlier_buffer, world, skybox, blue_noise_buffer, &self.first_hit_buffer, true)
+        });
+        self.config.camera.sample_count = self.samples;
+        self.config_buffers.write(&self.config);
+        let (width, height) = (self.config.render.width, self.config.render.height);
+        if self.config.render.morton_order != 0 {
+            kernel.enqueue(morton::tile_count(width, height), 1, 1, self.config.render.primary_pass != 0);
+        } else {
+            kernel.enqueue(width.div_ceil(8), height.div_ceil(8), 1, self.config.render.primary_pass != 0);
+        }
+        FW.poll_blocking();
+        self.samples += 1;
+    }
+
+    // Reads back the accumulation, upscaled to the full resolution image
+    fn resolve(&mut self, image_buffer: &mut [f32], screen_width: u32, screen_height: u32) {
+        self.output_buffer.read_mean(&mut self.image);
+        upscale_bilinear(&self.image, self.config.render.width, self.config.render.height, image_buffer, screen_width, screen_height);
+    }
+}
+
+// Creating an OIDN device takes hundreds of milliseconds, so the render thread keeps one around,
+// made the first time it denoises. The color filter borrows the auxiliary images, so it is made
+// for each frame, while the filter that prefilters them is only set up again when the image size changes.
 #[cfg(feature = "oidn")]
-fn denoise_image(width: usize, height: usize, input: &mut [f32]) {
-    let device = oidn::Device::new();
-    oidn::RayTracing::new(&device)
-        .hdr(true)
-        .srgb(false)
-        .image_dimensions(width, height)
-        .filter_in_place(input)
-        .expect("Filter config error!");
+struct Denoiser<'a> {
+    device: &'a std::cell::OnceCell<oidn::Device>,
+    prefilter: Option<((usize, usize), oidn::RayTracing<'a>)>,
+}
+
+#[cfg(feature = "oidn")]
+impl<'a> Denoiser<'a> {
+    fn new(device: &'a std::cell::OnceCell<oidn::Device>) -> Self {
+        Self { device, prefilter: None }
+    }
+
+    fn denoise(&mut self, width: usize, height: usize, input: &mut [f32], auxiliary: &mut DenoiseAuxiliary) {
+        puffin::profile_function!();
+        let device = self.device.get_or_init(oidn::Device::new);
+        if auxiliary.prefiltered.is_none() {
+            // Both are filtered as LDR color, so normals are moved into [0, 1] and back
+            let prefilter = match &mut self.prefilter {
+                Some((size, prefilter)) if *size == (width, height) => prefilter,
+                prefilter => {
+                    let mut ray_tracing = oidn::RayTracing::new(device);
+                    ray_tracing.hdr(false).srgb(false).image_dimensions(width, height);
+                    &mut prefilter.insert(((width, height), ray_tracing)).1
+                }
+            };
+            let mut albedo = auxiliary.albedo.iter().flat_map(|albedo| albedo.to_array()).collect::<Vec<_>>();
+            let mut normal = auxiliary.normal.iter().flat_map(|normal| (*normal * 0.5 + 0.5).to_array()).collect::<Vec<_>>();
+            prefilter.filter_in_place(&mut albedo).expect("Filter config error!");
+            prefilter.filter_in_place(&mut normal).expect("Filter config error!");
+            normal.iter_mut().for_each(|value| *value = *value * 2.0 - 1.0);
+            auxiliary.prefiltered = Some((albedo, normal));
+        }
+
+        let Some((albedo, normal)) = &auxiliary.prefiltered else {
+            return;
+        };
+        let mut filter = oidn::RayTracing::new(device);
+        filter.hdr(true).srgb(false).image_dimensions(width, height).albedo_normal(albedo, normal);
+        filter.filter_in_place(input).expect("Filter config error!");
+    }
+}
+
+// The denoiser's guide images are averaged from one jittered denoise_auxiliary ray per pixel,
+// taken each time the image is denoised, up to this many. They start out noisy, so OIDN cleans
+// them up on their own before they guide the color filter, as its documentation suggests.
+#[cfg(feature = "oidn")]
+const AUXILIARY_SAMPLES: u32 = 64;
+
+// First-hit albedo and normal of each pixel for OIDN, traced on the CPU like the motion vectors
+#[cfg(feature = "oidn")]
+#[derive(Default)]
+struct DenoiseAuxiliary {
+    albedo: Vec<Vec3>,
+    normal: Vec<Vec3>,
+    samples: u32,
+    prefiltered: Option<(Vec<f32>, Vec<f32>)>, // Albedo and normal for OIDN, taken at the current sample count
+}
+
+#[cfg(feature = "oidn")]
+impl DenoiseAuxiliary {
+    fn clear(&mut self) {
+        self.samples = 0;
+        self.prefiltered = None;
+    }
+
+    fn accumulate(
+        &mut self,
+        config: &TracingConfig,
+        per_vertex_buffer: &[PerVertexData],
+        index_buffer: &[UVec4],
+        nodes: &[BVHNode],
+        material_data_buffer: &[MaterialData],
+        atlas: &CpuImage,
+    ) {
+        if self.samples >= AUXILIARY_SAMPLES {
+            return;
+        }
+        puffin::profile_function!();
+        let width = config.render.width;
+        let pixel_count = (width * config.render.height) as usize;
+        self.albedo.resize(pixel_count, Vec3::ZERO);
+        self.normal.resize(pixel_count, Vec3::ZERO);
+        // The R2 sequence, offsetting every pixel the same way each time
+        let jitter = if config.render.jitter != 0 {
+            (Vec2::new(0.754_877_7, 0.569_840_3) * self.samples as f32 + 0.5).fract()
+        } else {
+            Vec2::splat(0.5)
+        };
+        let weight = 1.0 / (self.samples + 1) as f32;
+        self.albedo.par_iter_mut().zip(self.normal.par_iter_mut()).enumerate().for_each(|(i, (albedo, normal))| {
+            let pixel = UVec2::new(i as u32 % width, i as u32 / width);
+            let (sample_albedo, sample_normal) = kernels::denoise_auxiliary(
+                config,
+                pixel.as_vec2() + jitter,
+                per_vertex_buffer,
+                index_buffer,
+                nodes,
+                material_data_buffer,
+                &shared_structs::Sampler,
+                atlas,
+            );
+            *albedo = albedo.lerp(sample_albedo, weight);
+            *normal = normal.lerp(sample_normal, weight);
+        });
+        self.samples += 1;
+        self.prefiltered = None;
+    }
+}
+
+// Denoises, then puts back the raw samples the user wants to see, outside the denoise region or
+// for the comparison
+#[cfg(feature = "oidn")]
+fn denoise_frame(state: &TracingState, denoiser: &mut Denoiser, auxiliary: &mut DenoiseAuxiliary, width: u32, height: u32, image_buffer: &mut [f32]) {
+    let region = *state.denoise_region.read();
+    let comparison = state.denoise_comparison.load(Ordering::Relaxed);
+    let raw = (region.is_some() || comparison).then(|| image_buffer.to_vec());
+    denoiser.denoise(width as usize, height as usize, image_buffer, auxiliary);
+    if let Some(raw) = raw {
+        if let Some(region) = region {
+            blend_denoise_region(image_buffer, &raw, width, region);
+        }
+        if comparison {
+            compose_denoise_comparison(image_buffer, &raw, width);
+        }
+    }
+}
+
+// Pixels over which the denoise region fades into the raw samples
+const DENOISE_REGION_FEATHER: f32 = 8.0;
+
+// Keeps the denoised image only inside the region, fading into the raw samples at its edges
+pub fn blend_denoise_region(denoised: &mut [f32], raw: &[f32], width: u32, region: Tile) {
+    let (min_x, min_y) = (region.x as f32, region.y as f32);
+    let (max_x, max_y) = (min_x + region.width as f32, min_y + region.height as f32);
+    for (i, (denoised, raw)) in denoised.chunks_mut(3).zip(raw.chunks(3)).enumerate() {
+        let x = (i as u32 % width) as f32 + 0.5;
+        let y = (i as u32 / width) as f32 + 0.5;
+        let inside = (x - min_x).min(max_x - x).min(y - min_y).min(max_y - y);
+        let weight = (inside / DENOISE_REGION_FEATHER).clamp(0.0, 1.0);
+        for (denoised, raw) in denoised.iter_mut().zip(raw) {
+            *denoised = raw + (*denoised - raw) * weight;
+        }
+    }
+}
+
+// Puts the raw samples back into the left half of a denoised image, to compare the two
+pub fn compose_denoise_comparison(denoised: &mut [f32], raw: &[f32], width: u32) {
+    let row = width as usize * 3;
+    let left = (width / 2) as usize * 3;
+    for (denoised, raw) in denoised.chunks_mut(row).zip(raw.chunks(row)) {
+        denoised[..left].copy_from_slice(&raw[..left]);
+    }
 }
 
 pub fn trace_gpu(
@@ -138,88 +1272,415 @@ pub fn trace_gpu(
     skybox_path: Option<&str>,
     state: Arc<TracingState>,
 ) {
-    let Some(world) = World::from_path(scene_path).map(|w| w.into_gpu()) else {
-        return;
+    let key = WorldKey::new(scene_path, &state);
+    let (mut world, cached_gpu_world) = match take_cached_world(&key, &state) {
+        Some((world, gpu_world)) => (world, Some(gpu_world)),
+        None => match load_world(scene_path, &state) {
+            Some(world) => (world, None),
+            None => return,
+        },
     };
-    let skybox = skybox_path.and_then(load_dynamic_image).map(dynamic_image_to_gpu_image).unwrap_or_else(|| fallback_gpu_image());
 
-    let screen_width = state.config.read().width;
-    let screen_height = state.config.read().height;
-    let pixel_count = (screen_width * screen_height) as usize;
-    let mut rng = rand::thread_rng();
-    let mut rng_data_blue: Vec<UVec2> = vec![UVec2::ZERO; pixel_count];
-    let mut rng_data_uniform: Vec<UVec2> = vec![UVec2::ZERO; pixel_count];
-    for y in 0..screen_height {
-        for x in 0..screen_width {
-            let pixel_index = (y * screen_width + x) as usize;
-            let pixel = BLUE_TEXTURE.get_pixel(x % BLUE_TEXTURE.width(), y % BLUE_TEXTURE.height())[0] as f32 / 255.0;
-            rng_data_blue[pixel_index].x = 0;
-            rng_data_blue[pixel_index].y = (pixel * 4294967295.0) as u32;
-            rng_data_uniform[pixel_index].x = rand::Rng::gen(&mut rng);
-        }
+    let screen_width = state.config.read().render.width;
+    let screen_height = state.config.read().render.height;
+    let half_precision = state.half_precision.load(Ordering::Relaxed);
+    if let Err(err) = validate_gpu_limits(&world, (screen_width * screen_height) as u64, half_precision) {
+        tracing::error!("{} Falling back to CPU rendering.", err);
+        state.gpu_failed.store(true, Ordering::Relaxed);
+        return trace_cpu_world(world, skybox_path, state);
+    }
+
+    if !state.procedural_textures.read().is_empty() {
+        tracing::warn!("Procedural textures are only supported when rendering on the CPU, the GPU uses the material's own textures.");
     }
 
+    let skybox_source = load_skybox(&state, skybox_path);
+    let skybox_size = skybox_source.as_ref().map_or((2, 2), |s| s.dimensions());
+    *state.scene_statistics.write() = Some(world.statistics.clone());
+    publish_material_library(&state, &world);
+    state.config.write().render.ray_offset = scene_ray_offset(world.statistics.model_bounds);
+    let material_update = apply_material_edits(&state, &world.material_names, &world.per_vertex_buffer, &world.index_buffer, &mut world.material_data_buffer);
+    let (edited_materials, light_pick_table) = material_update.map_or((Vec::new(), None), |update| (update.edited, update.light_pick_table));
+    let lights_edited = light_pick_table.is_some();
+    if let Some(table) = light_pick_table {
+        world.light_pick_buffer = table;
+    }
+    let visibility_edited = sync_material_visibility(&state, &mut world.index_buffer);
+    let proxy_edited = match world.proxy.as_mut() {
+        Some(proxy) => apply_proxy_edits(&state, proxy, &world.per_vertex_buffer, &world.material_data_buffer, lights_edited),
+        None => false,
+    };
+    let texture_receiver = start_texture_loading(&mut world, &state);
+    record_memory_usage(&state, &world, skybox_size, (screen_width * screen_height) as u64);
+
+    let gpu_world = match cached_gpu_world {
+        // Only edits made since the last render have to be uploaded
+        Some(mut gpu_world) => {
+            upload_materials(&gpu_world.material_data_buffer, &world.material_data_buffer, &edited_materials);
+            if lights_edited {
+                gpu_world.light_pick_buffer = GpuBuffer::from_slice(&FW, &world.light_pick_buffer);
+            }
+            if visibility_edited {
+                let _ = gpu_world.index_buffer.write(&world.index_buffer);
+            }
+            if proxy_edited {
+                gpu_world.proxy = world.proxy.as_ref().map(Proxy::to_gpu);
+            }
+            gpu_world
+        }
+        None => world.to_gpu(),
+    };
+
+    // Kept around to rebuild the light pick table when emission is edited, and for the debug tracer
+    let World {
+        material_names,
+        per_vertex_buffer: per_vertex_data,
+        index_buffer: mut indices,
+        bvh,
+        material_data_buffer: mut material_datas,
+        light_pick_buffer: mut light_pick_table,
+        atlas,
+        statistics,
+        mut proxy,
+        ..
+    } = world;
+    let nodes = bvh.nodes;
+    let mut debug_textures = DebugTextures::new(atlas.clone(), skybox_source.clone());
+    // Streamed textures are written into this, and the whole atlas is uploaded again
+    let mut atlas_source = texture_receiver.as_ref().map(|_| atlas.clone());
+
+    let mut world = gpu_world;
+    let skybox = skybox_source.map(dynamic_image_to_gpu_image).unwrap_or_else(|| fallback_gpu_image());
+
+    let rng_data = initial_rng_state(screen_width, screen_height);
+
     // Restore previous state, if there is any
-    let samples_init = state.samples.load(Ordering::Relaxed) as f32;
-    let output_buffer_init = state.framebuffer.read().chunks(3).map(|c| Vec4::new(c[0], c[1], c[2], 1.0) * samples_init).collect::<Vec<_>>();
+    let mut output_buffer = AccumulationBuffer::new(half_precision, &state.framebuffer.read());
+    let mut odd_means = odd_means_from_image(&state.framebuffer.read());
+    let odd_buffer = GpuBuffer::from_slice(&FW, &odd_means);
 
     // Setup tracing state
     let pixel_count = (screen_width * screen_height) as u64;
-    let config_buffer = GpuUniformBuffer::from_slice(&FW, &[*state.config.read()]);
-    let rng_buffer = GpuBuffer::from_slice(&FW, if state.use_blue_noise.load(Ordering::Relaxed) { &rng_data_blue } else { &rng_data_uniform });
-    let output_buffer = GpuBuffer::from_slice(&FW, &output_buffer_init);
+    let mut outliers = vec![Vec4::ZERO; pixel_count as usize];
+    let outlier_buffer = GpuBuffer::from_slice(&FW, &outliers);
+    let mut config = *state.config.read();
+    update_previous_camera(&mut config, None);
+    let mut motion_vectors_stale = true;
+    let mut burst_pending = false;
+    let config_buffers = ConfigBuffers::new(&config);
+    let rng_buffer = GpuBuffer::from_slice(&FW, &rng_data);
+    let blue_noise_buffer = GpuBuffer::from_slice(&FW, &BLUE_NOISE);
+    let first_hit_buffer = GpuBuffer::from_slice(&FW, &vec![FirstHit::default(); pixel_count as usize]);
 
-    let mut image_buffer_raw: Vec<Vec4> = vec![Vec4::ZERO; pixel_count as usize];
     let mut image_buffer: Vec<f32> = vec![0.0; pixel_count as usize * 3];
+    let mut variance_buffer: Vec<f32> = Vec::new();
+    let mut preview = PreviewBuffer::new(pixel_count as usize * 3);
+    #[cfg(feature = "oidn")]
+    let oidn_device = std::cell::OnceCell::new();
+    #[cfg(feature = "oidn")]
+    let mut denoiser = Denoiser::new(&oidn_device);
+    #[cfg(feature = "oidn")]
+    let mut auxiliary = DenoiseAuxiliary::default();
+    let mut packed_buffer: Vec<u32> = Vec::new();
 
-    let rt = PathTracingKernel::new(&config_buffer, &rng_buffer, &output_buffer, &world, &skybox);
+    // The path tracing kernel is specialized on these, and swapped out when they change
+    let has_normal_maps = material_datas.iter().any(|material| material.has_normal_texture());
+    let mut features = kernel_features(&config, has_normal_maps);
+
+    // Replaced when hot reloading, which keeps the scene and camera
+    let mut kernel = Cow::Borrowed(KERNEL);
+    let mut kernel_watcher = KernelWatcher::new();
+    let mut rt = PathTracingKernel::new(&kernel, features, &config_buffers, &rng_buffer, &output_buffer, &odd_buffer, &outlier_buffer, &world, &skybox, &blue_noise_buffer, &first_hit_buffer, false);
+    let mut interactive_target = InteractiveTarget::new(half_precision, &config);
+    state.memory_usage.write().push(interactive_target.memory_usage(half_precision));
+
+    'render: while state.running.load(Ordering::Relaxed) {
+        puffin::profile_scope!("Sync");
+
+        if state.hot_reload.load(Ordering::Relaxed) {
+            if let Some(reloaded) = kernel_watcher.poll() {
+                // Invalid kernels make wgpu panic, which would otherwise fall back to the CPU
+                let created = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                    PathTracingKernel::new(&reloaded, features, &config_buffers, &rng_buffer, &output_buffer, &odd_buffer, &outlier_buffer, &world, &skybox, &blue_noise_buffer, &first_hit_buffer, false)
+                }));
+                match created {
+                    Ok(reloaded_rt) => {
+                        rt = reloaded_rt;
+                        interactive_target.kernel = None;
+                        kernel = Cow::Owned(reloaded);
+                        state.mark_dirty(DirtyFlags::LIGHTING);
+                    }
+                    Err(_) => tracing::error!("Failed to create the reloaded kernel, keeping the current one."),
+                }
+            }
+        }
 
-    while state.running.load(Ordering::Relaxed) {
         // Dispatch
-        let sync_rate = state.sync_rate.load(Ordering::Relaxed);
+        let mut sync_rate = state.sync_rate.load(Ordering::Relaxed);
+        let mut frame_budget = Duration::from_millis(state.frame_budget_ms.load(Ordering::Relaxed) as u64);
+        // Once the camera stops, the image gets a burst of samples to clean up quickly. Nobody is
+        // interacting by then, so the burst isn't held back by the frame budget.
+        if burst_pending && !state.interacting.load(Ordering::Relaxed) {
+            burst_pending = false;
+            let burst = state.burst_samples.load(Ordering::Relaxed).saturating_sub(state.samples.load(Ordering::Relaxed));
+            if burst > 0 {
+                sync_rate = burst;
+                frame_budget = Duration::ZERO;
+            }
+        }
+        let sample_limit = state.sample_limit.load(Ordering::Relaxed);
         let mut flush = false;
-        let mut finished_samples = 0;
-        for _ in 0..sync_rate {
-            rt.0.enqueue(screen_width.div_ceil(8), screen_height.div_ceil(8), 1);
-            FW.poll_blocking();
-            finished_samples += 1;
+        let mut finished_samples: u32 = 0;
+        // Each dispatch is waited on before the next, so wall time here is GPU time
+        let trace_start = Instant::now();
+        let interactive = state.interactive_upscale.load(Ordering::Relaxed) && state.interacting.load(Ordering::Relaxed);
+        if interactive {
+            puffin::profile_scope!("Dispatch");
+            interactive_target.trace(&kernel, features, &world, &skybox, &blue_noise_buffer);
+        } else {
+            for _ in 0..sync_rate {
+                puffin::profile_scope!("Dispatch");
+                config.camera.sample_count = state.samples.load(Ordering::Relaxed) + finished_samples;
+                // Only change the order pixels are traced in, and how first hits are found, so they are picked up without a reset
+                config.render.morton_order = state.config.read().render.morton_order;
+                config.render.primary_pass = state.config.read().render.primary_pass;
+                config_buffers.write(&config);
+                if config.render.morton_order != 0 {
+                    rt.enqueue(morton::tile_count(screen_width, screen_height), 1, 1, config.render.primary_pass != 0);
+                } else {
+                    rt.enqueue(screen_width.div_ceil(8), screen_height.div_ceil(8), 1, config.render.primary_pass != 0);
+                }
+                FW.poll_blocking();
+                finished_samples += 1;
             
-            flush |= state.interacting.load(Ordering::Relaxed) || state.dirty.load(Ordering::Relaxed);
-            if flush {
-                break;
-            }
-            if !state.running.load(Ordering::Relaxed) {
-                return;
+                flush |= state.interacting.load(Ordering::Relaxed) || state.is_dirty();
+                flush |= sample_limit != 0 && state.samples.load(Ordering::Relaxed) + finished_samples >= sample_limit;
+                // Caps the dispatches per present, so slow scenes don't leave the GUI waiting on a full sync
+                flush |= !frame_budget.is_zero() && trace_start.elapsed() >= frame_budget;
+                if flush {
+                    break;
+                }
+                if !state.running.load(Ordering::Relaxed) {
+                    break 'render;
+                }
             }
         }
+        let dispatch_time = trace_start.elapsed();
+        let trace_time = dispatch_time / finished_samples.max(1);
         state.samples.fetch_add(finished_samples, Ordering::Relaxed);
+        let dirty = state.take_dirty();
+        let reset = dirty.resets_accumulation();
+
+        if let Some(pixel) = state.debug_pixel.lock().take() {
+            let (atlas, skybox) = debug_textures.images();
+            let scene = DebugScene {
+                per_vertex_buffer: &per_vertex_data,
+                index_buffer: &indices,
+                nodes: &nodes,
+                material_data_buffer: &material_datas,
+                light_pick_buffer: &light_pick_table,
+                atlas: &atlas,
+                skybox: &skybox,
+            };
+            // The per-pixel RNG state lives on the GPU, so this is a path the renderer could take, rather than the next one
+            let rng = UVec2::new(state.samples.load(Ordering::Relaxed), pixel.y * screen_width + pixel.x);
+            *state.debug_path.write() = Some(trace_debug_path(&scene, &state.config.read(), pixel, rng));
+        }
 
         // Readback from GPU
-        let _ = output_buffer.read_blocking(&mut image_buffer_raw);
-        let sample_count = state.samples.load(Ordering::Relaxed) as f32;
-        for (i, col) in image_buffer_raw.iter().enumerate() {
-            image_buffer[i * 3] = col.x / sample_count;
-            image_buffer[i * 3 + 1] = col.y / sample_count;
-            image_buffer[i * 3 + 2] = col.z / sample_count;
+        let resolve_start = Instant::now();
+        {
+            puffin::profile_scope!("Resolve");
+            if interactive {
+                interactive_target.resolve(&mut image_buffer, screen_width, screen_height);
+            } else {
+                output_buffer.read_mean(&mut image_buffer);
+                let _ = odd_buffer.read_blocking(&mut odd_means);
+                publish_variance(&state, &image_buffer, &odd_means, &mut variance_buffer);
+                if config.render.firefly_rejection > 0.0 {
+                    let _ = outlier_buffer.read_blocking(&mut outliers);
+                    spread_rejected_energy(&mut image_buffer, &outliers, screen_width, screen_height);
+                }
+            }
         }
+        let resolve_time = resolve_start.elapsed();
 
         // Denoise
+        let denoise_start = Instant::now();
         #[cfg(feature = "oidn")]
-        if state.denoise.load(Ordering::Relaxed) && !flush {
-            denoise_image(screen_width as usize, screen_height as usize, &mut image_buffer);
+        if state.denoise.load(Ordering::Relaxed) && !reset && !interactive {
+            let (atlas, _) = debug_textures.images();
+            auxiliary.accumulate(&config, &per_vertex_data, &indices, &nodes, &material_datas, &atlas);
+            denoise_frame(&state, &mut denoiser, &mut auxiliary, screen_width, screen_height, &mut image_buffer);
         }
+        let denoise_time = denoise_start.elapsed();
+        let timings = PassTimings {
+            trace: trace_time,
+            resolve: resolve_time,
+            denoise: denoise_time,
+        };
+        *state.timings.write() = timings;
+        state.timing_totals.lock().add(&timings, finished_samples);
+        *state.render_time.lock() += dispatch_time + resolve_time + denoise_time;
+
+        preview.apply(&mut image_buffer, state.samples.load(Ordering::Relaxed));
 
         // Push to render thread
-        state.framebuffer.write().copy_from_slice(image_buffer.as_slice());
+        if half_precision {
+            pack_framebuffer(&image_buffer, &mut packed_buffer);
+            state.packed_framebuffer.publish(&mut packed_buffer);
+        }
+        state.framebuffer.publish(&mut image_buffer);
+        if !frame_budget.is_zero() {
+            wait_for_present(&state, half_precision, frame_budget);
+        }
+        wait_for_power(&state, dispatch_time);
 
         // Interaction
-        if flush {
-            state.dirty.store(false, Ordering::Relaxed);
+        if reset {
             state.samples.store(0, Ordering::Relaxed);
-            let _ = config_buffer.write(&[*state.config.read()]);
-            let _ = output_buffer.write(&vec![Vec4::ZERO; pixel_count as usize]);
-            let _ = rng_buffer.write(if state.use_blue_noise.load(Ordering::Relaxed) { &rng_data_blue } else { &rng_data_uniform });
+            *state.render_time.lock() = Duration::ZERO;
+            preview.active = dirty.wants_preview() && state.interactive_preview.load(Ordering::Relaxed);
+            let previous = config;
+            config = *state.config.read();
+            update_previous_camera(&mut config, dirty.contains(DirtyFlags::CAMERA).then_some(&previous));
+            motion_vectors_stale = true;
+            burst_pending |= dirty.contains(DirtyFlags::CAMERA);
+            config_buffers.write(&config);
+            interactive_target.reset(&config);
+            output_buffer.clear();
+            let _ = odd_buffer.write(&vec![0.0; pixel_count as usize]);
+            let _ = outlier_buffer.write(&vec![Vec4::ZERO; pixel_count as usize]);
+            #[cfg(feature = "oidn")]
+            auxiliary.clear();
+            if kernel_features(&config, has_normal_maps) != features {
+                features = kernel_features(&config, has_normal_maps);
+                rt = PathTracingKernel::new(&kernel, features, &config_buffers, &rng_buffer, &output_buffer, &odd_buffer, &outlier_buffer, &world, &skybox, &blue_noise_buffer, &first_hit_buffer, false);
+                interactive_target.kernel = None;
+            }
+            if dirty.contains(DirtyFlags::SAMPLING) {
+                let _ = rng_buffer.write(&rng_data);
+            }
+            if dirty.contains(DirtyFlags::MATERIALS) {
+                let mut lights_edited = false;
+                if let Some(update) = apply_material_edits(&state, &material_names, &per_vertex_data, &indices, &mut material_datas) {
+                    upload_materials(&world.material_data_buffer, &material_datas, &update.edited);
+                    // The table grows and shrinks with the number of emitters, so it gets a new buffer and bindings.
+                    // Linking edits keep the same table, and the kernel keeps its bindings.
+                    if let Some(table) = update.light_pick_table {
+                        world.light_pick_buffer = GpuBuffer::from_slice(&FW, &table);
+                        light_pick_table = table;
+                        lights_edited = true;
+                        rt = PathTracingKernel::new(&kernel, features, &config_buffers, &rng_buffer, &output_buffer, &odd_buffer, &outlier_buffer, &world, &skybox, &blue_noise_buffer, &first_hit_buffer, false);
+                        interactive_target.kernel = None;
+                    }
+                }
+                if sync_material_visibility(&state, &mut indices) {
+                    let _ = world.index_buffer.write(&indices);
+                }
+                if let Some(proxy) = proxy.as_mut() {
+                    if apply_proxy_edits(&state, proxy, &per_vertex_data, &material_datas, lights_edited) {
+                        world.proxy = Some(proxy.to_gpu());
+                        interactive_target.kernel = None;
+                    }
+                }
+            }
+            if dirty.contains(DirtyFlags::TEXTURES) {
+                if let (Some(receiver), Some(atlas)) = (&texture_receiver, &mut atlas_source) {
+                    for texture in receiver.try_iter() {
+                        texture.write_to_image(atlas);
+                    }
+                    world.atlas = GpuConstImage::from_bytes(&FW, &atlas.to_rgba8(), 4096, 4096);
+                    debug_textures.set_atlas(atlas.clone());
+                    rt = PathTracingKernel::new(&kernel, features, &config_buffers, &rng_buffer, &output_buffer, &odd_buffer, &outlier_buffer, &world, &skybox, &blue_noise_buffer, &first_hit_buffer, false);
+                    interactive_target.kernel = None;
+                    update_texture_cache_usage(&state);
+                }
+            }
+        }
+        if dirty.contains(DirtyFlags::SEED) {
+            config.render.seed = state.config.read().render.seed;
+        }
+        if state.motion_vectors_enabled.load(Ordering::Relaxed) {
+            if motion_vectors_stale {
+                publish_motion_vectors(&state, &config, &per_vertex_data, &indices, &nodes);
+                motion_vectors_stale = false;
+            }
+        } else {
+            motion_vectors_stale = true;
+        }
+
+        // Rendering a fixed number of samples, such as in tests
+        if sample_limit != 0 && state.samples.load(Ordering::Relaxed) >= sample_limit {
+            state.running.store(false, Ordering::Relaxed);
         }
+        if reached_error_target(&state) {
+            state.running.store(false, Ordering::Relaxed);
+        }
+    }
+
+    // A world whose textures were still streaming in would be missing them when reused
+    if texture_receiver.is_none() {
+        *WORLD_CACHE.lock() = Some(CachedWorld {
+            key,
+            gpu: world,
+            world: World {
+                bvh: BVH { nodes },
+                per_vertex_buffer: per_vertex_data,
+                index_buffer: indices,
+                atlas,
+                texture_jobs: Vec::new(),
+                material_data_buffer: material_datas,
+                light_pick_buffer: light_pick_table,
+                material_names,
+                // The number of emissive triangles follows the edits
+                statistics: state.scene_statistics.read().clone().unwrap_or(statistics),
+                proxy,
+            },
+        });
+    }
+}
+
+// The GUI shares the GPU, so give it a chance to present a published frame before dispatching more.
+// It shows either framebuffer in half precision mode, depending on the view. Without a GUI to take
+// the frame, this gives up after the timeout.
+fn wait_for_present(state: &TracingState, half_precision: bool, timeout: Duration) {
+    let start = Instant::now();
+    while !state.framebuffer.is_taken() && !(half_precision && state.packed_framebuffer.is_taken()) && start.elapsed() < timeout {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+// Saves power while the GUI asks for it. In low power mode, idles for as long as the last sync
+// took, roughly halving the load. While paused, nothing is rendered until resumed or stopped.
+fn wait_for_power(state: &TracingState, sync_time: Duration) {
+    if state.low_power.load(Ordering::Relaxed) {
+        std::thread::sleep(sync_time.min(Duration::from_secs(1)));
+    }
+    while state.paused.load(Ordering::Relaxed) && state.running.load(Ordering::Relaxed) {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// Device loss and driver issues surface as panics inside gpgpu. Rather than killing
+// the render thread, catch those and continue rendering on the CPU.
+pub fn trace_gpu_with_cpu_fallback(
+    scene_path: &str,
+    skybox_path: Option<&str>,
+    state: Arc<TracingState>,
+) {
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        trace_gpu(scene_path, skybox_path, state.clone())
+    }));
+    if let Err(panic) = result {
+        let reason = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown error".to_string());
+        tracing::error!("GPU rendering failed: {}. The GPU device may have been lost, or the driver rejected the kernel. Falling back to CPU rendering.", reason);
+        state.gpu_failed.store(true, Ordering::Relaxed);
+        trace_cpu(scene_path, skybox_path, state);
     }
 }
 
@@ -228,61 +1689,141 @@ pub fn trace_cpu(
     skybox_path: Option<&str>,
     state: Arc<TracingState>,
 ) {
-    let Some(world) = World::from_path(scene_path) else {
+    let Some(world) = load_world(scene_path, &state) else {
         return;
     };
+    trace_cpu_world(world, skybox_path, state);
+}
+
+// The CPU backend gets its own pool, so it can be resized and deprioritized to keep the GUI responsive.
+// A thread count of 0 uses one thread per logical core.
+fn make_cpu_thread_pool(threads: u32, low_priority: bool) -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads as usize)
+        .thread_name(|i| format!("CPU tracer {}", i))
+        .start_handler(move |_| {
+            if low_priority {
+                if let Err(err) = thread_priority::set_current_thread_priority(thread_priority::ThreadPriority::Min) {
+                    tracing::warn!("Failed to lower the priority of a CPU render thread: {:?}", err);
+                }
+            }
+        })
+        .build()
+        .expect("Failed to create CPU thread pool.")
+}
+
+pub fn trace_cpu_world(
+    mut world: World,
+    skybox_path: Option<&str>,
+    state: Arc<TracingState>,
+) {
     let mut skybox_image_buffer = fallback_cpu_buffer();
     let mut skybox_size = (2, 2);
-    if let Some(skybox_source) = skybox_path.and_then(load_dynamic_image) {
+    if let Some(skybox_source) = load_skybox(&state, skybox_path) {
         skybox_size = skybox_source.dimensions();
         skybox_image_buffer = dynamic_image_to_cpu_buffer(skybox_source);
     }
     let skybox_image = CpuImage::new(&skybox_image_buffer, skybox_size.0, skybox_size.1);
 
-    let screen_width = state.config.read().width;
-    let screen_height = state.config.read().height;
-    let pixel_count = (screen_width * screen_height) as usize;
-    let mut rng = rand::thread_rng();
-    let mut rng_data_blue: Vec<UVec2> = vec![UVec2::ZERO; pixel_count];
-    let mut rng_data_uniform: Vec<UVec2> = vec![UVec2::ZERO; pixel_count];
-    for y in 0..screen_height {
-        for x in 0..screen_width {
-            let pixel_index = (y * screen_width + x) as usize;
-            let pixel = BLUE_TEXTURE.get_pixel(x % BLUE_TEXTURE.width(), y % BLUE_TEXTURE.height())[0] as f32 / 255.0;
-            rng_data_blue[pixel_index].x = 0;
-            rng_data_blue[pixel_index].y = (pixel * 4294967295.0) as u32;
-            rng_data_uniform[pixel_index].x = rand::Rng::gen(&mut rng);
-        }
+    let screen_width = state.config.read().render.width;
+    let screen_height = state.config.read().render.height;
+    *state.scene_statistics.write() = Some(world.statistics.clone());
+    publish_material_library(&state, &world);
+    state.config.write().render.ray_offset = scene_ray_offset(world.statistics.model_bounds);
+    if let Some(table) = apply_material_edits(&state, &world.material_names, &world.per_vertex_buffer, &world.index_buffer, &mut world.material_data_buffer).and_then(|update| update.light_pick_table) {
+        world.light_pick_buffer = table;
     }
+    sync_material_visibility(&state, &mut world.index_buffer);
+    let texture_receiver = start_texture_loading(&mut world, &state);
+    record_memory_usage(&state, &world, skybox_size, (screen_width * screen_height) as u64);
 
     // Reset previous state, if there is any
-    let samples_init = state.samples.load(Ordering::Relaxed) as f32;
-    let mut output_buffer = state.framebuffer.read().chunks(3).map(|c| Vec4::new(c[0], c[1], c[2], 1.0) * samples_init).collect::<Vec<_>>();
+    let mut output_buffer = state.framebuffer.read().chunks(3).map(|c| Vec4::new(c[0], c[1], c[2], 1.0)).collect::<Vec<_>>();
+    let mut odd_means = odd_means_from_image(&state.framebuffer.read());
 
     // Setup tracing state
     let pixel_count = (screen_width * screen_height) as u64;
-    let mut rng_buffer = if state.use_blue_noise.load(Ordering::Relaxed) { &mut rng_data_blue } else { &mut rng_data_uniform };
+    let mut outliers = vec![Vec4::ZERO; pixel_count as usize];
+    let mut rng_buffer = initial_rng_state(screen_width, screen_height);
 
     let mut image_buffer: Vec<f32> = vec![0.0; pixel_count as usize * 3];
+    let mut variance_buffer: Vec<f32> = Vec::new();
+    let mut preview = PreviewBuffer::new(pixel_count as usize * 3);
+    #[cfg(feature = "oidn")]
+    let oidn_device = std::cell::OnceCell::new();
+    #[cfg(feature = "oidn")]
+    let mut denoiser = Denoiser::new(&oidn_device);
+    #[cfg(feature = "oidn")]
+    let mut auxiliary = DenoiseAuxiliary::default();
 
     let atlas_width = world.atlas.width();
     let atlas_height = world.atlas.height();
-    let atlas_buffer = dynamic_image_to_cpu_buffer(world.atlas);
-    let atlas_image = CpuImage::new(&atlas_buffer, atlas_width, atlas_height);
+    let mut atlas_buffer = dynamic_image_to_cpu_buffer(world.atlas);
+    let procedural_textures = bind_procedural_textures(&state.procedural_textures.read(), &world.material_names, &mut world.material_data_buffer);
+    let procedural_regions = procedural_regions(&procedural_textures);
+
+    let morton_order = morton_pixel_order(screen_width, screen_height);
+
+    let mut pool_settings = (state.cpu_threads.load(Ordering::Relaxed), state.cpu_low_priority.load(Ordering::Relaxed));
+    let mut pool = make_cpu_thread_pool(pool_settings.0, pool_settings.1);
+
+    // The CPU reads the live config every sample, so the camera motion is tracked separately
+    let mut motion_config = *state.config.read();
+    update_previous_camera(&mut motion_config, None);
+    let mut motion_vectors_stale = true;
 
     while state.running.load(Ordering::Relaxed) {
+        puffin::profile_scope!("Sync");
+
         // Dispatch
-        let flush = state.interacting.load(Ordering::Relaxed) || state.dirty.load(Ordering::Relaxed);
+        let dirty = state.take_dirty();
+        let reset = dirty.resets_accumulation();
+        if dirty.contains(DirtyFlags::MATERIALS) {
+            if let Some(table) = apply_material_edits(&state, &world.material_names, &world.per_vertex_buffer, &world.index_buffer, &mut world.material_data_buffer).and_then(|update| update.light_pick_table) {
+                world.light_pick_buffer = table;
+            }
+            sync_material_visibility(&state, &mut world.index_buffer);
+        }
+        if dirty.contains(DirtyFlags::TEXTURES) {
+            if let Some(receiver) = &texture_receiver {
+                for texture in receiver.try_iter() {
+                    texture.write_to_cpu_buffer(&mut atlas_buffer, atlas_width);
+                }
+                update_texture_cache_usage(&state);
+            }
+        }
+        let atlas_image = CpuImage::new(&atlas_buffer, atlas_width, atlas_height).with_procedural(&procedural_regions);
+        if let Some(pixel) = state.debug_pixel.lock().take() {
+            let scene = DebugScene {
+                per_vertex_buffer: &world.per_vertex_buffer,
+                index_buffer: &world.index_buffer,
+                nodes: &world.bvh.nodes,
+                material_data_buffer: &world.material_data_buffer,
+                light_pick_buffer: &world.light_pick_buffer,
+                atlas: &atlas_image,
+                skybox: &skybox_image,
+            };
+            // Uses the pixel's own RNG state, so this is exactly the path its next sample takes
+            let rng = rng_buffer[(pixel.y * screen_width + pixel.x) as usize];
+            *state.debug_path.write() = Some(trace_debug_path(&scene, &state.config.read(), pixel, rng));
+        }
+        let settings = (state.cpu_threads.load(Ordering::Relaxed), state.cpu_low_priority.load(Ordering::Relaxed));
+        if settings != pool_settings {
+            pool = make_cpu_thread_pool(settings.0, settings.1);
+            pool_settings = settings;
+        }
+        let trace_start = Instant::now();
         {
-            let config = state.config.read();
-            let outputs = output_buffer.par_chunks_mut(screen_width as usize).enumerate();
-            let rngs = rng_buffer.par_chunks_mut(screen_width as usize);
-            outputs.zip(rngs).for_each(|((y, output), rng)| {
-                for x in 0..screen_width {
-                    let (radiance, rng_state) = kernels::trace_pixel(
-                        UVec3::new(x, y as u32, 1),
+            puffin::profile_scope!("Dispatch");
+            pool.install(|| {
+                let config = state.config.read();
+                let sample_count = state.samples.load(Ordering::Relaxed);
+                let weight = 1.0 / (sample_count + 1) as f32;
+                let trace = |x: u32, y: u32, rng: UVec2| {
+                    kernels::trace_pixel(
+                        UVec3::new(x, y, 1),
                         &config,
-                        rng[x as usize],
+                        rng,
                         &world.per_vertex_buffer,
                         &world.index_buffer,
                         &world.bvh.nodes,
@@ -291,41 +1832,180 @@ pub fn trace_cpu(
                         &shared_structs::Sampler,
                         &atlas_image,
                         &skybox_image,
-                    );
-                    output[x as usize] += radiance;
-                    rng[x as usize] = rng_state;
+                        &BLUE_NOISE,
+                        &mut (),
+                    )
+                };
+                // New mean, odd mean and outlier state of a pixel, see kernels::reject_firefly
+                let firefly_rejection = config.render.firefly_rejection;
+                let accumulate = |mean: Vec4, odd_mean: f32, outlier: Vec4, radiance: Vec4| {
+                    let (radiance, outlier) = if firefly_rejection > 0.0 {
+                        kernels::reject_firefly(firefly_rejection, mean.truncate(), outlier, radiance, sample_count)
+                    } else {
+                        (radiance, outlier)
+                    };
+                    (mean.lerp(radiance, weight), kernels::accumulate_odd_sample(odd_mean, radiance.truncate(), sample_count), outlier)
+                };
+
+                if state.bucket_rendering.load(Ordering::Relaxed) {
+                    // Workers claim square buckets, which keeps neighbouring rays (and their BVH traversals) on the same core
+                    let tiles = make_tiles(screen_width, screen_height);
+                    state.tile_progress.lock().clear();
+                    let rng_snapshot: &[UVec2] = &rng_buffer[..];
+                    let output_snapshot: &[Vec4] = &output_buffer[..];
+                    let odd_snapshot: &[f32] = &odd_means[..];
+                    let outlier_snapshot: &[Vec4] = &outliers[..];
+                    let results = tiles.par_iter().map(|&tile| {
+                        state.tile_progress.lock().active.push(tile);
+                        let mut results = Vec::with_capacity((tile.width * tile.height) as usize);
+                        for y in tile.y..tile.y + tile.height {
+                            for x in tile.x..tile.x + tile.width {
+                                let index = (y * screen_width + x) as usize;
+                                let (radiance, rng_state) = trace(x, y, rng_snapshot[index]);
+                                let (output, odd_mean, outlier) = accumulate(output_snapshot[index], odd_snapshot[index], outlier_snapshot[index], radiance);
+                                results.push((output, odd_mean, outlier, rng_state));
+                            }
+                        }
+                        state.tile_progress.lock().finish(tile);
+                        (tile, results)
+                    }).collect::<Vec<_>>();
+
+                    for (tile, results) in results {
+                        for (i, (output, odd_mean, outlier, rng_state)) in results.into_iter().enumerate() {
+                            let x = tile.x + i as u32 % tile.width;
+                            let y = tile.y + i as u32 / tile.width;
+                            let index = (y * screen_width + x) as usize;
+                            output_buffer[index] = output;
+                            odd_means[index] = odd_mean;
+                            outliers[index] = outlier;
+                            rng_buffer[index] = rng_state;
+                        }
+                    }
+                } else if config.render.morton_order != 0 {
+                    // Workers claim runs of 64 pixels in the order the GPU traces them, so each run covers a small patch of the screen
+                    let tile_pixels = (morton::TILE_SIZE * morton::TILE_SIZE) as usize;
+                    let rng_snapshot: &[UVec2] = &rng_buffer[..];
+                    let output_snapshot: &[Vec4] = &output_buffer[..];
+                    let odd_snapshot: &[f32] = &odd_means[..];
+                    let outlier_snapshot: &[Vec4] = &outliers[..];
+                    let results = morton_order.par_chunks(tile_pixels).flat_map_iter(|pixels| {
+                        pixels.iter().map(|&index| {
+                            let index = index as usize;
+                            let (radiance, rng_state) = trace(index as u32 % screen_width, index as u32 / screen_width, rng_snapshot[index]);
+                            let (output, odd_mean, outlier) = accumulate(output_snapshot[index], odd_snapshot[index], outlier_snapshot[index], radiance);
+                            (index, output, odd_mean, outlier, rng_state)
+                        }).collect::<Vec<_>>()
+                    }).collect::<Vec<_>>();
+
+                    for (index, output, odd_mean, outlier, rng_state) in results {
+                        output_buffer[index] = output;
+                        odd_means[index] = odd_mean;
+                        outliers[index] = outlier;
+                        rng_buffer[index] = rng_state;
+                    }
+                } else {
+                    let outputs = output_buffer.par_chunks_mut(screen_width as usize).enumerate();
+                    let odds = odd_means.par_chunks_mut(screen_width as usize);
+                    let outlier_rows = outliers.par_chunks_mut(screen_width as usize);
+                    let rngs = rng_buffer.par_chunks_mut(screen_width as usize);
+                    outputs.zip(odds).zip(outlier_rows).zip(rngs).for_each(|((((y, output), odd), outlier), rng)| {
+                        for x in 0..screen_width as usize {
+                            let (radiance, rng_state) = trace(x as u32, y as u32, rng[x]);
+                            (output[x], odd[x], outlier[x]) = accumulate(output[x], odd[x], outlier[x], radiance);
+                            rng[x] = rng_state;
+                        }
+                    });
                 }
             });
         }
+        let trace_time = trace_start.elapsed();
         state.samples.fetch_add(1, Ordering::Relaxed);
 
         // Readback from GPU
-        let sample_count = state.samples.load(Ordering::Relaxed) as f32;
-        for (i, col) in output_buffer.iter().enumerate() {
-            image_buffer[i * 3] = col.x / sample_count;
-            image_buffer[i * 3 + 1] = col.y / sample_count;
-            image_buffer[i * 3 + 2] = col.z / sample_count;
+        let resolve_start = Instant::now();
+        {
+            puffin::profile_scope!("Resolve");
+            for (i, col) in output_buffer.iter().enumerate() {
+                image_buffer[i * 3] = col.x;
+                image_buffer[i * 3 + 1] = col.y;
+                image_buffer[i * 3 + 2] = col.z;
+            }
+            publish_variance(&state, &image_buffer, &odd_means, &mut variance_buffer);
+            if state.config.read().render.firefly_rejection > 0.0 {
+                spread_rejected_energy(&mut image_buffer, &outliers, screen_width, screen_height);
+            }
         }
+        let resolve_time = resolve_start.elapsed();
 
         // Denoise
+        let denoise_start = Instant::now();
         #[cfg(feature = "oidn")]
-        if state.denoise.load(Ordering::Relaxed) && !flush {
-            denoise_image(screen_width as usize, screen_height as usize, &mut image_buffer);
+        if state.denoise.load(Ordering::Relaxed) && !reset {
+            auxiliary.accumulate(&state.config.read(), &world.per_vertex_buffer, &world.index_buffer, &world.bvh.nodes, &world.material_data_buffer, &atlas_image);
+            denoise_frame(&state, &mut denoiser, &mut auxiliary, screen_width, screen_height, &mut image_buffer);
         }
+        let denoise_time = denoise_start.elapsed();
+        let timings = PassTimings {
+            trace: trace_time,
+            resolve: resolve_time,
+            denoise: denoise_time,
+        };
+        *state.timings.write() = timings;
+        state.timing_totals.lock().add(&timings, 1);
+        *state.render_time.lock() += trace_time + resolve_time + denoise_time;
+
+        preview.apply(&mut image_buffer, state.samples.load(Ordering::Relaxed));
 
         // Push to render thread
-        state.framebuffer.write().copy_from_slice(image_buffer.as_slice());
+        state.framebuffer.publish(&mut image_buffer);
+        wait_for_power(&state, trace_time);
 
         // Interaction
-        if flush {
-            state.dirty.store(false, Ordering::Relaxed);
+        if reset {
             state.samples.store(0, Ordering::Relaxed);
+            *state.render_time.lock() = Duration::ZERO;
+            preview.active = dirty.wants_preview() && state.interactive_preview.load(Ordering::Relaxed);
             output_buffer = vec![Vec4::ZERO; pixel_count as usize];
-            rng_buffer = if state.use_blue_noise.load(Ordering::Relaxed) { &mut rng_data_blue } else { &mut rng_data_uniform };
+            odd_means = vec![0.0; pixel_count as usize];
+            outliers = vec![Vec4::ZERO; pixel_count as usize];
+            #[cfg(feature = "oidn")]
+            auxiliary.clear();
+            if dirty.contains(DirtyFlags::SAMPLING) {
+                rng_buffer = initial_rng_state(screen_width, screen_height);
+            }
+            let previous = motion_config;
+            motion_config = *state.config.read();
+            update_previous_camera(&mut motion_config, dirty.contains(DirtyFlags::CAMERA).then_some(&previous));
+            motion_vectors_stale = true;
+        }
+        if state.motion_vectors_enabled.load(Ordering::Relaxed) {
+            if motion_vectors_stale {
+                publish_motion_vectors(&state, &motion_config, &world.per_vertex_buffer, &world.index_buffer, &world.bvh.nodes);
+                motion_vectors_stale = false;
+            }
+        } else {
+            motion_vectors_stale = true;
+        }
+
+        // Rendering a fixed number of samples, such as in tests
+        let sample_limit = state.sample_limit.load(Ordering::Relaxed);
+        if sample_limit != 0 && state.samples.load(Ordering::Relaxed) >= sample_limit {
+            state.running.store(false, Ordering::Relaxed);
+        }
+        if reached_error_target(&state) {
+            state.running.store(false, Ordering::Relaxed);
         }
     }
 }
 
+// The world belongs to the render thread, so material previews are rendered from their own copy
+fn publish_material_library(state: &TracingState, world: &World) {
+    let mut library = state.material_library.write();
+    if !library.as_ref().map_or(false, |library| library.matches(world)) {
+        *library = Some(Arc::new(MaterialLibrary::from_world(world)));
+    }
+}
+
 // Harness for running syncronous tracing
 #[allow(dead_code)]
 pub fn setup_trace(width: u32, height: u32, samples: u32) -> Arc<TracingState> {
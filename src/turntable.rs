@@ -0,0 +1,126 @@
+use glam::{Mat3, Vec3};
+use image::{codecs::gif::{GifEncoder, Repeat}, Delay, Frame, RgbaImage};
+use shared_structs::TracingConfig;
+use std::{fs::File, io::{BufWriter, Write}, path::Path, process::{Child, Command, Stdio}};
+
+use crate::{batch::{HeadlessScene, RenderProgress}, export::encode_framebuffer, tonemap::Tonemapping};
+
+const GIF_SPEED: i32 = 10; // 1 to 30, trading palette quality for encoding time
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VideoFormat {
+    Gif,
+    Mp4, // Piped through ffmpeg, which has to be on the PATH
+}
+
+impl VideoFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            VideoFormat::Gif => "gif",
+            VideoFormat::Mp4 => "mp4",
+        }
+    }
+}
+
+// Frames are encoded as they finish, so a long sequence never has to fit in memory
+enum FrameWriter {
+    Gif(Box<GifEncoder<BufWriter<File>>>, u32),
+    Ffmpeg(Child),
+}
+
+impl FrameWriter {
+    fn create(path: &Path, format: VideoFormat, width: u32, height: u32, fps: u32) -> Result<Self, String> {
+        match format {
+            VideoFormat::Gif => {
+                let file = File::create(path).map_err(|err| err.to_string())?;
+                let mut encoder = GifEncoder::new_with_speed(BufWriter::new(file), GIF_SPEED);
+                encoder.set_repeat(Repeat::Infinite).map_err(|err| err.to_string())?;
+                Ok(FrameWriter::Gif(Box::new(encoder), fps))
+            }
+            VideoFormat::Mp4 => {
+                let child = Command::new("ffmpeg")
+                    .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgb24"])
+                    .args(["-s", &format!("{}x{}", width, height), "-r", &fps.to_string(), "-i", "-"])
+                    // yuv420p needs even dimensions, and is what players expect
+                    .args(["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2", "-c:v", "libx264", "-pix_fmt", "yuv420p"])
+                    .arg(path)
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .map_err(|err| format!("Failed to start ffmpeg, make sure it is installed: {}", err))?;
+                Ok(FrameWriter::Ffmpeg(child))
+            }
+        }
+    }
+
+    fn write(&mut self, pixels: &[u8], width: u32, height: u32) -> Result<(), String> {
+        match self {
+            FrameWriter::Gif(encoder, fps) => {
+                let rgba = pixels.chunks_exact(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect();
+                let image = RgbaImage::from_raw(width, height, rgba).ok_or("Invalid frame")?;
+                let frame = Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(1000, *fps));
+                encoder.encode_frame(frame).map_err(|err| err.to_string())
+            }
+            FrameWriter::Ffmpeg(child) => {
+                let stdin = child.stdin.as_mut().ok_or("ffmpeg closed its input")?;
+                stdin.write_all(pixels).map_err(|err| format!("Failed to write to ffmpeg: {}", err))
+            }
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self {
+            FrameWriter::Gif(..) => Ok(()),
+            FrameWriter::Ffmpeg(mut child) => {
+                // Closing stdin tells ffmpeg the video is over
+                drop(child.stdin.take());
+                let status = child.wait().map_err(|err| err.to_string())?;
+                if status.success() { Ok(()) } else { Err(format!("ffmpeg failed with {}", status)) }
+            }
+        }
+    }
+}
+
+// Camera position and rotation looking at `center` from `radius` away, with the kernel's yaw and pitch
+pub fn orbit_camera(center: Vec3, radius: f32, yaw: f32, pitch: f32) -> (Vec3, Vec3) {
+    let forward = Mat3::from_rotation_y(yaw) * Mat3::from_rotation_x(pitch) * Vec3::Z;
+    (center - forward * radius, Vec3::new(pitch, yaw, 0.0))
+}
+
+// Renders a full orbit around a point, starting at the camera's yaw and keeping its pitch, and
+// encodes it into a looping animation
+#[derive(Clone)]
+pub struct Turntable {
+    pub scene: HeadlessScene,
+    pub config: TracingConfig,
+    pub tonemapping: Tonemapping,
+    pub center: Vec3,
+    pub radius: f32,
+    pub frames: u32,
+    pub samples: u32, // Per frame
+    pub fps: u32,
+    pub format: VideoFormat,
+}
+
+impl Turntable {
+    pub fn render(&self, path: &Path, progress: &RenderProgress) -> Result<(), String> {
+        let (width, height) = (self.config.render.width, self.config.render.height);
+        let mut writer = FrameWriter::create(path, self.format, width, height, self.fps)?;
+        let start_yaw = self.config.camera.cam_rotation.y;
+        let pitch = self.config.camera.cam_rotation.x;
+        for frame in 0..self.frames {
+            let yaw = start_yaw + frame as f32 / self.frames as f32 * std::f32::consts::TAU;
+            let (position, rotation) = orbit_camera(self.center, self.radius, yaw, pitch);
+            let mut config = self.config;
+            config.camera.cam_position = position.extend(config.camera.cam_position.w);
+            config.camera.cam_rotation = rotation.extend(config.camera.cam_rotation.w);
+            config.camera.prev_cam_position = config.camera.cam_position;
+            config.camera.prev_cam_rotation = config.camera.cam_rotation;
+
+            let framebuffer = self.scene.render(config, self.samples, progress)?;
+            writer.write(&encode_framebuffer(&framebuffer, self.tonemapping, 0.0), width, height)?;
+            progress.finish_one();
+        }
+        writer.finish()
+    }
+}
+
@@ -0,0 +1,28 @@
+use rayon::prelude::*;
+
+// Resamples an RGB image to a bigger size with bilinear filtering. Pixel centers are lined up, so a
+// pixel of the source covers the same part of the screen as the target pixels it is spread over.
+pub fn upscale_bilinear(source: &[f32], source_width: u32, source_height: u32, target: &mut [f32], target_width: u32, target_height: u32) {
+    let scale_x = source_width as f32 / target_width as f32;
+    let scale_y = source_height as f32 / target_height as f32;
+    let texel = |x: u32, y: u32| {
+        let index = (y * source_width + x) as usize * 3;
+        [source[index], source[index + 1], source[index + 2]]
+    };
+    target.par_chunks_mut(target_width as usize * 3).enumerate().for_each(|(y, row)| {
+        let source_y = ((y as f32 + 0.5) * scale_y - 0.5).clamp(0.0, (source_height - 1) as f32);
+        let (y0, fy) = (source_y as u32, source_y.fract());
+        let y1 = (y0 + 1).min(source_height - 1);
+        for (x, pixel) in row.chunks_mut(3).enumerate() {
+            let source_x = ((x as f32 + 0.5) * scale_x - 0.5).clamp(0.0, (source_width - 1) as f32);
+            let (x0, fx) = (source_x as u32, source_x.fract());
+            let x1 = (x0 + 1).min(source_width - 1);
+            let (top_left, top_right, bottom_left, bottom_right) = (texel(x0, y0), texel(x1, y0), texel(x0, y1), texel(x1, y1));
+            for channel in 0..3 {
+                let top = top_left[channel] + (top_right[channel] - top_left[channel]) * fx;
+                let bottom = bottom_left[channel] + (bottom_right[channel] - bottom_left[channel]) * fx;
+                pixel[channel] = top + (bottom - top) * fy;
+            }
+        }
+    });
+}
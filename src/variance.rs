@@ -0,0 +1,50 @@
+use glam::Vec3;
+use kernels::luminance;
+
+// Below this mean luminance, errors are measured in absolute terms, so black pixels don't dominate
+const RELATIVE_ERROR_FLOOR: f32 = 0.01;
+
+// A restored render doesn't have its odd samples anymore, so they are assumed to match the rest
+pub fn odd_means_from_image(image: &[f32]) -> Vec<f32> {
+    image.chunks(3).map(|color| luminance(Vec3::new(color[0], color[1], color[2]))).collect()
+}
+
+// Variance of the mean luminance of a pixel, from the mean of all of its samples and the mean of
+// its odd samples, see kernels::accumulate_odd_sample. The two halves are independent estimates,
+// so their squared difference is, in expectation, the sum of their variances.
+pub fn split_variance(mean: f32, odd_mean: f32, samples: u32) -> f32 {
+    if samples < 2 {
+        return 0.0;
+    }
+    let odd = (samples / 2) as f32;
+    let even = samples as f32 - odd;
+    let even_mean = (mean * samples as f32 - odd_mean * odd) / even;
+    let difference = even_mean - odd_mean;
+    difference * difference * odd * even / (samples as f32 * samples as f32)
+}
+
+// The variance AOV of an RGB image, one value per pixel
+pub fn estimate_variance(image: &[f32], odd_means: &[f32], samples: u32, variance: &mut Vec<f32>) {
+    variance.clear();
+    variance.extend(
+        image
+            .chunks(3)
+            .zip(odd_means)
+            .map(|(color, &odd_mean)| split_variance(luminance(Vec3::new(color[0], color[1], color[2])), odd_mean, samples)),
+    );
+}
+
+// Standard error of a pixel, relative to its brightness
+pub fn pixel_relative_error(color: &[f32], variance: f32) -> f32 {
+    variance.sqrt() / luminance(Vec3::new(color[0], color[1], color[2])).max(RELATIVE_ERROR_FLOOR)
+}
+
+// Root mean square of the relative error of each pixel
+pub fn relative_error(image: &[f32], variance: &[f32]) -> f32 {
+    let sum = image
+        .chunks(3)
+        .zip(variance)
+        .map(|(color, &variance)| pixel_relative_error(color, variance).powi(2))
+        .sum::<f32>();
+    (sum / variance.len().max(1) as f32).sqrt()
+}
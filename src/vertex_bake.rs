@@ -0,0 +1,174 @@
+use glam::{UVec2, Vec2, Vec3};
+use kernels::intersection::BVHReference;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::Serialize;
+use shared_structs::{TracingConfig, HIDDEN_FROM_SHADOWS};
+use std::{f32::consts::PI, path::{Path, PathBuf}};
+
+use crate::{
+    batch::{HeadlessScene, RenderProgress},
+    lightmap::{cosine_direction, BakeScene},
+    probe::{sh9, COSINE_LOBE_BANDS},
+    tangents::vertex_normals,
+};
+
+const AO_DISTANCE_SCALE: f32 = 0.1; // Of the scene's diagonal, when no distance is given
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VertexBakeKind {
+    AmbientOcclusion, // One value per vertex, 1 where nothing is in the way
+    Irradiance, // 9 RGB spherical harmonics coefficients per vertex, see probe::COSINE_LOBE_BANDS
+}
+
+impl VertexBakeKind {
+    fn components(self) -> usize {
+        match self {
+            VertexBakeKind::AmbientOcclusion => 1,
+            VertexBakeKind::Irradiance => 27,
+        }
+    }
+}
+
+// Layout of one mesh's data in the binary sidecar, as little-endian f32s
+#[derive(Serialize)]
+struct SidecarMesh {
+    mesh: usize,
+    material: String,
+    vertices: usize,
+    positions_offset: usize, // Bytes, 3 floats per vertex, to match vertices up with the source file
+    values_offset: usize,
+}
+
+#[derive(Serialize)]
+struct Sidecar {
+    kind: &'static str,
+    components: usize, // Floats per vertex. Irradiance is 9 coefficients of RGB, coefficient by coefficient.
+    binary: String,
+    meshes: Vec<SidecarMesh>,
+}
+
+// Uniform over the hemisphere around `normal`, where spherical harmonics projection has no cosine to divide by
+pub fn hemisphere_direction(normal: Vec3, xi: Vec2) -> Vec3 {
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+    let radius = (1.0 - xi.x * xi.x).max(0.0).sqrt();
+    let phi = 2.0 * PI * xi.y;
+    tangent * radius * phi.cos() + bitangent * radius * phi.sin() + normal * xi.x
+}
+
+// Irradiance over pi, projected onto spherical harmonics, from radiance arriving from directions
+// spread uniformly over a hemisphere. The diffuse lighting of a vertex with albedo A and normal N
+// is A times the sum of each coefficient times sh9(N).
+pub fn project_irradiance(samples: &[(Vec3, Vec3)]) -> [Vec3; 9] {
+    let mut coefficients = [Vec3::ZERO; 9];
+    for (direction, radiance) in samples {
+        for (coefficient, basis) in coefficients.iter_mut().zip(sh9(*direction)) {
+            *coefficient += *radiance * basis;
+        }
+    }
+    // Dividing by the pdf of uniform hemisphere directions
+    let weight = 2.0 * PI / samples.len().max(1) as f32;
+    for (coefficient, band) in coefficients.iter_mut().zip(COSINE_LOBE_BANDS) {
+        *coefficient *= weight * band;
+    }
+    coefficients
+}
+
+// Traces a hemisphere of rays from every vertex, for stylized pipelines that light with vertex
+// colors. Results go into a JSON description and a binary file of floats next to it, with the
+// vertices in the order they were imported in, which may differ from the source file.
+#[derive(Clone)]
+pub struct VertexBake {
+    pub scene: HeadlessScene,
+    pub config: TracingConfig,
+    pub kind: VertexBakeKind,
+    pub samples: u32, // Per vertex
+    pub ao_distance: f32, // Hits further away than this don't occlude. 0 scales it to the scene.
+}
+
+impl VertexBake {
+    fn irradiance(&self, scene: &BakeScene, seed: u64, id: UVec2, position: Vec3, normal: Vec3) -> Vec<f32> {
+        let mut random = StdRng::seed_from_u64(seed);
+        let mut rng = UVec2::new(0, random.gen());
+        let origin = scene.ray_origin(position, normal);
+        let samples = (0..self.samples.max(1))
+            .map(|_| {
+                let direction = hemisphere_direction(normal, Vec2::new(random.gen(), random.gen()));
+                let (radiance, next_rng) = scene.radiance(id, rng, origin, direction);
+                rng = next_rng;
+                (direction, radiance)
+            })
+            .collect::<Vec<_>>();
+        project_irradiance(&samples).iter().flat_map(|coefficient| coefficient.to_array()).collect()
+    }
+
+    fn ambient_occlusion(&self, scene: &BakeScene, seed: u64, position: Vec3, normal: Vec3, distance: f32) -> Vec<f32> {
+        let mut random = StdRng::seed_from_u64(seed);
+        let bvh = BVHReference { nodes: &scene.world.bvh.nodes, min_t: scene.config.render.ray_offset };
+        let origin = scene.ray_origin(position, normal);
+        let open = (0..self.samples)
+            .filter(|_| {
+                let direction = cosine_direction(normal, Vec2::new(random.gen(), random.gen()));
+                !bvh.intersect_any(&scene.world.per_vertex_buffer, &scene.world.index_buffer, origin, direction, distance, HIDDEN_FROM_SHADOWS).hit
+            })
+            .count();
+        vec![open as f32 / self.samples.max(1) as f32]
+    }
+
+    // Writes `path` as JSON and the values next to it with a .bin extension, and returns the path of the binary
+    pub fn bake(&self, path: &Path, progress: &RenderProgress) -> Result<PathBuf, String> {
+        let scene = BakeScene::load(&self.scene, self.config)?;
+        let (min, max) = scene.world.statistics.bounds;
+        let distance = if self.ao_distance > 0.0 { self.ao_distance } else { (max - min).length() * AO_DISTANCE_SCALE };
+
+        let mut binary = Vec::new();
+        let mut meshes = Vec::new();
+        for (mesh_index, mesh) in scene.description.meshes.iter().enumerate() {
+            if progress.is_cancelled() {
+                return Err("Cancelled".to_string());
+            }
+            let normals = vertex_normals(mesh);
+            let values = mesh
+                .positions
+                .par_iter()
+                .zip(normals.par_iter())
+                .enumerate()
+                .flat_map_iter(|(vertex, (position, normal))| {
+                    let seed = (mesh_index as u64) << 32 | vertex as u64;
+                    match self.kind {
+                        VertexBakeKind::AmbientOcclusion => self.ambient_occlusion(&scene, seed, *position, *normal, distance),
+                        VertexBakeKind::Irradiance => self.irradiance(&scene, seed, UVec2::new(vertex as u32, mesh_index as u32), *position, *normal),
+                    }
+                })
+                .collect::<Vec<f32>>();
+
+            let positions_offset = binary.len();
+            binary.extend(mesh.positions.iter().flat_map(|position| position.to_array()).flat_map(f32::to_le_bytes));
+            let values_offset = binary.len();
+            binary.extend(values.iter().flat_map(|value| value.to_le_bytes()));
+            meshes.push(SidecarMesh {
+                mesh: mesh_index,
+                material: scene.description.materials.get(mesh.material as usize).map(|material| material.name.clone()).unwrap_or_default(),
+                vertices: mesh.positions.len(),
+                positions_offset,
+                values_offset,
+            });
+            progress.finish_one();
+        }
+
+        let binary_path = path.with_extension("bin");
+        let sidecar = Sidecar {
+            kind: match self.kind {
+                VertexBakeKind::AmbientOcclusion => "ambient_occlusion",
+                VertexBakeKind::Irradiance => "sh9_irradiance",
+            },
+            components: self.kind.components(),
+            binary: binary_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+            meshes,
+        };
+        let json = serde_json::to_string_pretty(&sidecar).map_err(|err| err.to_string())?;
+        std::fs::write(path, json).map_err(|err| format!("Failed to save '{}': {}", path.display(), err))?;
+        std::fs::write(&binary_path, binary).map_err(|err| format!("Failed to save '{}': {}", binary_path.display(), err))?;
+        Ok(binary_path)
+    }
+}
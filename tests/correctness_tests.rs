@@ -1,7 +1,19 @@
 use std::sync::Arc;
 
+use glam::{UVec2, UVec4, Vec2, Vec3, Vec3Swizzles, Vec4, Vec4Swizzles};
+use kernels::intersection::{intersect_slow_as_shit, BVHReference};
+use kernels::light_pick::{sample_spherical_triangle, spherical_triangle_area};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rustic::asset::{LoadProgress, World};
+use rustic::bvh::BVHBuilder;
+use rustic::cancel::CancelToken;
+use rustic::ground::GroundSettings;
+use rustic::light_pick::{build_light_pick_table, try_build_light_pick_table};
+use rustic::scene::SceneDescription;
+use rustic::texture_cache::TextureCache;
 use rustic::trace::*;
-use shared_structs::NextEventEstimation;
+use shared_structs::{LightPickEntry, MaterialData, NextEventEstimation, PerVertexData, TracingConfig};
+use shared_structs::{HIDDEN_FROM_CAMERA, HIDDEN_FROM_INDIRECT, HIDDEN_FROM_SHADOWS};
 
 fn trace(use_cpu: bool, scene: &str, skybox: Option<&str>, state: &Arc<TracingState>) {
     if use_cpu {
@@ -11,6 +23,10 @@ fn trace(use_cpu: bool, scene: &str, skybox: Option<&str>, state: &Arc<TracingSt
     }
 }
 
+fn world_from(description: &SceneDescription) -> World {
+    World::from_description(description, &GroundSettings::default(), true, false, &LoadProgress::default()).unwrap()
+}
+
 fn furnace_test(use_cpu: bool, use_mis: bool) {
     let size = 128;
     let coord = (65, 75);
@@ -19,7 +35,7 @@ fn furnace_test(use_cpu: bool, use_mis: bool) {
 
     let state = setup_trace(size as u32, size as u32, 32);
     if use_mis {
-        state.config.write().nee = NextEventEstimation::MultipleImportanceSampling.to_u32();
+        state.config.write().render.nee = NextEventEstimation::MultipleImportanceSampling.to_u32();
     }
     trace(use_cpu, "scenes/FurnaceTest.glb", None, &state);
     let frame = state.framebuffer.read();
@@ -50,4 +66,1796 @@ fn furnace_test_cpu_mis() {
 #[test]
 fn furnace_test_gpu_mis() {
     furnace_test(false, true);
-}
\ No newline at end of file
+}
+
+fn parity_test(scene: &str) {
+    let size = 64;
+    let report = rustic::parity::render_parity(scene, None, size, size, 1);
+
+    // Paths that see a comparison land on the other side on one target go somewhere else
+    // entirely, but those should be rare, and the rest should agree closely.
+    assert!(report.diverged_pixels(1e-3) * 100 < (size * size) as usize);
+    assert!(report.mean_abs() < 1e-3);
+}
+
+#[test]
+fn parity_test_furnace() {
+    parity_test("scenes/FurnaceTest.glb");
+}
+
+#[test]
+fn parity_test_pbr() {
+    parity_test("scenes/PBRTest.glb");
+}
+
+#[test]
+fn russian_roulette_test() {
+    let mut config = TracingConfig::default();
+    config.render.min_bounces = 2;
+    let albedo = Vec3::splat(0.5);
+
+    // Fixed roulette leaves the first bounces alone, then survives by throughput
+    config.render.adaptive_roulette = 0;
+    assert_eq!(kernels::russian_roulette_survival(&config, 2, Vec3::splat(0.1), albedo), 1.0);
+    assert_eq!(kernels::russian_roulette_survival(&config, 3, Vec3::splat(0.1), albedo), 0.1);
+    assert_eq!(kernels::russian_roulette_survival(&config, 3, Vec3::splat(4.0), albedo), 1.0);
+
+    // Adaptive roulette starts right away, but only for paths darker than the surface they hit
+    config.render.adaptive_roulette = 1;
+    assert_eq!(kernels::russian_roulette_survival(&config, 0, Vec3::new(0.1, 0.2, 0.25), albedo), 0.5);
+    assert_eq!(kernels::russian_roulette_survival(&config, 0, Vec3::splat(0.5), albedo), 1.0);
+    assert_eq!(kernels::russian_roulette_survival(&config, 0, Vec3::splat(0.5), Vec3::ZERO), 1.0);
+}
+
+#[test]
+fn ray_cone_test() {
+    use kernels::{ray_cone::RayCone, LobeType};
+    let mut config = TracingConfig::default();
+    config.render.width = 100;
+
+    // A pixel wide at the screen, and growing linearly from there
+    let mut cone = RayCone::camera(&config);
+    cone.propagate(1.0);
+    assert_eq!(cone.width, 0.02);
+    cone.propagate(2.0);
+    assert!((cone.width - 0.06).abs() < 1e-6);
+
+    // Mirrors keep the cone narrow, rough surfaces widen it until glossy bounces are terminated
+    let limit = 0.5;
+    cone.scatter(LobeType::SpecularReflection, 0.0);
+    assert_eq!(cone.glossy_survival(limit, LobeType::SpecularReflection), 1.0);
+    cone.scatter(LobeType::SpecularReflection, 0.7);
+    let survival = cone.glossy_survival(limit, LobeType::SpecularReflection);
+    assert!(survival > 0.0 && survival < 1.0);
+    assert_eq!(cone.glossy_survival(limit, LobeType::DiffuseReflection), 1.0);
+    assert_eq!(cone.glossy_survival(0.0, LobeType::SpecularReflection), 1.0);
+}
+
+#[test]
+fn hot_reload_spirv_check_test() {
+    use rustic::hot_reload::is_spirv;
+    let kernel = std::fs::read(env!("kernels.spv")).unwrap();
+    assert!(is_spirv(&kernel));
+    assert!(!is_spirv(&kernel[..kernel.len() - 1]));
+    assert!(!is_spirv(&[]));
+    assert!(!is_spirv(b"not a kernel, but a multiple of four"));
+}
+
+// Every feature set the host can ask for has to be compiled into the kernel
+#[test]
+fn kernel_variants_test() {
+    let kernel = std::fs::read(env!("kernels.spv")).unwrap();
+    let has_entry_point = |name: &str| {
+        let name = [name.as_bytes(), &[0]].concat();
+        kernel.windows(name.len()).any(|window| window == name)
+    };
+    for nee in [NextEventEstimation::None, NextEventEstimation::MultipleImportanceSampling, NextEventEstimation::DirectLightSampling] {
+        for has_skybox in [0, 1] {
+            for normal_maps in [false, true] {
+                let mut config = TracingConfig::default();
+                config.render.nee = nee.to_u32();
+                config.environment.has_skybox = has_skybox;
+                let features = shared_structs::kernel_features(&config, normal_maps);
+                for half_precision in [false, true] {
+                    let entry_point = kernels::kernel_entry_point(features, half_precision);
+                    assert!(has_entry_point(&entry_point), "missing entry point {}", entry_point);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn ulp_distance_test() {
+    use rustic::parity::ulp_distance;
+    assert_eq!(ulp_distance(1.0, 1.0), 0);
+    assert_eq!(ulp_distance(0.0, -0.0), 0);
+    assert_eq!(ulp_distance(1.0, f32::from_bits(1.0f32.to_bits() + 1)), 1);
+    assert_eq!(ulp_distance(-f32::from_bits(1), f32::from_bits(1)), 2);
+    assert_eq!(ulp_distance(f32::NAN, 1.0), u32::MAX);
+}
+
+
+fn random_point(rng: &mut StdRng, extent: f32) -> Vec3 {
+    Vec3::new(rng.gen_range(-extent..extent), rng.gen_range(-extent..extent), rng.gen_range(-extent..extent))
+}
+
+// Triangles of wildly different sizes, scattered through a box, with some exact duplicates
+// and some sharing a plane, which are the cases where traversal shortcuts go wrong.
+fn random_triangle_soup(rng: &mut StdRng, triangle_count: usize) -> (Vec<Vec4>, Vec<UVec4>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for i in 0..triangle_count {
+        let offset = vertices.len() as u32;
+        if i > 0 && rng.gen_bool(0.05) {
+            let duplicate: UVec4 = indices[rng.gen_range(0..indices.len())];
+            indices.push(duplicate);
+            continue;
+        }
+        let center = random_point(rng, 10.0);
+        let size = 10f32.powf(rng.gen_range(-2.0..1.0));
+        let flat = rng.gen_bool(0.1);
+        for _ in 0..3 {
+            let mut vertex = center + random_point(rng, size);
+            if flat {
+                vertex.y = center.y;
+            }
+            vertices.push(vertex.extend(1.0));
+        }
+        indices.push(UVec4::new(offset, offset + 1, offset + 2, 0));
+    }
+    (vertices, indices)
+}
+
+fn bvh_fuzz_test(seed: u64, triangle_count: usize) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (vertices, mut indices) = random_triangle_soup(&mut rng, triangle_count);
+    let bvh = BVHBuilder::new(&vertices, &mut indices).build();
+    let per_vertex_data = vertices
+        .iter()
+        .map(|&vertex| PerVertexData { vertex, ..Default::default() })
+        .collect::<Vec<_>>();
+    let reference = BVHReference {
+        nodes: &bvh.nodes,
+        min_t: 0.001, // the brute force intersection uses this too
+    };
+
+    for _ in 0..5000 {
+        let ro = random_point(&mut rng, 15.0);
+        // Aim at a triangle half the time, since random rays mostly miss small soups
+        let rd = if rng.gen_bool(0.5) {
+            let triangle = indices[rng.gen_range(0..indices.len())];
+            let target = (vertices[triangle.x as usize] + vertices[triangle.y as usize] + vertices[triangle.z as usize]).xyz() / 3.0;
+            (target - ro).normalize()
+        } else {
+            random_point(&mut rng, 1.0).normalize()
+        };
+        if !rd.is_finite() {
+            continue;
+        }
+
+        let expected = intersect_slow_as_shit(&vertices, &indices, ro, rd);
+        let nearest = reference.intersect_nearest(&per_vertex_data, &indices, ro, rd, 0);
+        assert_eq!(nearest.hit, expected.hit, "nearest hit mismatch for ray {:?} {:?}", ro, rd);
+        if expected.hit {
+            assert!((nearest.t - expected.t).abs() <= 1e-5 * expected.t.max(1.0), "nearest t mismatch for ray {:?} {:?}", ro, rd);
+        }
+
+        let max_t = rng.gen_range(0.0..30.0);
+        let any = reference.intersect_any(&per_vertex_data, &indices, ro, rd, max_t, 0);
+        assert_eq!(any.hit, expected.hit && expected.t <= max_t, "any hit mismatch for ray {:?} {:?}, max_t {}", ro, rd, max_t);
+        if any.hit {
+            assert!(any.t > reference.min_t && any.t <= max_t);
+        }
+    }
+}
+
+#[test]
+fn bvh_fuzz_test_single_triangle() {
+    bvh_fuzz_test(0, 1);
+}
+
+#[test]
+fn bvh_fuzz_test_small() {
+    for seed in 0..16 {
+        bvh_fuzz_test(seed, 10);
+    }
+}
+
+#[test]
+fn bvh_fuzz_test_large() {
+    for seed in 0..4 {
+        bvh_fuzz_test(seed, 2000);
+    }
+}
+
+// Triangles with hidden flags are passed through by rays hiding them, and only by those
+#[test]
+fn hidden_triangles_test() {
+    let mut rng = StdRng::seed_from_u64(3);
+    let (vertices, mut indices) = random_triangle_soup(&mut rng, 500);
+    for triangle in indices.iter_mut() {
+        if rng.gen_bool(0.5) {
+            triangle.w |= HIDDEN_FROM_SHADOWS;
+        }
+    }
+    let bvh = BVHBuilder::new(&vertices, &mut indices).build();
+    let visible = indices.iter().copied().filter(|triangle| triangle.w & HIDDEN_FROM_SHADOWS == 0).collect::<Vec<_>>();
+    let per_vertex_data = vertices
+        .iter()
+        .map(|&vertex| PerVertexData { vertex, ..Default::default() })
+        .collect::<Vec<_>>();
+    let reference = BVHReference {
+        nodes: &bvh.nodes,
+        min_t: 0.001,
+    };
+
+    for _ in 0..2000 {
+        let ro = random_point(&mut rng, 15.0);
+        let triangle = indices[rng.gen_range(0..indices.len())];
+        let target = (vertices[triangle.x as usize] + vertices[triangle.y as usize] + vertices[triangle.z as usize]).xyz() / 3.0;
+        let rd = (target - ro).normalize();
+        if !rd.is_finite() {
+            continue;
+        }
+
+        let expected = intersect_slow_as_shit(&vertices, &visible, ro, rd);
+        let nearest = reference.intersect_nearest(&per_vertex_data, &indices, ro, rd, HIDDEN_FROM_SHADOWS);
+        assert_eq!(nearest.hit, expected.hit, "nearest hit mismatch for ray {:?} {:?}", ro, rd);
+        if expected.hit {
+            assert!((nearest.t - expected.t).abs() <= 1e-5 * expected.t.max(1.0), "nearest t mismatch for ray {:?} {:?}", ro, rd);
+        }
+        let max_t = rng.gen_range(0.0..30.0);
+        let any = reference.intersect_any(&per_vertex_data, &indices, ro, rd, max_t, HIDDEN_FROM_SHADOWS);
+        assert_eq!(any.hit, expected.hit && expected.t <= max_t, "any hit mismatch for ray {:?} {:?}, max_t {}", ro, rd, max_t);
+
+        // Other rays still see everything
+        let unhidden = intersect_slow_as_shit(&vertices, &indices, ro, rd);
+        let nearest = reference.intersect_nearest(&per_vertex_data, &indices, ro, rd, HIDDEN_FROM_CAMERA | HIDDEN_FROM_INDIRECT);
+        assert_eq!(nearest.hit, unhidden.hit, "unhidden hit mismatch for ray {:?} {:?}", ro, rd);
+    }
+}
+
+// screen_position has to undo camera_ray, and a camera that didn't move has no motion.
+#[test]
+fn motion_vectors_test() {
+    let mut rng = StdRng::seed_from_u64(3);
+    let (vertices, mut indices) = random_triangle_soup(&mut rng, 500);
+    let bvh = BVHBuilder::new(&vertices, &mut indices).build();
+    let per_vertex_data = vertices
+        .iter()
+        .map(|&vertex| PerVertexData { vertex, ..Default::default() })
+        .collect::<Vec<_>>();
+
+    let mut config = TracingConfig::default();
+    config.render.width = 64;
+    config.render.height = 48;
+    config.camera.cam_position = Vec4::new(0.0, 0.0, -15.0, 0.0);
+    config.camera.cam_rotation = Vec4::new(0.2, 0.3, 0.0, 0.0);
+    config.camera.prev_cam_position = config.camera.cam_position;
+    config.camera.prev_cam_rotation = config.camera.cam_rotation;
+    for y in 0..config.render.height {
+        for x in 0..config.render.width {
+            let screen = Vec2::new(x as f32, y as f32) + Vec2::new(rng.gen(), rng.gen());
+            let (_, rd) = kernels::camera_ray(&config, screen);
+            let projected = kernels::screen_position(&config, config.camera.cam_rotation, rd);
+            assert!(projected.z > 0.0);
+            assert!(projected.xy().distance(screen) < 1e-3, "{} != {}", projected.xy(), screen);
+
+            let motion = kernels::pixel_motion(&config, UVec2::new(x, y), &per_vertex_data, &indices, &bvh.nodes);
+            assert!(motion.length() < 1e-3, "pixel {} {} moved by {}", x, y, motion);
+        }
+    }
+
+    // The camera has since turned right, so what is in the middle now was further right before
+    config.camera.prev_cam_rotation.y -= 0.1;
+    let motion = kernels::pixel_motion(&config, UVec2::new(32, 24), &per_vertex_data, &indices, &bvh.nodes);
+    assert!(motion.x > 0.0, "{}", motion);
+}
+
+#[test]
+fn bvh_structure_test() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let (vertices, mut indices) = random_triangle_soup(&mut rng, 1000);
+    let bvh = BVHBuilder::new(&vertices, &mut indices).build();
+
+    // Every triangle is in exactly one leaf, and every node contains its triangles and children
+    let contains = |min: Vec3, max: Vec3, point: Vec3| point.cmpge(min).all() && point.cmple(max).all();
+    let mut seen = vec![0; indices.len()];
+    let mut stack = vec![0];
+    while let Some(node_index) = stack.pop() {
+        let node = &bvh.nodes[node_index];
+        let (min, max) = (node.aabb_min(), node.aabb_max());
+        if node.is_leaf() {
+            for i in node.first_triangle_index()..node.first_triangle_index() + node.triangle_count() {
+                seen[i as usize] += 1;
+                let triangle = indices[i as usize];
+                for vertex in [triangle.x, triangle.y, triangle.z] {
+                    assert!(contains(min, max, vertices[vertex as usize].xyz()));
+                }
+            }
+        } else {
+            for child_index in [node.left_node_index(), node.right_node_index()] {
+                let child = &bvh.nodes[child_index as usize];
+                assert!(contains(min, max, child.aabb_min()) && contains(min, max, child.aabb_max()));
+                stack.push(child_index as usize);
+            }
+        }
+    }
+    assert!(seen.iter().all(|&count| count == 1));
+    assert!(bvh.depth() <= 32, "traversal uses a fixed size stack of 32");
+}
+
+
+// One right triangle per light, each with its own material, so the power of each light is
+// exactly area times emission. Returns the table and the power of each triangle.
+fn light_pick_table(lights: &[(f32, f32)]) -> (Vec<LightPickEntry>, Vec<f32>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut material_datas = Vec::new();
+    let mut powers = Vec::new();
+    for (i, &(area, emission)) in lights.iter().enumerate() {
+        let side = (area * 2.0).sqrt();
+        let offset = vertices.len() as u32;
+        let origin = Vec3::new(i as f32 * 10.0, 0.0, 0.0);
+        vertices.push(origin.extend(1.0));
+        vertices.push((origin + Vec3::X * side).extend(1.0));
+        vertices.push((origin + Vec3::Y * side).extend(1.0));
+        indices.push(UVec4::new(offset, offset + 1, offset + 2, i as u32));
+
+        let mut material = MaterialData::default();
+        material.emissive = Vec4::new(emission, emission, emission, 1.0);
+        material_datas.push(material);
+        powers.push(area * emission * 3.0);
+    }
+    let mask = vec![true; indices.len()];
+    let table = build_light_pick_table(&vertices, &indices, &mask, &material_datas);
+    (table, powers)
+}
+
+// Checks the table against the power of each triangle, both exactly, by summing what each
+// entry contributes, and empirically, by picking lights the same way the kernel does.
+fn light_pick_test(lights: &[(f32, f32)]) {
+    let (table, powers) = light_pick_table(lights);
+    let total_power = powers.iter().sum::<f32>();
+    let expected = powers.iter().map(|power| (power / total_power) as f64).collect::<Vec<_>>();
+
+    let mut exact = vec![0.0f64; lights.len()];
+    for entry in table.iter() {
+        assert!((0.0..=1.0).contains(&entry.ratio));
+        assert!((entry.triangle_pick_pdf_a as f64 - expected[entry.triangle_index_a as usize]).abs() <= 1e-6 + expected[entry.triangle_index_a as usize] * 1e-4);
+        exact[entry.triangle_index_a as usize] += entry.ratio as f64 / table.len() as f64;
+        exact[entry.triangle_index_b as usize] += (1.0 - entry.ratio as f64) / table.len() as f64;
+    }
+    for (exact, expected) in exact.iter().zip(expected.iter()) {
+        assert!((exact - expected).abs() <= 1e-5 + expected * 1e-3, "picked {} of the time, expected {}", exact, expected);
+    }
+
+    let samples = 2_000_000;
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut counts = vec![0u32; lights.len()];
+    for _ in 0..samples {
+        let entry = table[(rng.gen::<f32>() * table.len() as f32) as usize];
+        let picked = if rng.gen::<f32>() < entry.ratio { entry.triangle_index_a } else { entry.triangle_index_b };
+        counts[picked as usize] += 1;
+    }
+    for (&count, &expected) in counts.iter().zip(expected.iter()) {
+        let empirical = count as f64 / samples as f64;
+        let sigma = (expected * (1.0 - expected) / samples as f64).sqrt();
+        assert!((empirical - expected).abs() <= 5.0 * sigma + 1e-6, "picked {} of the time, expected {}", empirical, expected);
+    }
+}
+
+#[test]
+fn light_pick_test_single_light() {
+    light_pick_test(&[(1.0, 5.0)]);
+}
+
+#[test]
+fn light_pick_test_equal_powers() {
+    light_pick_test(&[(1.0, 1.0); 7]);
+    // Same power from different areas and emission
+    light_pick_test(&[(1.0, 4.0), (2.0, 2.0), (4.0, 1.0), (0.5, 8.0)]);
+}
+
+#[test]
+fn light_pick_test_donor_drops_below_average() {
+    // The brightest light gives away so much filling the dimmest that it needs filling itself
+    light_pick_test(&[(0.1, 1.0), (0.45, 1.0), (0.45, 1.0)]);
+}
+
+#[test]
+fn light_pick_test_huge_power_ratios() {
+    light_pick_test(&[(1.0, 1e-4), (1.0, 1.0), (1.0, 1e4), (1.0, 1e-2), (1.0, 1e2)]);
+    light_pick_test(&[(1e-3, 1.0), (1e3, 1.0)]);
+}
+
+#[test]
+fn light_pick_test_random_powers() {
+    let mut rng = StdRng::seed_from_u64(2);
+    let lights = (0..500).map(|_| (rng.gen_range(0.01..10.0), rng.gen_range(0.01..10.0))).collect::<Vec<_>>();
+    light_pick_test(&lights);
+}
+
+#[test]
+fn light_pick_test_degenerate() {
+    // Triangles without area are never picked, and don't skew the others
+    let (table, _) = light_pick_table(&[(0.0, 1.0), (1.0, 1.0), (0.0, 1.0), (3.0, 1.0)]);
+    assert!(table.iter().all(|entry| {
+        let picks_a = entry.ratio > 0.0;
+        let picks_b = entry.ratio < 1.0;
+        (!picks_a || entry.triangle_index_a % 2 == 1) && (!picks_b || entry.triangle_index_b % 2 == 1)
+    }));
+    light_pick_test(&[(1.0, 1.0), (3.0, 1.0)]);
+
+    // Nothing to pick gives the sentinel
+    let (table, _) = light_pick_table(&[(0.0, 1.0), (0.0, 1.0)]);
+    assert_eq!(table.len(), 1);
+    assert!(table[0].ratio < 0.0);
+    let (table, _) = light_pick_table(&[]);
+    assert_eq!(table.len(), 1);
+    assert!(table[0].ratio < 0.0);
+}
+
+#[test]
+fn light_pick_test_double_sided() {
+    // A double-sided light emits twice the power of the same light with one side
+    let vertices = [Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::Z * 10.0, Vec3::Z * 10.0 + Vec3::X, Vec3::Z * 10.0 + Vec3::Y]
+        .map(|vertex| vertex.extend(1.0));
+    let indices = [UVec4::new(0, 1, 2, 0), UVec4::new(3, 4, 5, 1)];
+    let mut single_sided = MaterialData::default();
+    single_sided.emissive = Vec4::ONE;
+    let mut double_sided = single_sided;
+    double_sided.set_double_sided(true);
+    let table = build_light_pick_table(&vertices, &indices, &[true, true], &[single_sided, double_sided]);
+
+    let mut picked = [0.0f64; 2];
+    for entry in table.iter() {
+        picked[entry.triangle_index_a as usize] += entry.ratio as f64 / table.len() as f64;
+        picked[entry.triangle_index_b as usize] += (1.0 - entry.ratio as f64) / table.len() as f64;
+    }
+    assert!((picked[0] - 1.0 / 3.0).abs() < 1e-4, "single-sided light picked {} of the time", picked[0]);
+    assert!((picked[1] - 2.0 / 3.0).abs() < 1e-4, "double-sided light picked {} of the time", picked[1]);
+}
+
+// Directions sampled from a spherical triangle stay inside it, and are spread uniformly, so
+// each half of the triangle gets a share of the samples proportional to its solid angle.
+#[test]
+fn spherical_triangle_sampling_test() {
+    let mut rng = StdRng::seed_from_u64(6);
+    // Which side of the great circle through two directions the third is on
+    let side = |d: Vec3, a: Vec3, b: Vec3| a.cross(b).dot(d);
+    let inside = |d: Vec3, [a, b, c]: [Vec3; 3]| {
+        let sides = [side(d, a, b), side(d, b, c), side(d, c, a)];
+        let orientation = side(c, a, b).signum();
+        sides.iter().all(|&s| s * orientation >= -1e-4)
+    };
+
+    let mut tested = 0;
+    while tested < 20 {
+        let origin = random_point(&mut rng, 1.0);
+        let [a, b, c] = [(); 3].map(|_| random_point(&mut rng, 2.0));
+        let [da, db, dc] = [a, b, c].map(|vertex| (vertex - origin).normalize());
+        let area = spherical_triangle_area(da, db, dc);
+        if area < 0.05 {
+            continue;
+        }
+        tested += 1;
+
+        let dm = ((b + c) / 2.0 - origin).normalize();
+        let expected = spherical_triangle_area(da, db, dm) / area;
+        let samples = 20000;
+        let mut in_first_half = 0;
+        for _ in 0..samples {
+            let d = sample_spherical_triangle(da, db, dc, area, Vec2::new(rng.gen(), rng.gen()));
+            assert!((d.length() - 1.0).abs() < 1e-4);
+            assert!(inside(d, [da, db, dc]), "sampled {:?} outside of {:?}", d, [da, db, dc]);
+            if inside(d, [da, db, dm]) {
+                in_first_half += 1;
+            }
+        }
+        let fraction = in_first_half as f32 / samples as f32;
+        assert!((fraction - expected).abs() < 0.02, "{} of the samples in the first half, expected {}", fraction, expected);
+    }
+}
+
+// Scenes built in code take the same path as imported ones
+#[test]
+fn scene_description_test() {
+    use rustic::scene::{MaterialDescription, MeshDescription};
+
+    let quad = |y: f32, material: u32| MeshDescription {
+        positions: vec![Vec3::new(-1.0, y, -1.0), Vec3::new(1.0, y, -1.0), Vec3::new(1.0, y, 1.0), Vec3::new(-1.0, y, 1.0)],
+        normals: vec![Vec3::Y; 4],
+        triangles: vec![[0, 2, 1], [0, 3, 2]],
+        material,
+        ..Default::default()
+    };
+    let description = SceneDescription {
+        meshes: vec![quad(0.0, 0), quad(2.0, 1)],
+        materials: vec![
+            MaterialDescription { name: "Floor".to_string(), ..Default::default() },
+            MaterialDescription { name: "Light".to_string(), emissive: Vec3::splat(5.0), double_sided: true, ..Default::default() },
+        ],
+        camera: None,
+    };
+    assert_eq!(description.triangle_count(), 4);
+
+    let world = world_from(&description);
+    assert_eq!(world.statistics.triangles, 4);
+    assert_eq!(world.statistics.vertices, 8);
+    assert_eq!(world.statistics.emissive_triangles, 2);
+    assert_eq!(world.material_names, vec!["Floor".to_string(), "Light".to_string()]);
+    assert!(world.material_data_buffer[1].double_sided());
+    // The second mesh's indices are offset past the first mesh's vertices
+    assert!(world.index_buffer.iter().any(|triangle| triangle.x >= 4 && triangle.w == 1));
+
+    let empty = SceneDescription::default();
+    assert!(World::from_description(&empty, &GroundSettings::default(), true, false, &LoadProgress::default()).is_none());
+}
+
+#[test]
+fn scene_file_test() {
+    use rustic::scene::MaterialDescription;
+    use rustic::scene_file::SceneFile;
+
+    let ron = r#"(
+        scene: "models/cornell.glb",
+        materials: [(name: "Light", emissive: Some((10.0, 9.0, 8.0))), (name: "Missing", roughness: Some(0.5))],
+        camera: Some((position: (0.0, 1.0, -3.0), rotation: (0.1, 0.2))),
+        environment: (sun: Some((direction: (0.0, 2.0, 0.0), intensity: 3.0))),
+        render: (samples: Some(64), max_bounces: Some(8), nee: Some(2)),
+    )"#;
+    let json = r#"{
+        "scene": "models/cornell.glb",
+        "materials": [{ "name": "Light", "emissive": [10.0, 9.0, 8.0] }, { "name": "Missing", "roughness": 0.5 }],
+        "camera": { "position": [0.0, 1.0, -3.0], "rotation": [0.1, 0.2] },
+        "environment": { "sun": { "direction": [0.0, 2.0, 0.0], "intensity": 3.0 } },
+        "render": { "samples": 64, "max_bounces": 8, "nee": 2 }
+    }"#;
+    for file in [SceneFile::parse(ron, false).unwrap(), SceneFile::parse(json, true).unwrap()] {
+        let mut description = SceneDescription {
+            materials: vec![
+                MaterialDescription { name: "Floor".to_string(), ..Default::default() },
+                MaterialDescription { name: "Light".to_string(), ..Default::default() },
+            ],
+            ..Default::default()
+        };
+        file.apply_materials(&mut description);
+        assert_eq!(description.materials[0].emissive, Vec3::ZERO);
+        assert_eq!(description.materials[1].emissive, Vec3::new(10.0, 9.0, 8.0));
+        // Overrides for materials the scene doesn't have are ignored
+        assert_eq!(description.materials[1].roughness, 0.0);
+
+        let mut config = TracingConfig::default();
+        file.apply_settings(&mut config);
+        assert_eq!(config.camera.cam_position.xyz(), Vec3::new(0.0, 1.0, -3.0));
+        assert_eq!(config.camera.cam_rotation.xy(), Vec2::new(0.1, 0.2));
+        assert_eq!(config.environment.sun_direction, Vec4::new(0.0, 1.0, 0.0, 3.0));
+        assert_eq!(config.environment.has_skybox, 0);
+        assert_eq!(config.render.max_bounces, 8);
+        assert_eq!(config.render.nee, 2);
+        // Settings the file leaves out are untouched
+        assert_eq!(config.render.min_bounces, TracingConfig::default().render.min_bounces);
+        assert_eq!(file.render.samples, Some(64));
+    }
+
+    // Typos are errors, rather than settings that silently do nothing
+    assert!(SceneFile::parse("(scene: \"a.glb\", sampels: 4)", false).is_err());
+}
+
+#[test]
+fn batch_manifest_test() {
+    use rustic::batch::BatchManifest;
+    use std::path::{Path, PathBuf};
+
+    let manifest = r#"(
+        output_directory: Some("renders"),
+        jobs: [
+            (scene: "scenes/cornell.ron", samples: Some(16)),
+            (scene: "scenes/sponza.glb", output: Some("sponza_cpu.jpg"), cpu: true, tonemapping: Some(ACESHill)),
+        ],
+    )"#;
+    let manifest = BatchManifest::parse(manifest, false).unwrap();
+    assert_eq!(manifest.jobs.len(), 2);
+    assert_eq!(manifest.jobs[0].samples, Some(16));
+    assert!(!manifest.jobs[0].cpu && manifest.jobs[1].cpu);
+    assert_eq!(manifest.output_path(&manifest.jobs[0]), PathBuf::from("renders/cornell.png"));
+    assert_eq!(manifest.output_path(&manifest.jobs[1]), PathBuf::from("renders/sponza_cpu.jpg"));
+
+    // A directory becomes one job per scene file in it, in name order, rendered next to the scene files
+    let dir = std::env::temp_dir().join(format!("rustic_batch_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in ["b.ron", "a.json", "model.glb", "notes.txt"] {
+        std::fs::write(dir.join(name), "").unwrap();
+    }
+    let manifest = BatchManifest::load(&dir).unwrap();
+    let scenes = manifest.jobs.iter().map(|job| job.scene.file_name().unwrap().to_owned()).collect::<Vec<_>>();
+    assert_eq!(scenes, vec!["a.json", "b.ron"]);
+    assert_eq!(manifest.output_path(&manifest.jobs[1]), dir.join("b.png"));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(BatchManifest::load(Path::new("does/not/exist.ron")).is_err());
+}
+
+#[test]
+fn procedural_texture_test() {
+    use rustic::procedural::{bind_procedural_textures, checker, gradient, noise, procedural_regions, ProceduralBinding, TextureChannel};
+    use shared_structs::{CpuImage, Sampler};
+
+    let (black, white) = (Vec4::new(0.0, 0.0, 0.0, 1.0), Vec4::ONE);
+    let checker = checker(black, white, 2.0);
+    assert_eq!(checker(Vec2::new(0.1, 0.1)), black);
+    assert_eq!(checker(Vec2::new(0.6, 0.1)), white);
+    assert_eq!(checker(Vec2::new(-0.1, 0.1)), white);
+    assert_eq!(gradient(black, white)(Vec2::new(1.25, 0.0)), Vec4::new(0.25, 0.25, 0.25, 1.0));
+    let noise = noise(black, white, 4.0);
+    let value = noise(Vec2::new(0.3, 0.7));
+    assert!(value.x >= 0.0 && value.x <= 1.0);
+    assert_eq!(value, noise(Vec2::new(0.3, 0.7)));
+
+    let names = vec!["Floor".to_string(), "Wall".to_string()];
+    let mut materials = vec![MaterialData::default(); 2];
+    let bindings = vec![
+        ProceduralBinding { material: "Floor".to_string(), channel: TextureChannel::Albedo, texture: checker.clone() },
+        ProceduralBinding { material: "Missing".to_string(), channel: TextureChannel::Roughness, texture: checker.clone() },
+    ];
+    let placed = bind_procedural_textures(&bindings, &names, &mut materials);
+    assert_eq!(placed.len(), 1);
+    assert!(materials[0].has_albedo_texture());
+    assert!(!materials[1].has_albedo_texture() && !materials[1].has_roughness_texture());
+
+    // Lookups at the material's texture location call the texture, everything else reads the image
+    let atlas = vec![Vec4::new(0.5, 0.5, 0.5, 1.0); 4];
+    let regions = procedural_regions(&placed);
+    let image = CpuImage::new(&atlas, 2, 2).with_procedural(&regions);
+    let uv = Vec2::new(0.6, 0.1);
+    let location = materials[0].albedo;
+    assert_eq!(image.sample_by_lod(Sampler, location.xy() + uv * location.zw(), 0.0), white);
+    assert_eq!(image.sample_by_lod(Sampler, uv, 0.0), Vec4::new(0.5, 0.5, 0.5, 1.0));
+}
+
+#[test]
+fn material_pattern_test() {
+    use kernels::pattern::pattern_weight;
+    use rustic::scene::{MaterialDescription, MeshDescription, PatternDescription, PatternKind, TextureDescription};
+    use shared_structs::{PATTERN_CHECKER, PATTERN_GRADIENT, PATTERN_NOISE, PATTERN_NONE};
+
+    assert_eq!(pattern_weight(PATTERN_CHECKER, Vec2::new(0.1, 0.1), 2.0), 0.0);
+    assert_eq!(pattern_weight(PATTERN_CHECKER, Vec2::new(0.6, 0.1), 2.0), 1.0);
+    assert_eq!(pattern_weight(PATTERN_CHECKER, Vec2::new(0.6, 0.6), 2.0), 0.0);
+    assert_eq!(pattern_weight(PATTERN_GRADIENT, Vec2::new(0.25, 0.0), 1.0), 0.25);
+    assert_eq!(pattern_weight(PATTERN_NONE, Vec2::new(0.6, 0.1), 2.0), 0.0);
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..1000 {
+        let uv = Vec2::new(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0));
+        let weight = pattern_weight(PATTERN_NOISE, uv, 3.0);
+        assert!((0.0..=1.0).contains(&weight));
+    }
+
+    // A patterned channel doesn't take a slot in the atlas, even when it also has a texture
+    let mut material = MaterialDescription { name: "Tiles".to_string(), roughness: 0.2, ..Default::default() };
+    material.textures.albedo = Some(TextureDescription::Pixels { width: 1, height: 1, rgba: vec![255; 4] });
+    material.patterns.albedo = Some(PatternDescription { kind: PatternKind::Checker, value: Vec4::new(0.1, 0.1, 0.1, 1.0), scale: 8.0 });
+    material.patterns.roughness = Some(PatternDescription { kind: PatternKind::Noise, value: Vec4::splat(0.9), scale: 4.0 });
+    let description = SceneDescription {
+        meshes: vec![MeshDescription {
+            positions: vec![Vec3::ZERO, Vec3::X, Vec3::Z],
+            triangles: vec![[0, 2, 1]],
+            ..Default::default()
+        }],
+        materials: vec![material],
+        camera: None,
+    };
+    let world = world_from(&description);
+    assert_eq!(world.statistics.atlas_occupancy, 0.0);
+    let material = world.material_data_buffer[0];
+    assert!(!material.has_albedo_texture());
+    assert_eq!(material.patterns, UVec4::new(PATTERN_CHECKER, PATTERN_NOISE, PATTERN_NONE, 0));
+    assert_eq!(material.albedo_pattern, Vec4::new(0.1, 0.1, 0.1, 1.0));
+    assert_eq!(material.roughness.x, 0.2);
+    assert_eq!(material.roughness.y, 0.9);
+}
+
+#[test]
+fn udim_texture_test() {
+    use rustic::scene::{udim_tiles, MaterialDescription, MeshDescription, TextureDescription};
+    use shared_structs::pack_udim_grid;
+
+    let dir = std::env::temp_dir().join(format!("rustic_udim_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let colors = [("wall.1001.png", [255, 0, 0]), ("wall.1002.png", [0, 255, 0]), ("wall.1011.png", [0, 0, 255]), ("noise_2048.png", [0, 0, 0]), ("brick1001.png", [0, 0, 0])];
+    for (name, [r, g, b]) in colors {
+        image::RgbaImage::from_pixel(4, 4, image::Rgba([r, g, b, 255])).save(dir.join(name)).unwrap();
+    }
+
+    let tiles = udim_tiles(&dir.join("wall.1001.png")).unwrap();
+    let positions = tiles.iter().map(|tile| (tile.u, tile.v)).collect::<Vec<_>>();
+    assert_eq!(positions, vec![(0, 0), (1, 0), (0, 1)]);
+    assert_eq!(udim_tiles(&dir.join("wall.<UDIM>.png")).unwrap().len(), 3);
+    // Numbers outside the UDIM range, and lone numbered files, are ordinary textures
+    assert!(udim_tiles(&dir.join("noise_2048.png")).is_none());
+    assert!(udim_tiles(&dir.join("brick1001.png")).is_none());
+
+    let mut material = MaterialDescription { name: "Wall".to_string(), ..Default::default() };
+    material.textures.albedo = Some(TextureDescription::Udim(tiles));
+    let description = SceneDescription {
+        meshes: vec![MeshDescription {
+            positions: vec![Vec3::ZERO, Vec3::X, Vec3::Z],
+            uvs: vec![Vec2::ZERO, Vec2::new(2.0, 0.0), Vec2::new(0.0, 2.0)],
+            triangles: vec![[0, 2, 1]],
+            ..Default::default()
+        }],
+        materials: vec![material],
+        camera: None,
+    };
+    let world = world_from(&description);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // The tiles share one slot, and the material points at the first tile's cell
+    let material = world.material_data_buffer[0];
+    assert!(material.has_albedo_texture());
+    assert_eq!(material.udim_grid.x, pack_udim_grid(2, 2));
+    let cell = material.albedo * 4096.0;
+    let atlas = world.atlas.as_rgba8().unwrap();
+    let texel = |tile_x: f32, tile_y: f32| {
+        let x = cell.x + (tile_x + 0.5) * cell.z;
+        let y = cell.y + (tile_y + 0.5) * cell.w;
+        atlas.get_pixel(x as u32, y as u32).0
+    };
+    assert_eq!(texel(0.0, 0.0), [255, 0, 0, 255]);
+    assert_eq!(texel(1.0, 0.0), [0, 255, 0, 255]);
+    assert_eq!(texel(0.0, 1.0), [0, 0, 255, 255]);
+    assert_eq!(pack_udim_grid(1, 1), 0);
+}
+
+#[test]
+fn texture_transform_test() {
+    use rustic::scene::{MaterialDescription, MeshDescription, TextureDescription, TextureTransform};
+    use rustic::scene_file::SceneFile;
+
+    let file = SceneFile::parse(r#"(scene: "floor.gltf", materials: [(name: "Floor", texture_transform: Some((scale: (8.0, 8.0), rotation: 0.5)))])"#, false).unwrap();
+    let mut material = MaterialDescription { name: "Floor".to_string(), ..Default::default() };
+    material.textures.albedo = Some(TextureDescription::Pixels { width: 1, height: 1, rgba: vec![255; 4] });
+    material.textures.normal = Some(TextureDescription::Pixels { width: 1, height: 1, rgba: vec![255; 4] });
+    let mut description = SceneDescription {
+        meshes: vec![MeshDescription {
+            positions: vec![Vec3::ZERO, Vec3::X, Vec3::Z],
+            triangles: vec![[0, 2, 1]],
+            ..Default::default()
+        }],
+        materials: vec![material],
+        camera: None,
+    };
+    file.apply_materials(&mut description);
+    let transform = description.materials[0].transforms.albedo.unwrap();
+    assert_eq!(transform, TextureTransform { offset: Vec2::ZERO, scale: Vec2::splat(8.0), rotation: 0.5 });
+
+    // Channels without a transform are left with a zero scale, which the kernels skip
+    description.materials[0].transforms.metallic = None;
+    description.materials[0].transforms.normal = Some(TextureTransform { offset: Vec2::new(0.25, 0.5), ..Default::default() });
+    let world = world_from(&description);
+    let material = world.material_data_buffer[0];
+    assert_eq!(material.albedo_transform, Vec4::new(0.0, 0.0, 8.0, 8.0));
+    assert_eq!(material.metallic_transform, Vec4::ZERO);
+    assert_eq!(material.normal_transform, Vec4::new(0.25, 0.5, 1.0, 1.0));
+    assert_eq!(material.uv_rotation, Vec4::new(0.5, 0.0, 0.5, 0.0));
+}
+
+#[test]
+fn skybox_mips_test() {
+    use rustic::environment::pack_skybox_mips;
+
+    // Red on the left half, blue on the right
+    let image = image::Rgba32FImage::from_fn(16, 8, |x, _| if x < 8 { image::Rgba([1.0, 0.0, 0.0, 1.0]) } else { image::Rgba([0.0, 0.0, 1.0, 1.0]) });
+    let (packed, levels) = pack_skybox_mips(&image::DynamicImage::ImageRgba32F(image.clone()));
+    let packed = packed.into_rgba32f();
+    assert_eq!(packed.dimensions(), (16, 12));
+    assert_eq!(levels, 4);
+
+    // The full image is kept as is, with each smaller level below it, to the right of the previous one
+    for (x, y, pixel) in image.enumerate_pixels() {
+        assert_eq!(packed.get_pixel(x, y), pixel);
+    }
+    let close = |x: u32, y: u32, expected: [f32; 4]| {
+        let pixel = packed.get_pixel(x, y).0;
+        assert!(pixel.iter().zip(expected).all(|(a, b)| (a - b).abs() < 0.01), "{:?} at {}, {}", pixel, x, y);
+    };
+    close(0, 8, [1.0, 0.0, 0.0, 1.0]);
+    close(7, 11, [0.0, 0.0, 1.0, 1.0]);
+    close(8, 8, [1.0, 0.0, 0.0, 1.0]);
+    close(11, 9, [0.0, 0.0, 1.0, 1.0]);
+}
+
+#[test]
+fn sun_sampling_test() {
+    use kernels::skybox::{in_sun_cone, sample_sun_cone, SUN_CONE_COS, SUN_CONE_PDF};
+
+    // Sun samples stay in the cone, and its pdf matches the solid angle of the cone
+    let sun = Vec3::new(0.5, 1.3, 1.0).normalize().extend(15.0);
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..1000 {
+        let direction = sample_sun_cone(sun, Vec2::new(rng.gen(), rng.gen()));
+        assert!((direction.length() - 1.0).abs() < 1e-4);
+        assert!(direction.dot(sun.xyz()) >= SUN_CONE_COS - 1e-4);
+    }
+    let mut uniform_direction = || loop {
+        let p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+        if p.length_squared() <= 1.0 && p.length_squared() > 1e-6 {
+            break p.normalize();
+        }
+    };
+    let count = 100000;
+    let inside = (0..count).filter(|_| in_sun_cone(sun, uniform_direction())).count();
+    let expected = count as f32 / (4.0 * std::f32::consts::PI * SUN_CONE_PDF);
+    assert!((inside as f32 - expected).abs() < expected * 0.2);
+}
+
+#[test]
+fn sun_sampling_render_test() {
+    // Sampling the sun directly changes the noise, not the brightness of the image
+    let (width, height) = (64, 64);
+    let render = |nee: NextEventEstimation| {
+        let state = setup_trace(width, height, 16);
+        state.config.write().render.nee = nee.to_u32();
+        trace(true, "scenes/PBRTest.glb", None, &state);
+        let frame = state.framebuffer.read();
+        frame.iter().sum::<f32>() / frame.len() as f32
+    };
+    let reference = render(NextEventEstimation::None);
+    let mis = render(NextEventEstimation::MultipleImportanceSampling);
+    assert!((reference - mis).abs() < reference * 0.1, "{} vs {}", reference, mis);
+}
+
+#[test]
+fn solar_position_test() {
+    use rustic::batch::TimeLapse;
+    use rustic::scene_file::SceneFile;
+    use rustic::solar::SolarDescription;
+
+    // Greenwich at noon on the June solstice, when the sun is due south and 23.4° above the equator
+    let solar = SolarDescription { latitude: 51.48, longitude: 0.0, year: 2024, month: 6, day: 21, hour: 12.0, utc_offset: 0.0, intensity: 15.0 };
+    assert_eq!(solar.day_of_year(), 173);
+    let position = solar.position();
+    assert!((position.elevation.to_degrees() - 61.96).abs() < 0.5, "{}", position.elevation.to_degrees());
+    assert!((position.azimuth.to_degrees() - 180.0).abs() < 2.0, "{}", position.azimuth.to_degrees());
+    assert!(solar.direction().z > 0.9 * solar.direction().xz().length());
+    assert_eq!(solar.sun_intensity(), 15.0);
+
+    // The sun rises in the east, and is gone at midnight
+    let morning = solar.at_hour(6.0).position();
+    assert!((60.0..120.0).contains(&morning.azimuth.to_degrees()));
+    assert!(solar.at_hour(6.0).direction().x > 0.0);
+    assert_eq!(solar.at_hour(0.0).sun_intensity(), 0.0);
+    // Local time follows the UTC offset, so 13:00 in UTC+1 is noon in UTC
+    let summer_time = SolarDescription { hour: 13.0, utc_offset: 1.0, ..solar };
+    assert!((summer_time.position().elevation - position.elevation).abs() < 1e-4);
+    assert_eq!(SolarDescription { year: 2023, month: 3, day: 1, ..solar }.day_of_year(), 60);
+    assert_eq!(SolarDescription { year: 2024, month: 3, day: 1, ..solar }.day_of_year(), 61);
+
+    // Scene files can place the sun by time and place, and batch time-lapses step through the day
+    let file = SceneFile::parse(r#"(scene: "a.glb", environment: (solar: Some((latitude: 51.48, hour: 12.0))))"#, false).unwrap();
+    let mut config = TracingConfig::default();
+    file.apply_settings(&mut config);
+    assert_eq!(config.environment.sun_direction, SolarDescription { latitude: 51.48, hour: 12.0, ..Default::default() }.sun());
+    let time_lapse = TimeLapse { end_hour: 18.0, frames: 4 };
+    assert_eq!(time_lapse.hour(12.0, 0), 12.0);
+    assert_eq!(time_lapse.hour(12.0, 3), 18.0);
+    assert_eq!(TimeLapse::frame_path(std::path::Path::new("out/noon.png"), 1), std::path::PathBuf::from("out/noon_0002.png"));
+}
+
+#[test]
+fn aerial_perspective_test() {
+    use kernels::skybox::aerial_perspective;
+
+    let sun = Vec3::new(0.5, 1.3, 1.0).normalize().extend(15.0);
+    let origin = Vec3::new(0.0, 2.0, 0.0);
+    let direction = Vec3::new(1.0, 0.1, 0.0).normalize();
+    let (transmittance, inscattered) = aerial_perspective(sun, 1.0, origin, direction, 0.0);
+    assert_eq!(transmittance, Vec3::ONE);
+    assert_eq!(inscattered, Vec3::ZERO);
+
+    // Farther surfaces, and thicker air, fade more into the haze. Blue is scattered most.
+    let (near, near_light) = aerial_perspective(sun, 100.0, origin, direction, 100.0);
+    let (far, far_light) = aerial_perspective(sun, 100.0, origin, direction, 1000.0);
+    let (thick, thick_light) = aerial_perspective(sun, 1000.0, origin, direction, 100.0);
+    assert!(near.cmplt(Vec3::ONE).all() && far.cmplt(near).all() && thick.cmplt(near).all());
+    assert!(far_light.cmpgt(near_light).all() && thick_light.cmpgt(near_light).all());
+    assert!(far.z < far.x);
+}
+
+#[test]
+fn unlinked_environment_test() {
+    // Materials unlinked from the environment only get light from emitters, so the scene darkens
+    let (width, height) = (64, 64);
+    let linked = setup_trace(width, height, 16);
+    trace(true, "scenes/PBRTest.glb", None, &linked);
+    let unlinked = setup_trace(width, height, 16);
+    *unlinked.materials.write() = linked.materials.read().iter().cloned().map(|material| MaterialEdits { unlinked_environment: true, ..material }).collect();
+    trace(true, "scenes/PBRTest.glb", None, &unlinked);
+
+    let brightness = |state: &Arc<TracingState>| {
+        let frame = state.framebuffer.read();
+        frame.iter().sum::<f32>() / frame.len() as f32
+    };
+    assert!(brightness(&unlinked) < brightness(&linked) * 0.9);
+    assert_eq!(shared_structs::ENVIRONMENT_LIGHT_GROUP, 1 << 31);
+}
+
+#[test]
+fn world_cache_test() {
+    use std::sync::atomic::Ordering;
+
+    // Restarting on the same state reuses the uploaded world, which has to pick up the edits made since
+    let (width, height) = (64, 64);
+    let state = setup_trace(width, height, 16);
+    trace(false, "scenes/PBRTest.glb", None, &state);
+    let brightness = |state: &Arc<TracingState>| {
+        let frame = state.framebuffer.read();
+        frame.iter().sum::<f32>() / frame.len() as f32
+    };
+    let linked = brightness(&state);
+
+    for material in state.materials.write().iter_mut() {
+        material.unlinked_environment = true;
+    }
+    state.framebuffer.reset(vec![0.0; (width * height * 3) as usize]);
+    state.samples.store(0, Ordering::Relaxed);
+    state.sample_limit.store(16, Ordering::Relaxed);
+    state.running.store(true, Ordering::Relaxed);
+    trace(false, "scenes/PBRTest.glb", None, &state);
+    assert!(brightness(&state) < linked * 0.9);
+}
+
+#[test]
+fn split_variance_test() {
+    use kernels::accumulate_odd_sample;
+    use rustic::variance::split_variance;
+
+    // Pixels of uniform noise, accumulated the way the kernels do it
+    let mut rng = StdRng::seed_from_u64(7);
+    let (pixels, samples) = (4000, 33);
+    let estimated = (0..pixels)
+        .map(|_| {
+            let (mut mean, mut odd_mean) = (0.0, 0.0);
+            for sample in 0..samples {
+                let value = rng.gen::<f32>();
+                mean += (value - mean) / (sample + 1) as f32;
+                odd_mean = accumulate_odd_sample(odd_mean, Vec3::splat(value), sample);
+            }
+            split_variance(mean, odd_mean, samples)
+        })
+        .sum::<f32>()
+        / pixels as f32;
+    let expected = 1.0 / 12.0 / samples as f32;
+    assert!((estimated - expected).abs() < expected * 0.1, "{} vs {}", estimated, expected);
+    assert_eq!(split_variance(0.5, 0.0, 1), 0.0);
+}
+
+#[test]
+fn firefly_rejection_test() {
+    use kernels::reject_firefly;
+    use rustic::firefly::spread_rejected_energy;
+
+    // Noisy samples around 1, and a single firefly
+    let mut rng = StdRng::seed_from_u64(3);
+    let (mut mean, mut outlier, mut unclamped) = (Vec4::ZERO, Vec4::ZERO, Vec4::ZERO);
+    for sample in 0..64 {
+        let value = if sample == 40 { 1000.0 } else { rng.gen_range(0.5..1.5) };
+        let radiance = Vec4::new(value, value, value, 1.0);
+        let (kept, next_outlier) = reject_firefly(8.0, mean.xyz(), outlier, radiance, sample);
+        outlier = next_outlier;
+        mean = mean.lerp(kept, 1.0 / (sample + 1) as f32);
+        unclamped = unclamped.lerp(radiance, 1.0 / (sample + 1) as f32);
+    }
+    assert!(mean.x < 1.2, "{}", mean);
+    assert!(outlier.w > 0.0 && outlier.w < 0.5, "{}", outlier);
+    // Nothing is lost, the energy taken off is kept aside
+    assert!((mean.xyz() + outlier.xyz()).abs_diff_eq(unclamped.xyz(), 1e-3), "{} vs {}", mean + outlier, unclamped);
+
+    // Disabled, or still warming up, samples pass through untouched
+    let firefly = Vec4::new(1000.0, 0.0, 0.0, 1.0);
+    assert_eq!(reject_firefly(0.0, Vec3::ONE, Vec4::new(0.0, 0.0, 0.0, 0.1), firefly, 40).0, firefly);
+    assert_eq!(reject_firefly(8.0, Vec3::ONE, Vec4::new(0.0, 0.0, 0.0, 0.1), firefly, 2).0, firefly);
+
+    // Spreading keeps the energy in the image, even from a corner
+    let (width, height) = (4, 3);
+    let mut image = vec![0.0; width * height * 3];
+    let mut outliers = vec![Vec4::ZERO; width * height];
+    outliers[0] = Vec4::new(9.0, 0.0, 0.0, 0.0);
+    outliers[6] = Vec4::new(0.0, 4.0, 0.0, 0.0);
+    spread_rejected_energy(&mut image, &outliers, width as u32, height as u32);
+    let total = image.chunks(3).fold(Vec3::ZERO, |total, color| total + Vec3::from_slice(color));
+    assert!(total.abs_diff_eq(Vec3::new(9.0, 4.0, 0.0), 1e-4), "{}", total);
+    assert!(image[0] > image[3] && image[3] > 0.0 && image[2 * 3] == 0.0);
+}
+
+#[test]
+fn estimated_error_test() {
+    // More samples, less noise
+    let (width, height) = (64, 64);
+    let render = |samples: u32| {
+        let state = setup_trace(width, height, samples);
+        trace(true, "scenes/PBRTest.glb", None, &state);
+        let error = *state.estimated_error.read();
+        assert_eq!(state.variance.read().len(), (width * height) as usize);
+        error
+    };
+    let few = render(4);
+    let many = render(64);
+    assert!(many > 0.0 && many < few * 0.5, "{} vs {}", many, few);
+}
+
+#[test]
+fn denoise_comparison_test() {
+    // The left half of each row comes from the raw image, the rest stays denoised
+    let (width, height) = (5, 3);
+    let raw = vec![1.0; width * height * 3];
+    let mut denoised = vec![0.0; width * height * 3];
+    compose_denoise_comparison(&mut denoised, &raw, width as u32);
+    for (i, value) in denoised.iter().enumerate() {
+        let x = i / 3 % width;
+        assert_eq!(*value, if x < width / 2 { 1.0 } else { 0.0 });
+    }
+}
+
+#[test]
+fn denoise_region_test() {
+    // Denoised deep inside the region, raw outside it, and in between along its edge
+    let (width, height) = (64, 64);
+    let raw = vec![1.0; width * height * 3];
+    let mut denoised = vec![0.0; width * height * 3];
+    let region = Tile { x: 16, y: 16, width: 32, height: 32 };
+    blend_denoise_region(&mut denoised, &raw, width as u32, region);
+    let pixel = |x: usize, y: usize| denoised[(y * width + x) * 3];
+    assert_eq!(pixel(32, 32), 0.0);
+    assert_eq!(pixel(4, 32), 1.0);
+    assert_eq!(pixel(32, 60), 1.0);
+    assert!(pixel(18, 32) > 0.0 && pixel(18, 32) < 1.0);
+}
+
+#[test]
+fn denoise_auxiliary_test() {
+    use shared_structs::{CpuImage, Sampler};
+
+    // A wall filling the right half of the view, and sky on the left
+    let vertices = [Vec3::new(0.0, -20.0, 5.0), Vec3::new(20.0, -20.0, 5.0), Vec3::new(20.0, 20.0, 5.0), Vec3::new(0.0, 20.0, 5.0)]
+        .map(|vertex| vertex.extend(1.0));
+    let mut indices = vec![UVec4::new(0, 1, 2, 0), UVec4::new(0, 2, 3, 0)];
+    let bvh = BVHBuilder::new(&vertices, &mut indices).build();
+    let per_vertex_data = vertices
+        .iter()
+        .map(|&vertex| PerVertexData { vertex, normal: Vec4::new(0.0, 0.0, -1.0, 0.0), ..Default::default() })
+        .collect::<Vec<_>>();
+    let atlas_buffer = vec![Vec4::ONE; 4];
+    let atlas = CpuImage::new(&atlas_buffer, 2, 2);
+
+    let mut config = TracingConfig::default();
+    config.render.width = 64;
+    config.render.height = 48;
+    config.camera.cam_position = Vec4::ZERO;
+    config.camera.cam_rotation = Vec4::ZERO;
+    let auxiliary = |material: MaterialData, x: f32| {
+        kernels::denoise_auxiliary(&config, Vec2::new(x, 24.0), &per_vertex_data, &indices, &bvh.nodes, &[material], &Sampler, &atlas)
+    };
+
+    let mut diffuse = MaterialData::default();
+    diffuse.albedo = Vec4::new(0.2, 0.4, 0.6, 1.0);
+    diffuse.metallic = Vec4::ZERO;
+    let (albedo, normal) = auxiliary(diffuse, 40.0);
+    assert!(albedo.abs_diff_eq(Vec3::new(0.2, 0.4, 0.6), 1e-4), "{:?}", albedo);
+    assert!(normal.abs_diff_eq(Vec3::new(0.0, 0.0, -1.0), 1e-4), "{:?}", normal);
+    assert_eq!(auxiliary(diffuse, 10.0), (Vec3::ONE, Vec3::ZERO));
+
+    // Metals reflect more towards grazing angles, and albedo never goes above 1
+    let mut metal = diffuse;
+    metal.albedo = Vec4::new(2.0, 0.5, 0.5, 1.0);
+    metal.metallic = Vec4::ONE;
+    let (center, _) = auxiliary(metal, 33.0);
+    let (edge, _) = auxiliary(metal, 63.0);
+    assert!(center.cmple(Vec3::ONE).all() && center.x == 1.0);
+    assert!(edge.y > center.y);
+}
+
+#[test]
+fn texture_cache_eviction_test() {
+    let texture = |size: u32| Arc::new(image::DynamicImage::new_rgba8(size, size));
+    let bytes = |size: usize| size * size * 4;
+
+    // Room for two 16x16 textures, but not three
+    let mut cache = TextureCache::new(bytes(16) * 2 + 1);
+    cache.insert("a".to_string(), texture(16));
+    cache.insert("b".to_string(), texture(16));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.size_bytes(), bytes(16) * 2);
+
+    // Touching "a" makes "b" the least recently used, so it goes first
+    assert!(cache.get("a").is_some());
+    cache.insert("c".to_string(), texture(16));
+    assert!(cache.get("b").is_none());
+    assert!(cache.get("a").is_some());
+    assert!(cache.get("c").is_some());
+    assert_eq!(cache.size_bytes(), bytes(16) * 2);
+
+    // Replacing an entry doesn't count it twice
+    cache.insert("c".to_string(), texture(8));
+    assert_eq!(cache.size_bytes(), bytes(16) + bytes(8));
+
+    // Anything larger than the whole cache isn't kept, and doesn't evict anything
+    cache.insert("d".to_string(), texture(64));
+    assert!(cache.get("d").is_none());
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn cancelled_build_test() {
+    let mut rng = StdRng::seed_from_u64(5);
+    let (vertices, indices) = random_triangle_soup(&mut rng, 10000);
+    let mut material = MaterialData::default();
+    material.emissive = Vec4::ONE;
+    let material_datas = [material];
+    let mask = vec![true; indices.len()];
+
+    // Cancelled before starting, both builds give up
+    let cancel = CancelToken::default();
+    cancel.cancel();
+    let mut cancelled_indices = indices.clone();
+    assert!(BVHBuilder::new(&vertices, &mut cancelled_indices).cancel_token(cancel.clone()).try_build().is_none());
+    assert!(try_build_light_pick_table(&vertices, &indices, &mask, &material_datas, &cancel).is_none());
+
+    // A fresh token builds the same as without one
+    let cancel = CancelToken::default();
+    let mut restarted_indices = indices.clone();
+    let restarted = BVHBuilder::new(&vertices, &mut restarted_indices).cancel_token(cancel.clone()).try_build().unwrap();
+    let mut reference_indices = indices.clone();
+    let reference = BVHBuilder::new(&vertices, &mut reference_indices).build();
+    assert_eq!(restarted.nodes.len(), reference.nodes.len());
+    assert_eq!(restarted_indices, reference_indices);
+
+    let restarted = try_build_light_pick_table(&vertices, &indices, &mask, &material_datas, &cancel).unwrap();
+    let reference = build_light_pick_table(&vertices, &indices, &mask, &material_datas);
+    assert_eq!(restarted.len(), reference.len());
+}
+
+#[test]
+fn morton_order_covers_screen_test() {
+    use kernels::morton::{morton_decode, morton_encode, morton_pixel, tile_count, TILE_SIZE};
+    for code in 0..4096 {
+        assert_eq!(morton_encode(morton_decode(code)), code);
+    }
+    for (width, height) in [(64, 64), (100, 72), (1280, 720), (7, 300)] {
+        let mut seen = vec![0; (width * height) as usize];
+        for tile in 0..tile_count(width, height) {
+            for y in 0..TILE_SIZE {
+                for x in 0..TILE_SIZE {
+                    let pixel = morton_pixel(tile, glam::UVec2::new(x, y), width);
+                    if pixel.x < width && pixel.y < height {
+                        seen[(pixel.y * width + pixel.x) as usize] += 1;
+                    }
+                }
+            }
+        }
+        assert!(seen.iter().all(|&count| count == 1), "{}x{} isn't covered exactly once", width, height);
+    }
+}
+
+// Only the order of work changes, but sorting the triangles can break ties in the BVH differently
+fn morton_render_test(use_cpu: bool) {
+    let (width, height) = (100, 72);
+    let render = |morton_order: bool| {
+        let state = Arc::new(TracingState::new(width, height));
+        state.config.write().render.morton_order = morton_order as u32;
+        state.sample_limit.store(2, Ordering::Relaxed);
+        state.running.store(true, Ordering::Relaxed);
+        trace(use_cpu, "scenes/PBRTest.glb", None, &state);
+        let frame = state.framebuffer.read().clone();
+        frame
+    };
+    let report = rustic::parity::compare_framebuffers(width, height, &render(false), &render(true));
+    assert!(report.diverged_pixels(1e-3) * 100 < (width * height) as usize);
+    assert!(report.mean_abs() < 1e-3);
+}
+
+#[test]
+fn morton_render_test_cpu() {
+    morton_render_test(true);
+}
+
+#[test]
+fn morton_render_test_gpu() {
+    morton_render_test(false);
+}
+
+// Tracing camera rays in their own dispatch, or in the path tracing kernel, finds the same first hits
+#[test]
+fn primary_pass_render_test() {
+    let (width, height) = (100, 72);
+    let render = |primary_pass: bool| {
+        let state = Arc::new(TracingState::new(width, height));
+        state.config.write().render.primary_pass = primary_pass as u32;
+        state.sample_limit.store(2, Ordering::Relaxed);
+        state.running.store(true, Ordering::Relaxed);
+        trace(false, "scenes/PBRTest.glb", None, &state);
+        let frame = state.framebuffer.read().clone();
+        frame
+    };
+    let report = rustic::parity::compare_framebuffers(width, height, &render(false), &render(true));
+    assert!(report.diverged_pixels(1e-3) * 100 < (width * height) as usize);
+    assert!(report.mean_abs() < 1e-3);
+}
+
+#[test]
+fn upscale_bilinear_test() {
+    use rustic::upscale::upscale_bilinear;
+
+    // Pixel centers line up, so the outer pixels keep the edge values and the inner ones blend
+    let source = [0.0, 0.0, 0.0, 1.0, 2.0, 4.0];
+    let mut target = vec![0.0; 4 * 3];
+    upscale_bilinear(&source, 2, 1, &mut target, 4, 1);
+    let red = target.chunks(3).map(|pixel| pixel[0]).collect::<Vec<_>>();
+    assert_eq!(red, [0.0, 0.25, 0.75, 1.0]);
+    assert_eq!(&target[9..], &[1.0, 2.0, 4.0]);
+
+    // Odd sizes, as when the full resolution isn't divisible by two
+    let source = vec![0.5; 3 * 2 * 3];
+    let mut target = vec![0.0; 5 * 3 * 3];
+    upscale_bilinear(&source, 3, 2, &mut target, 5, 3);
+    assert!(target.iter().all(|&value| (value - 0.5).abs() < 1e-6));
+}
+
+#[test]
+fn proxy_simplification_test() {
+    use rustic::proxy::Proxy;
+
+    // Two flat grids of different materials, which simplify to a handful of triangles each
+    let size = 64u32;
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for material in 0..2u32 {
+        let offset = vertices.len() as u32;
+        for y in 0..=size {
+            for x in 0..=size {
+                vertices.push(Vec4::new(x as f32, y as f32, material as f32 * 10.0, 1.0));
+            }
+        }
+        for y in 0..size {
+            for x in 0..size {
+                let corner = offset + y * (size + 1) + x;
+                indices.push(UVec4::new(corner, corner + 1, corner + size + 1, material));
+                indices.push(UVec4::new(corner + 1, corner + size + 2, corner + size + 1, material));
+            }
+        }
+    }
+    let materials = vec![MaterialData::default(); 2];
+    let proxy = Proxy::build(&vertices, &indices, &materials, 2048, &CancelToken::default()).unwrap();
+    assert!(proxy.index_buffer.len() < indices.len());
+    for material in 0..2 {
+        assert!(proxy.index_buffer.iter().any(|triangle| triangle.w == material));
+    }
+    // Every triangle lies in the plane of the grid it came from
+    for triangle in &proxy.index_buffer {
+        let z = triangle.w as f32 * 10.0;
+        assert!([triangle.x, triangle.y, triangle.z].iter().all(|&index| vertices[index as usize].z == z));
+    }
+}
+
+#[test]
+fn proxy_point_test() {
+    use rustic::proxy::Proxy;
+    use shared_structs::{point_corners, PRIMITIVE_POINT};
+
+    // A point cloud big enough to be simplified if it were triangles, next to a grid that is
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for i in 0..4096u32 {
+        let offset = vertices.len() as u32;
+        let center = Vec3::new((i % 64) as f32, (i / 64) as f32, 0.0);
+        vertices.extend(point_corners(center, 0.25).map(|corner| corner.extend(1.0)));
+        indices.push(UVec4::new(offset, offset + 1, offset + 2, PRIMITIVE_POINT));
+    }
+    let size = 64u32;
+    let offset = vertices.len() as u32;
+    for y in 0..=size {
+        for x in 0..=size {
+            vertices.push(Vec4::new(x as f32, y as f32, 10.0, 1.0));
+        }
+    }
+    for y in 0..size {
+        for x in 0..size {
+            let corner = offset + y * (size + 1) + x;
+            indices.push(UVec4::new(corner, corner + 1, corner + size + 1, 1));
+            indices.push(UVec4::new(corner + 1, corner + size + 2, corner + size + 1, 1));
+        }
+    }
+    let materials = vec![MaterialData::default(); 2];
+    let proxy = Proxy::build(&vertices, &indices, &materials, 2048, &CancelToken::default()).unwrap();
+    assert!(proxy.index_buffer.iter().filter(|triangle| triangle.w == 1).count() < (size * size * 2) as usize);
+    // Every point is kept as it was
+    let mut points = proxy.index_buffer.iter().filter(|triangle| triangle.w & PRIMITIVE_POINT != 0).map(|triangle| triangle.x).collect::<Vec<_>>();
+    points.sort();
+    assert!(points.iter().copied().eq((0..4096u32).map(|i| i * 3)));
+    for triangle in proxy.index_buffer.iter().filter(|triangle| triangle.w & PRIMITIVE_POINT != 0) {
+        assert_eq!((triangle.y, triangle.z), (triangle.x + 1, triangle.x + 2));
+    }
+}
+
+#[test]
+fn clean_mesh_test() {
+    use rustic::asset::clean_mesh;
+
+    let positions = [Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::X, Vec3::X * 2.0];
+    let mut per_vertex_data = positions.iter().map(|p| PerVertexData { vertex: p.extend(1.0), ..Default::default() }).collect::<Vec<_>>();
+    let mut indices = vec![
+        UVec4::new(0, 1, 2, 0),
+        UVec4::new(0, 3, 2, 1), // Same as the first once welded, but with its own material
+        UVec4::new(0, 1, 4, 0), // Collinear
+        UVec4::new(1, 1, 2, 0), // Repeats a corner
+    ];
+    assert_eq!(clean_mesh(&mut per_vertex_data, &mut indices), 2);
+    assert_eq!(per_vertex_data.len(), 4);
+    assert_eq!(indices.len(), 2);
+    assert_eq!(indices[0].truncate(), indices[1].truncate());
+    assert_eq!((indices[0].w, indices[1].w), (0, 1));
+}
+
+#[test]
+fn generated_tangents_test() {
+    use rustic::scene::MeshDescription;
+    use rustic::tangents::{vertex_normals, vertex_tangents};
+
+    // A quad in the XY plane facing +Z, with U running along +X
+    let mut mesh = MeshDescription {
+        positions: vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)],
+        uvs: vec![Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(1.0, 1.0), Vec2::new(0.0, 1.0)],
+        triangles: vec![[0, 1, 2], [0, 2, 3]],
+        ..Default::default()
+    };
+    let normals = vertex_normals(&mesh);
+    assert!(normals.iter().all(|normal| normal.abs_diff_eq(Vec3::Z, 1e-5)));
+    let tangents = vertex_tangents(&mesh, &normals);
+    assert!(tangents.iter().all(|tangent| tangent.abs_diff_eq(Vec3::X, 1e-5)));
+
+    // Imported tangents are made orthogonal to the normal, zero ones are regenerated
+    mesh.tangents = vec![Vec3::new(1.0, 0.0, 1.0); 4];
+    assert!(vertex_tangents(&mesh, &normals).iter().all(|tangent| tangent.abs_diff_eq(Vec3::X, 1e-5)));
+    mesh.tangents[2] = Vec3::ZERO;
+    assert!(vertex_tangents(&mesh, &normals).iter().all(|tangent| tangent.abs_diff_eq(Vec3::X, 1e-5)));
+
+    // Without UVs there is nothing to follow, but the frame is still valid
+    mesh.tangents.clear();
+    mesh.uvs.clear();
+    for (tangent, normal) in vertex_tangents(&mesh, &normals).iter().zip(&normals) {
+        assert!(tangent.is_normalized() && tangent.dot(*normal).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn emissive_marker_test() {
+    use rustic::markers::{add_markers, Marker, MarkerShape};
+
+    let mut meshes = Vec::new();
+    let mut materials = Vec::new();
+    let markers = [
+        Marker { name: "Lamp".to_string(), shape: MarkerShape::Point(Vec3::ZERO), intensity: Vec3::splat(10.0) },
+        Marker { name: "Tube".to_string(), shape: MarkerShape::Line(Vec3::X, Vec3::new(1.0, 1.0, 0.0)), intensity: Vec3::splat(2.0) },
+    ];
+    add_markers(&mut meshes, &mut materials, &markers);
+    assert_eq!((meshes.len(), materials.len()), (2, 2));
+
+    // Every triangle faces away from the marker's center, and emits the requested intensity
+    let radius = meshes[0].positions[0].length();
+    for triangle in &meshes[0].triangles {
+        let [a, b, c] = triangle.map(|index| meshes[0].positions[index as usize]);
+        assert!((b - a).cross(c - a).dot(a + b + c) > 0.0);
+    }
+    let intensity = materials[0].emissive * std::f32::consts::PI * radius * radius;
+    assert!(intensity.abs_diff_eq(Vec3::splat(10.0), 1e-3));
+    assert!((materials[1].emissive * 2.0 * radius).abs_diff_eq(Vec3::splat(2.0), 1e-3));
+}
+
+#[test]
+fn export_preview_test() {
+    use rustic::export::{encode_framebuffer, encode_preview};
+    use rustic::tonemap::Tonemapping;
+
+    let (width, height) = (400, 100);
+    let framebuffer = vec![0.25; (width * height * 3) as usize];
+    let (pixels, preview_width, preview_height) = encode_preview(&framebuffer, width, height, 64, Tonemapping::None, 0.0);
+    assert_eq!((preview_width, preview_height), (64, 16));
+    assert_eq!(pixels.len(), 64 * 16 * 3);
+    assert_eq!(pixels[0], encode_framebuffer(&framebuffer[..3], Tonemapping::None, 0.0)[0]);
+
+    // One stop up doubles the linear value, which the preview reflects the same as the export
+    let brighter = encode_framebuffer(&framebuffer[..3], Tonemapping::None, 1.0);
+    assert_eq!(brighter, encode_framebuffer(&[0.5; 3], Tonemapping::None, 0.0));
+    assert_eq!(encode_preview(&framebuffer, width, height, 64, Tonemapping::None, 1.0).0[0], brighter[0]);
+}
+
+#[test]
+fn footer_burn_in_test() {
+    use rustic::export::{burn_in_footer, footer_lines, RenderMetadata};
+
+    let metadata = RenderMetadata {
+        entries: vec![("Scene", "cornell.glb".to_string()), ("Samples", "256".to_string()), ("Tonemapping", "ACES (H)".to_string())],
+    };
+    assert_eq!(footer_lines(&metadata, 1000), vec!["Scene: cornell.glb   Samples: 256   Tonemapping: ACES (H)"]);
+    assert_eq!(footer_lines(&metadata, 36), vec!["Scene: cornell.glb   Samples: 256", "Tonemapping: ACES (H)"]);
+    assert_eq!(footer_lines(&metadata, 5), vec!["Scene", "Sampl", "Tonem"]);
+
+    // The image is untouched, and the strip below it has both background and text
+    let (width, height) = (320, 10);
+    let mut pixels = vec![128; (width * height * 3) as usize];
+    let new_height = burn_in_footer(&mut pixels, width, height, &metadata);
+    assert!(new_height > height);
+    assert_eq!(pixels.len(), (width * new_height * 3) as usize);
+    assert!(pixels[..(width * height * 3) as usize].iter().all(|&c| c == 128));
+    let footer = &pixels[(width * height * 3) as usize..];
+    assert!(footer.iter().any(|&c| c < 128) && footer.iter().any(|&c| c > 128));
+}
+
+#[test]
+fn contact_sheet_composite_test() {
+    use rustic::contact_sheet::{composite, SweepAxis, SweepValue};
+    use rustic::tonemap::Tonemapping;
+
+    // Every axis changes what it says it does, and nothing else
+    let mut render = TracingConfig::default().render;
+    let mut tonemapping = Tonemapping::None;
+    SweepValue::MaxBounces(1).apply(&mut render, &mut tonemapping);
+    assert_eq!((render.min_bounces, render.max_bounces), (1, 1));
+    SweepValue::Tonemapping(Tonemapping::Reinhard).apply(&mut render, &mut tonemapping);
+    assert_eq!(tonemapping, Tonemapping::Reinhard);
+    assert_eq!(SweepAxis::None.values(), vec![SweepValue::Unchanged]);
+
+    // Cells land in row-major order, below and to the right of the labels
+    let (cell_width, cell_height) = (16, 8);
+    let cells = (0..6u8).map(|i| vec![i * 40; (cell_width * cell_height * 3) as usize]).collect::<Vec<_>>();
+    let rows = vec!["A".to_string(), "B".to_string()];
+    let columns = vec!["1".to_string(), "2".to_string(), "3".to_string()];
+    let (pixels, width, height) = composite(&cells, cell_width, cell_height, &rows, &columns, "");
+    assert_eq!(pixels.len(), (width * height * 3) as usize);
+    let pixel = |x: u32, y: u32| pixels[((y * width + x) * 3) as usize];
+    let (left, top) = (width - 3 * (cell_width + 4), height - 2 * (cell_height + 4));
+    assert_eq!(pixel(left, top), 0);
+    assert_eq!(pixel(left + 2 * (cell_width + 4), top), 80);
+    assert_eq!(pixel(left + cell_width + 4, top + cell_height + 4), 160);
+}
+
+#[test]
+fn turntable_orbit_test() {
+    use glam::Mat3;
+    use rustic::turntable::orbit_camera;
+
+    let center = Vec3::new(1.0, 2.0, 3.0);
+    for step in 0..8 {
+        let yaw = step as f32 / 8.0 * std::f32::consts::TAU;
+        let (position, rotation) = orbit_camera(center, 5.0, yaw, 0.3);
+        assert!((position.distance(center) - 5.0).abs() < 1e-4);
+        // The camera looks straight at the center, the same way the kernel turns its rotation into a direction
+        let forward = Mat3::from_rotation_y(rotation.y) * Mat3::from_rotation_x(rotation.x) * Vec3::Z;
+        assert!(forward.abs_diff_eq((center - position).normalize(), 1e-4));
+    }
+}
+
+#[test]
+fn material_preview_test() {
+    use rustic::material_preview::{render_preview, MaterialLibrary};
+
+    let library = MaterialLibrary {
+        names: vec!["Light".to_string()],
+        materials: vec![MaterialData::default()],
+        atlas: image::DynamicImage::new_rgba8(1, 1),
+    };
+    // The ball fills the middle of the thumbnail, and shows the edited emission rather than the loaded one
+    let image = render_preview(&library, 0, Vec4::new(4.0, 4.0, 4.0, 0.0)).unwrap();
+    let [width, height] = image.size;
+    let center = image.pixels[height / 2 * width + width / 2];
+    assert!(center.r() > 200 && center.g() > 200 && center.b() > 200);
+    assert!(render_preview(&library, 1, Vec4::ZERO).is_none());
+}
+
+#[test]
+fn probe_prefilter_test() {
+    use rustic::probe::{direction_uv, encode_ktx2, irradiance_cubemap, specular_cubemap, Panorama};
+
+    // Panoramic camera rays land where the probe's panorama lookups expect them
+    let mut config = TracingConfig::default();
+    config.render.width = 64;
+    config.render.height = 32;
+    config.camera.projection = shared_structs::PROJECTION_EQUIRECTANGULAR;
+    for screen in [Vec2::new(3.5, 7.5), Vec2::new(40.5, 20.5), Vec2::new(63.5, 0.5)] {
+        let (_, direction) = kernels::camera_ray(&config, screen);
+        let uv = direction_uv(direction) * Vec2::new(64.0, 32.0);
+        assert!(uv.abs_diff_eq(screen, 1e-3), "{} != {}", uv, screen);
+    }
+
+    // Uniform lighting stays uniform, whatever the roughness
+    let panorama = Panorama { width: 64, height: 32, pixels: vec![Vec3::splat(0.5); 64 * 32] };
+    let irradiance = irradiance_cubemap(&panorama, 8);
+    assert!(irradiance.levels[0].iter().all(|texel| texel.abs_diff_eq(Vec3::splat(0.5), 0.01)));
+    let specular = specular_cubemap(&panorama, 16);
+    assert_eq!(specular.levels.len(), 3);
+    assert!(specular.levels.iter().flatten().all(|texel| texel.abs_diff_eq(Vec3::splat(0.5), 0.01)));
+
+    // Header of a KTX2 cubemap, with every level's faces after it
+    let bytes = encode_ktx2(&specular);
+    let word = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    assert_eq!(&bytes[..12], &[0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n']);
+    assert_eq!((word(12), word(20), word(24), word(36), word(40)), (97, 16, 16, 6, 3));
+    let texels = 6 * (16 * 16 + 8 * 8 + 4 * 4);
+    assert!(bytes.len() >= texels * 8 && bytes.len() < texels * 8 + 256);
+}
+
+#[test]
+fn lightmap_rasterize_test() {
+    use rustic::lightmap::{dilate, rasterize};
+    use rustic::scene::MeshDescription;
+
+    // A unit quad whose lightmap UVs cover the left half of an 8x8 map
+    let mesh = MeshDescription {
+        positions: vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 1.0), Vec3::new(0.0, 0.0, 1.0)],
+        lightmap_uvs: vec![Vec2::new(0.0, 0.0), Vec2::new(0.5, 0.0), Vec2::new(0.5, 1.0), Vec2::new(0.0, 1.0)],
+        triangles: vec![[0, 2, 1], [0, 3, 2]],
+        ..Default::default()
+    };
+    let surface = rasterize(&mesh, &mesh.lightmap_uvs, &[Vec3::Y; 4], 8);
+    for (index, texel) in surface.iter().enumerate() {
+        let (x, y) = (index % 8, index / 8);
+        assert_eq!(texel.is_some(), x < 4);
+        if let Some(texel) = texel {
+            // Texel centers land where the UVs say, on the surface
+            let expected = Vec3::new((x as f32 + 0.5) / 4.0, 0.0, (y as f32 + 0.5) / 8.0);
+            assert!(texel.position.abs_diff_eq(expected, 1e-4));
+            assert_eq!(texel.normal, Vec3::Y);
+        }
+    }
+
+    // Each pass grows the baked texels by one ring
+    let mut texels = surface.iter().map(|texel| if texel.is_some() { Vec3::ONE } else { Vec3::ZERO }).collect::<Vec<_>>();
+    let mut covered = surface.iter().map(Option::is_some).collect::<Vec<_>>();
+    dilate(&mut texels, &mut covered, 8, 2);
+    for y in 0..8 {
+        assert_eq!(texels[y * 8 + 5], Vec3::ONE);
+        assert!(!covered[y * 8 + 6]);
+    }
+}
+
+#[test]
+fn vertex_curvature_test() {
+    use rustic::scene::MeshDescription;
+    use rustic::surface_maps::vertex_curvature;
+
+    // A ridge bends outwards along its crease, a valley inwards, and a flat strip not at all
+    let strip = |height: f32| MeshDescription {
+        positions: vec![Vec3::new(-1.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 1.0), Vec3::new(0.0, height, 0.0), Vec3::new(0.0, height, 1.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 1.0)],
+        triangles: vec![[0, 1, 2], [2, 1, 3], [2, 3, 4], [4, 3, 5]],
+        ..Default::default()
+    };
+    let normals = |height: f32| {
+        let left = Vec3::new(-height, 1.0, 0.0).normalize();
+        let right = Vec3::new(height, 1.0, 0.0).normalize();
+        vec![left, left, Vec3::Y, Vec3::Y, right, right]
+    };
+    let ridge = vertex_curvature(&strip(0.5), &normals(0.5));
+    let valley = vertex_curvature(&strip(-0.5), &normals(-0.5));
+    let flat = vertex_curvature(&strip(0.0), &normals(0.0));
+    assert!(ridge[2] > 0.1 && ridge[3] > 0.1);
+    assert!(valley[2] < -0.1 && valley[3] < -0.1);
+    assert!(flat.iter().all(|curvature| curvature.abs() < 1e-6));
+}
+
+#[test]
+fn vertex_irradiance_projection_test() {
+    use rustic::probe::sh9;
+    use rustic::vertex_bake::{hemisphere_direction, project_irradiance};
+
+    // Uniform light over the hemisphere gives irradiance of pi at its normal, so 1 once divided by pi
+    let mut rng = StdRng::seed_from_u64(7);
+    let samples = (0..8192).map(|_| (hemisphere_direction(Vec3::Y, Vec2::new(rng.gen(), rng.gen())), Vec3::ONE)).collect::<Vec<_>>();
+    let coefficients = project_irradiance(&samples);
+    let evaluate = |normal: Vec3| coefficients.iter().zip(sh9(normal)).map(|(coefficient, basis)| *coefficient * basis).sum::<Vec3>();
+    assert!(evaluate(Vec3::Y).abs_diff_eq(Vec3::ONE, 0.05), "{}", evaluate(Vec3::Y));
+    // And none reaches a surface facing away from it
+    assert!(evaluate(-Vec3::Y).abs_diff_eq(Vec3::ZERO, 0.1), "{}", evaluate(-Vec3::Y));
+}
+
+#[test]
+fn point_cloud_test() {
+    use rustic::point_cloud::parse_ply;
+    use rustic::scene::{MaterialDescription, MeshDescription};
+
+    let ascii = b"ply\nformat ascii 1.0\ncomment test\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\nend_header\n0 0 0 255 0 0\n1 2 3 0 0 255\n";
+    let cloud = parse_ply(ascii).unwrap();
+    assert_eq!(cloud.positions, vec![Vec3::ZERO, Vec3::new(1.0, 2.0, 3.0)]);
+    assert!(cloud.colors[0].abs_diff_eq(Vec3::X, 1e-5) && cloud.colors[1].abs_diff_eq(Vec3::Z, 1e-5));
+
+    // The same points in binary, without colors
+    let mut binary = b"ply\nformat binary_little_endian 1.0\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\nend_header\n".to_vec();
+    binary.extend([0.0f32, 0.0, 0.0, 1.0, 2.0, 3.0].iter().flat_map(|value| value.to_le_bytes()));
+    let cloud = parse_ply(&binary).unwrap();
+    assert_eq!(cloud.positions[1], Vec3::new(1.0, 2.0, 3.0));
+    assert_eq!(cloud.colors, vec![Vec3::ONE; 2]);
+
+    // Points are discs facing the ray, whichever way it comes from
+    let description = SceneDescription {
+        meshes: vec![MeshDescription { positions: cloud.positions, colors: cloud.colors, point_radius: 0.25, ..Default::default() }],
+        materials: vec![MaterialDescription::default()],
+        camera: None,
+    };
+    let world = world_from(&description);
+    assert_eq!(world.statistics.points, 2);
+    let bvh = BVHReference { nodes: &world.bvh.nodes, min_t: 0.001 };
+    for direction in [Vec3::Z, Vec3::X, Vec3::new(1.0, 1.0, 1.0).normalize()] {
+        let hit = bvh.intersect_nearest(&world.per_vertex_buffer, &world.index_buffer, -direction * 5.0, direction, 0);
+        assert!(hit.hit && (hit.t - 5.0).abs() < 1e-4, "{} {}", direction, hit.t);
+        let miss = bvh.intersect_nearest(&world.per_vertex_buffer, &world.index_buffer, -direction * 5.0 + direction.any_orthonormal_vector() * 0.3, direction, 0);
+        assert!(!miss.hit);
+    }
+    // Rays leaving a point pass through it
+    assert!(!bvh.intersect_nearest(&world.per_vertex_buffer, &world.index_buffer, Vec3::new(0.0, 0.1, 0.0), Vec3::Y, 0).hit);
+}
+
+#[test]
+fn sdf_intersection_test() {
+    use glam::UVec3;
+    use rustic::scene::{MaterialDescription, MeshDescription};
+    use rustic::sdf::{bake_grid, save_grid, SdfDescription, SdfShape};
+
+    // A sphere, and the same sphere baked into a grid next to it
+    let path = std::env::temp_dir().join("rustic_sdf_intersection_test.raw");
+    let resolution = UVec3::splat(32);
+    save_grid(&path, &bake_grid(resolution, Vec3::splat(1.2), |position| position.length() - 0.5)).unwrap();
+    let sdf = |shape: SdfShape, position: Vec3| MeshDescription { sdf: Some(SdfDescription { shape, position }), ..Default::default() };
+    let description = SceneDescription {
+        meshes: vec![
+            sdf(SdfShape::Sphere { radius: 0.5 }, Vec3::ZERO),
+            sdf(SdfShape::Grid { path: path.clone(), resolution, size: Vec3::splat(1.2) }, Vec3::new(3.0, 0.0, 0.0)),
+            sdf(SdfShape::Torus { radius: 1.0, thickness: 0.25 }, Vec3::new(-4.0, 0.0, 0.0)),
+        ],
+        materials: vec![MaterialDescription::default()],
+        camera: None,
+    };
+    let world = world_from(&description);
+    std::fs::remove_file(&path).ok();
+    assert_eq!(world.statistics.sdfs, 3);
+
+    let bvh = BVHReference { nodes: &world.bvh.nodes, min_t: 0.001 };
+    let trace = |origin: Vec3, direction: Vec3| bvh.intersect_nearest(&world.per_vertex_buffer, &world.index_buffer, origin, direction, 0);
+    let sphere = trace(Vec3::new(0.0, 0.0, -5.0), Vec3::Z);
+    assert!(sphere.hit && (sphere.t - 4.5).abs() < 1e-3 && !sphere.backface, "{}", sphere.t);
+    let grid = trace(Vec3::new(3.0, 0.0, -5.0), Vec3::Z);
+    assert!(grid.hit && (grid.t - 4.5).abs() < 0.02, "{}", grid.t);
+    // Through the hole of the torus, and then into its tube
+    assert!(!trace(Vec3::new(-4.0, -5.0, 0.0), Vec3::Y).hit);
+    let tube = trace(Vec3::new(-3.0, -5.0, 0.0), Vec3::Y);
+    assert!(tube.hit && (tube.t - 4.75).abs() < 1e-3, "{}", tube.t);
+    // Rays starting inside leave through the back face
+    let inside = trace(Vec3::ZERO, Vec3::X);
+    assert!(inside.hit && inside.backface && (inside.t - 0.5).abs() < 1e-3, "{}", inside.t);
+}
+
+// A closed box of half size 1 around the origin, with its triangles counter-clockwise seen from outside
+fn closed_box() -> (Vec<Vec3>, Vec<[u32; 3]>) {
+    let positions = (0..8).map(|i| Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2 & 1) as f32) * 2.0 - 1.0).collect::<Vec<_>>();
+    let mut triangles = Vec::new();
+    for [a, b, c, d] in [[0, 1, 3, 2], [4, 5, 7, 6], [0, 1, 5, 4], [2, 3, 7, 6], [0, 2, 6, 4], [1, 3, 7, 5]] {
+        for [a, b, c] in [[a, b, c], [a, c, d]] {
+            let outwards = (positions[b as usize] - positions[a as usize]).cross(positions[c as usize] - positions[a as usize]).dot(positions[a as usize]) > 0.0;
+            triangles.push(if outwards { [a, b, c] } else { [a, c, b] });
+        }
+    }
+    (positions, triangles)
+}
+
+#[test]
+fn csg_subtract_test() {
+    use rustic::scene::{CsgOperation, MaterialDescription, MeshDescription};
+    use rustic::sdf::{SdfDescription, SdfShape};
+
+    // A box of half size 1 around the origin, with a sphere around the middle of its front face
+    let (positions, triangles) = closed_box();
+    let world = |cutter: CsgOperation| {
+        let description = SceneDescription {
+            meshes: vec![
+                MeshDescription { positions: positions.clone(), triangles: triangles.clone(), material: 0, ..Default::default() },
+                MeshDescription { sdf: Some(SdfDescription { shape: SdfShape::Sphere { radius: 0.5 }, position: Vec3::new(0.0, 0.0, -1.0) }), material: 1, ..Default::default() },
+            ],
+            materials: vec![
+                MaterialDescription { name: "Solid".to_string(), csg: Some(CsgOperation::Union), ..Default::default() },
+                MaterialDescription { name: "Cutter".to_string(), csg: Some(cutter), ..Default::default() },
+            ],
+            camera: None,
+        };
+        world_from(&description)
+    };
+
+    let subtracted = world(CsgOperation::Subtract);
+    let bvh = BVHReference { nodes: &subtracted.bvh.nodes, min_t: 0.001 };
+    let trace = |origin: Vec3, direction: Vec3| bvh.intersect_nearest(&subtracted.per_vertex_buffer, &subtracted.index_buffer, origin, direction, 0);
+    // Off the diagonals of the box's faces, where rays would cross two triangles
+    let offset = Vec3::new(0.1, 0.05, 0.0);
+    let depth = (0.25 - offset.length_squared()).sqrt();
+    // Past the sphere outside the box and the box inside the sphere, onto the far side of the bite
+    let bite = trace(offset - Vec3::Z * 5.0, Vec3::Z);
+    assert!(bite.hit && (bite.t - 4.0 - depth).abs() < 1e-3 && !bite.backface, "{}", bite.t);
+    let face = trace(Vec3::new(0.8, 0.0, -5.0), Vec3::Z);
+    assert!(face.hit && (face.t - 4.0).abs() < 1e-3 && !face.backface, "{}", face.t);
+    // Leaving the solid into the bite
+    let inside = trace(offset, -Vec3::Z);
+    assert!(inside.hit && (inside.t - 1.0 + depth).abs() < 1e-3 && inside.backface, "{}", inside.t);
+    // Surfaces left out don't cast shadows either
+    assert!(!bvh.intersect_any(&subtracted.per_vertex_buffer, &subtracted.index_buffer, offset - Vec3::Z * 3.0, Vec3::Z, 2.2, HIDDEN_FROM_SHADOWS).hit);
+
+    // Only the part of the box inside the sphere is left
+    let intersected = world(CsgOperation::Intersect);
+    let bvh = BVHReference { nodes: &intersected.bvh.nodes, min_t: 0.001 };
+    let cap = bvh.intersect_nearest(&intersected.per_vertex_buffer, &intersected.index_buffer, offset - Vec3::Z * 5.0, Vec3::Z, 0);
+    assert!(cap.hit && (cap.t - 4.0).abs() < 1e-3 && !cap.backface, "{}", cap.t);
+    assert!(!bvh.intersect_nearest(&intersected.per_vertex_buffer, &intersected.index_buffer, Vec3::new(0.8, 0.0, -5.0), Vec3::Z, 0).hit);
+}
+
+#[test]
+fn section_plane_test() {
+    use kernels::section::SectionSpan;
+    use rustic::scene::{MaterialDescription, MeshDescription};
+    use rustic::scene_file::SectionDescription;
+    use shared_structs::RenderSettings;
+
+    let (positions, triangles) = closed_box();
+    let description = SceneDescription {
+        meshes: vec![MeshDescription { positions, triangles, material: 0, ..Default::default() }],
+        materials: vec![MaterialDescription { name: "Box".to_string(), ..Default::default() }],
+        camera: None,
+    };
+    let world = world_from(&description);
+    let bvh = BVHReference { nodes: &world.bvh.nodes, min_t: 0.001 };
+
+    // Cutting away the front half of the box, towards the camera
+    let mut render = RenderSettings::default();
+    render.section_planes[0] = SectionDescription { normal: -Vec3::Z, point: Vec3::ZERO }.to_plane();
+    render.section_plane_count = 1;
+    let trace = |render: &RenderSettings, origin: Vec3, direction: Vec3| {
+        let span = SectionSpan::new(render, origin, direction);
+        span.clip(render, bvh.intersect_nearest(&world.per_vertex_buffer, &world.index_buffer, span.origin(origin, direction), direction, HIDDEN_FROM_CAMERA))
+    };
+    let origin = Vec3::new(0.1, 0.05, -5.0);
+
+    // Filled, the cut is hit where the ray crosses the plane
+    let filled = trace(&render, origin, Vec3::Z);
+    assert!(filled.hit && filled.section && (filled.t - 5.0).abs() < 1e-3, "{}", filled.t);
+    // Left open, the ray sees the inside of the far face
+    render.section_color.w = 0.0;
+    let open = trace(&render, origin, Vec3::Z);
+    assert!(open.hit && !open.section && open.backface && (open.t - 6.0).abs() < 1e-3, "{}", open.t);
+    // Hits before the ray crosses into what is cut away are left alone
+    let behind = trace(&render, Vec3::new(0.0, 0.0, 5.0), -Vec3::Z);
+    assert!(behind.hit && !behind.backface && (behind.t - 4.0).abs() < 1e-3, "{}", behind.t);
+
+    // A second plane cutting away the back leaves a slab the far face is outside of
+    render.section_planes[1] = SectionDescription { normal: Vec3::Z, point: Vec3::Z * 0.5 }.to_plane();
+    render.section_plane_count = 2;
+    assert!(!trace(&render, origin, Vec3::Z).hit);
+    // Rays along the planes that are cut away see nothing
+    assert!(!trace(&render, Vec3::new(-5.0, 0.0, -0.5), Vec3::X).hit);
+}
+
+#[test]
+fn half_denormal_rounding_test() {
+    use kernels::half::{f16_bits_to_f32, f32_to_f16_bits};
+
+    // Rounded to the nearest multiple of 2^-24, rather than flushed to zero
+    let ulp = 1.0 / 16777216.0;
+    for value in [3e-6f32, -4.5e-5, 6.0e-5, ulp * 0.75] {
+        let rounded = f16_bits_to_f32(f32_to_f16_bits(value, 0xFFF));
+        assert!((rounded - value).abs() <= ulp * 0.5, "{} became {}", value, rounded);
+    }
+    // Just under the smallest normal half carries into it
+    assert_eq!(f32_to_f16_bits(6.1035e-5, 0xFFF), 0x400);
+
+    // Stochastic rounding keeps the mean of values far below the precision of a half
+    let mut random = StdRng::seed_from_u64(0);
+    let value = 1e-7f32;
+    let samples = 100_000;
+    let mean = (0..samples).map(|_| f16_bits_to_f32(f32_to_f16_bits(value, random.gen())) as f64).sum::<f64>() / samples as f64;
+    assert!((mean as f32 - value).abs() < value * 0.05, "mean {}", mean);
+}